@@ -6,7 +6,7 @@ use ethereum_types::consensus::{
     light_client_header::{LightClientFinalityUpdate, LightClientUpdate},
     spec::Spec,
 };
-use reqwest::{Client, StatusCode};
+use reqwest::{header::ACCEPT, Client, StatusCode};
 use serde::de::DeserializeOwned;
 use tracing::debug;
 
@@ -69,6 +69,29 @@ impl BeaconApiClient {
         Ok(resp.data)
     }
 
+    /// Fetches the Beacon block for a given block id as raw JSON, for blocks whose layout differs
+    /// from `BeaconBlock`
+    /// # Errors
+    /// Returns an error if the request fails or the response is not successful deserialized
+    pub async fn beacon_block_json(
+        &self,
+        block_id: &str,
+    ) -> Result<serde_json::Value, BeaconApiClientError> {
+        let resp: Response<serde_json::Value> = self
+            .get_json(&format!("{BEACON_BLOCKS_V2_PATH}/{block_id}"))
+            .await?;
+        Ok(resp.data)
+    }
+
+    /// Fetches the SSZ encoded signed Beacon block for a given block id, for beacon nodes that do
+    /// not serve the block as JSON
+    /// # Errors
+    /// Returns an error if the request fails
+    pub async fn beacon_block_ssz(&self, block_id: &str) -> Result<Vec<u8>, BeaconApiClientError> {
+        self.get_ssz(&format!("{BEACON_BLOCKS_V2_PATH}/{block_id}"))
+            .await
+    }
+
     /// Fetches the Beacon block root for a given block id
     /// # Errors
     /// Returns an error if the request fails or the response is not successful deserialized
@@ -132,4 +155,32 @@ impl BeaconApiClient {
             }),
         }
     }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_ssz(&self, path: &str) -> Result<Vec<u8>, BeaconApiClientError> {
+        let url = format!("{}{}", self.base_url, path);
+
+        debug!(%url, "get_ssz");
+
+        let res = self
+            .client
+            .get(url)
+            .header(ACCEPT, "application/octet-stream")
+            .send()
+            .await?;
+
+        match res.status() {
+            StatusCode::OK => Ok(res.bytes().await?.to_vec()),
+            StatusCode::NOT_FOUND => Err(BeaconApiClientError::NotFound(
+                res.json::<NotFoundError>().await?,
+            )),
+            StatusCode::INTERNAL_SERVER_ERROR => Err(BeaconApiClientError::Internal(
+                res.json::<InternalServerError>().await?,
+            )),
+            code => Err(BeaconApiClientError::Other {
+                code,
+                text: res.text().await?,
+            }),
+        }
+    }
 }
@@ -3,7 +3,7 @@
 use std::str::FromStr;
 
 use alloy::{
-    primitives::{Address, StorageKey},
+    primitives::{Address, StorageKey, B256},
     providers::Provider,
     rpc::types::{Block, EIP1186AccountProofResponse},
 };
@@ -33,15 +33,25 @@ impl<P: Provider + Clone> EthApiClient<P> {
         storage_keys: Vec<String>,
         block_hex: String,
     ) -> Result<EIP1186AccountProofResponse, EthClientError> {
-        let address: Address = Address::from_str(address)
-            .map_err(|e| EthClientError::ParseError(address.to_string(), e.to_string()))?;
-        let storage_keys: Vec<StorageKey> = storage_keys
-            .into_iter()
-            .map(|key| {
-                StorageKey::from_str(&key)
-                    .map_err(|e| EthClientError::ParseError(key, e.to_string()))
-            })
-            .collect::<Result<_, _>>()?;
+        let (address, storage_keys) = parse_proof_params(address, storage_keys)?;
+        Ok(self
+            .provider
+            .client()
+            .request(RPC_METHOD_GET_PROOF, (address, storage_keys, block_hex))
+            .await?)
+    }
+
+    /// Fetches the raw JSON proof for an account and optionally storage keys under the given
+    /// account at the block, for callers that handle the response shapes of different clients.
+    /// # Errors
+    /// Returns an error if the input fails to serialize or the request fails
+    pub async fn get_proof_json(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_hex: String,
+    ) -> Result<serde_json::Value, EthClientError> {
+        let (address, storage_keys) = parse_proof_params(address, storage_keys)?;
         Ok(self
             .provider
             .client()
@@ -65,4 +75,30 @@ impl<P: Provider + Clone> EthApiClient<P> {
             .await?
             .ok_or_else(|| EthClientError::BlockNotFound(block_number))
     }
+
+    /// Fetches a block by its hash
+    /// # Errors
+    /// Returns an error if the request fails
+    pub async fn get_block_by_hash(&self, block_hash: B256) -> Result<Block, EthClientError> {
+        self.provider
+            .get_block(block_hash.into())
+            .await?
+            .ok_or_else(|| EthClientError::BlockHashNotFound(block_hash))
+    }
+}
+
+/// Parses the address and the storage keys of a proof request.
+fn parse_proof_params(
+    address: &str,
+    storage_keys: Vec<String>,
+) -> Result<(Address, Vec<StorageKey>), EthClientError> {
+    let address: Address = Address::from_str(address)
+        .map_err(|e| EthClientError::ParseError(address.to_string(), e.to_string()))?;
+    let storage_keys: Vec<StorageKey> = storage_keys
+        .into_iter()
+        .map(|key| {
+            StorageKey::from_str(&key).map_err(|e| EthClientError::ParseError(key, e.to_string()))
+        })
+        .collect::<Result<_, _>>()?;
+    Ok((address, storage_keys))
 }
@@ -1,6 +1,6 @@
 //! This module defines errors for `EthApiClient`.
 
-use alloy::transports::TransportError;
+use alloy::{primitives::B256, transports::TransportError};
 
 #[derive(Debug, thiserror::Error)]
 #[allow(missing_docs, clippy::module_name_repetitions)]
@@ -13,4 +13,7 @@ pub enum EthClientError {
 
     #[error("block not found for block number {0}")]
     BlockNotFound(u64),
+
+    #[error("block not found for block hash {0}")]
+    BlockHashNotFound(B256),
 }
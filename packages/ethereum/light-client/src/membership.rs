@@ -1,9 +1,12 @@
 //! This module provides [`verify_membership`] function to verify the membership of a key in the
 //! storage trie.
 
-use alloy_primitives::{keccak256, Keccak256, U256};
+use std::io::Read;
+
+use alloy_primitives::{keccak256, Keccak256, B256, U256};
 use ethereum_trie_db::trie_db::{verify_storage_exclusion_proof, verify_storage_inclusion_proof};
 use ethereum_types::execution::storage_proof::StorageProof;
+use flate2::read::ZlibDecoder;
 
 use crate::{client_state::ClientState, consensus_state::ConsensusState, error::EthereumIBCError};
 
@@ -18,8 +21,7 @@ pub fn verify_membership(
     path: Vec<Vec<u8>>,
     raw_value: Vec<u8>,
 ) -> Result<(), EthereumIBCError> {
-    let storage_proof: StorageProof = serde_json::from_slice(proof.as_slice())
-        .map_err(|_| EthereumIBCError::StorageProofDecode)?;
+    let storage_proof = decode_storage_proof(&proof)?;
 
     check_commitment_path(
         &path,
@@ -55,8 +57,7 @@ pub fn verify_non_membership(
     proof: Vec<u8>,
     path: Vec<Vec<u8>>,
 ) -> Result<(), EthereumIBCError> {
-    let storage_proof: StorageProof = serde_json::from_slice(proof.as_slice())
-        .map_err(|_| EthereumIBCError::StorageProofDecode)?;
+    let storage_proof = decode_storage_proof(&proof)?;
 
     check_commitment_path(
         &path,
@@ -80,6 +81,67 @@ pub fn verify_non_membership(
     .map_err(|err| EthereumIBCError::VerifyStorageProof(err.to_string()))
 }
 
+/// The maximum size of a decompressed storage proof, to bound the work done on malicious input.
+const MAX_DECOMPRESSED_STORAGE_PROOF_SIZE: u64 = 1 << 20;
+
+/// The first byte of a compact encoded [`StorageProof`], which starts neither a json encoded nor a
+/// zlib compressed proof.
+pub const COMPACT_STORAGE_PROOF_TAG: u8 = 0xc1;
+
+/// Decodes a json encoded [`StorageProof`], which may have been zlib compressed by the relayer,
+/// or a compact encoded one, see [`decode_compact_storage_proof`].
+/// # Errors
+/// Returns an error if the proof cannot be decompressed or decoded.
+pub fn decode_storage_proof(proof: &[u8]) -> Result<StorageProof, EthereumIBCError> {
+    if proof.first() == Some(&COMPACT_STORAGE_PROOF_TAG) {
+        return decode_compact_storage_proof(proof);
+    }
+    if let Ok(storage_proof) = serde_json::from_slice(proof) {
+        return Ok(storage_proof);
+    }
+
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(proof)
+        .take(MAX_DECOMPRESSED_STORAGE_PROOF_SIZE)
+        .read_to_end(&mut decompressed)
+        .map_err(|_| EthereumIBCError::StorageProofDecode)?;
+    serde_json::from_slice(&decompressed).map_err(|_| EthereumIBCError::StorageProofDecode)
+}
+
+/// Decodes a compact encoded [`StorageProof`]: the [`COMPACT_STORAGE_PROOF_TAG`], the 32 bytes
+/// key, the 32 bytes big-endian value, and every trie node prefixed by its length as a big-endian
+/// `u16`.
+/// # Errors
+/// Returns an error if the proof is not compact encoded or is truncated.
+pub fn decode_compact_storage_proof(proof: &[u8]) -> Result<StorageProof, EthereumIBCError> {
+    let (&tag, rest) = proof
+        .split_first()
+        .ok_or(EthereumIBCError::StorageProofDecode)?;
+    ensure!(
+        tag == COMPACT_STORAGE_PROOF_TAG && rest.len() >= 64,
+        EthereumIBCError::StorageProofDecode
+    );
+    let (key, rest) = rest.split_at(32);
+    let (value, mut rest) = rest.split_at(32);
+
+    let mut nodes = Vec::new();
+    while !rest.is_empty() {
+        ensure!(rest.len() >= 2, EthereumIBCError::StorageProofDecode);
+        let (len, node) = rest.split_at(2);
+        let len = usize::from(u16::from_be_bytes([len[0], len[1]]));
+        ensure!(node.len() >= len, EthereumIBCError::StorageProofDecode);
+        let (node, next) = node.split_at(len);
+        nodes.push(node.to_vec().into());
+        rest = next;
+    }
+
+    Ok(StorageProof {
+        key: B256::from_slice(key),
+        value: U256::from_be_slice(value),
+        proof: nodes,
+    })
+}
+
 fn check_commitment_path(
     path: &[Vec<u8>],
     ibc_commitment_slot: U256,
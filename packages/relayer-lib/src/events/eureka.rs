@@ -1,9 +1,11 @@
 //! Define the events that can be retrieved by the relayer.
 
+use std::fmt;
+
 use alloy::{
     primitives::{hex, Bytes},
     rpc::types::Log,
-    sol_types::{SolEvent, SolEventInterface},
+    sol_types::{SolEvent, SolEventInterface, SolValue},
 };
 use ibc_eureka_solidity_types::ics26::{
     router::{routerEvents, SendPacket, WriteAcknowledgement},
@@ -11,22 +13,26 @@ use ibc_eureka_solidity_types::ics26::{
 };
 use ibc_proto_eureka::ibc::core::channel::v2::{Acknowledgement, Packet};
 use prost::Message;
+use serde::{Deserialize, Serialize};
 use tendermint::abci::Event as TmEvent;
 
 use super::cosmos_sdk;
 
 /// Events emitted by IBC Eureka implementations that the relayer is interested in.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[allow(clippy::module_name_repetitions)]
 pub struct EurekaEventWithHeight {
     /// The type of the event.
     pub event: EurekaEvent,
     /// The block number at which the event was emitted.
     pub block_number: Option<u64>,
+    /// The unix timestamp (in seconds) of the block at which the event was emitted.
+    pub block_timestamp: Option<u64>,
 }
 
 /// The event type
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "SerdeEurekaEvent", try_from = "SerdeEurekaEvent")]
 #[allow(clippy::module_name_repetitions)]
 pub enum EurekaEvent {
     /// A packet was sent.
@@ -35,6 +41,41 @@ pub enum EurekaEvent {
     WriteAcknowledgement(SolPacket, Vec<Bytes>),
 }
 
+/// The serializable form of [`EurekaEvent`], used to persist events and replay them later.
+/// Packets are ABI encoded since the solidity types do not implement serde.
+#[derive(Serialize, Deserialize)]
+enum SerdeEurekaEvent {
+    SendPacket(Bytes),
+    WriteAcknowledgement(Bytes, Vec<Bytes>),
+}
+
+impl From<EurekaEvent> for SerdeEurekaEvent {
+    fn from(event: EurekaEvent) -> Self {
+        match event {
+            EurekaEvent::SendPacket(packet) => Self::SendPacket(packet.abi_encode().into()),
+            EurekaEvent::WriteAcknowledgement(packet, acks) => {
+                Self::WriteAcknowledgement(packet.abi_encode().into(), acks)
+            }
+        }
+    }
+}
+
+impl TryFrom<SerdeEurekaEvent> for EurekaEvent {
+    type Error = alloy::sol_types::Error;
+
+    fn try_from(event: SerdeEurekaEvent) -> Result<Self, Self::Error> {
+        match event {
+            SerdeEurekaEvent::SendPacket(packet) => {
+                Ok(Self::SendPacket(SolPacket::abi_decode(&packet, true)?))
+            }
+            SerdeEurekaEvent::WriteAcknowledgement(packet, acks) => Ok(Self::WriteAcknowledgement(
+                SolPacket::abi_decode(&packet, true)?,
+                acks,
+            )),
+        }
+    }
+}
+
 impl EurekaEventWithHeight {
     /// Get the signature of the events for EVM.
     /// This is used to filter the logs.
@@ -44,6 +85,55 @@ impl EurekaEventWithHeight {
     }
 }
 
+/// Renders an [`EurekaEventWithHeight`] as a one line summary for the logs, with the clients, the
+/// sequence, the timeout timestamp and the block number of its packet. The payloads and the
+/// acknowledgements are only summarized by their counts and total lengths, so that the summary is
+/// cheap to log in hot loops.
+pub struct EventSummary<'a>(pub &'a EurekaEventWithHeight);
+
+impl fmt::Display for EventSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (kind, packet) = match &self.0.event {
+            EurekaEvent::SendPacket(packet) => ("SendPacket", packet),
+            EurekaEvent::WriteAcknowledgement(packet, _) => ("WriteAcknowledgement", packet),
+        };
+        write!(
+            f,
+            "{kind} {} -> {} sequence {} timeout {}",
+            packet.sourceClient, packet.destClient, packet.sequence, packet.timeoutTimestamp
+        )?;
+        match self.0.block_number {
+            Some(block_number) => write!(f, " at height {block_number}")?,
+            None => write!(f, " at unknown height")?,
+        }
+        write!(
+            f,
+            ", {} payloads ({} bytes)",
+            packet.payloads.len(),
+            packet
+                .payloads
+                .iter()
+                .map(|payload| payload.value.len())
+                .sum::<usize>()
+        )?;
+        if let EurekaEvent::WriteAcknowledgement(_, acks) = &self.0.event {
+            write!(
+                f,
+                ", {} acknowledgements ({} bytes)",
+                acks.len(),
+                acks.iter().map(Bytes::len).sum::<usize>()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the one line summary of `event`, see [`EventSummary`].
+#[must_use]
+pub fn summarize(event: &EurekaEventWithHeight) -> String {
+    EventSummary(event).to_string()
+}
+
 impl TryFrom<&Log> for EurekaEventWithHeight {
     type Error = anyhow::Error;
 
@@ -86,6 +176,7 @@ impl TryFrom<&Log> for EurekaEventWithHeight {
         Ok(Self {
             event: event_type,
             block_number: log.block_number,
+            block_timestamp: log.block_timestamp,
         })
     }
 }
@@ -146,3 +237,84 @@ impl TryFrom<TmEvent> for EurekaEvent {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use alloy::primitives::Bytes;
+    use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::{Packet, Payload};
+
+    use super::{summarize, EurekaEvent, EurekaEventWithHeight};
+
+    #[test]
+    fn test_eureka_event_serde_round_trip() {
+        let packet = Packet {
+            sequence: 7,
+            sourceClient: "07-tendermint-0".to_string(),
+            destClient: "08-wasm-0".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![Payload {
+                sourcePort: "transfer".to_string(),
+                destPort: "transfer".to_string(),
+                version: "ics20-1".to_string(),
+                encoding: "application/json".to_string(),
+                value: Bytes::from_static(b"{}"),
+            }],
+        };
+        let events = vec![
+            EurekaEventWithHeight {
+                event: EurekaEvent::SendPacket(packet.clone()),
+                block_number: Some(42),
+                block_timestamp: Some(1_699_999_000),
+            },
+            EurekaEventWithHeight {
+                event: EurekaEvent::WriteAcknowledgement(packet, vec![Bytes::from_static(b"ack")]),
+                block_number: None,
+                block_timestamp: None,
+            },
+        ];
+
+        let persisted = serde_json::to_vec(&events).unwrap();
+        let replayed: Vec<EurekaEventWithHeight> = serde_json::from_slice(&persisted).unwrap();
+        assert_eq!(replayed, events);
+    }
+
+    #[test]
+    fn test_summarize() {
+        let payload = |value: Bytes| Payload {
+            sourcePort: "transfer".to_string(),
+            destPort: "transfer".to_string(),
+            version: "ics20-1".to_string(),
+            encoding: "application/json".to_string(),
+            value,
+        };
+        let packet = Packet {
+            sequence: 7,
+            sourceClient: "07-tendermint-0".to_string(),
+            destClient: "08-wasm-0".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![
+                payload(Bytes::from(vec![0; 1000])),
+                payload(Bytes::from_static(b"{}")),
+            ],
+        };
+
+        assert_eq!(
+            summarize(&EurekaEventWithHeight {
+                event: EurekaEvent::SendPacket(packet.clone()),
+                block_number: Some(42),
+                block_timestamp: None,
+            }),
+            "SendPacket 07-tendermint-0 -> 08-wasm-0 sequence 7 timeout 1700000000 at height 42, \
+             2 payloads (1002 bytes)"
+        );
+        assert_eq!(
+            summarize(&EurekaEventWithHeight {
+                event: EurekaEvent::WriteAcknowledgement(packet, vec![Bytes::from_static(b"ack")]),
+                block_number: None,
+                block_timestamp: None,
+            }),
+            "WriteAcknowledgement 07-tendermint-0 -> 08-wasm-0 sequence 7 timeout 1700000000 at \
+             unknown height, 2 payloads (1002 bytes), 1 acknowledgements (3 bytes)"
+        );
+    }
+}
@@ -9,10 +9,8 @@
     unused_crate_dependencies
 )]
 
-use ibc_core_commitment_types as _;
-
 pub mod chain;
 pub mod events;
 pub mod listener;
 pub mod tx_builder;
-mod utils;
+pub mod utils;
@@ -43,6 +43,15 @@ impl ChainListener {
             .chain_id
             .into())
     }
+
+    /// Get the unix timestamp in seconds of the block at `height`, from its header.
+    ///
+    /// # Errors
+    /// Returns an error if the block cannot be fetched.
+    async fn block_timestamp(&self, height: Height) -> Result<Option<u64>> {
+        let block = self.client().block(height).await?;
+        Ok(u64::try_from(block.block.header.time.unix_timestamp()).ok())
+    }
 }
 
 #[async_trait::async_trait]
@@ -51,13 +60,15 @@ impl ChainListenerService<CosmosSdk> for ChainListener {
         Ok(
             future::try_join_all(tx_ids.into_iter().map(|tx_id| async move {
                 let tx_response = self.client().tx(tx_id, false).await?;
+                let block_timestamp = self.block_timestamp(tx_response.height).await?;
                 let height = tx_response.height.value();
-                Ok::<_, tendermint_rpc::Error>(tx_response.tx_result.events.into_iter().filter_map(
+                Ok::<_, anyhow::Error>(tx_response.tx_result.events.into_iter().filter_map(
                     move |e| {
                         let event_type = EurekaEvent::try_from(e).ok()?;
                         Some(EurekaEventWithHeight {
                             event: event_type,
                             block_number: Some(height),
+                            block_timestamp,
                         })
                     },
                 ))
@@ -77,7 +88,11 @@ impl ChainListenerService<CosmosSdk> for ChainListener {
         Ok(
             future::try_join_all((start_height..=end_height).map(|h| async move {
                 let height: Height = h.try_into()?;
-                let resp = self.client().block_results(height).await?;
+                let (resp, block_timestamp) = future::try_join(
+                    async { Ok(self.client().block_results(height).await?) },
+                    self.block_timestamp(height),
+                )
+                .await?;
                 Ok::<_, anyhow::Error>(
                     resp.txs_results
                         .unwrap_or_default()
@@ -91,6 +106,7 @@ impl ChainListenerService<CosmosSdk> for ChainListener {
                             Some(EurekaEventWithHeight {
                                 event: event_type,
                                 block_number: Some(h),
+                                block_timestamp,
                             })
                         }),
                 )
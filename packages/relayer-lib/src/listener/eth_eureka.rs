@@ -1,5 +1,7 @@
 //! This module defines the chain listener for 'solidity-ibc-eureka' contracts.
 
+use std::collections::{BTreeMap, BTreeSet};
+
 use alloy::{
     primitives::{Address, TxHash},
     providers::Provider,
@@ -43,6 +45,43 @@ where
             .await?
             .to_string())
     }
+
+    /// Sets the block timestamp of the `events` whose logs were served without one, from the
+    /// header of their block. The header of each block is fetched once.
+    /// # Errors
+    /// Returns an error if the header of a block cannot be fetched.
+    async fn with_block_timestamps(
+        &self,
+        mut events: Vec<EurekaEventWithHeight>,
+    ) -> Result<Vec<EurekaEventWithHeight>> {
+        let block_numbers = events
+            .iter()
+            .filter(|e| e.block_timestamp.is_none())
+            .filter_map(|e| e.block_number)
+            .collect::<BTreeSet<_>>();
+        let block_timestamps =
+            future::try_join_all(block_numbers.into_iter().map(|block_number| async move {
+                let block = self
+                    .ics26_router
+                    .provider()
+                    .get_block(block_number.into())
+                    .await?
+                    .ok_or_else(|| anyhow!("Block {} not found", block_number))?;
+                Ok::<_, anyhow::Error>((block_number, block.header.timestamp))
+            }))
+            .await?
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+
+        for event in &mut events {
+            if event.block_timestamp.is_none() {
+                event.block_timestamp = event
+                    .block_number
+                    .and_then(|block_number| block_timestamps.get(&block_number).copied());
+            }
+        }
+        Ok(events)
+    }
 }
 
 #[async_trait::async_trait]
@@ -51,38 +90,38 @@ where
     P: Provider,
 {
     async fn fetch_tx_events(&self, tx_ids: Vec<TxHash>) -> Result<Vec<EurekaEventWithHeight>> {
-        Ok(
-            future::try_join_all(tx_ids.into_iter().map(|tx_id| async move {
-                let block_hash = self
-                    .ics26_router
+        let events = future::try_join_all(tx_ids.into_iter().map(|tx_id| async move {
+            let block_hash = self
+                .ics26_router
+                .provider()
+                .get_transaction_by_hash(tx_id)
+                .await?
+                .ok_or_else(|| anyhow!("Transaction {} not found", tx_id))?
+                .block_hash
+                .ok_or_else(|| anyhow!("Transaction {} has not been mined", tx_id))?;
+
+            let event_filter = Filter::new()
+                .events(EurekaEventWithHeight::evm_signatures())
+                .address(*self.ics26_router.address())
+                .at_block_hash(block_hash);
+
+            Ok::<_, anyhow::Error>(
+                self.ics26_router
                     .provider()
-                    .get_transaction_by_hash(tx_id)
+                    .get_logs(&event_filter)
                     .await?
-                    .ok_or_else(|| anyhow!("Transaction {} not found", tx_id))?
-                    .block_hash
-                    .ok_or_else(|| anyhow!("Transaction {} has not been mined", tx_id))?;
-
-                let event_filter = Filter::new()
-                    .events(EurekaEventWithHeight::evm_signatures())
-                    .address(*self.ics26_router.address())
-                    .at_block_hash(block_hash);
-
-                Ok::<_, anyhow::Error>(
-                    self.ics26_router
-                        .provider()
-                        .get_logs(&event_filter)
-                        .await?
-                        .iter()
-                        .filter(|log| log.transaction_hash.unwrap_or_default() == tx_id)
-                        .filter_map(|log| EurekaEventWithHeight::try_from(log).ok())
-                        .collect::<Vec<_>>(),
-                )
-            }))
-            .await?
-            .into_iter()
-            .flatten()
-            .collect(),
-        )
+                    .iter()
+                    .filter(|log| log.transaction_hash.unwrap_or_default() == tx_id)
+                    .filter_map(|log| EurekaEventWithHeight::try_from(log).ok())
+                    .collect::<Vec<_>>(),
+            )
+        }))
+        .await?
+        .into_iter()
+        .flatten()
+        .collect();
+
+        self.with_block_timestamps(events).await
     }
 
     async fn fetch_events(
@@ -96,13 +135,15 @@ where
             .from_block(start_height)
             .to_block(end_height);
 
-        Ok(self
+        let events = self
             .ics26_router
             .provider()
             .get_logs(&event_filter)
             .await?
             .iter()
             .filter_map(|log| EurekaEventWithHeight::try_from(log).ok())
-            .collect())
+            .collect();
+
+        self.with_block_timestamps(events).await
     }
 }
@@ -17,7 +17,7 @@ use tendermint_rpc::HttpClient;
 use crate::{
     chain::CosmosSdk,
     events::EurekaEventWithHeight,
-    utils::cosmos::{self},
+    utils::{clock::SystemClock, cosmos, packet::normalize_client_id},
 };
 
 use super::r#trait::TxBuilderService;
@@ -61,6 +61,9 @@ impl TxBuilderService<CosmosSdk, CosmosSdk> for TxBuilder {
         src_packet_seqs: Vec<u64>,
         dst_packet_seqs: Vec<u64>,
     ) -> Result<Vec<u8>> {
+        let src_client_id = normalize_client_id(&src_client_id)?;
+        let dst_client_id = normalize_client_id(&dst_client_id)?;
+
         let client_state = ClientState::decode(
             self.target_tm_client
                 .client_state(dst_client_id.clone())
@@ -81,8 +84,6 @@ impl TxBuilderService<CosmosSdk, CosmosSdk> for TxBuilder {
             revision_height,
         };
 
-        let now_since_unix = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
-
         let mut timeout_msgs = cosmos::target_events_to_timeout_msgs(
             target_events,
             &src_client_id,
@@ -90,10 +91,12 @@ impl TxBuilderService<CosmosSdk, CosmosSdk> for TxBuilder {
             &dst_packet_seqs,
             &target_height,
             &self.signer_address,
-            now_since_unix.as_secs(),
-        );
+            None,
+            &SystemClock,
+            &cosmos::TimeoutFilterOptions::default(),
+        )?;
 
-        let (mut recv_msgs, mut ack_msgs) = cosmos::src_events_to_recv_and_ack_msgs(
+        let (mut recv_msgs, mut ack_msgs, _) = cosmos::src_events_to_recv_and_ack_msgs(
             src_events,
             &src_client_id,
             &dst_client_id,
@@ -101,8 +104,15 @@ impl TxBuilderService<CosmosSdk, CosmosSdk> for TxBuilder {
             &dst_packet_seqs,
             &target_height,
             &self.signer_address,
-            now_since_unix.as_secs(),
-        );
+            None,
+            &SystemClock,
+            &cosmos::MsgFilterOptions::default(),
+        )?;
+        if cosmos::is_batch_empty(&recv_msgs, &ack_msgs, &timeout_msgs) {
+            // An idle cycle is not an error, the empty transaction tells the caller to skip it.
+            tracing::info!("No messages to relay to Cosmos");
+            return Ok(vec![]);
+        }
 
         cosmos::inject_tendermint_proofs(
             &mut recv_msgs,
@@ -110,6 +120,8 @@ impl TxBuilderService<CosmosSdk, CosmosSdk> for TxBuilder {
             &mut timeout_msgs,
             &self.source_tm_client,
             &target_height,
+            cosmos::DEFAULT_COMMITMENT_PREFIX,
+            &cosmos::TendermintProofOptions::default(),
         )
         .await?;
 
@@ -134,10 +146,6 @@ impl TxBuilderService<CosmosSdk, CosmosSdk> for TxBuilder {
             .chain(recv_msgs.into_iter().map(|m| Any::from_msg(&m)))
             .chain(ack_msgs.into_iter().map(|m| Any::from_msg(&m)))
             .collect::<Result<Vec<_>, _>>()?;
-        if all_msgs.len() == 1 {
-            // The update message is the only message.
-            anyhow::bail!("No messages to relay to Cosmos");
-        }
 
         tracing::debug!(
             "Messages to be relayed to Cosmos: {:?}",
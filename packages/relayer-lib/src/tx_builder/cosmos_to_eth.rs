@@ -23,7 +23,12 @@ use sp1_prover::components::SP1ProverComponents;
 use crate::{
     chain::{CosmosSdk, EthEureka},
     events::EurekaEventWithHeight,
-    utils::eth_eureka::{self, inject_sp1_proof},
+    utils::{
+        clock::SystemClock,
+        cosmos,
+        eth_eureka::{self, inject_sp1_proof},
+        packet::normalize_client_id,
+    },
 };
 
 use super::r#trait::TxBuilderService;
@@ -97,6 +102,9 @@ where
         src_packet_seqs: Vec<u64>,
         dst_packet_seqs: Vec<u64>,
     ) -> Result<Vec<u8>> {
+        let src_client_id = normalize_client_id(&src_client_id)?;
+        let dst_client_id = normalize_client_id(&dst_client_id)?;
+
         let now_since_unix = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
 
         let latest_light_block = self.tm_client.get_light_block(None).await?;
@@ -114,8 +122,10 @@ where
             &dst_client_id,
             &dst_packet_seqs,
             &latest_height,
-            now_since_unix.as_secs(),
-        );
+            &SystemClock,
+            None,
+            None,
+        )?;
 
         let recv_and_ack_msgs = eth_eureka::src_events_to_recv_and_ack_msgs(
             src_events,
@@ -124,8 +134,11 @@ where
             &src_packet_seqs,
             &dst_packet_seqs,
             &latest_height,
-            now_since_unix.as_secs(),
-        );
+            &SystemClock,
+            None,
+            None,
+            None,
+        )?;
 
         let mut all_msgs = timeout_msgs
             .into_iter()
@@ -146,6 +159,7 @@ where
             &self.tm_client,
             latest_light_block,
             client_state,
+            cosmos::DEFAULT_COMMITMENT_PREFIX,
             now_since_unix.as_nanos(),
         )
         .await?;
@@ -23,7 +23,9 @@ use ibc_proto_eureka::ibc::lightclients::wasm::v1::ClientState as WasmClientStat
 use prost::Message;
 use tendermint_rpc::{Client, HttpClient};
 
-use crate::utils::{cosmos, wait_for_condition};
+use crate::utils::{
+    clock::SystemClock, cosmos, eth_proof, metrics, packet::normalize_client_id, wait_for_condition,
+};
 use crate::{
     chain::{CosmosSdk, EthEureka},
     events::EurekaEventWithHeight,
@@ -315,6 +317,9 @@ where
         src_packet_seqs: Vec<u64>,
         dst_packet_seqs: Vec<u64>,
     ) -> Result<Vec<u8>> {
+        let src_client_id = normalize_client_id(&src_client_id)?;
+        let dst_client_id = normalize_client_id(&dst_client_id)?;
+
         let latest_block_number = self.eth_client.get_block_number().await?;
         let minimum_block_number = if dest_events.is_empty() {
             let latest_block_from_events = src_events.iter().filter_map(|e| e.block_number).max();
@@ -331,6 +336,11 @@ where
 
         let now_since_unix = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
 
+        let max_packet_latency = src_events
+            .iter()
+            .filter_map(|e| metrics::packet_latency(e, now_since_unix.as_secs()))
+            .max();
+
         let mut timeout_msgs = cosmos::target_events_to_timeout_msgs(
             dest_events,
             &src_client_id,
@@ -338,10 +348,12 @@ where
             &dst_packet_seqs,
             &target_height,
             &self.signer_address,
-            now_since_unix.as_secs(),
-        );
+            None,
+            &SystemClock,
+            &cosmos::TimeoutFilterOptions::default(),
+        )?;
 
-        let (mut recv_msgs, mut ack_msgs) = cosmos::src_events_to_recv_and_ack_msgs(
+        let (mut recv_msgs, mut ack_msgs, _) = cosmos::src_events_to_recv_and_ack_msgs(
             src_events,
             &src_client_id,
             &dst_client_id,
@@ -349,8 +361,15 @@ where
             &dst_packet_seqs,
             &target_height,
             &self.signer_address,
-            now_since_unix.as_secs(),
-        );
+            None,
+            &SystemClock,
+            &cosmos::MsgFilterOptions::default(),
+        )?;
+        if cosmos::is_batch_empty(&recv_msgs, &ack_msgs, &timeout_msgs) {
+            // An idle cycle is not an error, the empty transaction tells the caller to skip it.
+            tracing::info!("No messages to relay to Cosmos");
+            return Ok(vec![]);
+        }
 
         let mut ethereum_client_state = self.ethereum_client_state(dst_client_id.clone()).await?;
 
@@ -379,15 +398,26 @@ where
                 h.consensus_update.finalized_header.beacon.slot
             });
 
-        cosmos::inject_ethereum_proofs(
+        eth_proof::inject_ethereum_proofs(
             &mut recv_msgs,
             &mut ack_msgs,
             &mut timeout_msgs,
             &self.eth_client,
             &self.beacon_api_client,
-            &ethereum_client_state.ibc_contract_address.to_string(),
-            ethereum_client_state.ibc_commitment_slot,
+            &eth_proof::EthProofLayout {
+                ibc_contract_address: &ethereum_client_state.ibc_contract_address.to_string(),
+                commitment_slots: eth_proof::CommitmentSlots::uniform(
+                    ethereum_client_state.ibc_commitment_slot,
+                ),
+                slot_layout: eth_proof::SlotLayout::Flat,
+                revision_number: 0,
+                proof_codec: &eth_proof::JsonProofCodec,
+            },
             proof_slot,
+            &eth_proof::EthProofOptions {
+                block_layout: eth_proof::BeaconBlockLayout::ExecutionPayload,
+                ..Default::default()
+            },
         )
         .await?;
 
@@ -455,7 +485,8 @@ where
                 latest trusted slot (after updates): {}, 
                 initial period: {}, 
                 latest period: {}, 
-                number of headers: #{}",
+                number of headers: #{},
+                max packet latency: {:?}",
             recv_msgs.len(),
             ack_msgs.len(),
             timeout_msgs.len(),
@@ -463,7 +494,8 @@ where
             proof_slot,
             initial_period,
             latest_period,
-            headers.len()
+            headers.len(),
+            max_packet_latency
         );
 
         Ok(tx_body.encode_to_vec())
@@ -496,6 +528,9 @@ where
         src_packet_seqs: Vec<u64>,
         dst_packet_seqs: Vec<u64>,
     ) -> Result<Vec<u8>> {
+        let src_client_id = normalize_client_id(&src_client_id)?;
+        let dst_client_id = normalize_client_id(&dst_client_id)?;
+
         let target_block_number = self.eth_client.get_block_number().await?;
 
         tracing::info!(
@@ -508,8 +543,6 @@ where
             revision_height: target_block_number,
         };
 
-        let now_since_unix = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
-
         let mut timeout_msgs = cosmos::target_events_to_timeout_msgs(
             dest_events,
             &src_client_id,
@@ -517,10 +550,12 @@ where
             &dst_packet_seqs,
             &target_height,
             &self.signer_address,
-            now_since_unix.as_secs(),
-        );
+            None,
+            &SystemClock,
+            &cosmos::TimeoutFilterOptions::default(),
+        )?;
 
-        let (mut recv_msgs, mut ack_msgs) = cosmos::src_events_to_recv_and_ack_msgs(
+        let (mut recv_msgs, mut ack_msgs, _) = cosmos::src_events_to_recv_and_ack_msgs(
             src_events,
             &src_client_id,
             &dst_client_id,
@@ -528,14 +563,22 @@ where
             &dst_packet_seqs,
             &target_height,
             &self.signer_address,
-            now_since_unix.as_secs(),
-        );
+            None,
+            &SystemClock,
+            &cosmos::MsgFilterOptions::default(),
+        )?;
 
         tracing::debug!("Timeout messages: #{}", timeout_msgs.len());
         tracing::debug!("Recv messages: #{}", recv_msgs.len());
         tracing::debug!("Ack messages: #{}", ack_msgs.len());
 
-        cosmos::inject_mock_proofs(&mut recv_msgs, &mut ack_msgs, &mut timeout_msgs);
+        cosmos::inject_mock_proofs(
+            &mut recv_msgs,
+            &mut ack_msgs,
+            &mut timeout_msgs,
+            &cosmos::MockProofs::default(),
+            Height::default(),
+        );
 
         let all_msgs = timeout_msgs
             .into_iter()
@@ -20,7 +20,8 @@ pub trait TxBuilderService<A: Chain, B: Chain> {
     /// relayed if empty.
     ///
     /// # Returns
-    /// The relay transaction bytes.
+    /// The relay transaction bytes. The transactions to Cosmos chains are empty if there are no
+    /// messages to relay.
     async fn relay_events(
         &self,
         src_events: Vec<A::Event>,
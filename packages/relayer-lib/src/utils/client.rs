@@ -0,0 +1,1949 @@
+//! Defines the client interfaces used by the relayer utilities to generate proofs.
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    primitives::{Bytes, B256, U256, U64},
+    providers::Provider,
+    rpc::types::EIP1186AccountProofResponse,
+};
+use anyhow::Result;
+use cosmos_sdk_proto::cosmos::base::tendermint::v1beta1::{AbciQueryRequest, AbciQueryResponse};
+use ethereum_apis::{beacon_api::client::BeaconApiClient, eth_api::client::EthApiClient};
+use ethereum_types::consensus::beacon_block::BeaconBlock;
+use futures::{future, stream::BoxStream};
+use futures_timer::Delay;
+use ibc_core_commitment_types::{merkle::MerkleProof, proto::ics23::CommitmentProof};
+use ibc_eureka_utils::rpc::TendermintRpcExt;
+use prost::Message;
+use serde::Deserialize;
+use tendermint_rpc::{Client, HttpClient};
+use tracing::Instrument;
+
+use super::{
+    eth_proof::ProofBlockId,
+    retry::{retry_with_backoff, RetryPolicy},
+};
+
+/// The `TendermintProofClient` trait defines the interface for a client that proves paths in a
+/// Cosmos SDK chain's Merkle tree.
+#[async_trait::async_trait]
+#[allow(clippy::module_name_repetitions)]
+pub trait TendermintProofClient: Sync {
+    /// Proves a path in the chain's Merkle tree and returns the value at the path and the proof.
+    /// If the value is empty, then this is a non-inclusion proof.
+    /// The `request_id`, if any, is attached to the request to correlate it with the RPC logs.
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)>;
+}
+
+#[async_trait::async_trait]
+impl TendermintProofClient for HttpClient {
+    // The RPC client does not support per request headers, so the request id is recorded in the
+    // span of the request instead.
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)> {
+        TendermintRpcExt::prove_path(self, path, height)
+            .instrument(tracing::debug_span!("prove_path", request_id))
+            .await
+    }
+}
+
+/// The `AbciQueryService` trait defines the interface of the `ABCIQuery` method of the
+/// `cosmos.base.tendermint.v1beta1.Service` gRPC service, exposed by nodes that do not serve the
+/// Tendermint RPC.
+#[async_trait::async_trait]
+pub trait AbciQueryService: Sync {
+    /// Queries the application state with `request`.
+    async fn abci_query(&self, request: AbciQueryRequest) -> Result<AbciQueryResponse>;
+}
+
+/// The `GrpcProofClient` proves paths through the gRPC `ABCIQuery` method, as an alternative to
+/// [`HttpClient`] for chains that only expose gRPC. It can be passed as the source client to
+/// [`inject_tendermint_proofs`](super::cosmos::inject_tendermint_proofs).
+pub struct GrpcProofClient<S: AbciQueryService> {
+    service: S,
+}
+
+impl<S: AbciQueryService> GrpcProofClient<S> {
+    /// Creates a new `GrpcProofClient` that queries `service`.
+    #[must_use]
+    pub const fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+/// Splits the path of a store query into the store key and the key in the store, i.e. the
+/// concatenation of the remaining segments.
+/// # Errors
+/// Returns an error if the path has no key segment or if the store key is not UTF-8.
+fn store_query_key(path: &[Vec<u8>]) -> Result<(&str, Vec<u8>)> {
+    let [store, key @ ..] = path else {
+        anyhow::bail!("The path of a store query must not be empty")
+    };
+    anyhow::ensure!(!key.is_empty(), "The path of a store query has no key");
+    Ok((std::str::from_utf8(store)?, key.concat()))
+}
+
+/// Returns the height of the state proved at `height`, i.e. the block before the target block,
+/// as the app hash of a block commits to the state of the previous block.
+/// # Errors
+/// Returns an error if `height` is zero.
+fn query_height(height: u64) -> Result<u64> {
+    height
+        .checked_sub(1)
+        .ok_or_else(|| anyhow::anyhow!("Cannot prove a path at height 0"))
+}
+
+#[async_trait::async_trait]
+impl<S: AbciQueryService> TendermintProofClient for GrpcProofClient<S> {
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)> {
+        let (store, key) = store_query_key(path)?;
+        let query_height = query_height(height)?;
+        let request = AbciQueryRequest {
+            data: key.clone(),
+            path: format!("store/{store}/key"),
+            height: query_height.try_into()?,
+            prove: true,
+        };
+        let res = self
+            .service
+            .abci_query(request)
+            .instrument(tracing::debug_span!("prove_path", request_id))
+            .await?;
+
+        if u64::try_from(res.height)? != query_height {
+            anyhow::bail!("Proof height mismatch");
+        }
+
+        if res.key != key {
+            anyhow::bail!("Key mismatch");
+        }
+
+        let proofs = res
+            .proof_ops
+            .ok_or_else(|| anyhow::anyhow!("Proof could not be retrieved"))?
+            .ops
+            .iter()
+            .map(|op| CommitmentProof::decode(op.data.as_slice()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if proofs.is_empty() {
+            anyhow::bail!("Empty proof");
+        }
+
+        Ok((res.value, MerkleProof { proofs }))
+    }
+}
+
+/// The `RestService` trait defines the interface for a service that sends GET requests to the
+/// REST API of a chain and returns the JSON responses.
+#[async_trait::async_trait]
+pub trait RestService: Sync {
+    /// Sends a GET request to `endpoint` with the `query` parameters.
+    async fn get(&self, endpoint: &str, query: &[(&str, String)]) -> Result<serde_json::Value>;
+}
+
+/// The `HttpRestService` sends the REST requests over HTTP to the API at `base_url`.
+pub struct HttpRestService {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl HttpRestService {
+    /// Creates a new `HttpRestService` that sends the requests with `client` to `base_url`.
+    #[must_use]
+    pub fn new(client: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RestService for HttpRestService {
+    async fn get(&self, endpoint: &str, query: &[(&str, String)]) -> Result<serde_json::Value> {
+        Ok(self
+            .client
+            .get(format!(
+                "{}/{endpoint}",
+                self.base_url.trim_end_matches('/')
+            ))
+            .query(query)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+/// The proof served by the `proof` endpoint of a REST API.
+#[derive(Debug, Deserialize)]
+struct RestProofResponse {
+    /// The height of the queried state.
+    height: u64,
+    /// The proved key in the store.
+    key: Bytes,
+    /// The value at the key, empty if the key is absent.
+    value: Bytes,
+    /// The protobuf encoded ICS-23 proofs, from the store up to the root.
+    proofs: Vec<Bytes>,
+}
+
+/// The `RestProofClient` proves paths through the `proof` endpoint of a bespoke REST API, for
+/// chains that expose their Merkle proofs neither through the Tendermint RPC nor through gRPC.
+///
+/// The endpoint is queried with the `store` key, the hex encoded `key` and the `height`, and
+/// responds with the JSON encoded height, key, value and ICS-23 `proofs`, with the bytes hex
+/// encoded.
+pub struct RestProofClient<S: RestService> {
+    service: S,
+}
+
+impl<S: RestService> RestProofClient<S> {
+    /// Creates a new `RestProofClient` that queries `service`.
+    #[must_use]
+    pub const fn new(service: S) -> Self {
+        Self { service }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: RestService> TendermintProofClient for RestProofClient<S> {
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)> {
+        let (store, key) = store_query_key(path)?;
+        let query_height = query_height(height)?;
+        let query = [
+            ("store", store.to_string()),
+            ("key", alloy::hex::encode(&key)),
+            ("height", query_height.to_string()),
+        ];
+        let res = self
+            .service
+            .get("proof", &query)
+            .instrument(tracing::debug_span!("prove_path", request_id))
+            .await?;
+        let res: RestProofResponse = serde_json::from_value(res)?;
+
+        if res.height != query_height {
+            anyhow::bail!("Proof height mismatch");
+        }
+
+        if res.key.as_ref() != key.as_slice() {
+            anyhow::bail!("Key mismatch");
+        }
+
+        let proofs = res
+            .proofs
+            .iter()
+            .map(|proof| CommitmentProof::decode(proof.as_ref()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if proofs.is_empty() {
+            anyhow::bail!("Empty proof");
+        }
+
+        Ok((res.value.to_vec(), MerkleProof { proofs }))
+    }
+}
+
+/// The `TwoLayerProofClient` proves the paths of chains that commit the IBC store under an
+/// application store, rather than directly in the app hash. The proof of a path chains the
+/// IBC-layer proof of the path up to the root of the IBC store, with the application-layer proof
+/// of the IBC store root at `app_path` up to the app hash.
+pub struct TwoLayerProofClient<I, A> {
+    ibc_client: I,
+    app_client: A,
+    app_path: Vec<Vec<u8>>,
+}
+
+impl<I: TendermintProofClient, A: TendermintProofClient> TwoLayerProofClient<I, A> {
+    /// Creates a new `TwoLayerProofClient` that proves the paths in the IBC store with
+    /// `ibc_client`, and the root of the IBC store at `app_path` with `app_client`.
+    #[must_use]
+    pub const fn new(ibc_client: I, app_client: A, app_path: Vec<Vec<u8>>) -> Self {
+        Self {
+            ibc_client,
+            app_client,
+            app_path,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<I: TendermintProofClient, A: TendermintProofClient> TendermintProofClient
+    for TwoLayerProofClient<I, A>
+{
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)> {
+        let ((value, ibc_proof), (ibc_root, app_proof)) = future::try_join(
+            self.ibc_client.prove_path(path, height, request_id),
+            self.app_client
+                .prove_path(&self.app_path, height, request_id),
+        )
+        .await?;
+        anyhow::ensure!(
+            !ibc_root.is_empty(),
+            "The root of the IBC store is empty in the application store"
+        );
+
+        // The proofs of a `MerkleProof` go from the store of the path up to the app hash.
+        let proofs = ibc_proof
+            .proofs
+            .into_iter()
+            .chain(app_proof.proofs)
+            .collect();
+        Ok((value, MerkleProof { proofs }))
+    }
+}
+
+/// The encoding of the height passed to the node when proving a path at the revision height of
+/// the target height.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeightParam {
+    /// The revision height, i.e. the height of the block whose app hash commits to the proof.
+    #[default]
+    RevisionHeight,
+    /// The height of the queried state, i.e. the block before the revision height, for nodes
+    /// that expect the height of the application state rather than of the block committing to it.
+    PreviousHeight,
+}
+
+impl HeightParam {
+    /// Encodes the revision height `height` in the form expected by the node.
+    /// # Errors
+    /// Returns an error if `height` cannot be encoded, i.e. if it is 0 for
+    /// [`HeightParam::PreviousHeight`].
+    pub fn encode(self, height: u64) -> Result<u64> {
+        match self {
+            Self::RevisionHeight => Ok(height),
+            Self::PreviousHeight => height
+                .checked_sub(1)
+                .ok_or_else(|| anyhow::anyhow!("The height 0 has no previous height")),
+        }
+    }
+}
+
+/// The `HeightParamClient` passes the height of the proofs to the wrapped client encoded as
+/// expected by its node, see [`HeightParam`].
+pub struct HeightParamClient<C> {
+    client: C,
+    height_param: HeightParam,
+}
+
+impl<C> HeightParamClient<C> {
+    /// Creates a new `HeightParamClient` that proves the paths with `client` at the heights
+    /// encoded with `height_param`.
+    #[must_use]
+    pub const fn new(client: C, height_param: HeightParam) -> Self {
+        Self {
+            client,
+            height_param,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: TendermintProofClient> TendermintProofClient for HeightParamClient<C> {
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)> {
+        let height = self.height_param.encode(height)?;
+        self.client.prove_path(path, height, request_id).await
+    }
+}
+
+/// The `LatestHeightClient` trait defines the interface for a client that looks up the latest
+/// committed height of a chain.
+#[async_trait::async_trait]
+pub trait LatestHeightClient: Sync {
+    /// Returns the latest committed height of the chain.
+    async fn latest_height(&self) -> Result<u64>;
+}
+
+#[async_trait::async_trait]
+impl LatestHeightClient for HttpClient {
+    async fn latest_height(&self) -> Result<u64> {
+        Ok(self.status().await?.sync_info.latest_block_height.value())
+    }
+}
+
+/// The `EthProofClient` trait defines the interface for a client that fetches storage proofs from
+/// an Ethereum execution client.
+#[async_trait::async_trait]
+pub trait EthProofClient: Sync {
+    /// Fetches the account and storage proofs for `storage_keys` under `address` at `block_id`.
+    /// The block can be identified either by its hex encoded number or by its hash.
+    /// The `request_id`, if any, is attached to the request to correlate it with the RPC logs.
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse>;
+
+    /// Fetches the header of the block at `block_id`, so that the proofs fetched at `block_id`
+    /// can be checked to be served at the requested block, e.g. by a node that pruned it.
+    /// Returns `None` if the client does not serve block headers, in which case the check is
+    /// skipped unless it is required.
+    async fn block_header(&self, _block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        Ok(None)
+    }
+
+    /// Fetches the proofs as [`Self::get_proof`] along with the header of the block at
+    /// `block_id`, as [`Self::block_header`]. A client that routes the requests across several
+    /// endpoints must serve both from the same endpoint, so that the proofs are checked against
+    /// the header of the node that served them.
+    async fn get_proof_at_block(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: ProofBlockId,
+        request_id: Option<&str>,
+    ) -> Result<(EIP1186AccountProofResponse, Option<ExecutionBlockHeader>)> {
+        future::try_join(
+            self.get_proof(address, storage_keys, block_id.to_rpc_param(), request_id),
+            self.block_header(block_id),
+        )
+        .await
+    }
+
+    /// Reads the value of the storage slot at `key` under `address` at `block_id`, as with
+    /// `eth_getStorageAt`. By default, the value is read from the storage proof of the slot.
+    async fn storage_at(&self, address: &str, key: U256, block_id: String) -> Result<U256> {
+        let proof = self
+            .get_proof(address, vec![B256::from(key).to_string()], block_id, None)
+            .await?;
+        proof
+            .storage_proof
+            .first()
+            .map(|storage_proof| storage_proof.value)
+            .ok_or_else(|| anyhow::anyhow!("Missing storage proof of slot {key:#x}"))
+    }
+}
+
+/// A write to a storage slot of a contract, as streamed by an execution client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageDiff {
+    /// The number of the block in which the slot was written.
+    pub block_number: u64,
+    /// The storage key of the slot.
+    pub key: B256,
+    /// The value written to the slot.
+    pub value: U256,
+}
+
+/// The `StateDiffClient` trait defines the interface for a client that streams the state diffs
+/// of the blocks of an Ethereum execution client, e.g. over a WebSocket subscription.
+#[async_trait::async_trait]
+pub trait StateDiffClient: Sync {
+    /// Subscribes to the writes to the storage of the contract at `address`, in block order.
+    async fn subscribe_storage_diffs(
+        &self,
+        address: &str,
+    ) -> Result<BoxStream<'static, Result<StorageDiff>>>;
+}
+
+/// The latest execution block verified by a light client of ethereum on another chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientHead {
+    /// The beacon slot of the latest verified header, which is the height of the light client.
+    pub slot: u64,
+    /// The hash of the execution block of the latest verified header.
+    pub execution_block_hash: B256,
+}
+
+/// The `ClientHeadClient` trait defines the interface for a client that looks up the latest
+/// header verified by a light client of ethereum, on the chain that hosts the light client.
+#[async_trait::async_trait]
+pub trait ClientHeadClient: Sync {
+    /// Returns the latest execution block verified by the light client `client_id`.
+    async fn client_head(&self, client_id: &str) -> Result<ClientHead>;
+}
+
+/// The header fields of an execution block that identify it, and its state root, against which
+/// the account proofs served at the block verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionBlockHeader {
+    /// The number of the block.
+    pub number: u64,
+    /// The hash of the block.
+    pub hash: B256,
+    /// The state root of the block.
+    pub state_root: B256,
+}
+
+#[async_trait::async_trait]
+impl<P: Provider + Clone> EthProofClient for EthApiClient<P> {
+    // The provider does not support per request headers, so the request id is recorded in the
+    // span of the request instead.
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        let response = self
+            .get_proof_json(address, storage_keys, block_id)
+            .instrument(tracing::debug_span!("get_proof", request_id))
+            .await?;
+        parse_account_proof(response)
+    }
+
+    async fn block_header(&self, block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        let block = match block_id {
+            ProofBlockId::Number(block_number) => self.get_block(block_number).await?,
+            ProofBlockId::Hash(block_hash) => self.get_block_by_hash(block_hash).await?,
+        };
+        Ok(Some(ExecutionBlockHeader {
+            number: block.header.number,
+            hash: block.header.hash,
+            state_root: block.header.state_root,
+        }))
+    }
+}
+
+/// The `HttpEthProofClient` sends the `eth_getProof` requests over JSON-RPC to the execution
+/// client at `url`, with a caller provided HTTP client. This allows the proof requests to go
+/// through a pre-configured client, e.g. with custom TLS roots, a proxy or authentication headers.
+pub struct HttpEthProofClient {
+    client: reqwest::Client,
+    url: String,
+    next_id: AtomicU64,
+    request_signer: Option<RequestSigner>,
+}
+
+/// A caller provided signer of the requests, returning the headers to add to each request, e.g.
+/// the HMAC headers required by some RPC providers. It is called with the request as built,
+/// without the default headers of the HTTP client.
+pub type RequestSigner = Box<dyn Fn(&reqwest::Request) -> reqwest::header::HeaderMap + Send + Sync>;
+
+impl HttpEthProofClient {
+    /// Creates a new `HttpEthProofClient` that sends the requests with `client` to `url`.
+    #[must_use]
+    pub fn new(client: reqwest::Client, url: impl Into<String>) -> Self {
+        Self {
+            client,
+            url: url.into(),
+            next_id: AtomicU64::new(1),
+            request_signer: None,
+        }
+    }
+
+    /// Signs every request with `request_signer` before sending it, see [`RequestSigner`].
+    #[must_use]
+    pub fn with_request_signer(mut self, request_signer: RequestSigner) -> Self {
+        self.request_signer = Some(request_signer);
+        self
+    }
+}
+
+impl HttpEthProofClient {
+    /// Sends the JSON-RPC request of `method` with `params`, and returns its result.
+    async fn request(
+        &self,
+        method: &str,
+        params: serde_json::Value,
+        request_id: Option<&str>,
+    ) -> Result<serde_json::Value> {
+        let body = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": self.next_id.fetch_add(1, Ordering::Relaxed),
+            "method": method,
+            "params": params,
+        });
+        let mut request = self.client.post(&self.url).json(&body);
+        if let Some(request_id) = request_id {
+            request = request.header("x-request-id", request_id);
+        }
+        let mut request = request.build()?;
+        if let Some(request_signer) = &self.request_signer {
+            let headers = request_signer(&request);
+            request.headers_mut().extend(headers);
+        }
+        let mut response: serde_json::Value = self
+            .client
+            .execute(request)
+            .instrument(tracing::debug_span!("request", method, request_id))
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if let Some(error) = response.get("error") {
+            anyhow::bail!("{method} failed: {error}");
+        }
+        response
+            .get_mut("result")
+            .map(serde_json::Value::take)
+            .ok_or_else(|| anyhow::anyhow!("Missing result in {method} response"))
+    }
+}
+
+#[async_trait::async_trait]
+impl LatestHeightClient for HttpEthProofClient {
+    async fn latest_height(&self) -> Result<u64> {
+        let result = self
+            .request("eth_blockNumber", serde_json::json!([]), None)
+            .await?;
+        Ok(serde_json::from_value::<U64>(result)?.to())
+    }
+}
+
+/// The header fields of a block returned by `eth_getBlockByNumber` and `eth_getBlockByHash`.
+#[derive(Debug, Deserialize)]
+struct RpcBlockHeader {
+    number: U64,
+    hash: B256,
+    #[serde(rename = "stateRoot")]
+    state_root: B256,
+}
+
+#[async_trait::async_trait]
+impl EthProofClient for HttpEthProofClient {
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        let result = self
+            .request(
+                "eth_getProof",
+                serde_json::json!([address, storage_keys, block_id]),
+                request_id,
+            )
+            .await?;
+        parse_account_proof(result)
+    }
+
+    async fn storage_at(&self, address: &str, key: U256, block_id: String) -> Result<U256> {
+        let result = self
+            .request(
+                "eth_getStorageAt",
+                serde_json::json!([address, B256::from(key), block_id]),
+                None,
+            )
+            .await?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    async fn block_header(&self, block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        let method = match block_id {
+            ProofBlockId::Number(_) => "eth_getBlockByNumber",
+            ProofBlockId::Hash(_) => "eth_getBlockByHash",
+        };
+        let result = self
+            .request(
+                method,
+                serde_json::json!([block_id.to_rpc_param(), false]),
+                None,
+            )
+            .await?;
+        anyhow::ensure!(
+            !result.is_null(),
+            "Block {} not found",
+            block_id.to_rpc_param()
+        );
+        let header: RpcBlockHeader = serde_json::from_value(result)?;
+        Ok(Some(ExecutionBlockHeader {
+            number: header.number.to(),
+            hash: header.hash,
+            state_root: header.state_root,
+        }))
+    }
+}
+
+/// The alternative names of the fields of an `eth_getProof` response used by some execution
+/// clients, along with their canonical names.
+const ACCOUNT_PROOF_FIELD_ALIASES: [(&str, &str); 3] = [
+    ("accountProofs", "accountProof"),
+    ("storageRoot", "storageHash"),
+    ("storageProofs", "storageProof"),
+];
+
+/// The alternative names of the fields of the storage proofs of an `eth_getProof` response, along
+/// with their canonical names.
+const STORAGE_PROOF_FIELD_ALIASES: [(&str, &str); 3] =
+    [("storageKey", "key"), ("slot", "key"), ("proofs", "proof")];
+
+/// Parses an `eth_getProof` response, accepting the alternative field names used by some
+/// execution clients so that proofs parse across clients.
+/// # Errors
+/// Returns an error if the response is not a valid account proof.
+pub fn parse_account_proof(mut response: serde_json::Value) -> Result<EIP1186AccountProofResponse> {
+    if let Some(account_proof) = response.as_object_mut() {
+        rename_field_aliases(account_proof, &ACCOUNT_PROOF_FIELD_ALIASES);
+        if let Some(storage_proofs) = account_proof
+            .get_mut("storageProof")
+            .and_then(serde_json::Value::as_array_mut)
+        {
+            storage_proofs
+                .iter_mut()
+                .filter_map(serde_json::Value::as_object_mut)
+                .for_each(|storage_proof| {
+                    rename_field_aliases(storage_proof, &STORAGE_PROOF_FIELD_ALIASES);
+                });
+        }
+    }
+
+    Ok(serde_json::from_value(response)?)
+}
+
+/// Renames the fields of `object` named by an alias to their canonical name, unless the canonical
+/// field is already present.
+fn rename_field_aliases(
+    object: &mut serde_json::Map<String, serde_json::Value>,
+    aliases: &[(&str, &str)],
+) {
+    for (alias, name) in aliases {
+        if object.contains_key(*name) {
+            continue;
+        }
+        if let Some(value) = object.remove(*alias) {
+            object.insert((*name).to_string(), value);
+        }
+    }
+}
+
+/// The `BeaconBlockClient` trait defines the interface for a client that fetches beacon blocks
+/// from a beacon node.
+#[async_trait::async_trait]
+pub trait BeaconBlockClient: Sync {
+    /// Fetches the JSON encoded beacon block for a given block id.
+    async fn beacon_block(&self, block_id: &str) -> Result<BeaconBlock>;
+
+    /// Fetches the JSON encoded beacon block for a given block id without parsing it, so that its
+    /// fields can be read from the layout of any beacon API version.
+    async fn beacon_block_json(&self, block_id: &str) -> Result<serde_json::Value> {
+        Ok(serde_json::to_value(self.beacon_block(block_id).await?)?)
+    }
+
+    /// Fetches the SSZ encoded signed beacon block for a given block id.
+    async fn beacon_block_ssz(&self, block_id: &str) -> Result<Vec<u8>>;
+
+    /// Fetches the latest finalized slot.
+    async fn finalized_slot(&self) -> Result<u64>;
+}
+
+#[async_trait::async_trait]
+impl BeaconBlockClient for BeaconApiClient {
+    async fn beacon_block(&self, block_id: &str) -> Result<BeaconBlock> {
+        Ok(Self::beacon_block(self, block_id).await?)
+    }
+
+    async fn beacon_block_json(&self, block_id: &str) -> Result<serde_json::Value> {
+        Ok(Self::beacon_block_json(self, block_id).await?)
+    }
+
+    async fn beacon_block_ssz(&self, block_id: &str) -> Result<Vec<u8>> {
+        Ok(Self::beacon_block_ssz(self, block_id).await?)
+    }
+
+    async fn finalized_slot(&self) -> Result<u64> {
+        Ok(self
+            .finality_update()
+            .await?
+            .data
+            .finalized_header
+            .beacon
+            .slot)
+    }
+}
+
+/// The `RoundRobinEthClient` distributes the proof requests across a pool of ethereum clients, so
+/// that the load is balanced between several RPC endpoints.
+pub struct RoundRobinEthClient<C: EthProofClient> {
+    clients: Vec<C>,
+    next: AtomicUsize,
+}
+
+impl<C: EthProofClient> RoundRobinEthClient<C> {
+    /// Creates a new `RoundRobinEthClient` over the given clients.
+    /// # Errors
+    /// Returns an error if `clients` is empty.
+    pub fn new(clients: Vec<C>) -> Result<Self> {
+        anyhow::ensure!(
+            !clients.is_empty(),
+            "At least one ethereum client is required"
+        );
+        Ok(Self {
+            clients,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the client that serves the next request.
+    fn next_client(&self) -> &C {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.clients.len();
+        &self.clients[index]
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: EthProofClient> EthProofClient for RoundRobinEthClient<C> {
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        self.next_client()
+            .get_proof(address, storage_keys, block_id, request_id)
+            .await
+    }
+
+    async fn block_header(&self, block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        self.next_client().block_header(block_id).await
+    }
+
+    async fn get_proof_at_block(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: ProofBlockId,
+        request_id: Option<&str>,
+    ) -> Result<(EIP1186AccountProofResponse, Option<ExecutionBlockHeader>)> {
+        self.next_client()
+            .get_proof_at_block(address, storage_keys, block_id, request_id)
+            .await
+    }
+}
+
+/// The `RateLimiter` trait defines the interface for a limiter of the RPC requests, shared by
+/// the clients of several relay workers so that they stay within one RPC budget.
+#[async_trait::async_trait]
+pub trait RateLimiter: Send + Sync {
+    /// Waits until a request is allowed by the limiter.
+    async fn acquire(&self);
+}
+
+/// The `TokenBucket` allows bursts of up to `capacity` requests, and refills one request every
+/// `refill_interval`.
+pub struct TokenBucket {
+    capacity: u32,
+    refill_interval: Duration,
+    /// The available tokens and the time they were last refilled.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// Creates a new full `TokenBucket`.
+    /// # Errors
+    /// Returns an error if `capacity` is zero or `refill_interval` is zero, as the bucket would
+    /// never allow a request or never limit them.
+    pub fn new(capacity: u32, refill_interval: Duration) -> Result<Self> {
+        anyhow::ensure!(capacity > 0, "The token bucket capacity must be positive");
+        anyhow::ensure!(
+            !refill_interval.is_zero(),
+            "The token bucket refill interval must be positive"
+        );
+
+        Ok(Self {
+            capacity,
+            refill_interval,
+            state: Mutex::new((f64::from(capacity), Instant::now())),
+        })
+    }
+
+    /// Takes a token if one is available, otherwise returns the time until the next token.
+    fn try_acquire(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (tokens, last_refill) = &mut *state;
+        let now = Instant::now();
+        let refilled =
+            now.duration_since(*last_refill).as_secs_f64() / self.refill_interval.as_secs_f64();
+        *tokens = (*tokens + refilled).min(f64::from(self.capacity));
+        *last_refill = now;
+
+        if *tokens >= 1.0 {
+            *tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(self.refill_interval.mul_f64(1.0 - *tokens))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimiter for TokenBucket {
+    async fn acquire(&self) {
+        while let Err(wait) = self.try_acquire() {
+            Delay::new(wait).await;
+        }
+    }
+}
+
+/// The `RateLimitedClient` acquires from a shared [`RateLimiter`] before each proof request of
+/// the wrapped client.
+pub struct RateLimitedClient<C, R: RateLimiter + ?Sized> {
+    client: C,
+    limiter: Arc<R>,
+}
+
+impl<C, R: RateLimiter + ?Sized> RateLimitedClient<C, R> {
+    /// Creates a new `RateLimitedClient` that limits the requests of `client` with `limiter`.
+    #[must_use]
+    pub const fn new(client: C, limiter: Arc<R>) -> Self {
+        Self { client, limiter }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: TendermintProofClient, R: RateLimiter + ?Sized> TendermintProofClient
+    for RateLimitedClient<C, R>
+{
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)> {
+        self.limiter.acquire().await;
+        self.client.prove_path(path, height, request_id).await
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: EthProofClient, R: RateLimiter + ?Sized> EthProofClient for RateLimitedClient<C, R> {
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        self.limiter.acquire().await;
+        self.client
+            .get_proof(address, storage_keys, block_id, request_id)
+            .await
+    }
+
+    async fn block_header(&self, block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        self.limiter.acquire().await;
+        self.client.block_header(block_id).await
+    }
+
+    async fn get_proof_at_block(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: ProofBlockId,
+        request_id: Option<&str>,
+    ) -> Result<(EIP1186AccountProofResponse, Option<ExecutionBlockHeader>)> {
+        // The proofs and the header are two requests.
+        self.limiter.acquire().await;
+        self.limiter.acquire().await;
+        self.client
+            .get_proof_at_block(address, storage_keys, block_id, request_id)
+            .await
+    }
+
+    async fn storage_at(&self, address: &str, key: U256, block_id: String) -> Result<U256> {
+        self.limiter.acquire().await;
+        self.client.storage_at(address, key, block_id).await
+    }
+}
+
+/// The `RetryingClient` retries the failed proof requests of the wrapped client with
+/// [`retry_with_backoff`], so that an intermittent failure of a public RPC endpoint does not fail
+/// a whole relay cycle.
+pub struct RetryingClient<C> {
+    client: C,
+    policy: RetryPolicy,
+    max_attempts: u32,
+}
+
+impl<C> RetryingClient<C> {
+    /// Creates a new `RetryingClient` that attempts each request of `client` up to
+    /// `max_attempts` times, spaced out by `policy`.
+    #[must_use]
+    pub const fn new(client: C, policy: RetryPolicy, max_attempts: u32) -> Self {
+        Self {
+            client,
+            policy,
+            max_attempts,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: TendermintProofClient> TendermintProofClient for RetryingClient<C> {
+    async fn prove_path(
+        &self,
+        path: &[Vec<u8>],
+        height: u64,
+        request_id: Option<&str>,
+    ) -> Result<(Vec<u8>, MerkleProof)> {
+        retry_with_backoff(&self.policy, self.max_attempts, || {
+            self.client.prove_path(path, height, request_id)
+        })
+        .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: EthProofClient> EthProofClient for RetryingClient<C> {
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        retry_with_backoff(&self.policy, self.max_attempts, || {
+            self.client
+                .get_proof(address, storage_keys.clone(), block_id.clone(), request_id)
+        })
+        .await
+    }
+
+    async fn block_header(&self, block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        retry_with_backoff(&self.policy, self.max_attempts, || {
+            self.client.block_header(block_id)
+        })
+        .await
+    }
+
+    async fn get_proof_at_block(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: ProofBlockId,
+        request_id: Option<&str>,
+    ) -> Result<(EIP1186AccountProofResponse, Option<ExecutionBlockHeader>)> {
+        retry_with_backoff(&self.policy, self.max_attempts, || {
+            self.client
+                .get_proof_at_block(address, storage_keys.clone(), block_id, request_id)
+        })
+        .await
+    }
+
+    async fn storage_at(&self, address: &str, key: U256, block_id: String) -> Result<U256> {
+        retry_with_backoff(&self.policy, self.max_attempts, || {
+            self.client.storage_at(address, key, block_id.clone())
+        })
+        .await
+    }
+}
+
+/// The configuration of a [`CircuitBreakerClient`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerConfig {
+    /// The number of consecutive failures after which the primary endpoint is unhealthy.
+    pub failure_threshold: u32,
+    /// The time during which the requests are routed to the backup endpoint once the primary
+    /// endpoint is unhealthy.
+    pub cooldown: Duration,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+}
+
+/// The `CircuitBreakerClient` routes the requests to a primary client, and to a backup client for
+/// a cooldown period once the primary client failed `failure_threshold` consecutive requests, so
+/// that an unhealthy RPC endpoint is not hammered by every relay cycle. After the cooldown, the
+/// primary client serves the requests again, and a single failure routes them back to the
+/// backup client.
+pub struct CircuitBreakerClient<C> {
+    primary: C,
+    backup: C,
+    config: CircuitBreakerConfig,
+    /// The consecutive failures of the primary client, and the time until which it is
+    /// unhealthy, if it is.
+    state: Mutex<(u32, Option<Instant>)>,
+}
+
+impl<C> CircuitBreakerClient<C> {
+    /// Creates a new `CircuitBreakerClient` that fails over from `primary` to `backup`.
+    #[must_use]
+    pub const fn new(primary: C, backup: C, config: CircuitBreakerConfig) -> Self {
+        Self {
+            primary,
+            backup,
+            config,
+            state: Mutex::new((0, None)),
+        }
+    }
+
+    /// Returns whether the requests are routed to the primary client.
+    #[must_use]
+    pub fn is_primary_healthy(&self) -> bool {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state
+            .1
+            .is_none_or(|unhealthy_until| Instant::now() >= unhealthy_until)
+    }
+
+    /// Records the outcome of a request served by the primary client.
+    fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (failures, unhealthy_until) = &mut *state;
+        if success {
+            *failures = 0;
+            *unhealthy_until = None;
+            return;
+        }
+        *failures = failures.saturating_add(1);
+        if *failures >= self.config.failure_threshold {
+            tracing::warn!(
+                "Routing the requests to the backup client for {:?} after {failures} consecutive failures",
+                self.config.cooldown
+            );
+            *unhealthy_until = Some(Instant::now() + self.config.cooldown);
+        }
+    }
+
+    /// Sends `request` to the primary client if it is healthy, or to the backup client otherwise.
+    async fn call<'a, T, Fut>(&'a self, request: impl FnOnce(&'a C) -> Fut) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        if !self.is_primary_healthy() {
+            return request(&self.backup).await;
+        }
+        let result = request(&self.primary).await;
+        self.record(result.is_ok());
+        result
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: EthProofClient> EthProofClient for CircuitBreakerClient<C> {
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        self.call(|client| client.get_proof(address, storage_keys, block_id, request_id))
+            .await
+    }
+
+    async fn block_header(&self, block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        self.call(|client| client.block_header(block_id)).await
+    }
+
+    async fn get_proof_at_block(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: ProofBlockId,
+        request_id: Option<&str>,
+    ) -> Result<(EIP1186AccountProofResponse, Option<ExecutionBlockHeader>)> {
+        self.call(|client| client.get_proof_at_block(address, storage_keys, block_id, request_id))
+            .await
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: BeaconBlockClient> BeaconBlockClient for CircuitBreakerClient<C> {
+    async fn beacon_block(&self, block_id: &str) -> Result<BeaconBlock> {
+        self.call(|client| client.beacon_block(block_id)).await
+    }
+
+    async fn beacon_block_json(&self, block_id: &str) -> Result<serde_json::Value> {
+        self.call(|client| client.beacon_block_json(block_id)).await
+    }
+
+    async fn beacon_block_ssz(&self, block_id: &str) -> Result<Vec<u8>> {
+        self.call(|client| client.beacon_block_ssz(block_id)).await
+    }
+
+    async fn finalized_slot(&self) -> Result<u64> {
+        self.call(BeaconBlockClient::finalized_slot).await
+    }
+}
+
+/// A proof backend whose health is checked by [`backend_health`].
+#[derive(Clone, Copy)]
+pub enum ProofBackend<'a> {
+    /// A Tendermint RPC or an execution client, whose latest height is looked up.
+    Chain(&'a dyn LatestHeightClient),
+    /// A beacon node, whose latest finalized slot is looked up.
+    Beacon(&'a dyn BeaconBlockClient),
+}
+
+/// The health of a proof backend, as reported by [`backend_health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackendHealth {
+    /// The name of the backend.
+    pub name: String,
+    /// Whether the backend answered.
+    pub reachable: bool,
+    /// The latest height of the chain of the backend, or its latest finalized slot for a beacon
+    /// node, if the backend answered.
+    pub latest_height: Option<u64>,
+    /// The error of the backend, if it did not answer.
+    pub error: Option<String>,
+}
+
+/// Pings every named backend concurrently and reports whether it is reachable along with its
+/// latest height, for the status endpoint of the relayer. The reports are in the order of
+/// `backends`.
+pub async fn backend_health(backends: &[(&str, ProofBackend<'_>)]) -> Vec<BackendHealth> {
+    future::join_all(backends.iter().map(|(name, backend)| async move {
+        let latest_height = match backend {
+            ProofBackend::Chain(client) => client.latest_height().await,
+            ProofBackend::Beacon(client) => client.finalized_slot().await,
+        };
+        if let Err(e) = &latest_height {
+            tracing::warn!("Proof backend {name} is unreachable: {e}");
+        }
+        BackendHealth {
+            name: (*name).to_string(),
+            reachable: latest_height.is_ok(),
+            error: latest_height.as_ref().err().map(ToString::to_string),
+            latest_height: latest_height.ok(),
+        }
+    }))
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    };
+
+    use alloy::{hex, primitives::keccak256, rpc::types::EIP1186AccountProofResponse};
+    use cosmos_sdk_proto::cosmos::base::tendermint::v1beta1::{
+        AbciQueryRequest, AbciQueryResponse, ProofOp, ProofOps,
+    };
+    use ethereum_types::consensus::beacon_block::BeaconBlock;
+    use futures::{executor::block_on, FutureExt};
+    use ibc_core_commitment_types::proto::ics23::{
+        commitment_proof, CommitmentProof, ExistenceProof,
+    };
+    use prost::Message;
+
+    use crate::utils::retry::RetryPolicy;
+
+    use super::{
+        backend_health, parse_account_proof, AbciQueryService, BackendHealth, BeaconBlockClient,
+        CircuitBreakerClient, CircuitBreakerConfig, EthProofClient, ExecutionBlockHeader,
+        GrpcProofClient, HeightParam, HeightParamClient, HttpEthProofClient, LatestHeightClient,
+        ProofBackend, ProofBlockId, RateLimitedClient, RequestSigner, RestProofClient, RestService,
+        RetryingClient, RoundRobinEthClient, TendermintProofClient, TokenBucket,
+        TwoLayerProofClient,
+    };
+
+    /// A mock gRPC service that records the queries and answers them at the queried height.
+    #[derive(Default)]
+    struct MockAbciQueryService {
+        requests: Mutex<Vec<AbciQueryRequest>>,
+    }
+
+    #[async_trait::async_trait]
+    impl AbciQueryService for MockAbciQueryService {
+        async fn abci_query(&self, request: AbciQueryRequest) -> anyhow::Result<AbciQueryResponse> {
+            self.requests.lock().unwrap().push(request.clone());
+            let proof = CommitmentProof {
+                proof: Some(commitment_proof::Proof::Exist(ExistenceProof {
+                    key: request.data.clone(),
+                    value: b"value".to_vec(),
+                    ..Default::default()
+                })),
+            };
+            Ok(AbciQueryResponse {
+                key: request.data,
+                value: b"value".to_vec(),
+                proof_ops: Some(ProofOps {
+                    ops: vec![ProofOp {
+                        r#type: "ics23:iavl".to_string(),
+                        key: vec![],
+                        data: proof.encode_to_vec(),
+                    }],
+                }),
+                height: request.height,
+                ..Default::default()
+            })
+        }
+    }
+
+    /// A mock REST API that records the queries and serves the proof of `value` at the queried
+    /// key and height.
+    struct MockRestService {
+        value: Vec<u8>,
+        requests: Mutex<Vec<(String, Vec<(String, String)>)>>,
+    }
+
+    #[async_trait::async_trait]
+    impl RestService for MockRestService {
+        async fn get(
+            &self,
+            endpoint: &str,
+            query: &[(&str, String)],
+        ) -> anyhow::Result<serde_json::Value> {
+            self.requests.lock().unwrap().push((
+                endpoint.to_string(),
+                query
+                    .iter()
+                    .map(|(name, value)| ((*name).to_string(), value.clone()))
+                    .collect(),
+            ));
+            let key = &query[1].1;
+            let proof = CommitmentProof {
+                proof: Some(commitment_proof::Proof::Exist(ExistenceProof {
+                    key: alloy::hex::decode(key)?,
+                    value: self.value.clone(),
+                    ..Default::default()
+                })),
+            };
+            Ok(serde_json::json!({
+                "height": query[2].1.parse::<u64>()?,
+                "key": format!("0x{key}"),
+                "value": alloy::hex::encode_prefixed(&self.value),
+                "proofs": [alloy::hex::encode_prefixed(proof.encode_to_vec())],
+            }))
+        }
+    }
+
+    /// A mock backend that answers with its latest height, or fails if it has none.
+    struct MockHealthClient(Option<u64>);
+
+    #[async_trait::async_trait]
+    impl LatestHeightClient for MockHealthClient {
+        async fn latest_height(&self) -> anyhow::Result<u64> {
+            self.0.ok_or_else(|| anyhow::anyhow!("connection refused"))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BeaconBlockClient for MockHealthClient {
+        async fn beacon_block(&self, _block_id: &str) -> anyhow::Result<BeaconBlock> {
+            anyhow::bail!("unimplemented")
+        }
+
+        async fn beacon_block_ssz(&self, _block_id: &str) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("unimplemented")
+        }
+
+        async fn finalized_slot(&self) -> anyhow::Result<u64> {
+            self.latest_height().await
+        }
+    }
+
+    /// A mock client that counts the proof and block header requests it serves.
+    #[derive(Default)]
+    struct CountingEthClient {
+        calls: AtomicUsize,
+        header_calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl EthProofClient for CountingEthClient {
+        async fn get_proof(
+            &self,
+            _address: &str,
+            _storage_keys: Vec<String>,
+            _block_id: String,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+            Ok(EIP1186AccountProofResponse::default())
+        }
+
+        async fn block_header(
+            &self,
+            _block_id: ProofBlockId,
+        ) -> anyhow::Result<Option<ExecutionBlockHeader>> {
+            self.header_calls.fetch_add(1, Ordering::Relaxed);
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_grpc_proof_client() {
+        let client = GrpcProofClient::new(MockAbciQueryService::default());
+
+        let (value, proof) =
+            block_on(client.prove_path(&[b"ibc".to_vec(), b"commitment".to_vec()], 10, None))
+                .unwrap();
+        assert_eq!(value, b"value".to_vec());
+        assert_eq!(proof.proofs.len(), 1);
+
+        let requests = client.service.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].path, "store/ibc/key");
+        assert_eq!(requests[0].data, b"commitment".to_vec());
+        assert_eq!(requests[0].height, 9);
+        assert!(requests[0].prove);
+        drop(requests);
+
+        // The key is the concatenation of the segments after the store key.
+        block_on(client.prove_path(
+            &[b"ibc".to_vec(), b"commit".to_vec(), b"ment".to_vec()],
+            10,
+            None,
+        ))
+        .unwrap();
+        assert_eq!(
+            client.service.requests.lock().unwrap()[1].data,
+            b"commitment".to_vec()
+        );
+        assert!(
+            block_on(client.prove_path(&[b"ibc".to_vec(), b"commitment".to_vec()], 0, None))
+                .is_err()
+        );
+        assert!(block_on(client.prove_path(&[b"ibc".to_vec()], 10, None)).is_err());
+        assert!(block_on(client.prove_path(&[], 10, None)).is_err());
+    }
+
+    #[test]
+    fn test_rest_proof_client() {
+        let client = RestProofClient::new(MockRestService {
+            value: b"value".to_vec(),
+            requests: Mutex::default(),
+        });
+
+        let (value, proof) =
+            block_on(client.prove_path(&[b"ibc".to_vec(), b"commitment".to_vec()], 10, None))
+                .unwrap();
+        assert_eq!(value, b"value".to_vec());
+        assert_eq!(proof.proofs.len(), 1);
+        assert!(matches!(
+            &proof.proofs[0].proof,
+            Some(commitment_proof::Proof::Exist(exist)) if exist.key == b"commitment"
+        ));
+
+        let requests = client.service.requests.lock().unwrap();
+        assert_eq!(
+            *requests,
+            vec![(
+                "proof".to_string(),
+                vec![
+                    ("store".to_string(), "ibc".to_string()),
+                    ("key".to_string(), alloy::hex::encode(b"commitment")),
+                    ("height".to_string(), "9".to_string()),
+                ]
+            )]
+        );
+        drop(requests);
+
+        assert!(
+            block_on(client.prove_path(&[b"ibc".to_vec(), b"commitment".to_vec()], 0, None))
+                .is_err()
+        );
+        assert!(block_on(client.prove_path(&[b"ibc".to_vec()], 10, None)).is_err());
+    }
+
+    #[test]
+    fn test_two_layer_proof_client() {
+        let client = TwoLayerProofClient::new(
+            GrpcProofClient::new(MockAbciQueryService::default()),
+            RestProofClient::new(MockRestService {
+                value: b"ibc-root".to_vec(),
+                requests: Mutex::default(),
+            }),
+            vec![b"app".to_vec(), b"ibc".to_vec()],
+        );
+
+        let (value, proof) =
+            block_on(client.prove_path(&[b"ibc".to_vec(), b"commitment".to_vec()], 10, None))
+                .unwrap();
+        assert_eq!(value, b"value".to_vec());
+        // The IBC-layer proof is followed by the application-layer proof.
+        let keys = proof
+            .proofs
+            .iter()
+            .map(|proof| match &proof.proof {
+                Some(commitment_proof::Proof::Exist(exist)) => exist.key.clone(),
+                _ => panic!("Expected an existence proof"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec![b"commitment".to_vec(), b"ibc".to_vec()]);
+        assert_eq!(
+            client.app_client.service.requests.lock().unwrap()[0].1[0],
+            ("store".to_string(), "app".to_string())
+        );
+    }
+
+    #[test]
+    fn test_height_param_client() {
+        let path = [b"ibc".to_vec(), b"commitment".to_vec()];
+        let queried_height = |height_param| {
+            let client = HeightParamClient::new(
+                GrpcProofClient::new(MockAbciQueryService::default()),
+                height_param,
+            );
+            block_on(client.prove_path(&path, 10, None)).unwrap();
+            let requests = client.client.service.requests.lock().unwrap();
+            requests[0].height
+        };
+
+        // The gRPC client queries the state of the block before the height it is passed.
+        assert_eq!(queried_height(HeightParam::RevisionHeight), 9);
+        assert_eq!(queried_height(HeightParam::PreviousHeight), 8);
+
+        assert_eq!(HeightParam::PreviousHeight.encode(1).unwrap(), 0);
+        assert!(HeightParam::PreviousHeight.encode(0).is_err());
+    }
+
+    #[test]
+    fn test_parse_account_proof() {
+        let canonical = serde_json::json!({
+            "address": "0x0000000000000000000000000000000000000001",
+            "balance": "0x0",
+            "codeHash": format!("0x{}", "00".repeat(32)),
+            "nonce": "0x1",
+            "storageHash": format!("0x{}", "11".repeat(32)),
+            "accountProof": ["0x01"],
+            "storageProof": [{
+                "key": format!("0x{}", "22".repeat(32)),
+                "value": "0x1",
+                "proof": ["0x02"],
+            }],
+        });
+        let aliased = serde_json::json!({
+            "address": "0x0000000000000000000000000000000000000001",
+            "balance": "0x0",
+            "codeHash": format!("0x{}", "00".repeat(32)),
+            "nonce": "0x1",
+            "storageRoot": format!("0x{}", "11".repeat(32)),
+            "accountProofs": ["0x01"],
+            "storageProofs": [{
+                "storageKey": format!("0x{}", "22".repeat(32)),
+                "value": "0x1",
+                "proofs": ["0x02"],
+            }],
+        });
+        let slot_aliased = serde_json::json!({
+            "address": "0x0000000000000000000000000000000000000001",
+            "balance": "0x0",
+            "codeHash": format!("0x{}", "00".repeat(32)),
+            "nonce": "0x1",
+            "storageHash": format!("0x{}", "11".repeat(32)),
+            "accountProof": ["0x01"],
+            "storageProof": [{
+                "slot": format!("0x{}", "22".repeat(32)),
+                "value": "0x1",
+                "proof": ["0x02"],
+            }],
+        });
+
+        let expected = parse_account_proof(canonical).unwrap();
+        assert_eq!(expected.storage_proof.len(), 1);
+        assert_eq!(expected.storage_proof[0].proof.len(), 1);
+        assert_eq!(parse_account_proof(aliased).unwrap(), expected);
+        assert_eq!(parse_account_proof(slot_aliased).unwrap(), expected);
+
+        assert!(parse_account_proof(serde_json::json!({ "storageProof": [] })).is_err());
+    }
+
+    #[test]
+    fn test_round_robin_eth_client() {
+        assert!(RoundRobinEthClient::<CountingEthClient>::new(vec![]).is_err());
+
+        let client = RoundRobinEthClient::new(vec![
+            CountingEthClient::default(),
+            CountingEthClient::default(),
+            CountingEthClient::default(),
+        ])
+        .unwrap();
+        for _ in 0..7 {
+            block_on(client.get_proof("0x0", vec![], "latest".to_string(), None)).unwrap();
+        }
+
+        let calls = client
+            .clients
+            .iter()
+            .map(|c| c.calls.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        assert_eq!(calls, vec![3, 2, 2]);
+
+        // The proofs and the header of a block are served by the same client.
+        let client = RoundRobinEthClient::new(vec![
+            CountingEthClient::default(),
+            CountingEthClient::default(),
+        ])
+        .unwrap();
+        block_on(client.get_proof_at_block("0x0", vec![], ProofBlockId::Number(1), None)).unwrap();
+        let calls = client
+            .clients
+            .iter()
+            .map(|c| {
+                (
+                    c.calls.load(Ordering::Relaxed),
+                    c.header_calls.load(Ordering::Relaxed),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(calls, vec![(1, 1), (0, 0)]);
+    }
+
+    /// A mock ethereum client whose first `failures` requests fail with a transport error.
+    #[derive(Default)]
+    struct FlakyEthClient {
+        failures: usize,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl EthProofClient for FlakyEthClient {
+        async fn get_proof(
+            &self,
+            _address: &str,
+            _storage_keys: Vec<String>,
+            _block_id: String,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+            anyhow::ensure!(calls > self.failures, "502 Bad Gateway");
+            Ok(EIP1186AccountProofResponse::default())
+        }
+    }
+
+    #[test]
+    fn test_retrying_client() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: 0.0,
+        };
+        let client = RetryingClient::new(
+            FlakyEthClient {
+                failures: 2,
+                ..Default::default()
+            },
+            policy,
+            3,
+        );
+        block_on(client.get_proof("0x0", vec![], "latest".to_string(), None)).unwrap();
+        assert_eq!(client.client.calls.load(Ordering::Relaxed), 3);
+
+        // A single attempt is not retried.
+        let client = RetryingClient::new(
+            FlakyEthClient {
+                failures: 1,
+                ..Default::default()
+            },
+            policy,
+            1,
+        );
+        assert!(block_on(client.get_proof("0x0", vec![], "latest".to_string(), None)).is_err());
+        assert_eq!(client.client.calls.load(Ordering::Relaxed), 1);
+
+        // The proofs and the header of a block are still served by the same wrapped client.
+        let client = RetryingClient::new(
+            RoundRobinEthClient::new(vec![
+                CountingEthClient::default(),
+                CountingEthClient::default(),
+            ])
+            .unwrap(),
+            policy,
+            3,
+        );
+        block_on(client.get_proof_at_block("0x0", vec![], ProofBlockId::Number(1), None)).unwrap();
+        let calls = client
+            .client
+            .clients
+            .iter()
+            .map(|c| {
+                (
+                    c.calls.load(Ordering::Relaxed),
+                    c.header_calls.load(Ordering::Relaxed),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(calls, vec![(1, 1), (0, 0)]);
+    }
+
+    #[test]
+    fn test_rate_limited_clients_share_budget() {
+        let bucket = Arc::new(TokenBucket::new(2, Duration::from_secs(3600)).unwrap());
+        let eth_client = RateLimitedClient::new(CountingEthClient::default(), bucket.clone());
+        let tm_client = RateLimitedClient::new(
+            GrpcProofClient::new(MockAbciQueryService::default()),
+            bucket,
+        );
+
+        block_on(eth_client.get_proof("0x0", vec![], "latest".to_string(), None)).unwrap();
+        block_on(tm_client.prove_path(&[b"ibc".to_vec(), b"commitment".to_vec()], 10, None))
+            .unwrap();
+
+        // The bucket is empty, so both clients block.
+        assert!(eth_client
+            .get_proof("0x0", vec![], "latest".to_string(), None)
+            .now_or_never()
+            .is_none());
+        assert!(tm_client
+            .prove_path(&[b"ibc".to_vec(), b"commitment".to_vec()], 10, None)
+            .now_or_never()
+            .is_none());
+        assert_eq!(eth_client.client.calls.load(Ordering::Relaxed), 1);
+        assert_eq!(tm_client.client.service.requests.lock().unwrap().len(), 1);
+
+        // The calls resume once the bucket is refilled.
+        let refilling_client = RateLimitedClient::new(
+            CountingEthClient::default(),
+            Arc::new(TokenBucket::new(1, Duration::from_millis(10)).unwrap()),
+        );
+        for _ in 0..3 {
+            block_on(refilling_client.get_proof("0x0", vec![], "latest".to_string(), None))
+                .unwrap();
+        }
+        assert_eq!(refilling_client.client.calls.load(Ordering::Relaxed), 3);
+
+        assert!(TokenBucket::new(0, Duration::from_millis(10)).is_err());
+        assert!(TokenBucket::new(1, Duration::ZERO).is_err());
+    }
+
+    /// Serves `requests` JSON-RPC requests on `listener`, one per connection, with an empty account
+    /// proof, and returns the raw requests.
+    fn serve_account_proofs(
+        listener: std::net::TcpListener,
+        requests: usize,
+    ) -> std::thread::JoinHandle<Vec<String>> {
+        std::thread::spawn(move || {
+            (0..requests)
+                .map(|_| {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut request = Vec::new();
+                    let mut buf = [0; 4096];
+                    // Read the headers and the body announced by the content length.
+                    loop {
+                        let n = std::io::Read::read(&mut stream, &mut buf).unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request).to_lowercase();
+                        if let Some(end) = text.find("\r\n\r\n") {
+                            let content_length = text
+                                .lines()
+                                .find_map(|line| line.strip_prefix("content-length:"))
+                                .map_or(0, |len| len.trim().parse::<usize>().unwrap());
+                            if request.len() >= end + 4 + content_length {
+                                break;
+                            }
+                        }
+                    }
+                    let body = serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": 1,
+                        "result": {
+                            "address": "0x0000000000000000000000000000000000000001",
+                            "balance": "0x0",
+                            "codeHash": format!("0x{}", "00".repeat(32)),
+                            "nonce": "0x1",
+                            "storageHash": format!("0x{}", "11".repeat(32)),
+                            "accountProof": ["0x01"],
+                            "storageProof": [],
+                        },
+                    })
+                    .to_string();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len()
+                    );
+                    std::io::Write::write_all(&mut stream, response.as_bytes()).unwrap();
+                    String::from_utf8(request).unwrap()
+                })
+                .collect()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_http_eth_proof_client_uses_injected_client() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = serve_account_proofs(listener, 1);
+
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::AUTHORIZATION,
+            reqwest::header::HeaderValue::from_static("Bearer secret"),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .unwrap();
+        let eth_client = HttpEthProofClient::new(client, url);
+
+        let proof = eth_client
+            .get_proof(
+                "0x0000000000000000000000000000000000000001",
+                vec![],
+                "latest".to_string(),
+                Some("req-1"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(proof.account_proof.len(), 1);
+
+        let request = server.join().unwrap().remove(0).to_lowercase();
+        assert!(request.starts_with("post / "));
+        assert!(request.contains("authorization: bearer secret"));
+        assert!(request.contains("x-request-id: req-1"));
+        assert!(request.contains("\"method\":\"eth_getproof\""));
+    }
+
+    #[tokio::test]
+    async fn test_http_eth_proof_client_signs_requests() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = serve_account_proofs(listener, 2);
+
+        let signed = Arc::new(AtomicUsize::new(0));
+        let signer_calls = Arc::clone(&signed);
+        let request_signer: RequestSigner = Box::new(move |request| {
+            let call = signer_calls.fetch_add(1, Ordering::Relaxed);
+            let body = request.body().and_then(reqwest::Body::as_bytes).unwrap();
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(
+                "x-signature",
+                format!("{call}-{}", hex::encode(keccak256(body)))
+                    .parse()
+                    .unwrap(),
+            );
+            headers
+        });
+        let eth_client = HttpEthProofClient::new(reqwest::Client::new(), url)
+            .with_request_signer(request_signer);
+
+        for _ in 0..2 {
+            eth_client
+                .get_proof(
+                    "0x0000000000000000000000000000000000000001",
+                    vec![],
+                    "latest".to_string(),
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+        assert_eq!(signed.load(Ordering::Relaxed), 2);
+
+        let requests = server.join().unwrap();
+        for (call, request) in requests.iter().enumerate() {
+            let (_, body) = request.split_once("\r\n\r\n").unwrap();
+            let signature = format!("x-signature: {call}-{}", hex::encode(keccak256(body)));
+            assert!(request.to_lowercase().contains(&signature));
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker_client() {
+        let config = CircuitBreakerConfig {
+            failure_threshold: 2,
+            cooldown: Duration::from_millis(50),
+        };
+        let client = CircuitBreakerClient::new(
+            FlakyEthClient {
+                failures: 3,
+                ..Default::default()
+            },
+            FlakyEthClient::default(),
+            config,
+        );
+        let get_proof = || block_on(client.get_proof("0x0", vec![], "latest".to_string(), None));
+
+        // The breaker trips after two consecutive failures of the primary client.
+        assert!(get_proof().is_err());
+        assert!(client.is_primary_healthy());
+        assert!(get_proof().is_err());
+        assert!(!client.is_primary_healthy());
+
+        // The requests fail over to the backup client during the cooldown.
+        get_proof().unwrap();
+        get_proof().unwrap();
+        assert_eq!(client.primary.calls.load(Ordering::Relaxed), 2);
+        assert_eq!(client.backup.calls.load(Ordering::Relaxed), 2);
+
+        // After the cooldown, a single failure of the primary client trips the breaker again.
+        std::thread::sleep(config.cooldown);
+        assert!(client.is_primary_healthy());
+        assert!(get_proof().is_err());
+        assert!(!client.is_primary_healthy());
+        get_proof().unwrap();
+        assert_eq!(client.backup.calls.load(Ordering::Relaxed), 3);
+
+        // Once the primary client recovers, it serves the requests again.
+        std::thread::sleep(config.cooldown);
+        get_proof().unwrap();
+        get_proof().unwrap();
+        assert!(client.is_primary_healthy());
+        assert_eq!(client.primary.calls.load(Ordering::Relaxed), 5);
+    }
+
+    #[test]
+    fn test_backend_health() {
+        let tendermint = MockHealthClient(Some(100));
+        let eth = MockHealthClient(None);
+        let beacon = MockHealthClient(Some(64));
+
+        let health = block_on(backend_health(&[
+            ("tendermint", ProofBackend::Chain(&tendermint)),
+            ("eth", ProofBackend::Chain(&eth)),
+            ("beacon", ProofBackend::Beacon(&beacon)),
+        ]));
+        assert_eq!(
+            health,
+            vec![
+                BackendHealth {
+                    name: "tendermint".to_string(),
+                    reachable: true,
+                    latest_height: Some(100),
+                    error: None,
+                },
+                BackendHealth {
+                    name: "eth".to_string(),
+                    reachable: false,
+                    latest_height: None,
+                    error: Some("connection refused".to_string()),
+                },
+                BackendHealth {
+                    name: "beacon".to_string(),
+                    reachable: true,
+                    latest_height: Some(64),
+                    error: None,
+                },
+            ]
+        );
+    }
+}
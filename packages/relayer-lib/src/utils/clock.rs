@@ -0,0 +1,48 @@
+//! Relayer utilities for reading the current time, so that it can be injected in tests.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The `Clock` trait defines the interface for a source of the current time.
+pub trait Clock {
+    /// Returns the current unix timestamp in seconds.
+    fn now_unix_secs(&self) -> u64;
+}
+
+/// The `SystemClock` reads the current time from the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_unix_secs(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// The `MockClock` returns a time that is set manually, for testing purposes.
+#[derive(Debug, Default)]
+pub struct MockClock(AtomicU64);
+
+impl MockClock {
+    /// Creates a new `MockClock` at the unix timestamp `now`, in seconds.
+    #[must_use]
+    pub const fn new(now: u64) -> Self {
+        Self(AtomicU64::new(now))
+    }
+
+    /// Sets the time of the clock to the unix timestamp `now`, in seconds.
+    pub fn set(&self, now: u64) {
+        self.0.store(now, Ordering::SeqCst);
+    }
+}
+
+impl Clock for MockClock {
+    fn now_unix_secs(&self) -> u64 {
+        self.0.load(Ordering::SeqCst)
+    }
+}
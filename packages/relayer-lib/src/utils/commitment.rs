@@ -0,0 +1,87 @@
+//! Relayer utilities for the schemes used by the IBC contract to commit packets.
+
+use alloy::{primitives::keccak256, sol_types::SolValue};
+use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
+
+/// The scheme used by the IBC contract to hash the packet commitments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitmentScheme {
+    /// The commitment defined by the IBC v2 specification, see [`Packet::commitment`].
+    Sha256,
+    /// The keccak256 hash of the ABI encoded packet.
+    Keccak256AbiEncoded,
+}
+
+impl CommitmentScheme {
+    /// Returns the commitment of the packet under this scheme.
+    #[must_use]
+    pub fn packet_commitment(self, packet: &Packet) -> Vec<u8> {
+        match self {
+            Self::Sha256 => packet.commitment(),
+            Self::Keccak256AbiEncoded => keccak256(packet.abi_encode()).to_vec(),
+        }
+    }
+}
+
+/// A change of the commitment scheme of the IBC contract at a hardfork, so that the packets
+/// committed on both sides of the fork can be proven.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommitmentSchemeFork {
+    /// The scheme of the packets committed before the fork block.
+    pub pre_fork: CommitmentScheme,
+    /// The scheme of the packets committed at or after the fork block.
+    pub post_fork: CommitmentScheme,
+    /// The first block of the fork.
+    pub fork_block: u64,
+}
+
+impl CommitmentSchemeFork {
+    /// Returns the scheme of the packets committed at `block_number`.
+    #[must_use]
+    pub const fn scheme_at(&self, block_number: u64) -> CommitmentScheme {
+        if block_number < self.fork_block {
+            self.pre_fork
+        } else {
+            self.post_fork
+        }
+    }
+
+    /// Returns the commitment of a packet committed at `block_number`.
+    #[must_use]
+    pub fn packet_commitment(&self, packet: &Packet, block_number: u64) -> Vec<u8> {
+        self.scheme_at(block_number).packet_commitment(packet)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloy::{primitives::keccak256, sol_types::SolValue};
+    use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
+
+    use super::{CommitmentScheme, CommitmentSchemeFork};
+
+    #[test]
+    fn test_commitment_scheme_fork() {
+        let packet = Packet {
+            sequence: 1,
+            sourceClient: "client-0".to_string(),
+            destClient: "client-1".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![],
+        };
+        let fork = CommitmentSchemeFork {
+            pre_fork: CommitmentScheme::Keccak256AbiEncoded,
+            post_fork: CommitmentScheme::Sha256,
+            fork_block: 100,
+        };
+
+        assert_eq!(fork.scheme_at(99), CommitmentScheme::Keccak256AbiEncoded);
+        assert_eq!(
+            fork.packet_commitment(&packet, 99),
+            keccak256(packet.abi_encode()).to_vec()
+        );
+        assert_eq!(fork.scheme_at(100), CommitmentScheme::Sha256);
+        assert_eq!(fork.packet_commitment(&packet, 100), packet.commitment());
+        assert_eq!(fork.packet_commitment(&packet, 101), packet.commitment());
+    }
+}
@@ -1,13 +1,32 @@
 //! Relayer utilities for `CosmosSDK` chains.
+//!
+//! The proofs are requested concurrently within the task that injects them, without spawning, and
+//! the timers come from `futures-timer`, so the injection runs on any executor, not only on tokio.
 
-use alloy::{hex, primitives::U256, providers::Provider};
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    ops::RangeInclusive,
+    pin::pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    hex,
+    primitives::{Bytes, Keccak256},
+};
 use anyhow::Result;
-use ethereum_apis::{beacon_api::client::BeaconApiClient, eth_api::client::EthApiClient};
-use ethereum_light_client::membership::evm_ics26_commitment_path;
-use ethereum_types::execution::storage_proof::StorageProof;
-use futures::future;
-use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
-use ibc_eureka_utils::rpc::TendermintRpcExt;
+use futures::{
+    future::{self, Either},
+    stream, StreamExt,
+};
+use futures_timer::Delay;
+use ibc_core_commitment_types::{
+    merkle::MerkleProof,
+    proto::ics23::{batch_entry, commitment_proof, BatchEntry, BatchProof, CommitmentProof},
+};
+use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::{Packet, Payload};
 use ibc_proto_eureka::{
     ibc::core::{
         channel::v2::{Acknowledgement, MsgAcknowledgement, MsgRecvPacket, MsgTimeout},
@@ -15,9 +34,32 @@ use ibc_proto_eureka::{
     },
     Protobuf,
 };
-use tendermint_rpc::HttpClient;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+
+use crate::events::{EurekaEvent, EurekaEventWithHeight, EventSummary};
+
+use super::{
+    client::{LatestHeightClient, TendermintProofClient},
+    clock::Clock,
+    error::ProofError,
+    metrics::ProofMetrics,
+    packet::{ensure_nonzero_sequence, is_packet_data_valid, packet_paths, PacketFilter},
+    proof_spec::LocalVerification,
+};
 
-use crate::events::{EurekaEvent, EurekaEventWithHeight};
+/// The options of [`target_events_to_timeout_msgs`], which filter the packets to time out. The
+/// default options keep all the packets.
+#[derive(Clone, Default)]
+pub struct TimeoutFilterOptions<'a> {
+    /// An optional filter of the packets to time out. If `None`, no filtering.
+    pub packet_filter: Option<&'a PacketFilter>,
+    /// An optional range of the sequences of the packets to time out, e.g. to replay a single
+    /// stuck packet. If `None`, no filtering.
+    pub sequence_range: Option<RangeInclusive<u64>>,
+}
 
 /// Converts a list of [`EurekaEvent`]s to a list of [`MsgTimeout`]s.
 ///
@@ -28,7 +70,16 @@ use crate::events::{EurekaEvent, EurekaEventWithHeight};
 /// - `dst_packet_seqs` - The list of dest packet sequences to filter. If empty, no filtering.
 /// - `target_height` - The target height.
 /// - `signer_address` - The signer address.
-/// - `now` - The current time.
+/// - `signer_for` - An optional selection of the signer of each packet, e.g. to spread the
+/// messages across several funded accounts. If `None`, every message is signed by
+/// `signer_address`.
+/// - `clock` - The clock giving the current time. A packet is timed out once the current time is
+/// at or past its `timeoutTimestamp`, i.e. exactly when it can no longer be received, see
+/// [`src_events_to_recv_and_ack_msgs`].
+/// - `options` - The filtering of the packets to time out, see [`TimeoutFilterOptions`].
+/// # Errors
+/// Returns an error if a packet to time out has the sequence 0.
+#[allow(clippy::too_many_arguments)]
 pub fn target_events_to_timeout_msgs(
     target_events: Vec<EurekaEventWithHeight>,
     src_client_id: &str,
@@ -36,26 +87,210 @@ pub fn target_events_to_timeout_msgs(
     dst_packet_seqs: &[u64],
     target_height: &Height,
     signer_address: &str,
-    now: u64,
-) -> Vec<MsgTimeout> {
+    mut signer_for: Option<&mut dyn FnMut(&Packet) -> String>,
+    clock: &dyn Clock,
+    options: &TimeoutFilterOptions,
+) -> Result<Vec<MsgTimeout>> {
+    let TimeoutFilterOptions {
+        packet_filter,
+        sequence_range,
+    } = options.clone();
+    let now = clock.now_unix_secs();
+    let mut signer_of = |packet: &Packet| {
+        signer_for.as_mut().map_or_else(
+            || signer_address.to_string(),
+            |signer_for| signer_for(packet),
+        )
+    };
     target_events
         .into_iter()
         .filter_map(|e| match e.event {
             EurekaEvent::SendPacket(packet) => (now >= packet.timeoutTimestamp
                 && packet.sourceClient == dst_client_id
                 && packet.destClient == src_client_id
-                && (dst_packet_seqs.is_empty() || dst_packet_seqs.contains(&packet.sequence)))
-            .then_some(MsgTimeout {
-                packet: Some(packet.into()),
-                proof_height: Some(*target_height),
-                proof_unreceived: vec![],
-                signer: signer_address.to_string(),
+                && (dst_packet_seqs.is_empty() || dst_packet_seqs.contains(&packet.sequence))
+                && sequence_range
+                    .as_ref()
+                    .is_none_or(|range| range.contains(&packet.sequence))
+                && packet_filter.is_none_or(|filter| filter.allows(&packet)))
+            .then(|| {
+                ensure_nonzero_sequence(&packet)?;
+                let signer = signer_of(&packet);
+                anyhow::Ok(MsgTimeout {
+                    packet: Some(packet.into()),
+                    proof_height: Some(*target_height),
+                    proof_unreceived: vec![],
+                    signer,
+                })
             }),
             EurekaEvent::WriteAcknowledgement(..) => None,
         })
         .collect()
 }
 
+/// The data of a sent packet persisted by relayers that do not store the full send events.
+/// The source client is not stored, as it is shared by all the packets of a relay path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketData {
+    /// The sequence of the packet.
+    pub sequence: u64,
+    /// The client of the packet on the destination chain.
+    pub dest_client: String,
+    /// The timeout timestamp of the packet, in unix seconds.
+    pub timeout_timestamp: u64,
+    /// The payloads of the packet.
+    pub payloads: Vec<Payload>,
+}
+
+/// Builds the [`MsgTimeout`] of a packet from its persisted `data`, with an empty proof.
+///
+/// # Arguments
+/// - `data` - The persisted data of the packet.
+/// - `source_client` - The client of the packet on the source chain.
+/// - `target_height` - The target height.
+/// - `signer_address` - The signer address.
+#[must_use]
+pub fn timeout_from_packet_data(
+    data: &PacketData,
+    source_client: &str,
+    target_height: &Height,
+    signer_address: &str,
+) -> MsgTimeout {
+    let packet = Packet {
+        sequence: data.sequence,
+        sourceClient: source_client.to_string(),
+        destClient: data.dest_client.clone(),
+        timeoutTimestamp: data.timeout_timestamp,
+        payloads: data.payloads.clone(),
+    };
+    MsgTimeout {
+        packet: Some(packet.into()),
+        proof_height: Some(*target_height),
+        proof_unreceived: vec![],
+        signer: signer_address.to_string(),
+    }
+}
+
+/// Drops the timeouts of the packets that were received on the destination chain after the
+/// timeouts were built, given the sequences of the packet `receipts` fetched from it, as a
+/// timeout of a received packet reverts.
+#[allow(clippy::implicit_hasher)]
+pub fn cancel_received_timeouts(timeouts: &mut Vec<MsgTimeout>, receipts: &HashSet<u64>) {
+    timeouts.retain(|msg| {
+        let sequence = msg.packet.as_ref().map_or(0, |packet| packet.sequence);
+        let is_received = receipts.contains(&sequence);
+        if is_received {
+            tracing::debug!("Cancelling the timeout of packet {sequence}, which was received");
+        }
+        !is_received
+    });
+}
+
+/// Computes a fingerprint of the batch of messages, which is equal for identical batches, so that
+/// the relayer can detect and skip resubmitting a batch.
+#[must_use]
+pub fn batch_fingerprint(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+) -> [u8; 32] {
+    let mut hasher = Keccak256::new();
+    // The messages are length delimited so that distinct batches cannot have the same encoding.
+    for msg in recv_msgs {
+        hasher.update(msg.encode_length_delimited_to_vec());
+    }
+    hasher.update([0]);
+    for msg in ack_msgs {
+        hasher.update(msg.encode_length_delimited_to_vec());
+    }
+    hasher.update([0]);
+    for msg in timeout_msgs {
+        hasher.update(msg.encode_length_delimited_to_vec());
+    }
+    hasher.finalize().into()
+}
+
+/// Collapses the packet sequences of the timeout messages into ranges of contiguous sequences.
+/// The ranges are sorted and duplicate sequences are ignored.
+#[must_use]
+pub fn compress_timeout_sequences(timeout_msgs: &[MsgTimeout]) -> Vec<RangeInclusive<u64>> {
+    let mut sequences = timeout_msgs
+        .iter()
+        .filter_map(|msg| msg.packet.as_ref().map(|packet| packet.sequence))
+        .collect::<Vec<_>>();
+    sequences.sort_unstable();
+    sequences.dedup();
+
+    sequences.into_iter().fold(vec![], |mut ranges, sequence| {
+        match ranges.last_mut() {
+            Some(range) if *range.end() + 1 == sequence => *range = *range.start()..=sequence,
+            _ => ranges.push(sequence..=sequence),
+        }
+        ranges
+    })
+}
+
+/// Groups the timeout messages by the source client of their packets, i.e. by the client on the
+/// source chain that they are submitted to, so that the timeouts of each source can be submitted
+/// concurrently. The messages keep their order within a group.
+#[must_use]
+pub fn group_timeouts_by_source(timeout_msgs: Vec<MsgTimeout>) -> HashMap<String, Vec<MsgTimeout>> {
+    let mut groups = HashMap::<String, Vec<MsgTimeout>>::new();
+    for msg in timeout_msgs {
+        let source_client = msg
+            .packet
+            .as_ref()
+            .map(|packet| packet.source_client.clone())
+            .unwrap_or_default();
+        groups.entry(source_client).or_default().push(msg);
+    }
+    groups
+}
+
+/// Counts the packets sent in `events` whose sequence is not in `relayed`, per source client of
+/// the packets, for monitoring the backlog of the relayer. Duplicate events of the same packet are
+/// counted once, and the clients without a backlog are omitted.
+#[must_use]
+#[allow(clippy::implicit_hasher)]
+pub fn backlog_depth(
+    events: &[EurekaEventWithHeight],
+    relayed: &HashSet<u64>,
+) -> HashMap<String, usize> {
+    let pending = events
+        .iter()
+        .filter_map(|e| match &e.event {
+            EurekaEvent::SendPacket(packet) => Some(packet),
+            EurekaEvent::WriteAcknowledgement(..) => None,
+        })
+        .filter(|packet| !relayed.contains(&packet.sequence))
+        .map(|packet| (packet.sourceClient.as_str(), packet.sequence))
+        .collect::<HashSet<_>>();
+
+    let mut depths = HashMap::<String, usize>::new();
+    for (source_client, _) in pending {
+        *depths.entry(source_client.to_string()).or_default() += 1;
+    }
+    depths
+}
+
+/// The options of [`src_events_to_recv_and_ack_msgs`], which filter the packets to receive and
+/// acknowledge and split their messages. The default options keep all the packets.
+#[derive(Clone, Default)]
+pub struct MsgFilterOptions<'a> {
+    /// An optional validation of the payload data of the sent packets. Packets that fail the
+    /// validation are skipped.
+    pub validate_packet_data: Option<&'a dyn Fn(&[u8]) -> Result<()>>,
+    /// An optional filter of the packets to receive and acknowledge. If `None`, no filtering.
+    pub packet_filter: Option<&'a PacketFilter>,
+    /// An optional range of the sequences of the packets to receive and acknowledge, e.g. to
+    /// replay a single stuck packet. If `None`, no filtering.
+    pub sequence_range: Option<RangeInclusive<u64>>,
+    /// The maximum number of app acknowledgements in a single [`MsgAcknowledgement`]. The
+    /// acknowledgements of a packet with more are split across several messages of the same
+    /// packet, in order. If `None`, all of them are in a single message.
+    pub max_acks_per_msg: Option<usize>,
+}
+
 /// Converts a list of [`EurekaEvent`]s to a list of [`MsgRecvPacket`]s and
 /// [`MsgAcknowledgement`]s.
 ///
@@ -67,7 +302,24 @@ pub fn target_events_to_timeout_msgs(
 /// - `dst_packet_seqs` - The list of dest packet sequences to filter. If empty, no filtering.
 /// - `target_height` - The target height.
 /// - `signer_address` - The signer address.
-/// - `now` - The current time.
+/// - `signer_for` - An optional selection of the signer of each packet, see
+/// [`target_events_to_timeout_msgs`].
+/// - `clock` - The clock giving the current time. A packet is received only while the current
+/// time is strictly before its `timeoutTimestamp`, as the destination chain rejects it from then
+/// on and it must be timed out instead, see [`target_events_to_timeout_msgs`].
+/// - `options` - The filtering and the splitting of the messages, see [`MsgFilterOptions`].
+///
+/// Events of the same packet, e.g. fetched from overlapping block ranges, are deduplicated by
+/// the source client, the destination client and the sequence of the packet, keeping the first
+/// occurrence.
+///
+/// A packet whose client on the destination chain is `dst_client_id` but whose counterparty
+/// client is not `src_client_id` is skipped with a warning, as its messages would revert on chain,
+/// e.g. if a client id is misconfigured. The number of such packets is returned along with the
+/// messages, counting each packet once, and only if it is not filtered out otherwise.
+/// # Errors
+/// Returns an error if `max_acks_per_msg` is zero or if a packet to receive or acknowledge has
+/// the sequence 0.
 #[allow(clippy::too_many_arguments)]
 pub fn src_events_to_recv_and_ack_msgs(
     src_events: Vec<EurekaEventWithHeight>,
@@ -77,21 +329,66 @@ pub fn src_events_to_recv_and_ack_msgs(
     dst_packet_seqs: &[u64],
     target_height: &Height,
     signer_address: &str,
-    now: u64,
-) -> (Vec<MsgRecvPacket>, Vec<MsgAcknowledgement>) {
+    mut signer_for: Option<&mut dyn FnMut(&Packet) -> String>,
+    clock: &dyn Clock,
+    options: &MsgFilterOptions,
+) -> Result<(Vec<MsgRecvPacket>, Vec<MsgAcknowledgement>, usize)> {
+    let MsgFilterOptions {
+        validate_packet_data,
+        packet_filter,
+        sequence_range,
+        max_acks_per_msg,
+    } = options.clone();
+    anyhow::ensure!(
+        max_acks_per_msg != Some(0),
+        "The maximum number of acknowledgements per message must be positive"
+    );
+    let now = clock.now_unix_secs();
+    let packet_key = |packet: &Packet| {
+        (
+            packet.sourceClient.clone(),
+            packet.destClient.clone(),
+            packet.sequence,
+        )
+    };
+    let mut mismatched_packets = 0;
+    let mut has_counterparty = |packet: &Packet, counterparty_client_id: &str| {
+        if counterparty_client_id == src_client_id {
+            return true;
+        }
+        tracing::warn!(
+            "Skipping packet {} to client {dst_client_id}, whose counterparty client is {counterparty_client_id} instead of {src_client_id}",
+            packet.sequence
+        );
+        mismatched_packets += 1;
+        false
+    };
+    let (mut sent_packets, mut acked_packets) = (HashSet::new(), HashSet::new());
     let (src_send_events, src_ack_events): (Vec<_>, Vec<_>) = src_events
         .into_iter()
         .filter(|e| match &e.event {
             EurekaEvent::SendPacket(packet) => {
                 packet.timeoutTimestamp > now
-                    && packet.sourceClient == src_client_id
                     && packet.destClient == dst_client_id
+                    && sequence_range
+                        .as_ref()
+                        .is_none_or(|range| range.contains(&packet.sequence))
                     && (src_packet_seqs.is_empty() || src_packet_seqs.contains(&packet.sequence))
+                    && validate_packet_data
+                        .is_none_or(|validate| is_packet_data_valid(packet, validate))
+                    && packet_filter.is_none_or(|filter| filter.allows(packet))
+                    && sent_packets.insert(packet_key(packet))
+                    && has_counterparty(packet, &packet.sourceClient)
             }
             EurekaEvent::WriteAcknowledgement(packet, _) => {
                 packet.sourceClient == dst_client_id
-                    && packet.destClient == src_client_id
+                    && sequence_range
+                        .as_ref()
+                        .is_none_or(|range| range.contains(&packet.sequence))
                     && (dst_packet_seqs.is_empty() || dst_packet_seqs.contains(&packet.sequence))
+                    && packet_filter.is_none_or(|filter| filter.allows(packet))
+                    && acked_packets.insert(packet_key(packet))
+                    && has_counterparty(packet, &packet.destClient)
             }
         })
         .partition(|e| match e.event {
@@ -99,14 +396,29 @@ pub fn src_events_to_recv_and_ack_msgs(
             EurekaEvent::WriteAcknowledgement(..) => false,
         });
 
+    for e in src_send_events.iter().chain(&src_ack_events) {
+        tracing::debug!("Relaying {}", EventSummary(e));
+        match &e.event {
+            EurekaEvent::SendPacket(packet) | EurekaEvent::WriteAcknowledgement(packet, _) => {
+                ensure_nonzero_sequence(packet)?;
+            }
+        }
+    }
+
+    let mut signer_of = |packet: &Packet| {
+        signer_for.as_mut().map_or_else(
+            || signer_address.to_string(),
+            |signer_for| signer_for(packet),
+        )
+    };
     let recv_msgs = src_send_events
         .into_iter()
         .map(|e| match e.event {
             EurekaEvent::SendPacket(packet) => MsgRecvPacket {
+                signer: signer_of(&packet),
                 packet: Some(packet.into()),
                 proof_height: Some(*target_height),
                 proof_commitment: vec![],
-                signer: signer_address.to_string(),
             },
             EurekaEvent::WriteAcknowledgement(..) => unreachable!(),
         })
@@ -114,224 +426,4395 @@ pub fn src_events_to_recv_and_ack_msgs(
 
     let ack_msgs = src_ack_events
         .into_iter()
-        .map(|e| match e.event {
-            EurekaEvent::WriteAcknowledgement(packet, acks) => MsgAcknowledgement {
-                packet: Some(packet.into()),
-                acknowledgement: Some(Acknowledgement {
-                    app_acknowledgements: acks.into_iter().map(Into::into).collect(),
-                }),
-                proof_height: Some(*target_height),
-                proof_acked: vec![],
-                signer: signer_address.to_string(),
-            },
+        .flat_map(|e| match e.event {
+            EurekaEvent::WriteAcknowledgement(packet, acks) => {
+                let signer = signer_of(&packet);
+                ack_msgs_of(&packet, &acks, max_acks_per_msg, target_height, &signer)
+            }
             EurekaEvent::SendPacket(_) => unreachable!(),
         })
         .collect::<Vec<MsgAcknowledgement>>();
 
-    (recv_msgs, ack_msgs)
+    Ok((recv_msgs, ack_msgs, mismatched_packets))
 }
 
-/// Generates and injects tendermint proofs for rec, ack and timeout messages.
+/// Converts the [`EurekaEvent::WriteAcknowledgement`]s of the target chain to a list of
+/// [`MsgAcknowledgement`]s submitted back to the source chain, for bidirectional relaying. These
+/// are the acknowledgements of the packets received from the source chain, i.e. of the packets
+/// sent by `src_client_id` to `dst_client_id`. The proof of each message is injected later from
+/// the acknowledgement path of its packet on the target chain, as for the messages of
+/// [`src_events_to_recv_and_ack_msgs`].
+///
+/// # Arguments
+/// - `target_events` - The list of target events.
+/// - `src_client_id` - The source client ID.
+/// - `dst_client_id` - The destination client ID.
+/// - `src_packet_seqs` - The list of source packet sequences to filter. If empty, no filtering.
+/// - `target_height` - The height of the target chain at which the acknowledgements are proved.
+/// - `signer_address` - The signer address.
+/// - `packet_filter` - An optional filter of the packets to acknowledge. If `None`, no filtering.
+/// - `max_acks_per_msg` - The maximum number of app acknowledgements in a single
+/// [`MsgAcknowledgement`], see [`src_events_to_recv_and_ack_msgs`].
+///
+/// Events of the same packet are deduplicated, keeping the first occurrence.
+/// # Errors
+/// Returns an error if `max_acks_per_msg` is zero or if a packet to acknowledge has the
+/// sequence 0.
+#[allow(clippy::too_many_arguments)]
+pub fn target_events_to_ack_msgs(
+    target_events: Vec<EurekaEventWithHeight>,
+    src_client_id: &str,
+    dst_client_id: &str,
+    src_packet_seqs: &[u64],
+    target_height: &Height,
+    signer_address: &str,
+    packet_filter: Option<&PacketFilter>,
+    max_acks_per_msg: Option<usize>,
+) -> Result<Vec<MsgAcknowledgement>> {
+    anyhow::ensure!(
+        max_acks_per_msg != Some(0),
+        "The maximum number of acknowledgements per message must be positive"
+    );
+    let mut acked_packets = HashSet::new();
+    let mut ack_msgs = vec![];
+    for e in target_events {
+        let EurekaEvent::WriteAcknowledgement(packet, acks) = e.event else {
+            continue;
+        };
+        if packet.sourceClient != src_client_id
+            || packet.destClient != dst_client_id
+            || !(src_packet_seqs.is_empty() || src_packet_seqs.contains(&packet.sequence))
+            || !packet_filter.is_none_or(|filter| filter.allows(&packet))
+            || !acked_packets.insert(packet.sequence)
+        {
+            continue;
+        }
+        ensure_nonzero_sequence(&packet)?;
+        tracing::debug!(
+            "Relaying the acknowledgement of packet {} from client {dst_client_id} back to client {src_client_id}",
+            packet.sequence
+        );
+        ack_msgs.extend(ack_msgs_of(
+            &packet,
+            &acks,
+            max_acks_per_msg,
+            target_height,
+            signer_address,
+        ));
+    }
+    Ok(ack_msgs)
+}
+
+/// Builds the [`MsgAcknowledgement`]s of a packet with the app acknowledgements `acks`, split into
+/// messages of at most `max_acks_per_msg` acknowledgements each, with empty proofs.
+fn ack_msgs_of(
+    packet: &Packet,
+    acks: &[Bytes],
+    max_acks_per_msg: Option<usize>,
+    target_height: &Height,
+    signer: &str,
+) -> Vec<MsgAcknowledgement> {
+    let ack_chunks = if acks.is_empty() {
+        vec![&[][..]]
+    } else {
+        acks.chunks(max_acks_per_msg.unwrap_or(acks.len()))
+            .collect()
+    };
+    ack_chunks
+        .into_iter()
+        .map(|acks| MsgAcknowledgement {
+            packet: Some(packet.clone().into()),
+            acknowledgement: Some(Acknowledgement {
+                app_acknowledgements: acks.iter().cloned().map(Into::into).collect(),
+            }),
+            proof_height: Some(*target_height),
+            proof_acked: vec![],
+            signer: signer.to_string(),
+        })
+        .collect()
+}
+
+/// The parameters shared by the recv, ack and timeout messages built by [`build_relay_messages`],
+/// see [`src_events_to_recv_and_ack_msgs`] and [`target_events_to_timeout_msgs`] for each of them.
+#[derive(Clone)]
+pub struct RelayParams<'a> {
+    /// The source client ID.
+    pub src_client_id: &'a str,
+    /// The destination client ID.
+    pub dst_client_id: &'a str,
+    /// The source packet sequences to filter. If empty, no filtering.
+    pub src_packet_seqs: &'a [u64],
+    /// The dest packet sequences to filter. If empty, no filtering.
+    pub dst_packet_seqs: &'a [u64],
+    /// The target height of the proofs.
+    pub target_height: &'a Height,
+    /// The signer address.
+    pub signer_address: &'a str,
+    /// The clock giving the current time, which decides whether a packet is received or timed out.
+    pub clock: &'a dyn Clock,
+    /// An optional validation of the payload data of the sent packets.
+    pub validate_packet_data: Option<&'a dyn Fn(&[u8]) -> Result<()>>,
+    /// An optional filter of the packets to relay. If `None`, no filtering.
+    pub packet_filter: Option<&'a PacketFilter>,
+    /// An optional range of the sequences of the packets to relay. If `None`, no filtering.
+    pub sequence_range: Option<RangeInclusive<u64>>,
+    /// The maximum number of app acknowledgements in a single [`MsgAcknowledgement`].
+    pub max_acks_per_msg: Option<usize>,
+}
+
+/// All the messages to relay between two clients, built by [`build_relay_messages`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayMessages {
+    /// The messages receiving the packets sent on the source chain.
+    pub recv_msgs: Vec<MsgRecvPacket>,
+    /// The messages acknowledging the packets received on the source chain.
+    pub ack_msgs: Vec<MsgAcknowledgement>,
+    /// The messages timing out the packets sent on the target chain.
+    pub timeout_msgs: Vec<MsgTimeout>,
+    /// The number of packets skipped because of a mismatched counterparty client.
+    pub mismatched_packets: usize,
+}
+
+/// Builds all the messages to relay from `src_events` and `target_events` with the same `params`,
+/// so that the recv, ack and timeout messages agree on the target height, the signer and the
+/// current time. The timeout messages are deduplicated by packet, as the recv and ack messages.
 /// # Errors
-/// Returns an error a proof cannot be generated for any of the provided messages.
-pub async fn inject_tendermint_proofs(
+/// Returns an error if the recv, ack or timeout messages cannot be built.
+pub fn build_relay_messages(
+    src_events: Vec<EurekaEventWithHeight>,
+    target_events: Vec<EurekaEventWithHeight>,
+    params: &RelayParams,
+) -> Result<RelayMessages> {
+    let (recv_msgs, ack_msgs, mismatched_packets) = src_events_to_recv_and_ack_msgs(
+        src_events,
+        params.src_client_id,
+        params.dst_client_id,
+        params.src_packet_seqs,
+        params.dst_packet_seqs,
+        params.target_height,
+        params.signer_address,
+        None,
+        params.clock,
+        &MsgFilterOptions {
+            validate_packet_data: params.validate_packet_data,
+            packet_filter: params.packet_filter,
+            sequence_range: params.sequence_range.clone(),
+            max_acks_per_msg: params.max_acks_per_msg,
+        },
+    )?;
+    let mut timeout_msgs = target_events_to_timeout_msgs(
+        target_events,
+        params.src_client_id,
+        params.dst_client_id,
+        params.dst_packet_seqs,
+        params.target_height,
+        params.signer_address,
+        None,
+        params.clock,
+        &TimeoutFilterOptions {
+            packet_filter: params.packet_filter,
+            sequence_range: params.sequence_range.clone(),
+        },
+    )?;
+    let mut timed_out_packets = HashSet::new();
+    timeout_msgs.retain(|msg| {
+        msg.packet.as_ref().is_none_or(|packet| {
+            timed_out_packets.insert((
+                packet.source_client.clone(),
+                packet.destination_client.clone(),
+                packet.sequence,
+            ))
+        })
+    });
+
+    Ok(RelayMessages {
+        recv_msgs,
+        ack_msgs,
+        timeout_msgs,
+        mismatched_packets,
+    })
+}
+
+/// Sets the signer of each message to the account returned by `signer_for` for its packet, so
+/// that packets between different clients can be signed by different accounts.
+pub fn assign_signers(
     recv_msgs: &mut [MsgRecvPacket],
     ack_msgs: &mut [MsgAcknowledgement],
     timeout_msgs: &mut [MsgTimeout],
-    source_tm_client: &HttpClient,
-    target_height: &Height,
-) -> Result<()> {
-    future::try_join_all(recv_msgs.iter_mut().map(|msg| async {
-        let packet: Packet = msg.packet.clone().unwrap().into();
-        let commitment_path = packet.commitment_path();
-        let (value, proof) = source_tm_client
-            .prove_path(
-                &[b"ibc".to_vec(), commitment_path],
-                target_height.revision_height,
-            )
-            .await?;
-        if value.is_empty() {
-            anyhow::bail!("Membership value is empty")
-        }
+    signer_for: impl Fn(&Packet) -> String,
+) {
+    for msg in recv_msgs.iter_mut() {
+        msg.signer = signer_for(&Packet::from(msg.packet.clone().unwrap()));
+    }
+    for msg in ack_msgs.iter_mut() {
+        msg.signer = signer_for(&Packet::from(msg.packet.clone().unwrap()));
+    }
+    for msg in timeout_msgs.iter_mut() {
+        msg.signer = signer_for(&Packet::from(msg.packet.clone().unwrap()));
+    }
+}
 
-        msg.proof_commitment = proof.encode_vec();
-        msg.proof_height = Some(*target_height);
-        anyhow::Ok(())
-    }))
-    .await?;
+/// Returns the unique contract addresses that `address_for` resolves for the packets of the
+/// messages, so that the relayer can check that each contract exists before proving against it.
+#[must_use]
+pub fn referenced_addresses(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+    address_for: impl Fn(&Packet) -> String,
+) -> HashSet<String> {
+    recv_msgs
+        .iter()
+        .map(|msg| &msg.packet)
+        .chain(ack_msgs.iter().map(|msg| &msg.packet))
+        .chain(timeout_msgs.iter().map(|msg| &msg.packet))
+        .flatten()
+        .map(|packet| address_for(&Packet::from(packet.clone())))
+        .collect()
+}
 
-    future::try_join_all(ack_msgs.iter_mut().map(|msg| async {
-        let packet: Packet = msg.packet.clone().unwrap().into();
-        let ack_path = packet.ack_commitment_path();
-        let (value, proof) = source_tm_client
-            .prove_path(&[b"ibc".to_vec(), ack_path], target_height.revision_height)
-            .await?;
-        if value.is_empty() {
-            anyhow::bail!("Membership value is empty")
+/// The kind of the proof generated for a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofKind {
+    /// The membership proof of the packet commitment, used to receive the packet.
+    Commitment,
+    /// The membership proof of the acknowledgement commitment, used to acknowledge the packet.
+    Acknowledgement,
+    /// The non-membership proof of the packet receipt, used to time out the packet.
+    Receipt,
+}
+
+/// The ICS-24 commitment prefix of the `CosmosSDK` chains, which is the key of the IBC store.
+pub const DEFAULT_COMMITMENT_PREFIX: &[u8] = b"ibc";
+
+/// Returns the path components passed to [`TendermintProofClient::prove_path`] to generate the
+/// proof of `kind` for `packet`, so that operators can replicate the ABCI query by hand.
+/// The first component is the `commitment_prefix` of the chain, see
+/// [`DEFAULT_COMMITMENT_PREFIX`], and the second is the key in the IBC store.
+#[must_use]
+pub fn abci_query_path(packet: &Packet, kind: ProofKind, commitment_prefix: &[u8]) -> Vec<Vec<u8>> {
+    vec![
+        commitment_prefix.to_vec(),
+        packet_paths(packet).path(kind).to_vec(),
+    ]
+}
+
+/// Renders the key of the ABCI query that proves the path of `kind` for `packet`, as
+/// `<commitment_prefix>/<hex encoded key>`, e.g. `ibc/30372d...`, so that the queried keys can be
+/// logged for reproducible audits. The key is derived as in [`abci_query_path`].
+#[must_use]
+pub fn audit_query_key(packet: &Packet, kind: ProofKind, commitment_prefix: &[u8]) -> String {
+    let path = abci_query_path(packet, kind, commitment_prefix);
+    format!(
+        "{}/{}",
+        String::from_utf8_lossy(&path[0]),
+        hex::encode(&path[1])
+    )
+}
+
+/// The format of the tendermint proofs set on the messages, which depends on what the verifier of
+/// the light client on the target chain expects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TendermintProofFormat {
+    /// The protobuf encoded [`MerkleProof`].
+    #[default]
+    Proof,
+    /// The JSON encoded [`ValueAndProof`], for verifiers that need the proved value alongside the
+    /// proof.
+    ValueAndProof,
+}
+
+/// A proved value packaged with its protobuf encoded [`MerkleProof`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ValueAndProof {
+    /// The proved value, empty for a non-membership proof.
+    pub value: Bytes,
+    /// The protobuf encoded [`MerkleProof`].
+    pub proof: Bytes,
+}
+
+impl TendermintProofFormat {
+    /// Encodes the proof of `value` in this format.
+    /// # Errors
+    /// Returns an error if the proof cannot be serialized.
+    pub fn encode(self, value: &[u8], proof: &MerkleProof) -> Result<Vec<u8>> {
+        match self {
+            Self::Proof => Ok(proof.encode_vec()),
+            Self::ValueAndProof => Ok(serde_json::to_vec(&ValueAndProof {
+                value: value.to_vec().into(),
+                proof: proof.encode_vec().into(),
+            })?),
         }
+    }
+}
 
-        msg.proof_acked = proof.encode_vec();
-        msg.proof_height = Some(*target_height);
-        anyhow::Ok(())
-    }))
-    .await?;
+/// The default maximum number of proofs generated at the same time, see
+/// [`inject_tendermint_proofs`].
+pub const DEFAULT_MAX_PROOF_CONCURRENCY: usize = 32;
 
-    future::try_join_all(timeout_msgs.iter_mut().map(|msg| async {
-        let packet: Packet = msg.packet.clone().unwrap().into();
-        let receipt_path = packet.receipt_commitment_path();
-        let (value, proof) = source_tm_client
-            .prove_path(
-                &[b"ibc".to_vec(), receipt_path],
-                target_height.revision_height,
-            )
-            .await?;
+/// The options of [`inject_tendermint_proofs`]. The default options generate the proofs in the
+/// [`TendermintProofFormat::Proof`] format, without local verification, deadline or
+/// cancellation, and at most [`DEFAULT_MAX_PROOF_CONCURRENCY`] at the same time.
+#[derive(Debug, Clone, Copy)]
+pub struct TendermintProofOptions<'a> {
+    /// The parameters to verify every proof locally against the proof spec of the source chain's
+    /// IBC store before it is injected, if any.
+    pub local_verification: Option<LocalVerification<'a>>,
+    /// The format of the proofs set on the messages.
+    pub proof_format: TendermintProofFormat,
+    /// The deadline for generating the proofs of the batch, if any.
+    pub batch_deadline: Option<Instant>,
+    /// The token cancelling the proof generation of the batch, if any.
+    pub cancellation: Option<&'a CancellationToken>,
+    /// The maximum number of proofs requested at the same time.
+    pub max_concurrency: usize,
+    /// The metrics counting the generated and failed proofs, if any.
+    pub metrics: Option<&'a ProofMetrics>,
+    /// The request id attached to every proof request, if any.
+    pub request_id: Option<&'a str>,
+}
 
-        if !value.is_empty() {
-            anyhow::bail!("Non-Membership value is empty")
+impl Default for TendermintProofOptions<'_> {
+    fn default() -> Self {
+        Self {
+            local_verification: None,
+            proof_format: TendermintProofFormat::default(),
+            batch_deadline: None,
+            cancellation: None,
+            max_concurrency: DEFAULT_MAX_PROOF_CONCURRENCY,
+            metrics: None,
+            request_id: None,
         }
-        msg.proof_unreceived = proof.encode_vec();
-        msg.proof_height = Some(*target_height);
-        anyhow::Ok(())
-    }))
-    .await?;
+    }
+}
 
-    Ok(())
+/// The summary of an injection of proofs into recv, ack and timeout messages, so that the caller
+/// can pair the messages with a client update at the height of their proofs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProofInjectionReport {
+    /// The proof height set on all the messages.
+    pub used_height: Height,
+    /// The number of recv messages proved.
+    pub recv_count: usize,
+    /// The number of ack messages proved.
+    pub ack_count: usize,
+    /// The number of timeout messages proved.
+    pub timeout_count: usize,
 }
 
-#[allow(clippy::too_many_arguments)]
-pub async fn inject_ethereum_proofs<P: Provider + Clone>(
+impl ProofInjectionReport {
+    /// Creates the report of the proofs injected at `used_height` into the messages.
+    #[must_use]
+    pub const fn new(
+        used_height: Height,
+        recv_msgs: &[MsgRecvPacket],
+        ack_msgs: &[MsgAcknowledgement],
+        timeout_msgs: &[MsgTimeout],
+    ) -> Self {
+        Self {
+            used_height,
+            recv_count: recv_msgs.len(),
+            ack_count: ack_msgs.len(),
+            timeout_count: timeout_msgs.len(),
+        }
+    }
+
+    /// Returns the total number of messages proved.
+    #[must_use]
+    pub const fn msg_count(&self) -> usize {
+        self.recv_count + self.ack_count + self.timeout_count
+    }
+}
+
+/// Generates and injects tendermint proofs for rec, ack and timeout messages.
+///
+/// At most `max_concurrency` proofs of `options` are requested at the same time, so that a large
+/// backlog does not exhaust the connections or the rate limits of the RPC endpoint.
+/// If the batch deadline is reached before all the proofs are generated, the remaining proofs are
+/// abandoned and [`ProofError::DeadlineExceeded`] is returned. Likewise, the remaining proofs are
+/// abandoned as soon as the cancellation token is cancelled, and [`ProofError::Cancelled`] is
+/// returned. The request id, if any, is attached to every proof request to correlate it with the
+/// RPC logs.
+/// Every proof is traced in a span of its message, see [`proof_span`], and counted in the metrics
+/// if set.
+/// The proofs are generated under the `commitment_prefix` of the source chain, see
+/// [`TendermintProofOptions`] for the other options.
+/// # Errors
+/// Returns an error if `max_concurrency` is zero, or if a proof cannot be generated for any of
+/// the provided messages. A commitment that is empty or proved absent is reported as
+/// [`ProofError::MembershipValueEmpty`] or [`ProofError::UnexpectedNonMembership`].
+pub async fn inject_tendermint_proofs<C: TendermintProofClient>(
     recv_msgs: &mut [MsgRecvPacket],
     ack_msgs: &mut [MsgAcknowledgement],
     timeout_msgs: &mut [MsgTimeout],
-    eth_client: &EthApiClient<P>,
-    beacon_api_client: &BeaconApiClient,
-    ibc_contrct_address: &str,
-    ibc_contract_slot: U256,
-    proof_slot: u64,
-) -> Result<()> {
-    let current_beacon_block = beacon_api_client
-        .beacon_block(&format!("{proof_slot:?}"))
-        .await?;
+    source_tm_client: &C,
+    target_height: &Height,
+    commitment_prefix: &[u8],
+    options: &TendermintProofOptions<'_>,
+) -> Result<ProofInjectionReport, ProofError> {
+    let TendermintProofOptions {
+        local_verification,
+        proof_format,
+        batch_deadline,
+        cancellation,
+        max_concurrency,
+        metrics,
+        request_id,
+    } = *options;
+    ensure_max_concurrency(max_concurrency)?;
+    let prover = TendermintProver {
+        source_tm_client,
+        target_height,
+        commitment_prefix,
+        local_verification,
+        proof_format,
+        metrics,
+        request_id,
+    };
+    let (recv_count, ack_count) = (recv_msgs.len(), ack_msgs.len());
+    let mut completed = vec![];
+
+    try_join_proofs(
+        recv_msgs.iter_mut().map(|msg| prover.prove_recv(msg)),
+        0,
+        batch_deadline,
+        cancellation,
+        max_concurrency,
+        &mut completed,
+    )
+    .await?;
 
-    let proof_block_number = current_beacon_block
-        .message
-        .body
-        .execution_payload
-        .block_number;
+    try_join_proofs(
+        ack_msgs.iter_mut().map(|msg| prover.prove_ack(msg)),
+        recv_count,
+        batch_deadline,
+        cancellation,
+        max_concurrency,
+        &mut completed,
+    )
+    .await?;
+
+    try_join_proofs(
+        timeout_msgs.iter_mut().map(|msg| prover.prove_timeout(msg)),
+        recv_count + ack_count,
+        batch_deadline,
+        cancellation,
+        max_concurrency,
+        &mut completed,
+    )
+    .await?;
+
+    ensure_proofs_injected(recv_msgs, ack_msgs, timeout_msgs)?;
+    Ok(ProofInjectionReport::new(
+        *target_height,
+        recv_msgs,
+        ack_msgs,
+        timeout_msgs,
+    ))
+}
 
-    let proof_slot_height = Height {
-        revision_number: 0,
-        revision_height: proof_slot,
+/// Generates and injects tendermint proofs like [`inject_tendermint_proofs`], but does not abort
+/// the batch when a message cannot be proved. The other messages are proved, so that they can be
+/// submitted while the failed ones are logged or retried separately. The batch is still stopped
+/// by the deadline or the cancellation token of `options`, as for [`inject_tendermint_proofs`].
+///
+/// # Returns
+/// The failures, sorted by the index of the failed message in `recv_msgs`, followed by
+/// `ack_msgs` and `timeout_msgs`.
+/// # Errors
+/// Returns an error if `max_concurrency` is zero, or if the batch is stopped before all the
+/// proofs are generated.
+pub async fn inject_tendermint_proofs_partial<C: TendermintProofClient>(
+    recv_msgs: &mut [MsgRecvPacket],
+    ack_msgs: &mut [MsgAcknowledgement],
+    timeout_msgs: &mut [MsgTimeout],
+    source_tm_client: &C,
+    target_height: &Height,
+    commitment_prefix: &[u8],
+    options: &TendermintProofOptions<'_>,
+) -> Result<Vec<(usize, anyhow::Error)>> {
+    let TendermintProofOptions {
+        local_verification,
+        proof_format,
+        batch_deadline,
+        cancellation,
+        max_concurrency,
+        metrics,
+        request_id,
+    } = *options;
+    ensure_max_concurrency(max_concurrency)?;
+    let prover = TendermintProver {
+        source_tm_client,
+        target_height,
+        commitment_prefix,
+        local_verification,
+        proof_format,
+        metrics,
+        request_id,
     };
-    // recv messages
-    future::try_join_all(recv_msgs.iter_mut().map(|msg| async {
-        let packet: Packet = msg.packet.clone().unwrap().into();
-        let commitment_path = packet.commitment_path();
-        let storage_proof = get_commitment_proof(
-            eth_client,
-            ibc_contrct_address,
-            proof_block_number,
-            commitment_path,
-            ibc_contract_slot,
+    let (recv_count, ack_count) = (recv_msgs.len(), ack_msgs.len());
+    let mut completed = Vec::new();
+
+    let mut failures = collect_proof_failures(
+        recv_msgs.iter_mut().map(|msg| prover.prove_recv(msg)),
+        0,
+        batch_deadline,
+        cancellation,
+        max_concurrency,
+        &mut completed,
+    )
+    .await?;
+    failures.extend(
+        collect_proof_failures(
+            ack_msgs.iter_mut().map(|msg| prover.prove_ack(msg)),
+            recv_count,
+            batch_deadline,
+            cancellation,
+            max_concurrency,
+            &mut completed,
         )
-        .await?;
-        if storage_proof.value.is_zero() {
-            anyhow::bail!("Membership value is empty")
+        .await?,
+    );
+    failures.extend(
+        collect_proof_failures(
+            timeout_msgs.iter_mut().map(|msg| prover.prove_timeout(msg)),
+            recv_count + ack_count,
+            batch_deadline,
+            cancellation,
+            max_concurrency,
+            &mut completed,
+        )
+        .await?,
+    );
+    Ok(failures)
+}
+
+/// Generates the tendermint proofs of the messages of a batch.
+struct TendermintProver<'a, C> {
+    source_tm_client: &'a C,
+    target_height: &'a Height,
+    commitment_prefix: &'a [u8],
+    local_verification: Option<LocalVerification<'a>>,
+    proof_format: TendermintProofFormat,
+    metrics: Option<&'a ProofMetrics>,
+    request_id: Option<&'a str>,
+}
+
+impl<C: TendermintProofClient> TendermintProver<'_, C> {
+    /// Proves the path of `kind` for `packet`, verifying the proof locally if requested.
+    async fn prove(&self, packet: &Packet, kind: ProofKind) -> Result<(Vec<u8>, MerkleProof)> {
+        let path = abci_query_path(packet, kind, self.commitment_prefix);
+        let (value, proof) = self
+            .source_tm_client
+            .prove_path(&path, self.target_height.revision_height, self.request_id)
+            .await?;
+        if value.is_empty() && kind != ProofKind::Receipt {
+            let path = hex::encode(&path[1]);
+            let is_non_membership = matches!(
+                proof.proofs.first().and_then(|proof| proof.proof.as_ref()),
+                Some(commitment_proof::Proof::Nonexist(_))
+            );
+            return Err(if is_non_membership {
+                ProofError::UnexpectedNonMembership { path }
+            } else {
+                ProofError::MembershipValueEmpty { path }
+            }
+            .into());
         }
+        if let Some(verification) = self.local_verification {
+            verification.verify(&proof, &path, &value)?;
+        }
+        Ok((value, proof))
+    }
 
-        msg.proof_commitment = serde_json::to_vec(&storage_proof)?;
-        msg.proof_height = Some(proof_slot_height);
-        anyhow::Ok(())
-    }))
-    .await?;
+    async fn prove_recv(&self, msg: &mut MsgRecvPacket) -> Result<()> {
+        let packet: Packet = msg.packet.clone().unwrap().into();
+        let span = proof_span("recv", &packet, &packet_paths(&packet).commitment);
+        let prove = async {
+            let (value, proof) = self.prove(&packet, ProofKind::Commitment).await?;
+            if value != packet.commitment() {
+                anyhow::bail!("Membership value does not match the packet commitment")
+            }
+
+            msg.proof_commitment = self.proof_format.encode(&value, &proof)?;
+            msg.proof_height = Some(*self.target_height);
+            Ok(())
+        };
+        instrument_proof(prove, span, self.metrics).await
+    }
 
-    // ack messages
-    future::try_join_all(ack_msgs.iter_mut().map(|msg| async {
+    async fn prove_ack(&self, msg: &mut MsgAcknowledgement) -> Result<()> {
         let packet: Packet = msg.packet.clone().unwrap().into();
-        let ack_path = packet.ack_commitment_path();
-        let storage_proof = get_commitment_proof(
-            eth_client,
-            ibc_contrct_address,
-            proof_block_number,
-            ack_path,
-            ibc_contract_slot,
-        )
-        .await?;
-        if storage_proof.value.is_zero() {
-            anyhow::bail!("Membership value is empty")
-        }
+        let span = proof_span("ack", &packet, &packet_paths(&packet).ack);
+        let prove = async {
+            let (value, proof) = self.prove(&packet, ProofKind::Acknowledgement).await?;
 
-        msg.proof_acked = serde_json::to_vec(&storage_proof)?;
-        msg.proof_height = Some(proof_slot_height);
-        anyhow::Ok(())
-    }))
-    .await?;
+            msg.proof_acked = self.proof_format.encode(&value, &proof)?;
+            msg.proof_height = Some(*self.target_height);
+            Ok(())
+        };
+        instrument_proof(prove, span, self.metrics).await
+    }
 
-    // timeout messages
-    future::try_join_all(timeout_msgs.iter_mut().map(|msg| async {
+    async fn prove_timeout(&self, msg: &mut MsgTimeout) -> Result<()> {
         let packet: Packet = msg.packet.clone().unwrap().into();
-        let receipt_path = packet.receipt_commitment_path();
-        let storage_proof = get_commitment_proof(
-            eth_client,
-            ibc_contrct_address,
-            proof_block_number,
-            receipt_path,
-            ibc_contract_slot,
-        )
-        .await?;
-        if !storage_proof.value.is_zero() {
-            anyhow::bail!("Non-Membership value is empty")
-        }
-        msg.proof_unreceived = serde_json::to_vec(&storage_proof)?;
-        msg.proof_height = Some(proof_slot_height);
-        anyhow::Ok(())
-    }))
-    .await?;
+        let span = proof_span("timeout", &packet, &packet_paths(&packet).receipt);
+        let prove = async {
+            let (value, proof) = self.prove(&packet, ProofKind::Receipt).await?;
+            assert_timeout_non_membership(msg, &value, ReceiptScheme::Tendermint)?;
 
-    Ok(())
+            msg.proof_unreceived = self.proof_format.encode(&value, &proof)?;
+            msg.proof_height = Some(*self.target_height);
+            Ok(())
+        };
+        instrument_proof(prove, span, self.metrics).await
+    }
 }
 
-async fn get_commitment_proof<P: Provider + Clone>(
-    eth_client: &EthApiClient<P>,
-    ibc_contrct_address: &str,
-    block_number: u64,
-    path: Vec<u8>,
-    slot: U256,
-) -> Result<StorageProof> {
-    let storage_key = evm_ics26_commitment_path(&path, slot);
-    let storage_key_be_bytes = storage_key.to_be_bytes_vec();
-    let storage_key_hex = hex::encode(storage_key_be_bytes);
-    let block_hex = format!("0x{block_number:x}");
-
-    let proof = eth_client
-        .get_proof(ibc_contrct_address, vec![storage_key_hex], block_hex)
+/// Returns the span in which the proof of the `action` message of `packet` is generated, with the
+/// sequence, the source and destination clients of the packet, and the proved `path`.
+pub(crate) fn proof_span(action: &'static str, packet: &Packet, path: &[u8]) -> tracing::Span {
+    tracing::debug_span!(
+        "prove",
+        action,
+        sequence = packet.sequence,
+        source_client = %packet.sourceClient,
+        dest_client = %packet.destClient,
+        path = %hex::encode(path),
+    )
+}
+
+/// Runs `prove` in `span`, emitting an event in the span with the elapsed duration once the proof
+/// is generated, or with the error if it failed, e.g. on a failed membership or non-membership
+/// check. The outcome is also counted in `metrics`, if set.
+pub(crate) async fn instrument_proof<T>(
+    prove: impl Future<Output = Result<T>>,
+    span: tracing::Span,
+    metrics: Option<&ProofMetrics>,
+) -> Result<T> {
+    let start = Instant::now();
+    let result = prove.instrument(span.clone()).await;
+    let elapsed_ms = u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX);
+    span.in_scope(|| match &result {
+        Ok(_) => tracing::debug!(elapsed_ms, "Generated the proof"),
+        Err(e) => tracing::warn!(elapsed_ms, error = %e, "Failed to generate the proof"),
+    });
+    if let Some(metrics) = metrics {
+        if result.is_ok() {
+            metrics.add_generated(1);
+        } else {
+            metrics.add_failed(1);
+        }
+    }
+    result
+}
+
+/// Proves the membership of `path` at `start_height`, or at the latest committed height if the
+/// value is not yet committed at `start_height`, e.g. because the node served a stale height.
+/// The proof height never exceeds the latest committed height, so it can always be served.
+///
+/// # Returns
+/// The height of the proof along with the value and the proof.
+/// # Errors
+/// Returns an error if `start_height` is past the latest committed height, or if the value is
+/// empty at both heights.
+pub async fn prove_membership_advancing_height<C, L>(
+    source_tm_client: &C,
+    latest_height_client: &L,
+    path: &[Vec<u8>],
+    start_height: u64,
+    request_id: Option<&str>,
+) -> Result<(u64, Vec<u8>, MerkleProof)>
+where
+    C: TendermintProofClient,
+    L: LatestHeightClient,
+{
+    let latest_height = latest_height_client.latest_height().await?;
+    anyhow::ensure!(
+        start_height <= latest_height,
+        "Proof height {start_height} is past the latest committed height {latest_height}"
+    );
+
+    let (value, proof) = source_tm_client
+        .prove_path(path, start_height, request_id)
+        .await?;
+    if !value.is_empty() || start_height == latest_height {
+        anyhow::ensure!(!value.is_empty(), "Membership value is empty");
+        return Ok((start_height, value, proof));
+    }
+
+    tracing::debug!(
+        "Membership value is empty at height {start_height}, retrying at {latest_height}"
+    );
+    let (value, proof) = source_tm_client
+        .prove_path(path, latest_height, request_id)
         .await?;
-    let storage_proof = proof.storage_proof.first().unwrap();
+    anyhow::ensure!(
+        !value.is_empty(),
+        "Membership value is empty up to the latest height {latest_height}"
+    );
+    Ok((latest_height, value, proof))
+}
 
-    Ok(StorageProof {
-        key: storage_proof.key.as_b256(),
-        value: storage_proof.value,
-        proof: storage_proof.proof.clone(),
+/// Resolves the latest height of the source chain at which `source_tm_client` serves proofs, to
+/// pass as the target height of [`inject_tendermint_proofs`]. The proofs at the latest committed
+/// height are often not available yet, as the state of a block is only committed in the next
+/// one, so if `probe_path` cannot be proved at the latest height, the height before it is tried.
+/// Any path can be probed, e.g. the path of the first message of the batch.
+///
+/// # Returns
+/// The resolved height in `revision_number`.
+/// # Errors
+/// Returns an error if the latest height cannot be looked up, or if `probe_path` cannot be
+/// proved at either height.
+pub async fn latest_provable_height<C>(
+    source_tm_client: &C,
+    probe_path: &[Vec<u8>],
+    revision_number: u64,
+    request_id: Option<&str>,
+) -> Result<Height>
+where
+    C: TendermintProofClient + LatestHeightClient,
+{
+    let latest_height = source_tm_client.latest_height().await?;
+    let revision_height = match source_tm_client
+        .prove_path(probe_path, latest_height, request_id)
+        .await
+    {
+        Ok(_) => latest_height,
+        Err(e) => {
+            anyhow::ensure!(
+                latest_height > 1,
+                "No provable height before the latest height {latest_height}: {e}"
+            );
+            let previous_height = latest_height - 1;
+            tracing::debug!(
+                "Proofs are not available at the latest height {latest_height} ({e}), retrying at {previous_height}"
+            );
+            source_tm_client
+                .prove_path(probe_path, previous_height, request_id)
+                .await
+                .map_err(|e| {
+                    e.context(format!(
+                        "Proofs are not available at heights {latest_height} and {previous_height}"
+                    ))
+                })?;
+            previous_height
+        }
+    };
+    Ok(Height {
+        revision_number,
+        revision_height,
     })
 }
 
-pub fn inject_mock_proofs(
-    recv_msgs: &mut [MsgRecvPacket],
-    ack_msgs: &mut [MsgAcknowledgement],
-    timeout_msgs: &mut [MsgTimeout],
-) {
-    for msg in recv_msgs.iter_mut() {
-        msg.proof_commitment = b"mock".to_vec();
-        msg.proof_height = Some(Height::default());
+/// Proves the membership of `path` at `height` against a node that may be an eventually
+/// consistent read replica, on which a just written value may not be visible yet. While the value
+/// is empty, the same height is queried again after `retry_delay`, up to `max_retries` times.
+/// Unlike [`prove_membership_advancing_height`], the proof height is never changed.
+///
+/// # Returns
+/// The value and the proof.
+/// # Errors
+/// Returns an error if the value is still empty after all the retries, or if the path cannot be
+/// proved.
+pub async fn prove_membership_read_after_write<C: TendermintProofClient>(
+    source_tm_client: &C,
+    path: &[Vec<u8>],
+    height: u64,
+    max_retries: u32,
+    retry_delay: Duration,
+    request_id: Option<&str>,
+) -> Result<(Vec<u8>, MerkleProof)> {
+    let mut retries = 0;
+    loop {
+        let (value, proof) = source_tm_client
+            .prove_path(path, height, request_id)
+            .await?;
+        if !value.is_empty() {
+            return Ok((value, proof));
+        }
+        anyhow::ensure!(
+            retries < max_retries,
+            "Membership value is empty at height {height} after {max_retries} retries"
+        );
+
+        retries += 1;
+        tracing::debug!(
+            "Membership value is empty at height {height}, retrying in {retry_delay:?} ({retries}/{max_retries})"
+        );
+        Delay::new(retry_delay).await;
     }
+}
 
-    for msg in ack_msgs.iter_mut() {
-        msg.proof_acked = b"mock".to_vec();
-        msg.proof_height = Some(Height::default());
+/// Drops the packets that the target chain has already received, so that a restarted relayer
+/// does not resubmit packets that revert on chain. `query_fn` returns whether the receipt at the
+/// receipt path of a packet exists on `target_client`, see [`tendermint_receipt_exists`] and
+/// [`ethereum_receipt_exists`](super::eth_proof::ethereum_receipt_exists).
+///
+/// At most `max_concurrency` receipts are queried at the same time, as in the proof injection.
+/// # Errors
+/// Returns an error if `max_concurrency` is zero or if a receipt cannot be queried.
+pub async fn filter_unreceived_packets<'a, C, F, Fut>(
+    packets: Vec<Packet>,
+    target_client: &'a C,
+    query_fn: F,
+    max_concurrency: usize,
+) -> Result<Vec<Packet>>
+where
+    F: Fn(&'a C, Vec<u8>) -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    ensure_max_concurrency(max_concurrency)?;
+    let is_received = stream::iter(&packets)
+        .map(|packet| query_fn(target_client, packet.receipt_commitment_path()))
+        .buffered(max_concurrency)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut unreceived = Vec::with_capacity(packets.len());
+    for (packet, is_received) in packets.into_iter().zip(is_received) {
+        if is_received? {
+            tracing::debug!(
+                "Skipping packet {} from client {}, which was already received",
+                packet.sequence,
+                packet.sourceClient
+            );
+        } else {
+            unreceived.push(packet);
+        }
     }
+    Ok(unreceived)
+}
 
-    for msg in timeout_msgs.iter_mut() {
-        msg.proof_unreceived = b"mock".to_vec();
-        msg.proof_height = Some(Height::default());
+/// Drops the timeouts of the packets whose receipt exists on the destination chain, before their
+/// proofs are injected, as the non-membership proof of a received packet fails. `query_fn`
+/// returns whether the receipt at the receipt path of a packet exists on `dest_client`, as in
+/// [`filter_unreceived_packets`], and the number of dropped timeouts is logged.
+///
+/// At most `max_concurrency` receipts are queried at the same time, as in the proof injection.
+/// # Errors
+/// Returns an error if `max_concurrency` is zero, if a timeout has no packet or if a receipt
+/// cannot be queried.
+pub async fn drop_received_timeouts<'a, C, F, Fut>(
+    timeout_msgs: Vec<MsgTimeout>,
+    dest_client: &'a C,
+    query_fn: F,
+    max_concurrency: usize,
+) -> Result<Vec<MsgTimeout>>
+where
+    F: Fn(&'a C, Vec<u8>) -> Fut,
+    Fut: Future<Output = Result<bool>>,
+{
+    let packets = timeout_msgs
+        .iter()
+        .map(|msg| {
+            msg.packet
+                .clone()
+                .map(Packet::from)
+                .ok_or_else(|| anyhow::anyhow!("The timeout message has no packet"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let candidates = timeout_msgs.len();
+    let unreceived = filter_unreceived_packets(packets, dest_client, query_fn, max_concurrency)
+        .await?
+        .into_iter()
+        .map(|packet| (packet.sourceClient, packet.sequence))
+        .collect::<HashSet<_>>();
+
+    let timeout_msgs = timeout_msgs
+        .into_iter()
+        .filter(|msg| {
+            msg.packet.as_ref().is_some_and(|packet| {
+                unreceived.contains(&(packet.source_client.clone(), packet.sequence))
+            })
+        })
+        .collect::<Vec<_>>();
+    if timeout_msgs.len() < candidates {
+        tracing::info!(
+            "Dropped {} of {candidates} timeouts, whose packets were received",
+            candidates - timeout_msgs.len()
+        );
+    }
+    Ok(timeout_msgs)
+}
+
+/// Returns whether the receipt at `receipt_path` under `commitment_prefix` exists at `height` on
+/// a `CosmosSDK` chain, i.e. whether its value is not empty.
+/// # Errors
+/// Returns an error if the path cannot be proved.
+pub async fn tendermint_receipt_exists<C: TendermintProofClient>(
+    client: &C,
+    receipt_path: Vec<u8>,
+    commitment_prefix: &[u8],
+    height: u64,
+) -> Result<bool> {
+    let (value, _) = client
+        .prove_path(&[commitment_prefix.to_vec(), receipt_path], height, None)
+        .await?;
+    Ok(!value.is_empty())
+}
+
+/// Generates a single ICS-23 batch non-membership proof covering the receipt paths of all the
+/// timeout messages under `commitment_prefix`, and injects it into each of them.
+/// This should only be used if the light client on the target chain supports batch proofs.
+/// At most `max_concurrency` non-membership proofs are generated at the same time.
+/// # Errors
+/// Returns an error if `max_concurrency` is zero, or if a non-membership proof cannot be
+/// generated for any of the messages.
+pub async fn prove_batch_non_membership<C: TendermintProofClient>(
+    timeout_msgs: &mut [MsgTimeout],
+    source_tm_client: &C,
+    target_height: &Height,
+    commitment_prefix: &[u8],
+    max_concurrency: usize,
+    request_id: Option<&str>,
+) -> Result<()> {
+    ensure_max_concurrency(max_concurrency)?;
+    let proofs = stream::iter(timeout_msgs.iter())
+        .map(|msg| async {
+            let packet: Packet = msg.packet.clone().unwrap().into();
+            let (value, proof) = source_tm_client
+                .prove_path(
+                    &abci_query_path(&packet, ProofKind::Receipt, commitment_prefix),
+                    target_height.revision_height,
+                    request_id,
+                )
+                .await?;
+
+            assert_timeout_non_membership(msg, &value, ReceiptScheme::Tendermint)?;
+            anyhow::Ok(proof)
+        })
+        .buffer_unordered(max_concurrency)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+
+    let Some(first_proof) = proofs.first() else {
+        return Ok(());
+    };
+    let root_proofs = first_proof
+        .proofs
+        .get(1..)
+        .ok_or_else(|| anyhow::anyhow!("Expected a non-existence proof, but the proof is empty"))?
+        .to_vec();
+
+    // The proofs of the store root are shared by all the proofs at the same height, so only
+    // the non-existence proofs in the IBC store need to be batched.
+    let entries = proofs
+        .into_iter()
+        .map(
+            |proof| match proof.proofs.into_iter().next().and_then(|p| p.proof) {
+                Some(commitment_proof::Proof::Nonexist(non_exist)) => Ok(BatchEntry {
+                    proof: Some(batch_entry::Proof::Nonexist(non_exist)),
+                }),
+                _ => Err(anyhow::anyhow!("Expected a non-existence proof")),
+            },
+        )
+        .collect::<Result<Vec<_>>>()?;
+
+    let batch_proof = MerkleProof {
+        proofs: std::iter::once(CommitmentProof {
+            proof: Some(commitment_proof::Proof::Batch(BatchProof { entries })),
+        })
+        .chain(root_proofs)
+        .collect(),
+    }
+    .encode_vec();
+
+    for msg in timeout_msgs.iter_mut() {
+        msg.proof_unreceived.clone_from(&batch_proof);
+        msg.proof_height = Some(*target_height);
+    }
+
+    Ok(())
+}
+
+/// The scheme used by the source chain to store the packet receipts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReceiptScheme {
+    /// The receipts are stored in the IBC store of a `CosmosSDK` chain, and the value of a missing
+    /// receipt is empty.
+    Tendermint,
+    /// The receipts are stored in the commitments mapping of the ICS26 router contract, and the
+    /// value of a missing receipt is the zero word.
+    Ethereum,
+}
+
+/// Checks that the proved `value` at the receipt path of the timeout message is the value of a
+/// missing receipt under `scheme`, i.e. that the packet has not been received.
+/// # Errors
+/// Returns an error if the receipt exists, in which case the packet cannot be timed out.
+pub fn assert_timeout_non_membership(
+    msg: &MsgTimeout,
+    value: &[u8],
+    scheme: ReceiptScheme,
+) -> Result<()> {
+    let is_absent = match scheme {
+        ReceiptScheme::Tendermint => value.is_empty(),
+        ReceiptScheme::Ethereum => value.iter().all(|byte| *byte == 0),
+    };
+    if !is_absent {
+        let (sequence, client_id) = msg
+            .packet
+            .as_ref()
+            .map(|packet| (packet.sequence, packet.destination_client.as_str()))
+            .unwrap_or_default();
+        anyhow::bail!(
+            "Packet receipt for sequence {sequence} on client {client_id} exists, the packet was received and cannot be timed out"
+        )
+    }
+    Ok(())
+}
+
+/// Returns the number of proofs that must be injected into the given messages, one per message.
+#[must_use]
+pub const fn expected_proof_count(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+) -> usize {
+    recv_msgs.len() + ack_msgs.len() + timeout_msgs.len()
+}
+
+/// Returns whether the batch has no message to submit, so that no transaction is built for it.
+#[must_use]
+pub const fn is_batch_empty(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+) -> bool {
+    recv_msgs.is_empty() && ack_msgs.is_empty() && timeout_msgs.is_empty()
+}
+
+/// The messages submitted in a single transaction, see [`pack_messages`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MessageBatch {
+    /// The recv messages.
+    pub recv_msgs: Vec<MsgRecvPacket>,
+    /// The ack messages.
+    pub ack_msgs: Vec<MsgAcknowledgement>,
+    /// The timeout messages.
+    pub timeout_msgs: Vec<MsgTimeout>,
+}
+
+impl MessageBatch {
+    /// Returns the total size of the protobuf encoded messages of the batch, in bytes.
+    #[must_use]
+    pub fn encoded_len(&self) -> usize {
+        self.recv_msgs
+            .iter()
+            .map(Message::encoded_len)
+            .sum::<usize>()
+            + self
+                .ack_msgs
+                .iter()
+                .map(Message::encoded_len)
+                .sum::<usize>()
+            + self
+                .timeout_msgs
+                .iter()
+                .map(Message::encoded_len)
+                .sum::<usize>()
+    }
+}
+
+/// Packs the messages into batches whose protobuf encoded messages add up to at most
+/// `max_tx_bytes`, so that large proofs are submitted in several transactions under the
+/// transaction size limit of the chain. The messages are packed greedily in order, the timeout
+/// messages first and then the recv and the ack messages, as in a relay transaction. A message is
+/// never split across batches.
+/// # Errors
+/// Returns an error if a single message is larger than `max_tx_bytes`.
+pub fn pack_messages(
+    recv_msgs: Vec<MsgRecvPacket>,
+    ack_msgs: Vec<MsgAcknowledgement>,
+    timeout_msgs: Vec<MsgTimeout>,
+    max_tx_bytes: usize,
+) -> Result<Vec<MessageBatch>> {
+    let mut packer = MessagePacker {
+        max_tx_bytes,
+        batches: vec![],
+        batch: MessageBatch::default(),
+        batch_len: 0,
+    };
+    for msg in timeout_msgs {
+        packer.reserve(
+            &msg,
+            msg.packet.as_ref().map_or(0, |packet| packet.sequence),
+        )?;
+        packer.batch.timeout_msgs.push(msg);
+    }
+    for msg in recv_msgs {
+        packer.reserve(
+            &msg,
+            msg.packet.as_ref().map_or(0, |packet| packet.sequence),
+        )?;
+        packer.batch.recv_msgs.push(msg);
+    }
+    for msg in ack_msgs {
+        packer.reserve(
+            &msg,
+            msg.packet.as_ref().map_or(0, |packet| packet.sequence),
+        )?;
+        packer.batch.ack_msgs.push(msg);
+    }
+
+    let MessagePacker {
+        mut batches, batch, ..
+    } = packer;
+    if !is_batch_empty(&batch.recv_msgs, &batch.ack_msgs, &batch.timeout_msgs) {
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+/// The state of [`pack_messages`], with the batches packed so far and the batch being packed.
+struct MessagePacker {
+    max_tx_bytes: usize,
+    batches: Vec<MessageBatch>,
+    batch: MessageBatch,
+    batch_len: usize,
+}
+
+impl MessagePacker {
+    /// Reserves the encoded size of `msg`, of the packet `sequence`, in the batch being packed, and
+    /// starts a new batch if it does not fit.
+    fn reserve(&mut self, msg: &impl Message, sequence: u64) -> Result<()> {
+        let len = msg.encoded_len();
+        if len > self.max_tx_bytes {
+            anyhow::bail!(
+                "The message of packet {sequence} is {len} bytes, more than the maximum transaction size of {} bytes",
+                self.max_tx_bytes
+            )
+        }
+        if self.batch_len + len > self.max_tx_bytes {
+            self.batches.push(std::mem::take(&mut self.batch));
+            self.batch_len = 0;
+        }
+        self.batch_len += len;
+        Ok(())
+    }
+}
+
+/// Checks that a proof was injected into every message, so that no message is submitted with an
+/// empty proof.
+pub(crate) fn ensure_proofs_injected(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+) -> Result<()> {
+    let injected = recv_msgs
+        .iter()
+        .filter(|msg| !msg.proof_commitment.is_empty())
+        .count()
+        + ack_msgs
+            .iter()
+            .filter(|msg| !msg.proof_acked.is_empty())
+            .count()
+        + timeout_msgs
+            .iter()
+            .filter(|msg| !msg.proof_unreceived.is_empty())
+            .count();
+    let expected = expected_proof_count(recv_msgs, ack_msgs, timeout_msgs);
+    anyhow::ensure!(
+        injected == expected,
+        "Expected {expected} proofs to be injected, but {injected} were injected"
+    );
+    Ok(())
+}
+
+/// Checks that no packet is both received and timed out in the same batch. A receive proves that
+/// the packet is committed on the source chain while a timeout proves that it was never received
+/// on the destination chain, so only one of them can be submitted.
+/// # Errors
+/// Returns an error with the first packet that is both received and timed out.
+pub fn assert_no_conflicting_msgs(
+    recv_msgs: &[MsgRecvPacket],
+    timeout_msgs: &[MsgTimeout],
+) -> Result<()> {
+    let received = recv_msgs
+        .iter()
+        .filter_map(|msg| msg.packet.as_ref())
+        .map(|packet| (packet.source_client.as_str(), packet.sequence))
+        .collect::<HashSet<_>>();
+
+    timeout_msgs
+        .iter()
+        .filter_map(|msg| msg.packet.as_ref())
+        .find(|packet| received.contains(&(packet.source_client.as_str(), packet.sequence)))
+        .map_or(Ok(()), |packet| {
+            Err(anyhow::anyhow!(
+                "Packet {} from client {} is both received and timed out",
+                packet.sequence,
+                packet.source_client
+            ))
+        })
+}
+
+/// Checks that the acknowledgement of a packet that is also received in the batch has one app
+/// acknowledgement per payload of the received packet, as every app writes its own
+/// acknowledgement. A mismatch is a protocol violation of the destination chain.
+/// # Errors
+/// Returns an error with the first packet whose acknowledgement count does not match.
+pub fn assert_ack_counts_match(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+) -> Result<()> {
+    let payload_counts = recv_msgs
+        .iter()
+        .filter_map(|msg| msg.packet.as_ref())
+        .map(|packet| {
+            (
+                (packet.source_client.as_str(), packet.sequence),
+                packet.payloads.len(),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+
+    for msg in ack_msgs {
+        let Some(packet) = msg.packet.as_ref() else {
+            continue;
+        };
+        let Some(payload_count) =
+            payload_counts.get(&(packet.source_client.as_str(), packet.sequence))
+        else {
+            continue;
+        };
+        let ack_count = msg
+            .acknowledgement
+            .as_ref()
+            .map_or(0, |ack| ack.app_acknowledgements.len());
+        anyhow::ensure!(
+            ack_count == *payload_count,
+            "Packet {} from client {} has {ack_count} app acknowledgements for {payload_count} payloads",
+            packet.sequence,
+            packet.source_client
+        );
+    }
+    Ok(())
+}
+
+/// Checks that the proof height of every message is `proof_height`, the height at which the
+/// proofs were fetched, so that a message is never submitted with a proof from another block.
+/// # Errors
+/// Returns an error if the proof height of any message differs from `proof_height`.
+pub fn ensure_proof_heights(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+    proof_height: &Height,
+) -> Result<()> {
+    let msg_heights = recv_msgs
+        .iter()
+        .map(|msg| (&msg.packet, msg.proof_height))
+        .chain(ack_msgs.iter().map(|msg| (&msg.packet, msg.proof_height)))
+        .chain(
+            timeout_msgs
+                .iter()
+                .map(|msg| (&msg.packet, msg.proof_height)),
+        );
+
+    for (packet, msg_height) in msg_heights {
+        if msg_height.as_ref() != Some(proof_height) {
+            anyhow::bail!(
+                "Proof height {msg_height:?} of packet {} does not match the height {proof_height:?} of the proof",
+                packet.as_ref().map_or(0, |packet| packet.sequence)
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Checks that every timeout message is proved at or below `trusted_height`, the latest height
+/// of the source chain verified by its light client on the destination chain, as the timeout
+/// reverts if the light client has no consensus state at the proof height.
+/// # Errors
+/// Returns an error if a timeout message has no proof height or is proved above `trusted_height`.
+pub fn ensure_timeout_proofs_trusted(
+    timeout_msgs: &[MsgTimeout],
+    trusted_height: &Height,
+) -> Result<()> {
+    for msg in timeout_msgs {
+        let sequence = msg.packet.as_ref().map_or(0, |packet| packet.sequence);
+        let proof_height = msg
+            .proof_height
+            .ok_or_else(|| anyhow::anyhow!("Timeout of packet {sequence} has no proof height"))?;
+        if (proof_height.revision_number, proof_height.revision_height)
+            > (
+                trusted_height.revision_number,
+                trusted_height.revision_height,
+            )
+        {
+            anyhow::bail!(
+                "Timeout of packet {sequence} is proved at {proof_height:?}, above the trusted height {trusted_height:?} of the source client"
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the summed size of the injected proofs of the messages is at most
+/// `max_total_proof_bytes`, to bound the size of the transaction before it is submitted.
+/// # Errors
+/// Returns an error if the budget is exceeded, listing the messages to drop to fit in it: the
+/// messages from the first one that overflows the budget, in the order recv, ack and timeout.
+pub fn ensure_max_total_proof_bytes(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+    max_total_proof_bytes: usize,
+) -> Result<()> {
+    let msg_proofs = recv_msgs
+        .iter()
+        .map(|msg| ("recv", &msg.packet, msg.proof_commitment.len()))
+        .chain(
+            ack_msgs
+                .iter()
+                .map(|msg| ("ack", &msg.packet, msg.proof_acked.len())),
+        )
+        .chain(
+            timeout_msgs
+                .iter()
+                .map(|msg| ("timeout", &msg.packet, msg.proof_unreceived.len())),
+        );
+
+    let mut total_proof_bytes = 0;
+    let mut to_drop = vec![];
+    for (kind, packet, proof_bytes) in msg_proofs {
+        let sequence = packet.as_ref().map_or(0, |packet| packet.sequence);
+        total_proof_bytes += proof_bytes;
+        if total_proof_bytes > max_total_proof_bytes {
+            to_drop.push(format!("{kind} of packet {sequence}"));
+        }
+    }
+    anyhow::ensure!(
+        to_drop.is_empty(),
+        "The proofs total {total_proof_bytes} bytes, exceeding the budget of {max_total_proof_bytes} bytes; drop the messages: {}",
+        to_drop.join(", ")
+    );
+    Ok(())
+}
+
+/// Checks that the block time of the proof height of every recv and ack message is at most
+/// `max_clock_drift` ahead of `destination_time`, the current time of the destination chain, as
+/// the light client on the destination chain rejects headers from the future beyond its clock
+/// drift. The block time of a proof height, in unix seconds, is looked up with
+/// `proof_block_time`.
+/// # Errors
+/// Returns an error if a message has no proof height, if the block time of a proof height cannot
+/// be looked up or if it is ahead of `destination_time` by more than `max_clock_drift`.
+pub fn ensure_within_clock_drift(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    proof_block_time: impl Fn(&Height) -> Result<u64>,
+    destination_time: u64,
+    max_clock_drift: Duration,
+) -> Result<()> {
+    let msg_heights = recv_msgs
+        .iter()
+        .map(|msg| (&msg.packet, msg.proof_height))
+        .chain(ack_msgs.iter().map(|msg| (&msg.packet, msg.proof_height)));
+
+    for (packet, proof_height) in msg_heights {
+        let sequence = packet.as_ref().map_or(0, |packet| packet.sequence);
+        let proof_height =
+            proof_height.ok_or_else(|| anyhow::anyhow!("Packet {sequence} has no proof height"))?;
+        let block_time = proof_block_time(&proof_height)?;
+        let drift = block_time.saturating_sub(destination_time);
+        if drift > max_clock_drift.as_secs() {
+            anyhow::bail!(
+                "Proof of packet {sequence} at {proof_height:?} is {drift}s ahead of the destination chain, exceeding the max clock drift of {}s",
+                max_clock_drift.as_secs()
+            )
+        }
+    }
+    Ok(())
+}
+
+/// Checks that at least one proof can be generated at a time, as a zero `max_concurrency` would
+/// never make progress.
+fn ensure_max_concurrency(max_concurrency: usize) -> Result<()> {
+    anyhow::ensure!(
+        max_concurrency > 0,
+        "The maximum number of concurrent proofs must be positive"
+    );
+    Ok(())
+}
+
+/// Runs the proof futures concurrently, at most `max_concurrency` at a time, returning the first
+/// error encountered.
+///
+/// If `batch_deadline` is reached, or `cancellation` is cancelled, before all the futures
+/// complete, the remaining ones are abandoned and [`ProofError::DeadlineExceeded`] or
+/// [`ProofError::Cancelled`] is returned. The index of every future that completes, offset by
+/// `offset`, is pushed to `completed`, so that progress can be tracked across several calls.
+async fn try_join_proofs<F>(
+    proofs: impl IntoIterator<Item = F>,
+    offset: usize,
+    batch_deadline: Option<Instant>,
+    cancellation: Option<&CancellationToken>,
+    max_concurrency: usize,
+    completed: &mut Vec<usize>,
+) -> Result<()>
+where
+    F: Future<Output = Result<()>>,
+{
+    let mut pending = stream::iter(proofs.into_iter().enumerate())
+        .map(|(i, proof)| async move { proof.await.map(|()| offset + i) })
+        .buffer_unordered(max_concurrency);
+    let mut stop = pin!(batch_stop(batch_deadline, cancellation));
+
+    loop {
+        let next = match future::select(pending.next(), stop.as_mut()).await {
+            Either::Left((next, _)) => next,
+            Either::Right((stop, _)) => {
+                completed.sort_unstable();
+                return Err(stop.into_error(completed.clone()).into());
+            }
+        };
+
+        match next {
+            Some(result) => completed.push(result?),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// The reason a batch of proofs was stopped before all its proofs were generated.
+pub(crate) enum BatchStop {
+    Deadline,
+    Cancelled,
+}
+
+impl BatchStop {
+    /// Returns the error of the stopped batch, given the indices of its `completed` proofs.
+    pub(crate) fn into_error(self, completed: Vec<usize>) -> ProofError {
+        match self {
+            Self::Deadline => ProofError::DeadlineExceeded {
+                completed: completed.len(),
+            },
+            Self::Cancelled => ProofError::Cancelled { completed },
+        }
+    }
+}
+
+/// Resolves when `batch_deadline` is reached or `cancellation` is cancelled, whichever comes
+/// first, or never if neither is set.
+pub(crate) async fn batch_stop(
+    batch_deadline: Option<Instant>,
+    cancellation: Option<&CancellationToken>,
+) -> BatchStop {
+    let deadline = async {
+        match batch_deadline {
+            Some(deadline) => Delay::new(deadline.saturating_duration_since(Instant::now())).await,
+            None => future::pending().await,
+        }
+    };
+    let cancelled = async {
+        match cancellation {
+            Some(cancellation) => cancellation.cancelled().await,
+            None => future::pending().await,
+        }
+    };
+    match future::select(pin!(cancelled), pin!(deadline)).await {
+        Either::Left(_) => BatchStop::Cancelled,
+        Either::Right(_) => BatchStop::Deadline,
+    }
+}
+
+/// Runs the `proofs` with at most `max_concurrency` of them at the same time, and returns the
+/// errors of the failed ones with their index, offset by `offset`, in ascending order. The
+/// indices of the proved ones are pushed to `completed`, to report them if the batch is stopped
+/// by `batch_deadline` or `cancellation`, see [`try_join_proofs`].
+async fn collect_proof_failures<F>(
+    proofs: impl IntoIterator<Item = F>,
+    offset: usize,
+    batch_deadline: Option<Instant>,
+    cancellation: Option<&CancellationToken>,
+    max_concurrency: usize,
+    completed: &mut Vec<usize>,
+) -> Result<Vec<(usize, anyhow::Error)>>
+where
+    F: Future<Output = Result<()>>,
+{
+    let mut pending = stream::iter(proofs.into_iter().enumerate())
+        .map(|(i, proof)| async move { (offset + i, proof.await) })
+        .buffer_unordered(max_concurrency);
+    let mut stop = pin!(batch_stop(batch_deadline, cancellation));
+    let mut failures = Vec::new();
+
+    loop {
+        let next = match future::select(pending.next(), stop.as_mut()).await {
+            Either::Left((next, _)) => next,
+            Either::Right((stop, _)) => {
+                completed.sort_unstable();
+                return Err(stop.into_error(completed.clone()).into());
+            }
+        };
+
+        match next {
+            Some((i, Ok(()))) => completed.push(i),
+            Some((i, Err(e))) => failures.push((i, e)),
+            None => break,
+        }
+    }
+    failures.sort_by_key(|(i, _)| *i);
+    Ok(failures)
+}
+
+/// The mock proofs injected into each type of message by [`inject_mock_proofs`], so that tests
+/// can assert which proof field was targeted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MockProofs<'a> {
+    /// The proof injected into recv messages.
+    pub recv: &'a [u8],
+    /// The proof injected into ack messages.
+    pub ack: &'a [u8],
+    /// The proof injected into timeout messages.
+    pub timeout: &'a [u8],
+}
+
+impl Default for MockProofs<'_> {
+    fn default() -> Self {
+        Self {
+            recv: b"mock",
+            ack: b"mock",
+            timeout: b"mock",
+        }
+    }
+}
+
+/// Injects mock proofs at `proof_height` into recv, ack and timeout messages for testing
+/// purposes, and returns the report of the messages stamped.
+pub fn inject_mock_proofs(
+    recv_msgs: &mut [MsgRecvPacket],
+    ack_msgs: &mut [MsgAcknowledgement],
+    timeout_msgs: &mut [MsgTimeout],
+    mock_proofs: &MockProofs<'_>,
+    proof_height: Height,
+) -> ProofInjectionReport {
+    for msg in recv_msgs.iter_mut() {
+        msg.proof_commitment = mock_proofs.recv.to_vec();
+        msg.proof_height = Some(proof_height);
+    }
+
+    for msg in ack_msgs.iter_mut() {
+        msg.proof_acked = mock_proofs.ack.to_vec();
+        msg.proof_height = Some(proof_height);
+    }
+
+    for msg in timeout_msgs.iter_mut() {
+        msg.proof_unreceived = mock_proofs.timeout.to_vec();
+        msg.proof_height = Some(proof_height);
+    }
+
+    ProofInjectionReport::new(proof_height, recv_msgs, ack_msgs, timeout_msgs)
+}
+
+/// A message of a dry run, see [`build_dry_run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DryRunMsg {
+    /// The sequence of the packet of the message.
+    pub sequence: u64,
+    /// The size of the protobuf encoded message, in bytes.
+    pub encoded_len: usize,
+}
+
+/// The messages that a relay would submit, as built by [`build_dry_run`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DryRunReport {
+    /// The recv messages.
+    pub recv_msgs: Vec<DryRunMsg>,
+    /// The ack messages.
+    pub ack_msgs: Vec<DryRunMsg>,
+    /// The timeout messages.
+    pub timeout_msgs: Vec<DryRunMsg>,
+}
+
+impl DryRunReport {
+    /// Returns the total size of the encoded messages, in bytes, which approximates the size of
+    /// the relay transaction.
+    #[must_use]
+    pub fn total_encoded_len(&self) -> usize {
+        self.recv_msgs
+            .iter()
+            .chain(&self.ack_msgs)
+            .chain(&self.timeout_msgs)
+            .map(|msg| msg.encoded_len)
+            .sum()
+    }
+}
+
+/// Builds the messages that a relay would submit, without any network access: the events are
+/// converted to messages as by [`src_events_to_recv_and_ack_msgs`] and
+/// [`target_events_to_timeout_msgs`], without filtering, and `mock_proofs` are injected in place
+/// of the real proofs, see [`inject_mock_proofs`]. This allows validating a configuration, e.g. in
+/// CI, and estimating the size of the relay transaction.
+///
+/// # Arguments
+/// - `src_events` - The events of the source chain.
+/// - `target_events` - The events of the target chain, whose packets are timed out.
+/// - `src_client_id` - The source client ID.
+/// - `dst_client_id` - The destination client ID.
+/// - `target_height` - The target height.
+/// - `signer_address` - The signer address.
+/// - `clock` - The clock giving the current time.
+/// - `mock_proofs` - The proofs injected into the messages.
+/// # Errors
+/// Returns an error if the events cannot be converted to messages.
+#[allow(clippy::too_many_arguments)]
+pub fn build_dry_run(
+    src_events: Vec<EurekaEventWithHeight>,
+    target_events: Vec<EurekaEventWithHeight>,
+    src_client_id: &str,
+    dst_client_id: &str,
+    target_height: &Height,
+    signer_address: &str,
+    clock: &dyn Clock,
+    mock_proofs: &MockProofs<'_>,
+) -> Result<DryRunReport> {
+    let (mut recv_msgs, mut ack_msgs, _) = src_events_to_recv_and_ack_msgs(
+        src_events,
+        src_client_id,
+        dst_client_id,
+        &[],
+        &[],
+        target_height,
+        signer_address,
+        None,
+        clock,
+        &MsgFilterOptions::default(),
+    )?;
+    let mut timeout_msgs = target_events_to_timeout_msgs(
+        target_events,
+        src_client_id,
+        dst_client_id,
+        &[],
+        target_height,
+        signer_address,
+        None,
+        clock,
+        &TimeoutFilterOptions::default(),
+    )?;
+    inject_mock_proofs(
+        &mut recv_msgs,
+        &mut ack_msgs,
+        &mut timeout_msgs,
+        mock_proofs,
+        Height::default(),
+    );
+
+    Ok(DryRunReport {
+        recv_msgs: recv_msgs
+            .iter()
+            .map(|msg| DryRunMsg {
+                sequence: msg.packet.as_ref().map_or(0, |packet| packet.sequence),
+                encoded_len: msg.encoded_len(),
+            })
+            .collect(),
+        ack_msgs: ack_msgs
+            .iter()
+            .map(|msg| DryRunMsg {
+                sequence: msg.packet.as_ref().map_or(0, |packet| packet.sequence),
+                encoded_len: msg.encoded_len(),
+            })
+            .collect(),
+        timeout_msgs: timeout_msgs
+            .iter()
+            .map(|msg| DryRunMsg {
+                sequence: msg.packet.as_ref().map_or(0, |packet| packet.sequence),
+                encoded_len: msg.encoded_len(),
+            })
+            .collect(),
+    })
+}
+
+/// The `ProofInjector` trait defines the interface for a source of proofs that populates the
+/// proofs of the recv, ack and timeout messages of a batch, so that relayer loops can be generic
+/// over the chain the proofs are generated from.
+#[async_trait::async_trait]
+pub trait ProofInjector: Sync {
+    /// Generates and injects the proofs into the messages.
+    async fn inject(
+        &self,
+        recv_msgs: &mut [MsgRecvPacket],
+        ack_msgs: &mut [MsgAcknowledgement],
+        timeout_msgs: &mut [MsgTimeout],
+    ) -> Result<()>;
+}
+
+/// A [`ProofInjector`] that generates tendermint proofs, see [`inject_tendermint_proofs`].
+pub struct TendermintProofInjector<'a, C> {
+    /// The client of the source chain.
+    pub client: C,
+    /// The height of the source chain at which the proofs are generated.
+    pub target_height: Height,
+    /// The commitment prefix of the source chain.
+    pub commitment_prefix: Vec<u8>,
+    /// The parameters to verify the proofs locally, if any.
+    pub local_verification: Option<LocalVerification<'a>>,
+    /// The format of the proofs set on the messages.
+    pub proof_format: TendermintProofFormat,
+    /// The deadline for generating the proofs of a batch, if any.
+    pub batch_deadline: Option<Instant>,
+    /// The token cancelling the proof generation of a batch, if any.
+    pub cancellation: Option<CancellationToken>,
+    /// The maximum number of proofs requested at the same time.
+    pub max_concurrency: usize,
+    /// The metrics counting the generated and failed proofs, if any.
+    pub metrics: Option<&'a ProofMetrics>,
+    /// The request id attached to the proof requests, if any.
+    pub request_id: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl<C: TendermintProofClient> ProofInjector for TendermintProofInjector<'_, C> {
+    async fn inject(
+        &self,
+        recv_msgs: &mut [MsgRecvPacket],
+        ack_msgs: &mut [MsgAcknowledgement],
+        timeout_msgs: &mut [MsgTimeout],
+    ) -> Result<()> {
+        inject_tendermint_proofs(
+            recv_msgs,
+            ack_msgs,
+            timeout_msgs,
+            &self.client,
+            &self.target_height,
+            &self.commitment_prefix,
+            &TendermintProofOptions {
+                local_verification: self.local_verification,
+                proof_format: self.proof_format,
+                batch_deadline: self.batch_deadline,
+                cancellation: self.cancellation.as_ref(),
+                max_concurrency: self.max_concurrency,
+                metrics: self.metrics,
+                request_id: self.request_id.as_deref(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// A [`ProofInjector`] that injects mock proofs for testing purposes, see
+/// [`inject_mock_proofs`].
+#[derive(Default)]
+pub struct MockProofInjector<'a>(pub MockProofs<'a>);
+
+#[async_trait::async_trait]
+impl ProofInjector for MockProofInjector<'_> {
+    async fn inject(
+        &self,
+        recv_msgs: &mut [MsgRecvPacket],
+        ack_msgs: &mut [MsgAcknowledgement],
+        timeout_msgs: &mut [MsgTimeout],
+    ) -> Result<()> {
+        inject_mock_proofs(
+            recv_msgs,
+            ack_msgs,
+            timeout_msgs,
+            &self.0,
+            Height::default(),
+        );
+        Ok(())
+    }
+}
+
+/// A [`ProofInjector`] that falls back to mock proofs in degraded mode, once the proofs of its
+/// injector failed for `max_consecutive_failures` batches in a row, so that a testnet relayer
+/// keeps relaying while its RPC cannot serve proofs. The mock proofs are set at the target height
+/// of the batch, i.e. the proof height the messages were built with. The mock proofs are rejected
+/// by any real light client, so the degraded mode must never be enabled in production.
+pub struct DegradedModeInjector<I> {
+    injector: I,
+    degraded_mode: bool,
+    max_consecutive_failures: usize,
+    consecutive_failures: AtomicUsize,
+}
+
+impl<I: ProofInjector> DegradedModeInjector<I> {
+    /// Creates a new `DegradedModeInjector` over `injector`, which only falls back to mock proofs
+    /// if `degraded_mode` is set.
+    #[must_use]
+    pub const fn new(injector: I, degraded_mode: bool, max_consecutive_failures: usize) -> Self {
+        Self {
+            injector,
+            degraded_mode,
+            max_consecutive_failures,
+            consecutive_failures: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<I: ProofInjector> ProofInjector for DegradedModeInjector<I> {
+    async fn inject(
+        &self,
+        recv_msgs: &mut [MsgRecvPacket],
+        ack_msgs: &mut [MsgAcknowledgement],
+        timeout_msgs: &mut [MsgTimeout],
+    ) -> Result<()> {
+        let err = match self
+            .injector
+            .inject(recv_msgs, ack_msgs, timeout_msgs)
+            .await
+        {
+            Ok(()) => {
+                self.consecutive_failures.store(0, Ordering::Relaxed);
+                return Ok(());
+            }
+            Err(err) => err,
+        };
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.degraded_mode || failures < self.max_consecutive_failures {
+            return Err(err);
+        }
+
+        tracing::warn!(
+            error = %err,
+            "DEGRADED MODE: injecting MOCK proofs after {failures} consecutive proof failures, the relayed messages will be rejected by any real light client, never enable the degraded mode in production"
+        );
+        let target_height = batch_target_height(recv_msgs, ack_msgs, timeout_msgs);
+        inject_mock_proofs(
+            recv_msgs,
+            ack_msgs,
+            timeout_msgs,
+            &MockProofs::default(),
+            target_height,
+        );
+        Ok(())
+    }
+}
+
+/// Returns the target height of a batch, i.e. the proof height its messages were built with.
+fn batch_target_height(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+) -> Height {
+    recv_msgs
+        .iter()
+        .map(|msg| msg.proof_height)
+        .chain(ack_msgs.iter().map(|msg| msg.proof_height))
+        .chain(timeout_msgs.iter().map(|msg| msg.proof_height))
+        .flatten()
+        .next()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use alloy::primitives::{Bytes, U256};
+    use futures::{
+        executor::{block_on, LocalPool},
+        future,
+    };
+    use futures_timer::Delay;
+    use ibc_core_commitment_types::{
+        merkle::MerkleProof,
+        proto::ics23::{
+            batch_entry, commitment_proof, CommitmentProof, ExistenceProof, NonExistenceProof,
+        },
+    };
+    use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::{Packet, Payload};
+    use ibc_proto_eureka::{
+        ibc::core::{
+            channel::v2::{Acknowledgement, MsgAcknowledgement, MsgRecvPacket, MsgTimeout},
+            client::v1::Height,
+        },
+        Protobuf,
+    };
+    use prost::Message;
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        events::{EurekaEvent, EurekaEventWithHeight},
+        utils::{
+            client::{LatestHeightClient, TendermintProofClient},
+            clock::MockClock,
+            error::ProofError,
+            metrics::ProofMetrics,
+            packet::packet_paths,
+        },
+    };
+
+    use super::{
+        abci_query_path, assert_ack_counts_match, assert_no_conflicting_msgs,
+        assert_timeout_non_membership, assign_signers, audit_query_key, backlog_depth,
+        batch_fingerprint, build_dry_run, build_relay_messages, cancel_received_timeouts,
+        compress_timeout_sequences, drop_received_timeouts, ensure_max_total_proof_bytes,
+        ensure_proof_heights, ensure_proofs_injected, ensure_timeout_proofs_trusted,
+        ensure_within_clock_drift, expected_proof_count, filter_unreceived_packets,
+        group_timeouts_by_source, inject_mock_proofs, inject_tendermint_proofs,
+        inject_tendermint_proofs_partial, is_batch_empty, latest_provable_height, pack_messages,
+        prove_batch_non_membership, prove_membership_advancing_height,
+        prove_membership_read_after_write, referenced_addresses, src_events_to_recv_and_ack_msgs,
+        target_events_to_ack_msgs, target_events_to_timeout_msgs, tendermint_receipt_exists,
+        timeout_from_packet_data, DegradedModeInjector, DryRunMsg, MockProofInjector, MockProofs,
+        MsgFilterOptions, PacketData, ProofInjectionReport, ProofInjector, ProofKind,
+        ReceiptScheme, RelayParams, TendermintProofFormat, TendermintProofInjector,
+        TendermintProofOptions, TimeoutFilterOptions, ValueAndProof, DEFAULT_COMMITMENT_PREFIX,
+        DEFAULT_MAX_PROOF_CONCURRENCY,
+    };
+
+    /// A mock client whose value is committed from `committed_at` onwards, and whose latest
+    /// committed height is `latest_height`.
+    struct MockCommittingClient {
+        committed_at: u64,
+        latest_height: u64,
+        proved_heights: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockCommittingClient {
+        async fn prove_path(
+            &self,
+            _path: &[Vec<u8>],
+            height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            self.proved_heights.lock().unwrap().push(height);
+            let value = if height >= self.committed_at {
+                b"value".to_vec()
+            } else {
+                vec![]
+            };
+            Ok((value, MerkleProof { proofs: vec![] }))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LatestHeightClient for MockCommittingClient {
+        async fn latest_height(&self) -> anyhow::Result<u64> {
+            Ok(self.latest_height)
+        }
+    }
+
+    /// A mock client whose latest height is `latest_height`, but which only serves proofs up to
+    /// `provable_height`.
+    struct MockUncommittedHeightClient {
+        latest_height: u64,
+        provable_height: u64,
+        proved_heights: Mutex<Vec<u64>>,
+    }
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockUncommittedHeightClient {
+        async fn prove_path(
+            &self,
+            _path: &[Vec<u8>],
+            height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            self.proved_heights.lock().unwrap().push(height);
+            anyhow::ensure!(
+                height <= self.provable_height,
+                "height {height} is not committed yet"
+            );
+            Ok((vec![], MerkleProof { proofs: vec![] }))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LatestHeightClient for MockUncommittedHeightClient {
+        async fn latest_height(&self) -> anyhow::Result<u64> {
+            Ok(self.latest_height)
+        }
+    }
+
+    /// A mock read replica that serves an empty value for its first `stale_queries` queries.
+    struct MockReplicaClient {
+        stale_queries: AtomicUsize,
+        queries: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockReplicaClient {
+        async fn prove_path(
+            &self,
+            _path: &[Vec<u8>],
+            _height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            self.queries.fetch_add(1, Ordering::SeqCst);
+            let stale = self
+                .stale_queries
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            let value = if stale { vec![] } else { b"value".to_vec() };
+            Ok((value, MerkleProof { proofs: vec![] }))
+        }
+    }
+
+    /// A mock client that proves every path with the same value.
+    struct MockTendermintClient {
+        value: Vec<u8>,
+    }
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockTendermintClient {
+        async fn prove_path(
+            &self,
+            _path: &[Vec<u8>],
+            _height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            let proof = MerkleProof {
+                proofs: vec![CommitmentProof::default()],
+            };
+            Ok((self.value.clone(), proof))
+        }
+    }
+
+    /// A mock client that proves the absence of every path.
+    struct MockNonMembershipClient;
+
+    /// A mock tendermint client that answers with an empty proof, as a misbehaving node may.
+    struct MockEmptyProofClient;
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockEmptyProofClient {
+        async fn prove_path(
+            &self,
+            _path: &[Vec<u8>],
+            _height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            Ok((vec![], MerkleProof { proofs: vec![] }))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockNonMembershipClient {
+        async fn prove_path(
+            &self,
+            path: &[Vec<u8>],
+            _height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            let non_exist = NonExistenceProof {
+                key: path[1].clone(),
+                ..Default::default()
+            };
+            let root_proof = ExistenceProof {
+                key: path[0].clone(),
+                ..Default::default()
+            };
+            Ok((
+                vec![],
+                MerkleProof {
+                    proofs: vec![
+                        CommitmentProof {
+                            proof: Some(commitment_proof::Proof::Nonexist(non_exist)),
+                        },
+                        CommitmentProof {
+                            proof: Some(commitment_proof::Proof::Exist(root_proof)),
+                        },
+                    ],
+                },
+            ))
+        }
+    }
+
+    /// A mock client that proves the absence of every path, taking `delay` for the packet with
+    /// `delayed_sequence`.
+    struct MockDelayedClient {
+        delayed_sequence: u64,
+        delay: Duration,
+    }
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockDelayedClient {
+        async fn prove_path(
+            &self,
+            path: &[Vec<u8>],
+            _height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            let sequence = u64::from_be_bytes(path[1][path[1].len() - 8..].try_into()?);
+            if sequence == self.delayed_sequence {
+                Delay::new(self.delay).await;
+            }
+            Ok((vec![], MerkleProof { proofs: vec![] }))
+        }
+    }
+
+    /// A mock client that records the maximum number of proofs requested at the same time, and
+    /// proves the absence of the requested keys.
+    #[derive(Default)]
+    struct MockConcurrencyClient {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl TendermintProofClient for MockConcurrencyClient {
+        async fn prove_path(
+            &self,
+            path: &[Vec<u8>],
+            _height: u64,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<(Vec<u8>, MerkleProof)> {
+            let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+            Delay::new(Duration::from_millis(5)).await;
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+            let proof = CommitmentProof {
+                proof: Some(commitment_proof::Proof::Nonexist(NonExistenceProof {
+                    key: path[1].clone(),
+                    ..Default::default()
+                })),
+            };
+            Ok((
+                vec![],
+                MerkleProof {
+                    proofs: vec![proof],
+                },
+            ))
+        }
+    }
+
+    fn test_packet() -> Packet {
+        Packet {
+            sequence: 1,
+            sourceClient: "07-tendermint-0".to_string(),
+            destClient: "08-wasm-0".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![],
+        }
+    }
+
+    fn recv_msg(packet: Packet) -> MsgRecvPacket {
+        MsgRecvPacket {
+            packet: Some(packet.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_inject_tendermint_recv_proof() {
+        let packet = test_packet();
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 10,
+        };
+        let client = MockTendermintClient {
+            value: packet.commitment(),
+        };
+
+        let mut recv_msgs = vec![recv_msg(packet)];
+        let report = block_on(inject_tendermint_proofs(
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+            &client,
+            &target_height,
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(recv_msgs[0].proof_height, Some(target_height));
+        assert_eq!(report.used_height, target_height);
+        assert_eq!(report.recv_count, 1);
+        assert_eq!(report.msg_count(), 1);
+    }
+
+    #[test]
+    fn test_inject_tendermint_proofs_partial() {
+        let packet = test_packet();
+        let other_packet = Packet {
+            sequence: 2,
+            ..test_packet()
+        };
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 10,
+        };
+        // The client only serves the commitment of `packet`.
+        let client = MockTendermintClient {
+            value: packet.commitment(),
+        };
+
+        let mut recv_msgs = vec![
+            recv_msg(packet.clone()),
+            recv_msg(other_packet),
+            recv_msg(packet.clone()),
+        ];
+        let mut ack_msgs = vec![MsgAcknowledgement {
+            packet: Some(packet.into()),
+            ..Default::default()
+        }];
+        let metrics = ProofMetrics::default();
+        let failures = block_on(inject_tendermint_proofs_partial(
+            &mut recv_msgs,
+            &mut ack_msgs,
+            &mut [],
+            &client,
+            &target_height,
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions {
+                metrics: Some(&metrics),
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        assert_eq!(
+            failures.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert!(recv_msgs[1].proof_commitment.is_empty());
+        assert!(!recv_msgs[0].proof_commitment.is_empty());
+        assert!(!recv_msgs[2].proof_commitment.is_empty());
+        assert!(!ack_msgs[0].proof_acked.is_empty());
+        assert_eq!(metrics.generated(), 3);
+        assert_eq!(metrics.failed(), 1);
+    }
+
+    /// Injects the proofs of a batch with any [`ProofInjector`].
+    fn inject_batch(
+        injector: &impl ProofInjector,
+        recv_msgs: &mut [MsgRecvPacket],
+        ack_msgs: &mut [MsgAcknowledgement],
+        timeout_msgs: &mut [MsgTimeout],
+    ) -> anyhow::Result<()> {
+        block_on(injector.inject(recv_msgs, ack_msgs, timeout_msgs))
+    }
+
+    #[test]
+    fn test_proof_injectors() {
+        let packet = test_packet();
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 10,
+        };
+        let metrics = ProofMetrics::default();
+        let tendermint = TendermintProofInjector {
+            client: MockTendermintClient {
+                value: packet.commitment(),
+            },
+            target_height,
+            commitment_prefix: DEFAULT_COMMITMENT_PREFIX.to_vec(),
+            local_verification: None,
+            proof_format: TendermintProofFormat::Proof,
+            batch_deadline: None,
+            cancellation: None,
+            max_concurrency: DEFAULT_MAX_PROOF_CONCURRENCY,
+            metrics: Some(&metrics),
+            request_id: None,
+        };
+
+        let mut recv_msgs = vec![recv_msg(packet.clone())];
+        inject_batch(&tendermint, &mut recv_msgs, &mut [], &mut []).unwrap();
+        assert_eq!(recv_msgs[0].proof_height, Some(target_height));
+        assert_eq!(metrics.generated(), 1);
+
+        let mut recv_msgs = vec![recv_msg(packet)];
+        inject_batch(
+            &MockProofInjector::default(),
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+        )
+        .unwrap();
+        assert_eq!(recv_msgs[0].proof_commitment, b"mock");
+        assert_eq!(recv_msgs[0].proof_height, Some(Height::default()));
+    }
+
+    #[test]
+    fn test_inject_mock_proofs() {
+        let mut recv_msgs = vec![recv_msg(test_packet())];
+        let mut ack_msgs = vec![MsgAcknowledgement::default()];
+        let mut timeout_msgs = vec![MsgTimeout::default(), MsgTimeout::default()];
+        let proof_height = Height {
+            revision_number: 1,
+            revision_height: 42,
+        };
+
+        let report = inject_mock_proofs(
+            &mut recv_msgs,
+            &mut ack_msgs,
+            &mut timeout_msgs,
+            &MockProofs {
+                recv: b"recv",
+                ack: b"ack",
+                timeout: b"timeout",
+            },
+            proof_height,
+        );
+
+        assert_eq!(report.msg_count(), 4);
+        assert_eq!(
+            report,
+            ProofInjectionReport {
+                used_height: proof_height,
+                recv_count: 1,
+                ack_count: 1,
+                timeout_count: 2,
+            }
+        );
+        assert!(recv_msgs
+            .iter()
+            .map(|msg| msg.proof_height)
+            .chain(ack_msgs.iter().map(|msg| msg.proof_height))
+            .chain(timeout_msgs.iter().map(|msg| msg.proof_height))
+            .all(|height| height == Some(proof_height)));
+        assert_eq!(recv_msgs[0].proof_commitment, b"recv");
+        assert_eq!(ack_msgs[0].proof_acked, b"ack");
+        assert_eq!(timeout_msgs[0].proof_unreceived, b"timeout");
+        assert!(ensure_proofs_injected(&recv_msgs, &ack_msgs, &timeout_msgs).is_ok());
+    }
+
+    #[test]
+    fn test_assert_ack_counts_match() {
+        let packet = Packet {
+            payloads: vec![
+                Payload {
+                    sourcePort: "transfer".to_string(),
+                    destPort: "transfer".to_string(),
+                    version: "ics20-1".to_string(),
+                    encoding: "application/json".to_string(),
+                    value: Bytes::default(),
+                };
+                2
+            ],
+            ..test_packet()
+        };
+        let ack_msg = |app_acks: usize| MsgAcknowledgement {
+            packet: Some(packet.clone().into()),
+            acknowledgement: Some(Acknowledgement {
+                app_acknowledgements: vec![b"ack".to_vec(); app_acks],
+            }),
+            ..Default::default()
+        };
+        let recv_msgs = vec![recv_msg(packet.clone())];
+
+        assert!(assert_ack_counts_match(&recv_msgs, &[ack_msg(2)]).is_ok());
+        // Acknowledgements of packets that are not received in the batch are not checked.
+        assert!(assert_ack_counts_match(&[], &[ack_msg(1)]).is_ok());
+
+        let err = assert_ack_counts_match(&recv_msgs, &[ack_msg(1)]).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("has 1 app acknowledgements for 2 payloads"));
+    }
+
+    #[test]
+    fn test_assert_no_conflicting_msgs() {
+        let timeout_msg = |packet: Packet| MsgTimeout {
+            packet: Some(packet.into()),
+            ..Default::default()
+        };
+        let recv_msgs = vec![recv_msg(test_packet())];
+
+        let other_sequence = Packet {
+            sequence: 2,
+            ..test_packet()
+        };
+        assert!(assert_no_conflicting_msgs(&recv_msgs, &[timeout_msg(other_sequence)]).is_ok());
+
+        let err =
+            assert_no_conflicting_msgs(&recv_msgs, &[timeout_msg(test_packet())]).unwrap_err();
+        assert!(err.to_string().contains("both received and timed out"));
+    }
+
+    #[test]
+    fn test_inject_tendermint_merkle_proof() {
+        let packet = test_packet();
+        let client = MockTendermintClient {
+            value: packet.commitment(),
+        };
+
+        let mut recv_msgs = vec![recv_msg(packet)];
+        block_on(inject_tendermint_proofs(
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions::default(),
+        ))
+        .unwrap();
+
+        // The ICS-23 proofs are wrapped in a protobuf encoded `MerkleProof`.
+        let proof = MerkleProof::decode_vec(&recv_msgs[0].proof_commitment).unwrap();
+        assert_eq!(proof.proofs, vec![CommitmentProof::default()]);
+    }
+
+    #[test]
+    fn test_inject_tendermint_value_and_proof() {
+        let packet = test_packet();
+        let client = MockTendermintClient {
+            value: packet.commitment(),
+        };
+
+        let mut recv_msgs = vec![recv_msg(packet.clone())];
+        block_on(inject_tendermint_proofs(
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions {
+                proof_format: TendermintProofFormat::ValueAndProof,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        let value_and_proof: ValueAndProof =
+            serde_json::from_slice(&recv_msgs[0].proof_commitment).unwrap();
+        assert_eq!(value_and_proof.value.to_vec(), packet.commitment());
+        assert_eq!(
+            MerkleProof::decode_vec(&value_and_proof.proof).unwrap(),
+            MerkleProof {
+                proofs: vec![CommitmentProof::default()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_is_batch_empty() {
+        assert!(is_batch_empty(&[], &[], &[]));
+        assert!(!is_batch_empty(&[], &[], &[MsgTimeout::default()]));
+        assert!(!is_batch_empty(&[recv_msg(test_packet())], &[], &[]));
+        assert!(!is_batch_empty(&[], &[MsgAcknowledgement::default()], &[]));
+    }
+
+    #[test]
+    fn test_pack_messages() {
+        let packet = |sequence| Packet {
+            sequence,
+            ..test_packet()
+        };
+        let recv_msgs = (1..=3)
+            .map(|sequence| MsgRecvPacket {
+                proof_commitment: vec![0; 100],
+                ..recv_msg(packet(sequence))
+            })
+            .collect::<Vec<_>>();
+        let timeout_msgs = vec![MsgTimeout {
+            packet: Some(packet(4).into()),
+            proof_unreceived: vec![0; 100],
+            ..Default::default()
+        }];
+        let msg_len = recv_msgs[0].encoded_len();
+        let timeout_len = timeout_msgs[0].encoded_len();
+
+        let batches = pack_messages(recv_msgs.clone(), vec![], vec![], 2 * msg_len).unwrap();
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].recv_msgs, recv_msgs[..2]);
+        assert_eq!(batches[1].recv_msgs, recv_msgs[2..]);
+        assert!(batches
+            .iter()
+            .all(|batch| batch.encoded_len() <= 2 * msg_len));
+
+        // The timeout messages are packed first.
+        let batches = pack_messages(
+            recv_msgs.clone(),
+            vec![],
+            timeout_msgs.clone(),
+            msg_len.max(timeout_len),
+        )
+        .unwrap();
+        assert_eq!(batches.len(), 4);
+        assert_eq!(batches[0].timeout_msgs, timeout_msgs);
+        assert!(batches[0].recv_msgs.is_empty());
+
+        // A message is never split, and a message larger than the limit is an error.
+        let batches = pack_messages(recv_msgs.clone(), vec![], vec![], msg_len).unwrap();
+        assert_eq!(batches.len(), 3);
+        let err = pack_messages(recv_msgs, vec![], vec![], msg_len - 1).unwrap_err();
+        assert!(err.to_string().contains("packet 1"));
+
+        assert!(pack_messages(vec![], vec![], vec![], 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_expected_proof_count() {
+        let packet = test_packet();
+        let client = MockTendermintClient {
+            value: packet.commitment(),
+        };
+        let mut recv_msgs = vec![recv_msg(packet.clone()), recv_msg(packet)];
+        let mut timeout_msgs = vec![MsgTimeout::default()];
+
+        assert_eq!(expected_proof_count(&recv_msgs, &[], &timeout_msgs), 3);
+        assert!(ensure_proofs_injected(&recv_msgs, &[], &timeout_msgs).is_err());
+
+        block_on(inject_tendermint_proofs(
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions::default(),
+        ))
+        .unwrap();
+        assert!(ensure_proofs_injected(&recv_msgs, &[], &[]).is_ok());
+        // The timeout message has not been proved yet.
+        assert!(ensure_proofs_injected(&recv_msgs, &[], &timeout_msgs).is_err());
+
+        timeout_msgs[0].proof_unreceived = b"proof".to_vec();
+        assert!(ensure_proofs_injected(&recv_msgs, &[], &timeout_msgs).is_ok());
+    }
+
+    #[test]
+    fn test_inject_tendermint_proofs_typed_errors() {
+        fn inject_recv(client: &impl TendermintProofClient) -> ProofError {
+            let mut recv_msgs = vec![recv_msg(test_packet())];
+            block_on(inject_tendermint_proofs(
+                &mut recv_msgs,
+                &mut [],
+                &mut [],
+                client,
+                &Height::default(),
+                DEFAULT_COMMITMENT_PREFIX,
+                &TendermintProofOptions::default(),
+            ))
+            .unwrap_err()
+        }
+        let packet = test_packet();
+        let commitment_path = alloy::hex::encode(packet.commitment_path());
+
+        match inject_recv(&MockTendermintClient { value: vec![] }) {
+            ProofError::MembershipValueEmpty { path } => assert_eq!(path, commitment_path),
+            err => panic!("unexpected error: {err}"),
+        }
+        match inject_recv(&MockNonMembershipClient) {
+            ProofError::UnexpectedNonMembership { path } => assert_eq!(path, commitment_path),
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_inject_tendermint_recv_proof_commitment_mismatch() {
+        let client = MockTendermintClient {
+            value: b"not the commitment".to_vec(),
+        };
+
+        let mut recv_msgs = vec![recv_msg(test_packet())];
+        let err = block_on(inject_tendermint_proofs(
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions::default(),
+        ))
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("does not match the packet commitment"));
+    }
+
+    #[test]
+    fn test_inject_tendermint_timeout_with_receipt() {
+        let client = MockTendermintClient {
+            value: b"receipt".to_vec(),
+        };
+
+        let mut timeout_msgs = vec![MsgTimeout {
+            packet: Some(test_packet().into()),
+            ..Default::default()
+        }];
+        let err = block_on(inject_tendermint_proofs(
+            &mut [],
+            &mut [],
+            &mut timeout_msgs,
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions::default(),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("cannot be timed out"));
+        assert!(timeout_msgs[0].proof_unreceived.is_empty());
+    }
+
+    #[test]
+    fn test_prove_membership_advancing_height() {
+        let path = abci_query_path(
+            &test_packet(),
+            ProofKind::Commitment,
+            DEFAULT_COMMITMENT_PREFIX,
+        );
+        let client = MockCommittingClient {
+            committed_at: 20,
+            latest_height: 20,
+            proved_heights: Mutex::default(),
+        };
+
+        let (height, value, _) = block_on(prove_membership_advancing_height(
+            &client, &client, &path, 10, None,
+        ))
+        .unwrap();
+        assert_eq!((height, value), (20, b"value".to_vec()));
+        assert_eq!(*client.proved_heights.lock().unwrap(), vec![10, 20]);
+
+        // The value committed at the start height is proved there.
+        let (height, _, _) = block_on(prove_membership_advancing_height(
+            &client, &client, &path, 20, None,
+        ))
+        .unwrap();
+        assert_eq!(height, 20);
+
+        // The height is never advanced past the latest height.
+        assert!(block_on(prove_membership_advancing_height(
+            &client, &client, &path, 21, None
+        ))
+        .is_err());
+        let uncommitted = MockCommittingClient {
+            committed_at: 30,
+            latest_height: 20,
+            proved_heights: Mutex::default(),
+        };
+        assert!(block_on(prove_membership_advancing_height(
+            &uncommitted,
+            &uncommitted,
+            &path,
+            10,
+            None
+        ))
+        .is_err());
+        assert_eq!(*uncommitted.proved_heights.lock().unwrap(), vec![10, 20]);
+    }
+
+    #[test]
+    fn test_latest_provable_height() {
+        let path = abci_query_path(
+            &test_packet(),
+            ProofKind::Commitment,
+            DEFAULT_COMMITMENT_PREFIX,
+        );
+        let client = |provable_height| MockUncommittedHeightClient {
+            latest_height: 20,
+            provable_height,
+            proved_heights: Mutex::default(),
+        };
+
+        // The proofs at the latest height are not committed yet, so the height before is used.
+        let uncommitted = client(19);
+        let height = block_on(latest_provable_height(&uncommitted, &path, 1, None)).unwrap();
+        assert_eq!(
+            height,
+            Height {
+                revision_number: 1,
+                revision_height: 19,
+            }
+        );
+        assert_eq!(*uncommitted.proved_heights.lock().unwrap(), vec![20, 19]);
+
+        let committed = client(20);
+        let height = block_on(latest_provable_height(&committed, &path, 1, None)).unwrap();
+        assert_eq!(height.revision_height, 20);
+        assert_eq!(*committed.proved_heights.lock().unwrap(), vec![20]);
+
+        assert!(block_on(latest_provable_height(&client(18), &path, 1, None)).is_err());
+    }
+
+    #[test]
+    fn test_prove_membership_read_after_write() {
+        let path = abci_query_path(
+            &test_packet(),
+            ProofKind::Commitment,
+            DEFAULT_COMMITMENT_PREFIX,
+        );
+        // The replica becomes consistent with the write after two queries.
+        let replica = MockReplicaClient {
+            stale_queries: AtomicUsize::new(2),
+            queries: AtomicUsize::new(0),
+        };
+
+        let (value, _) = block_on(prove_membership_read_after_write(
+            &replica,
+            &path,
+            10,
+            3,
+            Duration::from_millis(1),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(value, b"value");
+        assert_eq!(replica.queries.load(Ordering::SeqCst), 3);
+
+        let lagging_replica = MockReplicaClient {
+            stale_queries: AtomicUsize::new(5),
+            queries: AtomicUsize::new(0),
+        };
+        assert!(block_on(prove_membership_read_after_write(
+            &lagging_replica,
+            &path,
+            10,
+            3,
+            Duration::from_millis(1),
+            None,
+        ))
+        .is_err());
+        assert_eq!(lagging_replica.queries.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_inject_proofs_on_local_executor() {
+        let timeout_msgs = |sequences: [u64; 2]| {
+            sequences.map(|sequence| MsgTimeout {
+                packet: Some(
+                    Packet {
+                        sequence,
+                        ..test_packet()
+                    }
+                    .into(),
+                ),
+                ..Default::default()
+            })
+        };
+        let (mut first_msgs, mut second_msgs) = (timeout_msgs([1, 2]), timeout_msgs([3, 4]));
+        let client = MockConcurrencyClient::default();
+
+        let mut pool = LocalPool::new();
+        let (first, second) = pool.run_until(future::join(
+            inject_tendermint_proofs(
+                &mut [],
+                &mut [],
+                &mut first_msgs,
+                &client,
+                &Height::default(),
+                DEFAULT_COMMITMENT_PREFIX,
+                &TendermintProofOptions::default(),
+            ),
+            inject_tendermint_proofs(
+                &mut [],
+                &mut [],
+                &mut second_msgs,
+                &client,
+                &Height::default(),
+                DEFAULT_COMMITMENT_PREFIX,
+                &TendermintProofOptions::default(),
+            ),
+        ));
+        first.unwrap();
+        second.unwrap();
+
+        // Both injections were in progress at the same time on the single threaded executor.
+        assert!(client.max_in_flight.load(Ordering::SeqCst) > 2);
+        assert!(first_msgs
+            .iter()
+            .chain(&second_msgs)
+            .all(|msg| !msg.proof_unreceived.is_empty()));
+    }
+
+    #[test]
+    fn test_audit_query_key() {
+        let packet = test_packet();
+        assert_eq!(
+            audit_query_key(&packet, ProofKind::Commitment, DEFAULT_COMMITMENT_PREFIX),
+            format!(
+                "ibc/{}",
+                alloy::hex::encode(b"07-tendermint-0\x01\0\0\0\0\0\0\0\x01")
+            )
+        );
+
+        // The key is the one queried by the proof injection.
+        let mut timeout_msgs = vec![MsgTimeout {
+            packet: Some(packet.clone().into()),
+            ..Default::default()
+        }];
+        block_on(inject_tendermint_proofs(
+            &mut [],
+            &mut [],
+            &mut timeout_msgs,
+            &MockNonMembershipClient,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions::default(),
+        ))
+        .unwrap();
+        let proof = MerkleProof::decode_vec(&timeout_msgs[0].proof_unreceived).unwrap();
+        let [non_exist, root] = proof.proofs.try_into().unwrap();
+        let (
+            Some(commitment_proof::Proof::Nonexist(non_exist)),
+            Some(commitment_proof::Proof::Exist(root)),
+        ) = (non_exist.proof, root.proof)
+        else {
+            panic!("unexpected proofs");
+        };
+        assert_eq!(
+            audit_query_key(&packet, ProofKind::Receipt, DEFAULT_COMMITMENT_PREFIX),
+            format!(
+                "{}/{}",
+                String::from_utf8(root.key).unwrap(),
+                alloy::hex::encode(non_exist.key)
+            )
+        );
+    }
+
+    #[test]
+    fn test_abci_query_path() {
+        let packet = test_packet();
+        assert_eq!(
+            abci_query_path(
+                &packet,
+                ProofKind::Acknowledgement,
+                DEFAULT_COMMITMENT_PREFIX
+            ),
+            vec![b"ibc".to_vec(), packet.ack_commitment_path()]
+        );
+        assert_eq!(
+            abci_query_path(&packet, ProofKind::Commitment, b"custom"),
+            vec![b"custom".to_vec(), packet.commitment_path()]
+        );
+
+        // The non-membership proof is generated for the key of the query path, under the custom
+        // commitment prefix.
+        let mut timeout_msgs = vec![MsgTimeout {
+            packet: Some(packet.clone().into()),
+            ..Default::default()
+        }];
+        block_on(inject_tendermint_proofs(
+            &mut [],
+            &mut [],
+            &mut timeout_msgs,
+            &MockNonMembershipClient,
+            &Height::default(),
+            b"custom",
+            &TendermintProofOptions::default(),
+        ))
+        .unwrap();
+
+        let proof = MerkleProof::decode_vec(&timeout_msgs[0].proof_unreceived).unwrap();
+        let keys = proof
+            .proofs
+            .into_iter()
+            .map(|proof| match proof.proof {
+                Some(commitment_proof::Proof::Nonexist(non_exist)) => non_exist.key,
+                Some(commitment_proof::Proof::Exist(exist)) => exist.key,
+                _ => unreachable!(),
+            })
+            .rev()
+            .collect::<Vec<_>>();
+        assert_eq!(keys[0], b"custom".to_vec());
+        assert_eq!(
+            keys,
+            abci_query_path(&packet, ProofKind::Receipt, b"custom")
+        );
+    }
+
+    #[test]
+    fn test_prove_batch_non_membership() {
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 10,
+        };
+        let packets = [
+            test_packet(),
+            Packet {
+                sequence: 2,
+                ..test_packet()
+            },
+        ];
+        let mut timeout_msgs = packets
+            .iter()
+            .map(|packet| MsgTimeout {
+                packet: Some(packet.clone().into()),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        block_on(prove_batch_non_membership(
+            &mut timeout_msgs,
+            &MockNonMembershipClient,
+            &target_height,
+            DEFAULT_COMMITMENT_PREFIX,
+            DEFAULT_MAX_PROOF_CONCURRENCY,
+            None,
+        ))
+        .unwrap();
+
+        assert_eq!(
+            timeout_msgs[0].proof_unreceived,
+            timeout_msgs[1].proof_unreceived
+        );
+        assert!(timeout_msgs
+            .iter()
+            .all(|msg| msg.proof_height == Some(target_height)));
+
+        // An empty proof is rejected rather than indexed.
+        assert!(block_on(prove_batch_non_membership(
+            &mut timeout_msgs.clone(),
+            &MockEmptyProofClient,
+            &target_height,
+            DEFAULT_COMMITMENT_PREFIX,
+            DEFAULT_MAX_PROOF_CONCURRENCY,
+            None,
+        ))
+        .is_err());
+        assert!(block_on(prove_batch_non_membership(
+            &mut timeout_msgs.clone(),
+            &MockNonMembershipClient,
+            &target_height,
+            DEFAULT_COMMITMENT_PREFIX,
+            0,
+            None,
+        ))
+        .is_err());
+
+        let batch_proof = MerkleProof::decode_vec(&timeout_msgs[0].proof_unreceived).unwrap();
+        assert_eq!(batch_proof.proofs.len(), 2);
+        let Some(commitment_proof::Proof::Batch(batch)) = &batch_proof.proofs[0].proof else {
+            panic!("expected a batch proof");
+        };
+        let keys = batch
+            .entries
+            .iter()
+            .map(|entry| match &entry.proof {
+                Some(batch_entry::Proof::Nonexist(non_exist)) => non_exist.key.clone(),
+                _ => panic!("expected a non-existence proof"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            packets
+                .iter()
+                .map(Packet::receipt_commitment_path)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_compress_timeout_sequences() {
+        let timeout_msgs = [5, 1, 2, 3, 9, 2, 10, 7]
+            .into_iter()
+            .map(|sequence| MsgTimeout {
+                packet: Some(
+                    Packet {
+                        sequence,
+                        ..test_packet()
+                    }
+                    .into(),
+                ),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            compress_timeout_sequences(&timeout_msgs),
+            vec![1..=3, 5..=5, 7..=7, 9..=10]
+        );
+        assert!(compress_timeout_sequences(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_group_timeouts_by_source() {
+        let timeout_msg = |source_client: &str, sequence| MsgTimeout {
+            packet: Some(
+                Packet {
+                    sequence,
+                    sourceClient: source_client.to_string(),
+                    ..test_packet()
+                }
+                .into(),
+            ),
+            ..Default::default()
+        };
+        let timeout_msgs = vec![
+            timeout_msg("07-tendermint-0", 1),
+            timeout_msg("07-tendermint-1", 2),
+            timeout_msg("07-tendermint-0", 3),
+        ];
+
+        let groups = group_timeouts_by_source(timeout_msgs.clone());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(
+            groups["07-tendermint-0"],
+            vec![timeout_msgs[0].clone(), timeout_msgs[2].clone()]
+        );
+        assert_eq!(groups["07-tendermint-1"], vec![timeout_msgs[1].clone()]);
+        assert!(group_timeouts_by_source(vec![]).is_empty());
+    }
+
+    #[test]
+    fn test_inject_tendermint_proofs_deadline_exceeded() {
+        let client = MockDelayedClient {
+            delayed_sequence: 2,
+            delay: Duration::from_secs(10),
+        };
+        let mut timeout_msgs = [1, 2]
+            .into_iter()
+            .map(|sequence| MsgTimeout {
+                packet: Some(
+                    Packet {
+                        sequence,
+                        ..test_packet()
+                    }
+                    .into(),
+                ),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+
+        let err = block_on(inject_tendermint_proofs(
+            &mut [],
+            &mut [],
+            &mut timeout_msgs,
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions {
+                batch_deadline: Some(Instant::now() + Duration::from_millis(100)),
+                ..Default::default()
+            },
+        ))
+        .unwrap_err();
+        assert!(matches!(err, ProofError::DeadlineExceeded { completed: 1 }));
+    }
+
+    #[test]
+    fn test_inject_tendermint_proofs_cancelled() {
+        let client = MockDelayedClient {
+            delayed_sequence: 2,
+            delay: Duration::from_secs(10),
+        };
+        let timeout_msg = |sequence| MsgTimeout {
+            packet: Some(
+                Packet {
+                    sequence,
+                    ..test_packet()
+                }
+                .into(),
+            ),
+            ..Default::default()
+        };
+        let mut timeout_msgs = vec![timeout_msg(1), timeout_msg(2), timeout_msg(3)];
+        let cancellation = CancellationToken::new();
+        cancellation.cancel();
+
+        let started = Instant::now();
+        let err = block_on(inject_tendermint_proofs(
+            &mut [],
+            &mut [],
+            &mut timeout_msgs,
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions {
+                cancellation: Some(&cancellation),
+                ..Default::default()
+            },
+        ))
+        .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        match err {
+            ProofError::Cancelled { completed } => assert_eq!(completed, vec![0, 2]),
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_events_to_msgs_with_zero_sequence() {
+        let zero_sequence_packet = Packet {
+            sequence: 0,
+            ..test_packet()
+        };
+        let send_event = EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(zero_sequence_packet.clone()),
+            block_number: None,
+            block_timestamp: None,
+        };
+        let ack_event = EurekaEventWithHeight {
+            event: EurekaEvent::WriteAcknowledgement(
+                Packet {
+                    sourceClient: "08-wasm-0".to_string(),
+                    destClient: "07-tendermint-0".to_string(),
+                    ..zero_sequence_packet
+                },
+                vec![Bytes::from_static(b"ack")],
+            ),
+            block_number: None,
+            block_timestamp: None,
+        };
+
+        let recv_err = src_events_to_recv_and_ack_msgs(
+            vec![send_event.clone()],
+            "07-tendermint-0",
+            "08-wasm-0",
+            &[],
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(0),
+            &MsgFilterOptions::default(),
+        )
+        .unwrap_err();
+        assert!(recv_err.to_string().contains("invalid sequence 0"));
+        assert!(src_events_to_recv_and_ack_msgs(
+            vec![ack_event],
+            "07-tendermint-0",
+            "08-wasm-0",
+            &[],
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(0),
+            &MsgFilterOptions::default()
+        )
+        .is_err());
+        assert!(target_events_to_timeout_msgs(
+            vec![send_event.clone()],
+            "08-wasm-0",
+            "07-tendermint-0",
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(u64::MAX),
+            &TimeoutFilterOptions::default()
+        )
+        .is_err());
+
+        // Packets that are filtered out are not rejected.
+        let (recv_msgs, _, _) = src_events_to_recv_and_ack_msgs(
+            vec![send_event],
+            "07-tendermint-1",
+            "08-wasm-0",
+            &[],
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(0),
+            &MsgFilterOptions::default(),
+        )
+        .unwrap();
+        assert!(recv_msgs.is_empty());
+    }
+
+    #[test]
+    fn test_drop_received_timeouts() {
+        let timeout_msgs = [1, 2, 3].map(|sequence| MsgTimeout {
+            packet: Some(
+                Packet {
+                    sequence,
+                    ..test_packet()
+                }
+                .into(),
+            ),
+            ..Default::default()
+        });
+        let received = HashSet::from([Packet {
+            sequence: 2,
+            ..test_packet()
+        }
+        .receipt_commitment_path()]);
+        let query_fn = |received: &HashSet<Vec<u8>>, path: Vec<u8>| {
+            let is_received = received.contains(&path);
+            async move { anyhow::Ok(is_received) }
+        };
+
+        let unreceived = block_on(drop_received_timeouts(
+            timeout_msgs.to_vec(),
+            &received,
+            query_fn,
+            2,
+        ))
+        .unwrap();
+        assert_eq!(
+            unreceived,
+            vec![timeout_msgs[0].clone(), timeout_msgs[2].clone()]
+        );
+
+        // A timeout without a packet has no receipt to query.
+        assert!(block_on(drop_received_timeouts(
+            vec![MsgTimeout::default()],
+            &received,
+            query_fn,
+            2
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_filter_unreceived_packets() {
+        let packets = [1, 2, 3].map(|sequence| Packet {
+            sequence,
+            ..test_packet()
+        });
+        let received = HashSet::from([packets[1].receipt_commitment_path()]);
+        let query_fn = |received: &HashSet<Vec<u8>>, path: Vec<u8>| {
+            let is_received = received.contains(&path);
+            async move { anyhow::Ok(is_received) }
+        };
+
+        let unreceived = block_on(filter_unreceived_packets(
+            packets.to_vec(),
+            &received,
+            query_fn,
+            2,
+        ))
+        .unwrap();
+        assert_eq!(unreceived, vec![packets[0].clone(), packets[2].clone()]);
+
+        assert!(block_on(filter_unreceived_packets(
+            packets.to_vec(),
+            &received,
+            query_fn,
+            0
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_tendermint_receipt_exists() {
+        let packet = test_packet();
+        let client = MockTendermintClient { value: vec![1] };
+        assert!(block_on(tendermint_receipt_exists(
+            &client,
+            packet.receipt_commitment_path(),
+            DEFAULT_COMMITMENT_PREFIX,
+            10
+        ))
+        .unwrap());
+        assert!(!block_on(tendermint_receipt_exists(
+            &MockNonMembershipClient,
+            packet.receipt_commitment_path(),
+            DEFAULT_COMMITMENT_PREFIX,
+            10
+        ))
+        .unwrap());
+    }
+
+    #[test]
+    fn test_cancel_received_timeouts() {
+        let timeout = |sequence| MsgTimeout {
+            packet: Some(
+                Packet {
+                    sequence,
+                    ..test_packet()
+                }
+                .into(),
+            ),
+            ..Default::default()
+        };
+        let mut timeouts = vec![timeout(1), timeout(2), timeout(3)];
+
+        cancel_received_timeouts(&mut timeouts, &HashSet::from([2, 4]));
+        assert_eq!(timeouts, vec![timeout(1), timeout(3)]);
+
+        cancel_received_timeouts(&mut timeouts, &HashSet::new());
+        assert_eq!(timeouts.len(), 2);
+    }
+
+    #[test]
+    fn test_timeout_from_packet_data() {
+        let packet = test_packet();
+        let send_event = EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(packet.clone()),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 10,
+        };
+        let data = PacketData {
+            sequence: packet.sequence,
+            dest_client: packet.destClient.clone(),
+            timeout_timestamp: packet.timeoutTimestamp,
+            payloads: packet.payloads,
+        };
+
+        let timeout_msg =
+            timeout_from_packet_data(&data, &packet.sourceClient, &target_height, "signer");
+        let from_event = target_events_to_timeout_msgs(
+            vec![send_event],
+            "08-wasm-0",
+            "07-tendermint-0",
+            &[],
+            &target_height,
+            "signer",
+            None,
+            &MockClock::new(u64::MAX),
+            &TimeoutFilterOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(vec![timeout_msg], from_event);
+    }
+
+    #[test]
+    fn test_timeout_boundary() {
+        let packet = test_packet();
+        let send_event = EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(packet.clone()),
+            block_number: None,
+            block_timestamp: None,
+        };
+        let clock = MockClock::new(packet.timeoutTimestamp - 1);
+        let to_msgs = |clock: &MockClock| {
+            let (recv_msgs, _, _) = src_events_to_recv_and_ack_msgs(
+                vec![send_event.clone()],
+                &packet.sourceClient,
+                &packet.destClient,
+                &[],
+                &[],
+                &Height::default(),
+                "signer",
+                None,
+                clock,
+                &MsgFilterOptions::default(),
+            )
+            .unwrap();
+            let timeout_msgs = target_events_to_timeout_msgs(
+                vec![send_event.clone()],
+                &packet.destClient,
+                &packet.sourceClient,
+                &[],
+                &Height::default(),
+                "signer",
+                None,
+                clock,
+                &TimeoutFilterOptions::default(),
+            )
+            .unwrap();
+            (recv_msgs.len(), timeout_msgs.len())
+        };
+
+        // Before its timeout, the packet is only received.
+        assert_eq!(to_msgs(&clock), (1, 0));
+        // At its timeout, the packet can no longer be received and is only timed out.
+        clock.set(packet.timeoutTimestamp);
+        assert_eq!(to_msgs(&clock), (0, 1));
+        clock.set(packet.timeoutTimestamp + 1);
+        assert_eq!(to_msgs(&clock), (0, 1));
+    }
+
+    #[test]
+    fn test_backlog_depth() {
+        let send_event = |source_client: &str, sequence| EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(Packet {
+                sourceClient: source_client.to_string(),
+                sequence,
+                ..test_packet()
+            }),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let events = [
+            send_event("07-tendermint-0", 1),
+            send_event("07-tendermint-0", 2),
+            send_event("07-tendermint-0", 2),
+            send_event("07-tendermint-0", 3),
+            send_event("07-tendermint-1", 1),
+            send_event("07-tendermint-2", 4),
+            EurekaEventWithHeight {
+                event: EurekaEvent::WriteAcknowledgement(test_packet(), vec![]),
+                block_number: Some(10),
+                block_timestamp: None,
+            },
+        ];
+
+        assert_eq!(
+            backlog_depth(&events, &HashSet::from([1, 3])),
+            HashMap::from([
+                ("07-tendermint-0".to_string(), 1),
+                ("07-tendermint-2".to_string(), 1),
+            ])
+        );
+        assert_eq!(
+            backlog_depth(&events, &HashSet::new()),
+            HashMap::from([
+                ("07-tendermint-0".to_string(), 3),
+                ("07-tendermint-1".to_string(), 1),
+                ("07-tendermint-2".to_string(), 1),
+            ])
+        );
+        assert!(backlog_depth(&events, &HashSet::from([1, 2, 3, 4])).is_empty());
+    }
+
+    #[test]
+    fn test_target_events_to_ack_msgs() {
+        let packet = test_packet();
+        let ack_event = |packet: Packet| EurekaEventWithHeight {
+            event: EurekaEvent::WriteAcknowledgement(
+                packet,
+                vec![Bytes::from_static(b"ack-1"), Bytes::from_static(b"ack-2")],
+            ),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let events = vec![
+            ack_event(packet.clone()),
+            // Not a packet between the clients.
+            ack_event(Packet {
+                destClient: "08-wasm-1".to_string(),
+                ..test_packet()
+            }),
+            // The send events of the target chain are not acknowledged.
+            EurekaEventWithHeight {
+                event: EurekaEvent::SendPacket(Packet {
+                    sequence: 2,
+                    ..test_packet()
+                }),
+                block_number: Some(10),
+                block_timestamp: None,
+            },
+            ack_event(packet.clone()),
+        ];
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 42,
+        };
+
+        let ack_msgs = target_events_to_ack_msgs(
+            events.clone(),
+            &packet.sourceClient,
+            &packet.destClient,
+            &[],
+            &target_height,
+            "signer",
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            ack_msgs,
+            vec![MsgAcknowledgement {
+                packet: Some(packet.clone().into()),
+                acknowledgement: Some(Acknowledgement {
+                    app_acknowledgements: vec![b"ack-1".to_vec(), b"ack-2".to_vec()],
+                }),
+                proof_height: Some(target_height),
+                proof_acked: vec![],
+                signer: "signer".to_string(),
+            }]
+        );
+        // The acknowledgement is proved at the same path as the ones of the source chain.
+        let msg_packet = Packet::from(ack_msgs[0].packet.clone().unwrap());
+        assert_eq!(packet_paths(&msg_packet).ack, packet.ack_commitment_path());
+
+        let split = target_events_to_ack_msgs(
+            events.clone(),
+            &packet.sourceClient,
+            &packet.destClient,
+            &[],
+            &target_height,
+            "signer",
+            None,
+            Some(1),
+        )
+        .unwrap();
+        assert_eq!(split.len(), 2);
+
+        assert!(target_events_to_ack_msgs(
+            events.clone(),
+            &packet.sourceClient,
+            &packet.destClient,
+            &[2],
+            &target_height,
+            "signer",
+            None,
+            None,
+        )
+        .unwrap()
+        .is_empty());
+        assert!(target_events_to_ack_msgs(
+            events,
+            &packet.sourceClient,
+            &packet.destClient,
+            &[],
+            &target_height,
+            "signer",
+            None,
+            Some(0),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_src_events_to_recv_and_ack_msgs_dedupes_packets() {
+        let send_event = EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(test_packet()),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let ack_event = EurekaEventWithHeight {
+            event: EurekaEvent::WriteAcknowledgement(
+                Packet {
+                    sourceClient: "08-wasm-0".to_string(),
+                    destClient: "07-tendermint-0".to_string(),
+                    ..test_packet()
+                },
+                vec![Bytes::from_static(b"ack")],
+            ),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+
+        let (recv_msgs, ack_msgs, _) = src_events_to_recv_and_ack_msgs(
+            vec![send_event.clone(), ack_event.clone(), send_event, ack_event],
+            "07-tendermint-0",
+            "08-wasm-0",
+            &[],
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(0),
+            &MsgFilterOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(recv_msgs.len(), 1);
+        assert_eq!(ack_msgs.len(), 1);
+    }
+
+    #[test]
+    fn test_build_relay_messages() {
+        let event = |event| EurekaEventWithHeight {
+            event,
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let sent_packet = Packet {
+            timeoutTimestamp: 2_000_000_000,
+            ..test_packet()
+        };
+        let acked_packet = Packet {
+            sourceClient: "08-wasm-0".to_string(),
+            destClient: "07-tendermint-0".to_string(),
+            sequence: 2,
+            ..test_packet()
+        };
+        let timed_out_packet = Packet {
+            sequence: 3,
+            ..acked_packet.clone()
+        };
+        let src_events = vec![
+            event(EurekaEvent::SendPacket(sent_packet)),
+            event(EurekaEvent::WriteAcknowledgement(
+                acked_packet,
+                vec![vec![1].into()],
+            )),
+        ];
+        let target_events = vec![
+            event(EurekaEvent::SendPacket(timed_out_packet.clone())),
+            event(EurekaEvent::SendPacket(timed_out_packet)),
+        ];
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 42,
+        };
+        let params = RelayParams {
+            src_client_id: "07-tendermint-0",
+            dst_client_id: "08-wasm-0",
+            src_packet_seqs: &[],
+            dst_packet_seqs: &[],
+            target_height: &target_height,
+            signer_address: "signer",
+            clock: &MockClock::new(1_800_000_000),
+            validate_packet_data: None,
+            packet_filter: None,
+            sequence_range: None,
+            max_acks_per_msg: None,
+        };
+
+        let msgs = build_relay_messages(src_events, target_events, &params).unwrap();
+        assert_eq!(msgs.recv_msgs.len(), 1);
+        assert_eq!(msgs.ack_msgs.len(), 1);
+        // The duplicated timeout is only built once.
+        assert_eq!(msgs.timeout_msgs.len(), 1);
+        assert_eq!(msgs.timeout_msgs[0].packet.as_ref().unwrap().sequence, 3);
+        assert_eq!(msgs.mismatched_packets, 0);
+        for (proof_height, signer) in msgs
+            .recv_msgs
+            .iter()
+            .map(|msg| (msg.proof_height, &msg.signer))
+            .chain(
+                msgs.ack_msgs
+                    .iter()
+                    .map(|msg| (msg.proof_height, &msg.signer)),
+            )
+            .chain(
+                msgs.timeout_msgs
+                    .iter()
+                    .map(|msg| (msg.proof_height, &msg.signer)),
+            )
+        {
+            assert_eq!(proof_height, Some(target_height));
+            assert_eq!(signer, "signer");
+        }
+    }
+
+    #[test]
+    fn test_events_to_msgs_signer_for() {
+        let event = |event| EurekaEventWithHeight {
+            event,
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let src_events = vec![
+            event(EurekaEvent::SendPacket(test_packet())),
+            event(EurekaEvent::SendPacket(Packet {
+                sequence: 2,
+                ..test_packet()
+            })),
+            event(EurekaEvent::WriteAcknowledgement(
+                Packet {
+                    sourceClient: "08-wasm-0".to_string(),
+                    destClient: "07-tendermint-0".to_string(),
+                    ..test_packet()
+                },
+                vec![vec![1].into(), vec![2].into()],
+            )),
+        ];
+        let signers = ["signer-0", "signer-1"];
+        let mut next_signer = 0;
+        let mut round_robin = |_: &Packet| {
+            next_signer += 1;
+            signers[(next_signer - 1) % signers.len()].to_string()
+        };
+
+        let (recv_msgs, ack_msgs, _) = src_events_to_recv_and_ack_msgs(
+            src_events,
+            "07-tendermint-0",
+            "08-wasm-0",
+            &[],
+            &[],
+            &Height::default(),
+            "signer",
+            Some(&mut round_robin),
+            &MockClock::new(0),
+            &MsgFilterOptions {
+                max_acks_per_msg: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let recv_signers = recv_msgs
+            .iter()
+            .map(|msg| msg.signer.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(recv_signers, vec!["signer-0", "signer-1"]);
+        // The acknowledgements of a packet split across messages share its signer.
+        assert_eq!(ack_msgs.len(), 2);
+        assert!(ack_msgs.iter().all(|msg| msg.signer == "signer-0"));
+
+        let target_events = vec![event(EurekaEvent::SendPacket(Packet {
+            sourceClient: "08-wasm-0".to_string(),
+            destClient: "07-tendermint-0".to_string(),
+            sequence: 7,
+            ..test_packet()
+        }))];
+        let timeout_msgs = |signer_for: Option<&mut dyn FnMut(&Packet) -> String>| {
+            target_events_to_timeout_msgs(
+                target_events.clone(),
+                "07-tendermint-0",
+                "08-wasm-0",
+                &[],
+                &Height::default(),
+                "signer",
+                signer_for,
+                &MockClock::new(u64::MAX),
+                &TimeoutFilterOptions::default(),
+            )
+            .unwrap()
+        };
+        let mut by_sequence = |packet: &Packet| format!("signer-{}", packet.sequence);
+        assert_eq!(timeout_msgs(Some(&mut by_sequence))[0].signer, "signer-7");
+        // Without a selection, every message is signed by the signer address.
+        assert_eq!(timeout_msgs(None)[0].signer, "signer");
+    }
+
+    #[test]
+    fn test_events_to_msgs_sequence_range() {
+        let events = |packet: Packet| {
+            (1..=5)
+                .map(|sequence| EurekaEventWithHeight {
+                    event: EurekaEvent::SendPacket(Packet {
+                        sequence,
+                        ..packet.clone()
+                    }),
+                    block_number: Some(10),
+                    block_timestamp: None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let recv_sequences = |sequence_range| {
+            let (recv_msgs, _, _) = src_events_to_recv_and_ack_msgs(
+                events(test_packet()),
+                "07-tendermint-0",
+                "08-wasm-0",
+                &[],
+                &[],
+                &Height::default(),
+                "signer",
+                None,
+                &MockClock::new(0),
+                &MsgFilterOptions {
+                    sequence_range,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            recv_msgs
+                .iter()
+                .map(|msg| msg.packet.as_ref().unwrap().sequence)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(recv_sequences(None), vec![1, 2, 3, 4, 5]);
+        assert_eq!(recv_sequences(Some(2..=3)), vec![2, 3]);
+        assert_eq!(recv_sequences(Some(4..=4)), vec![4]);
+
+        let target_packet = Packet {
+            sourceClient: "08-wasm-0".to_string(),
+            destClient: "07-tendermint-0".to_string(),
+            ..test_packet()
+        };
+        let timeout_msgs = target_events_to_timeout_msgs(
+            events(target_packet),
+            "07-tendermint-0",
+            "08-wasm-0",
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(u64::MAX),
+            &TimeoutFilterOptions {
+                sequence_range: Some(5..=10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(timeout_msgs.len(), 1);
+        assert_eq!(timeout_msgs[0].packet.as_ref().unwrap().sequence, 5);
+    }
+
+    #[test]
+    fn test_src_events_to_recv_and_ack_msgs_mismatched_counterparty() {
+        let send_event = |source_client: &str| EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(Packet {
+                sourceClient: source_client.to_string(),
+                ..test_packet()
+            }),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let ack_event = |dest_client: &str| EurekaEventWithHeight {
+            event: EurekaEvent::WriteAcknowledgement(
+                Packet {
+                    sourceClient: "08-wasm-0".to_string(),
+                    destClient: dest_client.to_string(),
+                    ..test_packet()
+                },
+                vec![Bytes::from_static(b"ack")],
+            ),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+
+        let (recv_msgs, ack_msgs, mismatched_packets) = src_events_to_recv_and_ack_msgs(
+            vec![
+                send_event("07-tendermint-0"),
+                send_event("07-tendermint-5"),
+                ack_event("07-tendermint-0"),
+                ack_event("07-tendermint-5"),
+                // The duplicate events of the mismatched packets are not counted again.
+                send_event("07-tendermint-5"),
+                ack_event("07-tendermint-5"),
+                // A packet to another client is not relayed by this pair of clients at all.
+                EurekaEventWithHeight {
+                    event: EurekaEvent::SendPacket(Packet {
+                        destClient: "08-wasm-1".to_string(),
+                        ..test_packet()
+                    }),
+                    block_number: Some(10),
+                    block_timestamp: None,
+                },
+            ],
+            "07-tendermint-0",
+            "08-wasm-0",
+            &[],
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(0),
+            &MsgFilterOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(recv_msgs.len(), 1);
+        assert_eq!(
+            recv_msgs[0].packet.as_ref().unwrap().source_client,
+            "07-tendermint-0"
+        );
+        assert_eq!(ack_msgs.len(), 1);
+        assert_eq!(
+            ack_msgs[0].packet.as_ref().unwrap().destination_client,
+            "07-tendermint-0"
+        );
+        assert_eq!(mismatched_packets, 2);
+    }
+
+    #[test]
+    fn test_src_events_to_ack_msgs_split_acks() {
+        let acks =
+            ["ack-0", "ack-1", "ack-2", "ack-3"].map(|ack| Bytes::from_static(ack.as_bytes()));
+        let ack_event = EurekaEventWithHeight {
+            event: EurekaEvent::WriteAcknowledgement(
+                Packet {
+                    sourceClient: "08-wasm-0".to_string(),
+                    destClient: "07-tendermint-0".to_string(),
+                    ..test_packet()
+                },
+                acks.to_vec(),
+            ),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let to_ack_msgs = |max_acks_per_msg| {
+            src_events_to_recv_and_ack_msgs(
+                vec![ack_event.clone()],
+                "07-tendermint-0",
+                "08-wasm-0",
+                &[],
+                &[],
+                &Height::default(),
+                "signer",
+                None,
+                &MockClock::new(0),
+                &MsgFilterOptions {
+                    max_acks_per_msg,
+                    ..Default::default()
+                },
+            )
+            .map(|(_, ack_msgs, _)| {
+                ack_msgs
+                    .into_iter()
+                    .map(|msg| msg.acknowledgement.unwrap().app_acknowledgements)
+                    .collect::<Vec<_>>()
+            })
+        };
+        let acks = acks.map(|ack| ack.to_vec());
+
+        assert_eq!(to_ack_msgs(None).unwrap(), vec![acks.to_vec()]);
+        // The acknowledgements are split exactly on the boundary.
+        assert_eq!(to_ack_msgs(Some(4)).unwrap(), vec![acks.to_vec()]);
+        assert_eq!(
+            to_ack_msgs(Some(2)).unwrap(),
+            vec![acks[..2].to_vec(), acks[2..].to_vec()]
+        );
+        assert_eq!(
+            to_ack_msgs(Some(3)).unwrap(),
+            vec![acks[..3].to_vec(), acks[3..].to_vec()]
+        );
+        assert!(to_ack_msgs(Some(0)).is_err());
+    }
+
+    #[test]
+    fn test_src_events_to_recv_msgs_with_invalid_data() {
+        let send_event = |data: &'static [u8]| EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(Packet {
+                payloads: vec![Payload {
+                    sourcePort: "transfer".to_string(),
+                    destPort: "transfer".to_string(),
+                    version: "ics20-1".to_string(),
+                    encoding: "application/json".to_string(),
+                    value: Bytes::from_static(data),
+                }],
+                ..test_packet()
+            }),
+            block_number: None,
+            block_timestamp: None,
+        };
+        let validate_json = |data: &[u8]| -> anyhow::Result<()> {
+            serde_json::from_slice::<serde_json::Value>(data)?;
+            Ok(())
+        };
+
+        let (recv_msgs, ack_msgs, _) = src_events_to_recv_and_ack_msgs(
+            vec![send_event(br#"{"amount":"1"}"#), send_event(b"not json")],
+            "07-tendermint-0",
+            "08-wasm-0",
+            &[],
+            &[],
+            &Height::default(),
+            "signer",
+            None,
+            &MockClock::new(0),
+            &MsgFilterOptions {
+                validate_packet_data: Some(&validate_json),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        assert_eq!(recv_msgs.len(), 1);
+        assert_eq!(
+            recv_msgs[0].packet.as_ref().unwrap().payloads[0].value,
+            br#"{"amount":"1"}"#.to_vec()
+        );
+        assert!(ack_msgs.is_empty());
+    }
+
+    #[test]
+    fn test_assert_timeout_non_membership() {
+        let msg = MsgTimeout {
+            packet: Some(test_packet().into()),
+            ..Default::default()
+        };
+
+        assert!(assert_timeout_non_membership(&msg, &[], ReceiptScheme::Tendermint).is_ok());
+        let err = assert_timeout_non_membership(&msg, &[1], ReceiptScheme::Tendermint).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("sequence 1 on client 08-wasm-0 exists"));
+
+        let absent = U256::ZERO.to_be_bytes::<32>();
+        let received = U256::from(42).to_be_bytes::<32>();
+        assert!(assert_timeout_non_membership(&msg, &absent, ReceiptScheme::Ethereum).is_ok());
+        assert!(assert_timeout_non_membership(&msg, &received, ReceiptScheme::Ethereum).is_err());
+        // A zero word is a stored receipt under the tendermint scheme.
+        assert!(assert_timeout_non_membership(&msg, &absent, ReceiptScheme::Tendermint).is_err());
+    }
+
+    #[test]
+    fn test_assign_signers() {
+        let mut recv_msgs = vec![
+            recv_msg(test_packet()),
+            recv_msg(Packet {
+                sourceClient: "07-tendermint-1".to_string(),
+                ..test_packet()
+            }),
+        ];
+        let mut timeout_msgs = vec![MsgTimeout {
+            packet: Some(test_packet().into()),
+            ..Default::default()
+        }];
+
+        assign_signers(
+            &mut recv_msgs,
+            &mut [],
+            &mut timeout_msgs,
+            |packet| match packet.sourceClient.as_str() {
+                "07-tendermint-0" => "signer-0".to_string(),
+                _ => "signer-1".to_string(),
+            },
+        );
+
+        assert_eq!(recv_msgs[0].signer, "signer-0");
+        assert_eq!(recv_msgs[1].signer, "signer-1");
+        assert_eq!(timeout_msgs[0].signer, "signer-0");
+    }
+
+    #[test]
+    fn test_referenced_addresses() {
+        let recv_msgs = vec![
+            recv_msg(test_packet()),
+            recv_msg(Packet {
+                sourceClient: "07-tendermint-1".to_string(),
+                ..test_packet()
+            }),
+        ];
+        let timeout_msgs = vec![MsgTimeout {
+            packet: Some(test_packet().into()),
+            ..Default::default()
+        }];
+
+        let addresses = referenced_addresses(&recv_msgs, &[], &timeout_msgs, |packet| match packet
+            .sourceClient
+            .as_str()
+        {
+            "07-tendermint-0" => "0x0000000000000000000000000000000000000001".to_string(),
+            _ => "0x0000000000000000000000000000000000000002".to_string(),
+        });
+
+        assert_eq!(
+            addresses,
+            HashSet::from([
+                "0x0000000000000000000000000000000000000001".to_string(),
+                "0x0000000000000000000000000000000000000002".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_ensure_proof_heights() {
+        let proof_height = Height {
+            revision_number: 0,
+            revision_height: 42,
+        };
+        let mut recv_msgs = vec![recv_msg(test_packet()), recv_msg(test_packet())];
+        recv_msgs[0].proof_height = Some(proof_height);
+        recv_msgs[1].proof_height = Some(proof_height);
+        assert!(ensure_proof_heights(&recv_msgs, &[], &[], &proof_height).is_ok());
+
+        recv_msgs[1].proof_height = Some(Height {
+            revision_number: 0,
+            revision_height: 41,
+        });
+        assert!(ensure_proof_heights(&recv_msgs, &[], &[], &proof_height).is_err());
+
+        recv_msgs[1].proof_height = None;
+        assert!(ensure_proof_heights(&recv_msgs, &[], &[], &proof_height).is_err());
+    }
+
+    #[test]
+    fn test_inject_tendermint_proofs_max_concurrency() {
+        let packets = (1..=10)
+            .map(|sequence| Packet {
+                sequence,
+                ..test_packet()
+            })
+            .collect::<Vec<_>>();
+        let mut timeout_msgs = packets
+            .iter()
+            .map(|packet| MsgTimeout {
+                packet: Some(packet.clone().into()),
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let client = MockConcurrencyClient::default();
+
+        block_on(inject_tendermint_proofs(
+            &mut [],
+            &mut [],
+            &mut timeout_msgs,
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions {
+                max_concurrency: 3,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        let max_in_flight = client.max_in_flight.load(Ordering::SeqCst);
+        assert!((1..=3).contains(&max_in_flight), "{max_in_flight}");
+        // Each message is injected the proof of its own packet.
+        for (msg, packet) in timeout_msgs.iter().zip(&packets) {
+            let proof = MerkleProof::decode_vec(&msg.proof_unreceived).unwrap();
+            assert!(matches!(
+                &proof.proofs[0].proof,
+                Some(commitment_proof::Proof::Nonexist(non_exist))
+                    if non_exist.key == packet.receipt_commitment_path()
+            ));
+        }
+
+        let err = block_on(inject_tendermint_proofs(
+            &mut [],
+            &mut [],
+            &mut timeout_msgs,
+            &client,
+            &Height::default(),
+            DEFAULT_COMMITMENT_PREFIX,
+            &TendermintProofOptions {
+                max_concurrency: 0,
+                ..Default::default()
+            },
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("must be positive"));
+    }
+
+    #[test]
+    fn test_ensure_max_total_proof_bytes() {
+        let mut recv_msgs = vec![recv_msg(test_packet())];
+        recv_msgs[0].proof_commitment = vec![0; 60];
+        let mut timeout_msgs = vec![MsgTimeout {
+            packet: Some(
+                Packet {
+                    sequence: 2,
+                    ..test_packet()
+                }
+                .into(),
+            ),
+            proof_unreceived: vec![0; 50],
+            ..Default::default()
+        }];
+        assert!(ensure_max_total_proof_bytes(&recv_msgs, &[], &timeout_msgs, 110).is_ok());
+
+        let err = ensure_max_total_proof_bytes(&recv_msgs, &[], &timeout_msgs, 100).unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("drop the messages: timeout of packet 2"));
+
+        timeout_msgs[0].proof_unreceived.clear();
+        assert!(ensure_max_total_proof_bytes(&recv_msgs, &[], &timeout_msgs, 60).is_ok());
+    }
+
+    #[test]
+    fn test_ensure_within_clock_drift() {
+        let proof_height = Height {
+            revision_number: 0,
+            revision_height: 42,
+        };
+        let mut recv_msgs = vec![recv_msg(test_packet())];
+        recv_msgs[0].proof_height = Some(proof_height);
+        let max_clock_drift = Duration::from_secs(10);
+        let block_time = |height: &Height| {
+            anyhow::ensure!(*height == proof_height, "Unknown height {height:?}");
+            Ok(1_000)
+        };
+
+        assert!(
+            ensure_within_clock_drift(&recv_msgs, &[], block_time, 990, max_clock_drift).is_ok()
+        );
+        assert!(
+            ensure_within_clock_drift(&recv_msgs, &[], block_time, 2_000, max_clock_drift).is_ok()
+        );
+        // The proof block is 11 seconds ahead of the destination chain.
+        assert!(
+            ensure_within_clock_drift(&recv_msgs, &[], block_time, 989, max_clock_drift).is_err()
+        );
+
+        recv_msgs[0].proof_height = None;
+        assert!(
+            ensure_within_clock_drift(&recv_msgs, &[], block_time, 1_000, max_clock_drift).is_err()
+        );
+    }
+
+    #[test]
+    fn test_ensure_timeout_proofs_trusted() {
+        let trusted_height = Height {
+            revision_number: 1,
+            revision_height: 42,
+        };
+        let timeout_msg = |revision_number, revision_height| MsgTimeout {
+            packet: Some(test_packet().into()),
+            proof_height: Some(Height {
+                revision_number,
+                revision_height,
+            }),
+            ..Default::default()
+        };
+
+        assert!(ensure_timeout_proofs_trusted(
+            &[timeout_msg(1, 41), timeout_msg(1, 42), timeout_msg(0, 100)],
+            &trusted_height
+        )
+        .is_ok());
+        assert!(ensure_timeout_proofs_trusted(&[timeout_msg(1, 43)], &trusted_height).is_err());
+        assert!(ensure_timeout_proofs_trusted(&[timeout_msg(2, 1)], &trusted_height).is_err());
+        assert!(ensure_timeout_proofs_trusted(
+            &[MsgTimeout {
+                packet: Some(test_packet().into()),
+                ..Default::default()
+            }],
+            &trusted_height
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_batch_fingerprint() {
+        let recv_msgs = vec![recv_msg(test_packet())];
+        let timeout_msgs = vec![MsgTimeout {
+            packet: Some(test_packet().into()),
+            ..Default::default()
+        }];
+        let fingerprint = batch_fingerprint(&recv_msgs, &[], &timeout_msgs);
+
+        // An identical batch built from the same packets has the same fingerprint.
+        assert_eq!(
+            fingerprint,
+            batch_fingerprint(&[recv_msg(test_packet())], &[], &timeout_msgs)
+        );
+        assert_ne!(fingerprint, batch_fingerprint(&recv_msgs, &[], &[]));
+        assert_ne!(fingerprint, batch_fingerprint(&[], &[], &timeout_msgs));
+
+        let mut changed_msgs = recv_msgs.clone();
+        changed_msgs[0].signer = "other".to_string();
+        assert_ne!(
+            fingerprint,
+            batch_fingerprint(&changed_msgs, &[], &timeout_msgs)
+        );
+    }
+
+    #[test]
+    fn test_build_dry_run() {
+        let packet = test_packet();
+        let send_event = EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(packet.clone()),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let ack_event = EurekaEventWithHeight {
+            event: EurekaEvent::WriteAcknowledgement(
+                Packet {
+                    sequence: 2,
+                    sourceClient: packet.destClient.clone(),
+                    destClient: packet.sourceClient.clone(),
+                    ..test_packet()
+                },
+                vec![Bytes::from_static(b"ack")],
+            ),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        let timeout_event = EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(Packet {
+                sequence: 3,
+                sourceClient: packet.destClient.clone(),
+                destClient: packet.sourceClient.clone(),
+                ..test_packet()
+            }),
+            block_number: Some(10),
+            block_timestamp: None,
+        };
+        // The recv packet has not timed out yet, while the packet of the target chain has.
+        let clock = MockClock::new(packet.timeoutTimestamp - 1);
+        let timeout_clock = MockClock::new(packet.timeoutTimestamp);
+
+        let report = build_dry_run(
+            vec![send_event.clone(), ack_event],
+            vec![],
+            &packet.sourceClient,
+            &packet.destClient,
+            &Height::default(),
+            "signer",
+            &clock,
+            &MockProofs::default(),
+        )
+        .unwrap();
+        let mut recv_msg = recv_msg(packet.clone());
+        recv_msg.signer = "signer".to_string();
+        inject_mock_proofs(
+            std::slice::from_mut(&mut recv_msg),
+            &mut [],
+            &mut [],
+            &MockProofs::default(),
+            Height::default(),
+        );
+        assert_eq!(
+            report.recv_msgs,
+            vec![DryRunMsg {
+                sequence: 1,
+                encoded_len: recv_msg.encoded_len(),
+            }]
+        );
+        assert_eq!(report.ack_msgs.len(), 1);
+        assert_eq!(report.ack_msgs[0].sequence, 2);
+        assert!(report.timeout_msgs.is_empty());
+        assert_eq!(
+            report.total_encoded_len(),
+            report.recv_msgs[0].encoded_len + report.ack_msgs[0].encoded_len
+        );
+
+        let report = build_dry_run(
+            vec![send_event],
+            vec![timeout_event],
+            &packet.sourceClient,
+            &packet.destClient,
+            &Height::default(),
+            "signer",
+            &timeout_clock,
+            &MockProofs::default(),
+        )
+        .unwrap();
+        assert!(report.recv_msgs.is_empty());
+        assert_eq!(
+            report
+                .timeout_msgs
+                .iter()
+                .map(|msg| msg.sequence)
+                .collect::<Vec<_>>(),
+            vec![3]
+        );
+    }
+
+    /// A mock injector whose proofs always fail.
+    struct MockFailingInjector;
+
+    #[async_trait::async_trait]
+    impl ProofInjector for MockFailingInjector {
+        async fn inject(
+            &self,
+            _recv_msgs: &mut [MsgRecvPacket],
+            _ack_msgs: &mut [MsgAcknowledgement],
+            _timeout_msgs: &mut [MsgTimeout],
+        ) -> anyhow::Result<()> {
+            anyhow::bail!("proof RPC unavailable")
+        }
+    }
+
+    /// A tracing subscriber that counts the warnings emitted.
+    #[derive(Default)]
+    struct MockWarningSubscriber {
+        warnings: Arc<AtomicUsize>,
+    }
+
+    impl tracing::Subscriber for MockWarningSubscriber {
+        fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            if *event.metadata().level() == tracing::Level::WARN {
+                self.warnings.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        fn enter(&self, _span: &tracing::span::Id) {}
+
+        fn exit(&self, _span: &tracing::span::Id) {}
+    }
+
+    #[test]
+    fn test_degraded_mode_injector() {
+        let subscriber = MockWarningSubscriber::default();
+        let warnings = subscriber.warnings.clone();
+        let target_height = Height {
+            revision_number: 1,
+            revision_height: 10,
+        };
+        let mut recv_msgs = vec![MsgRecvPacket {
+            proof_height: Some(target_height),
+            ..recv_msg(test_packet())
+        }];
+
+        tracing::subscriber::with_default(subscriber, || {
+            let injector = DegradedModeInjector::new(MockFailingInjector, true, 2);
+            // The first failure is returned, the second one falls back to mock proofs.
+            assert!(inject_batch(&injector, &mut recv_msgs, &mut [], &mut []).is_err());
+            assert!(recv_msgs[0].proof_commitment.is_empty());
+            assert_eq!(warnings.load(Ordering::SeqCst), 0);
+            inject_batch(&injector, &mut recv_msgs, &mut [], &mut []).unwrap();
+            assert_eq!(recv_msgs[0].proof_commitment, MockProofs::default().recv);
+            // The mock proofs are at the target height of the batch.
+            assert_eq!(recv_msgs[0].proof_height, Some(target_height));
+            assert_eq!(warnings.load(Ordering::SeqCst), 1);
+
+            // Without the degraded mode, the failures are always returned.
+            let injector = DegradedModeInjector::new(MockFailingInjector, false, 2);
+            for _ in 0..3 {
+                assert!(inject_batch(&injector, &mut recv_msgs, &mut [], &mut []).is_err());
+            }
+            assert_eq!(warnings.load(Ordering::SeqCst), 1);
+        });
     }
 }
@@ -0,0 +1,159 @@
+//! Defines the errors returned by the relayer utilities.
+
+/// The error type for proof generation.
+#[derive(Debug, thiserror::Error)]
+#[allow(clippy::module_name_repetitions)]
+pub enum ProofError {
+    /// The batch deadline was reached before all the proofs were generated.
+    #[error("batch deadline exceeded after {completed} proofs were generated")]
+    DeadlineExceeded {
+        /// The number of proofs generated before the deadline.
+        completed: usize,
+    },
+    /// The proof generation was cancelled, e.g. because the relayer is shutting down, before all
+    /// the proofs were generated.
+    #[error("proof generation cancelled after {} proofs were generated", completed.len())]
+    Cancelled {
+        /// The indices of the messages whose proofs were generated before the cancellation, in
+        /// ascending order, counting the recv messages, then the ack and the timeout messages.
+        completed: Vec<usize>,
+    },
+    /// The RPC returned no storage proof for the requested storage key.
+    #[error("no storage proof returned for storage key {storage_key}")]
+    MissingStorageProof {
+        /// The hex encoded storage key that was requested.
+        storage_key: String,
+    },
+    /// The value of a path expected to exist is empty, without a proof of its absence, e.g.
+    /// because the node has not caught up with the proof height yet. The proof can be retried.
+    #[error("membership value is empty at path {path}")]
+    MembershipValueEmpty {
+        /// The hex encoded commitment path.
+        path: String,
+    },
+    /// The node proved the absence of a path expected to exist, e.g. because the packet was
+    /// never committed or its commitment was already deleted.
+    #[error("unexpected non-membership proof at path {path}")]
+    UnexpectedNonMembership {
+        /// The hex encoded commitment path.
+        path: String,
+    },
+    /// The node served the proofs against a block other than the requested one, e.g. because it
+    /// pruned the requested block. The proof can be retried against another node.
+    #[error("proofs requested at block {requested} were served at block {served}")]
+    ProofBlockMismatch {
+        /// The requested block, as its hex encoded number or hash.
+        requested: String,
+        /// The block at which the node served the proofs.
+        served: String,
+    },
+    /// The proofs were requested at a beacon slot after the latest finalized slot, which the
+    /// counterparty client cannot verify yet. The proof can be retried once the slot is finalized.
+    #[error("slot {slot} is not finalized yet, the latest finalized slot is {finalized_slot}")]
+    SlotNotFinalized {
+        /// The requested slot.
+        slot: u64,
+        /// The latest finalized slot.
+        finalized_slot: u64,
+    },
+    /// Any other failure to generate a proof, e.g. a failed RPC request.
+    #[error(transparent)]
+    RpcFailure(anyhow::Error),
+}
+
+impl From<anyhow::Error> for ProofError {
+    /// Recovers the proof error wrapped in `err`, if any.
+    fn from(err: anyhow::Error) -> Self {
+        err.downcast().unwrap_or_else(Self::RpcFailure)
+    }
+}
+
+/// The category of a relay failure, used by alerting systems to decide its severity, and by
+/// [`is_retryable`](super::retry::is_retryable) to decide whether to retry it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureCategory {
+    /// The failure is expected to resolve itself, and the relay can be retried.
+    Transient,
+    /// The relay can never succeed, and retrying it is pointless.
+    Permanent,
+    /// The failure is caused by the relayer configuration, and requires an operator.
+    Config,
+}
+
+/// Categorizes a proof error for alerting.
+#[must_use]
+pub const fn categorize_failure(err: &ProofError) -> FailureCategory {
+    match err {
+        ProofError::DeadlineExceeded { .. }
+        | ProofError::Cancelled { .. }
+        | ProofError::MissingStorageProof { .. }
+        | ProofError::MembershipValueEmpty { .. }
+        | ProofError::ProofBlockMismatch { .. }
+        | ProofError::SlotNotFinalized { .. }
+        | ProofError::RpcFailure(_) => FailureCategory::Transient,
+        ProofError::UnexpectedNonMembership { .. } => FailureCategory::Permanent,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{categorize_failure, FailureCategory, ProofError};
+
+    #[test]
+    fn test_categorize_failure() {
+        assert_eq!(
+            categorize_failure(&ProofError::DeadlineExceeded { completed: 3 }),
+            FailureCategory::Transient
+        );
+        assert_eq!(
+            categorize_failure(&ProofError::Cancelled {
+                completed: vec![0, 1]
+            }),
+            FailureCategory::Transient
+        );
+        assert_eq!(
+            categorize_failure(&ProofError::MissingStorageProof {
+                storage_key: "00".to_string()
+            }),
+            FailureCategory::Transient
+        );
+        assert_eq!(
+            categorize_failure(&ProofError::MembershipValueEmpty {
+                path: "00".to_string()
+            }),
+            FailureCategory::Transient
+        );
+        assert_eq!(
+            categorize_failure(&ProofError::ProofBlockMismatch {
+                requested: "0xa".to_string(),
+                served: "9".to_string(),
+            }),
+            FailureCategory::Transient
+        );
+        assert_eq!(
+            categorize_failure(&ProofError::SlotNotFinalized {
+                slot: 11,
+                finalized_slot: 10,
+            }),
+            FailureCategory::Transient
+        );
+        assert_eq!(
+            categorize_failure(&ProofError::UnexpectedNonMembership {
+                path: "00".to_string()
+            }),
+            FailureCategory::Permanent
+        );
+    }
+
+    #[test]
+    fn test_proof_error_from_anyhow() {
+        let err = ProofError::from(anyhow::Error::from(ProofError::DeadlineExceeded {
+            completed: 3,
+        }));
+        assert!(matches!(err, ProofError::DeadlineExceeded { completed: 3 }));
+
+        let err = ProofError::from(anyhow::anyhow!("connection refused"));
+        assert!(matches!(err, ProofError::RpcFailure(_)));
+        assert_eq!(err.to_string(), "connection refused");
+    }
+}
@@ -1,5 +1,7 @@
 //! Relayer utilities for `solidity-ibc-eureka` chains.
 
+use std::ops::RangeInclusive;
+
 use alloy::{primitives::Bytes, sol_types::SolValue};
 use anyhow::Result;
 use futures::future;
@@ -27,6 +29,11 @@ use tendermint_rpc::HttpClient;
 
 use crate::events::{EurekaEvent, EurekaEventWithHeight};
 
+use super::{
+    clock::Clock,
+    packet::{ensure_nonzero_sequence, is_packet_data_valid, PacketFilter},
+};
+
 /// Converts a list of [`EurekaEvent`]s to a list of [`routerCalls::timeoutPacket`]s with empty
 /// proofs.
 ///
@@ -36,31 +43,48 @@ use crate::events::{EurekaEvent, EurekaEventWithHeight};
 /// - `dst_client_id`: The destination client ID.
 /// - `dst_packet_seqs`: The list of dest packet sequences to filter by. If empty, no filtering.
 /// - `target_height`: The target height for the proofs.
-/// - `now`: The current time.
+/// - `clock`: The clock giving the current time. A packet is timed out once the current time is
+/// at or past its `timeoutTimestamp`, i.e. exactly when it can no longer be received.
+/// - `packet_filter`: An optional filter of the packets to time out. If `None`, no filtering.
+/// - `sequence_range`: An optional range of the sequences of the packets to time out. If `None`,
+/// no filtering.
+/// # Errors
+/// Returns an error if a packet to time out has the sequence 0.
+#[allow(clippy::too_many_arguments)]
 pub fn target_events_to_timeout_msgs(
     target_events: Vec<EurekaEventWithHeight>,
     src_client_id: &str,
     dst_client_id: &str,
     dst_packet_seqs: &[u64],
     target_height: &Height,
-    now: u64,
-) -> Vec<routerCalls> {
+    clock: &dyn Clock,
+    packet_filter: Option<&PacketFilter>,
+    sequence_range: Option<RangeInclusive<u64>>,
+) -> Result<Vec<routerCalls>> {
+    let now = clock.now_unix_secs();
     target_events
         .into_iter()
         .filter_map(|e| match e.event {
             EurekaEvent::SendPacket(packet) => (now >= packet.timeoutTimestamp
                 && packet.sourceClient == dst_client_id
                 && packet.destClient == src_client_id
-                && (dst_packet_seqs.is_empty() || dst_packet_seqs.contains(&packet.sequence)))
-            .then_some(routerCalls::timeoutPacket(
-                ibc_eureka_solidity_types::ics26::router::timeoutPacketCall {
-                    msg_: MsgTimeoutPacket {
-                        packet,
-                        proofHeight: target_height.clone(),
-                        proofTimeout: Bytes::default(),
+                && (dst_packet_seqs.is_empty() || dst_packet_seqs.contains(&packet.sequence))
+                && sequence_range
+                    .as_ref()
+                    .is_none_or(|range| range.contains(&packet.sequence))
+                && packet_filter.is_none_or(|filter| filter.allows(&packet)))
+            .then(|| {
+                ensure_nonzero_sequence(&packet)?;
+                anyhow::Ok(routerCalls::timeoutPacket(
+                    ibc_eureka_solidity_types::ics26::router::timeoutPacketCall {
+                        msg_: MsgTimeoutPacket {
+                            packet,
+                            proofHeight: target_height.clone(),
+                            proofTimeout: Bytes::default(),
+                        },
                     },
-                },
-            )),
+                ))
+            }),
             EurekaEvent::WriteAcknowledgement(..) => None,
         })
         .collect()
@@ -76,7 +100,17 @@ pub fn target_events_to_timeout_msgs(
 /// - `src_packet_seqs`: The list of source packet sequences to filter by. If empty, no filtering.
 /// - `dst_packet_seqs`: The list of dest packet sequences to filter by. If empty, no filtering.
 /// - `target_height`: The target height for the proofs.
-/// - `now`: The current time.
+/// - `clock`: The clock giving the current time. A packet is received only while the current
+/// time is strictly before its `timeoutTimestamp`.
+/// - `validate_packet_data`: An optional validation of the payload data of the sent packets.
+/// Packets that fail the validation are skipped.
+/// - `packet_filter`: An optional filter of the packets to receive and acknowledge. If `None`, no
+/// filtering.
+/// - `sequence_range`: An optional range of the sequences of the packets to receive and
+/// acknowledge. If `None`, no filtering.
+/// # Errors
+/// Returns an error if a packet to receive or acknowledge has the sequence 0.
+#[allow(clippy::too_many_arguments)]
 pub fn src_events_to_recv_and_ack_msgs(
     src_events: Vec<EurekaEventWithHeight>,
     src_client_id: &str,
@@ -84,42 +118,64 @@ pub fn src_events_to_recv_and_ack_msgs(
     src_packet_seqs: &[u64],
     dst_packet_seqs: &[u64],
     target_height: &Height,
-    now: u64,
-) -> Vec<routerCalls> {
+    clock: &dyn Clock,
+    validate_packet_data: Option<&dyn Fn(&[u8]) -> Result<()>>,
+    packet_filter: Option<&PacketFilter>,
+    sequence_range: Option<RangeInclusive<u64>>,
+) -> Result<Vec<routerCalls>> {
+    let now = clock.now_unix_secs();
     src_events
         .into_iter()
         .filter_map(|e| match e.event {
             EurekaEvent::SendPacket(packet) => (packet.timeoutTimestamp > now
                 && packet.sourceClient == src_client_id
                 && packet.destClient == dst_client_id
-                && (src_packet_seqs.is_empty() || src_packet_seqs.contains(&packet.sequence)))
-            .then_some(routerCalls::recvPacket(recvPacketCall {
-                msg_: MsgRecvPacket {
-                    packet,
-                    proofHeight: target_height.clone(),
-                    proofCommitment: Bytes::default(),
-                },
-            })),
-            EurekaEvent::WriteAcknowledgement(packet, acks) => {
-                (packet.sourceClient == dst_client_id
-                    && packet.destClient == src_client_id
-                    && (dst_packet_seqs.is_empty() || dst_packet_seqs.contains(&packet.sequence)))
-                .then_some(routerCalls::ackPacket(ackPacketCall {
-                    msg_: MsgAckPacket {
+                && (src_packet_seqs.is_empty() || src_packet_seqs.contains(&packet.sequence))
+                && sequence_range
+                    .as_ref()
+                    .is_none_or(|range| range.contains(&packet.sequence))
+                && validate_packet_data
+                    .is_none_or(|validate| is_packet_data_valid(&packet, validate))
+                && packet_filter.is_none_or(|filter| filter.allows(&packet)))
+            .then(|| {
+                ensure_nonzero_sequence(&packet)?;
+                anyhow::Ok(routerCalls::recvPacket(recvPacketCall {
+                    msg_: MsgRecvPacket {
                         packet,
-                        acknowledgement: acks[0].clone(), // TODO: handle multiple acks (#93)
                         proofHeight: target_height.clone(),
-                        proofAcked: Bytes::default(),
+                        proofCommitment: Bytes::default(),
                     },
                 }))
+            }),
+            EurekaEvent::WriteAcknowledgement(packet, acks) => {
+                (packet.sourceClient == dst_client_id
+                    && packet.destClient == src_client_id
+                    && (dst_packet_seqs.is_empty() || dst_packet_seqs.contains(&packet.sequence))
+                    && sequence_range
+                        .as_ref()
+                        .is_none_or(|range| range.contains(&packet.sequence))
+                    && packet_filter.is_none_or(|filter| filter.allows(&packet)))
+                .then(|| {
+                    ensure_nonzero_sequence(&packet)?;
+                    anyhow::Ok(routerCalls::ackPacket(ackPacketCall {
+                        msg_: MsgAckPacket {
+                            packet,
+                            acknowledgement: acks[0].clone(), // TODO: handle multiple acks (#93)
+                            proofHeight: target_height.clone(),
+                            proofAcked: Bytes::default(),
+                        },
+                    }))
+                })
             }
         })
         .collect()
 }
 
-/// Generates and injects an SP1 proof into the first message in `msgs`.
+/// Generates and injects an SP1 proof into the first message in `msgs`, proving the packet paths
+/// under the `commitment_prefix` of the source chain.
 /// # Errors
 /// Returns an error if the sp1 proof cannot be generated.
+#[allow(clippy::too_many_arguments)]
 pub async fn inject_sp1_proof<C: SP1ProverComponents>(
     sp1_prover: &Sp1Prover<C>,
     uc_and_mem_program: &UpdateClientAndMembershipProgram,
@@ -127,6 +183,7 @@ pub async fn inject_sp1_proof<C: SP1ProverComponents>(
     tm_client: &HttpClient,
     target_light_block: LightBlock,
     client_state: ClientState,
+    commitment_prefix: &[u8],
     now: u128,
 ) -> Result<()> {
     let target_height = target_light_block.height().value();
@@ -139,7 +196,7 @@ pub async fn inject_sp1_proof<C: SP1ProverComponents>(
             routerCalls::ackPacket(call) => call.msg_.packet.ack_commitment_path(),
             _ => unreachable!(),
         })
-        .map(|path| vec![b"ibc".into(), path]);
+        .map(|path| vec![commitment_prefix.to_vec(), path]);
 
     let kv_proofs: Vec<(_, _)> = future::try_join_all(ibc_paths.into_iter().map(|path| async {
         let (value, proof) = tm_client.prove_path(&path, target_height).await?;
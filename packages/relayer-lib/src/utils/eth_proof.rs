@@ -0,0 +1,3862 @@
+//! Relayer utilities for proving the packets of `solidity-ibc-eureka` contracts on ethereum
+//! chains, from the storage of the IBC contract.
+//!
+//! As in [`cosmos`](super::cosmos), the proofs are requested concurrently within the task that
+//! injects them, without spawning, so the injection runs on any executor, not only on tokio.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Write,
+    pin::pin,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use alloy::{
+    hex,
+    primitives::{keccak256, Address, Bytes, Keccak256, B256, U256},
+    rpc::types::EIP1186AccountProofResponse,
+    sol_types::SolValue,
+};
+use anyhow::Result;
+use ethereum_apis::beacon_api::error::BeaconApiClientError;
+use ethereum_light_client::membership::{
+    decode_compact_storage_proof, decode_storage_proof, evm_ics26_commitment_path,
+    COMPACT_STORAGE_PROOF_TAG,
+};
+use ethereum_trie_db::trie_db::{
+    verify_account_storage_root, verify_storage_exclusion_proof, verify_storage_inclusion_proof,
+};
+use ethereum_types::execution::storage_proof::StorageProof;
+use flate2::{write::ZlibEncoder, Compression};
+use futures::{
+    future::{self, Either},
+    StreamExt,
+};
+use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
+use ibc_proto_eureka::ibc::core::{
+    channel::v2::{Acknowledgement, MsgAcknowledgement, MsgRecvPacket, MsgTimeout},
+    client::v1::Height,
+};
+use tokio_util::sync::CancellationToken;
+
+#[cfg(test)]
+use super::snapshot::StorageSnapshot;
+use super::{
+    client::{
+        BeaconBlockClient, ClientHeadClient, EthProofClient, ExecutionBlockHeader, StateDiffClient,
+    },
+    clock::Clock,
+    cosmos::{
+        assert_timeout_non_membership, batch_stop, ensure_proof_heights, ensure_proofs_injected,
+        expected_proof_count, instrument_proof, proof_span, ProofInjectionReport, ProofInjector,
+        ProofKind, ReceiptScheme,
+    },
+    error::ProofError,
+    metrics::ProofMetrics,
+    packet::packet_paths,
+    snapshot::{nibbles, rlp_bytes},
+};
+
+/// Returns whether the receipt at `receipt_path` exists at `block_id` in the IBC contract at
+/// `ibc_contract_address`, i.e. whether its value is not zero. The storage key is derived as in
+/// [`get_commitment_proof`].
+/// # Errors
+/// Returns an error if the storage proof cannot be fetched.
+pub async fn ethereum_receipt_exists<C: EthProofClient>(
+    client: &C,
+    receipt_path: Vec<u8>,
+    ibc_contract_address: &str,
+    block_id: ProofBlockId,
+    slot: U256,
+    slot_layout: SlotLayout,
+) -> Result<bool> {
+    let storage_proof = get_commitment_proof(
+        client,
+        ibc_contract_address,
+        block_id,
+        receipt_path,
+        slot,
+        slot_layout,
+        &StorageProofOptions::default(),
+    )
+    .await?;
+    Ok(!storage_proof.value.is_zero()
+        || storage_proof_terminal(&storage_proof)? == StorageProofTerminal::Inclusion)
+}
+
+/// The options of [`inject_ethereum_proofs`] and the other injections of ethereum proofs. The
+/// default options read the beacon blocks in the default layout without a cache, put no deadline
+/// on the batch, cannot cancel it, decode the proofs strictly and neither verify nor bound them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EthProofOptions<'a> {
+    /// The layout of the JSON encoded beacon blocks, from which [`inject_ethereum_proofs`] reads
+    /// the execution block of the proof slot.
+    pub block_layout: BeaconBlockLayout,
+    /// The cache of the execution blocks of the beacon blocks, if any, see [`BeaconBlockCache`].
+    pub block_cache: Option<&'a BeaconBlockCache>,
+    /// Whether the proofs are verified against the storage root of the contract before they are
+    /// injected, see [`StorageProofOptions::verify`].
+    pub verify: bool,
+    /// How the trie nodes of the proofs are decoded, see
+    /// [`StorageProofOptions::trie_node_decoding`].
+    pub trie_node_decoding: TrieNodeDecoding,
+    /// The maximum number of trie nodes of a proof, if any, see
+    /// [`StorageProofOptions::max_proof_depth`].
+    pub max_proof_depth: Option<usize>,
+    /// Whether the proofs must be checked against the header of the block they are served at,
+    /// see [`StorageProofOptions::require_proof_block`].
+    pub require_proof_block: bool,
+    /// The deadline for fetching the proofs of the batch, if any.
+    pub batch_deadline: Option<Instant>,
+    /// The token cancelling the proof request of the batch, if any.
+    pub cancellation: Option<&'a CancellationToken>,
+    /// The metrics counting the generated and failed proofs, if any.
+    pub metrics: Option<&'a ProofMetrics>,
+    /// The request id attached to the proof request, if any.
+    pub request_id: Option<&'a str>,
+}
+
+/// The IBC contract whose storage is proved by the ethereum proofs, the layout of the commitments
+/// in its storage, and how the proofs are set on the messages.
+#[derive(Clone, Copy)]
+pub struct EthProofLayout<'a> {
+    /// The address of the IBC contract.
+    pub ibc_contract_address: &'a str,
+    /// The storage slots of the commitments in the IBC contract.
+    pub commitment_slots: CommitmentSlots,
+    /// The layout of the commitments in the storage of the IBC contract.
+    pub slot_layout: SlotLayout,
+    /// The revision number of the proof height, as tracked by the counterparty client.
+    pub revision_number: u64,
+    /// The serialization of the proofs, see [`ProofCodec`].
+    pub proof_codec: &'a dyn ProofCodec,
+}
+
+/// Generates and injects ethereum storage proofs for recv, ack and timeout messages.
+/// The proofs are generated at the execution block of the beacon block at `proof_slot`, which is
+/// read from the JSON encoded beacon block in the block layout of `options`. If the beacon node
+/// does not serve the block as JSON, it is fetched SSZ encoded instead.
+///
+/// The storage proofs of all the messages are fetched with a single `eth_getProof` request, see
+/// [`get_commitment_proofs_batch`].
+/// If the batch deadline of `options` is reached before the proofs are fetched, the request is
+/// abandoned and [`ProofError::DeadlineExceeded`] is returned. Likewise, the request is abandoned
+/// as soon as the cancellation token is cancelled, and [`ProofError::Cancelled`] is returned.
+/// The proofs of all the messages are serialized with the proof codec of `layout`, see
+/// [`ProofCodec`].
+/// The execution block of `proof_slot` is looked up in the block cache of `options` if set, and
+/// the finalized slot and the beacon block are only fetched on a miss, see [`BeaconBlockCache`].
+/// The storage key of each path is derived from the slot of its kind in the commitment slots of
+/// `layout` with its slot layout, see [`EthProofLayout`].
+/// The proof height of the messages is `proof_slot` in the revision number of `layout`, as
+/// tracked by the counterparty client.
+/// The request id, if any, is attached to every proof request to correlate it with the RPC logs.
+/// Every proof is checked in a span of its message, see [`proof_span`], and counted in the
+/// metrics if set. A failure to fetch the batch counts all its proofs as failed.
+/// # Errors
+/// Returns [`ProofError::SlotNotFinalized`] if `proof_slot` is after the latest finalized slot,
+/// as the counterparty client cannot verify proofs at an unfinalized slot, an error if the slot
+/// was skipped or its beacon block cannot be fetched, or if a proof cannot be generated for
+/// any of the provided messages. A commitment that is empty is
+/// reported as [`ProofError::MembershipValueEmpty`] if the RPC returned no proof nodes, or as
+/// [`ProofError::UnexpectedNonMembership`] otherwise.
+#[allow(clippy::too_many_arguments)]
+pub async fn inject_ethereum_proofs<E: EthProofClient, B: BeaconBlockClient>(
+    recv_msgs: &mut [MsgRecvPacket],
+    ack_msgs: &mut [MsgAcknowledgement],
+    timeout_msgs: &mut [MsgTimeout],
+    eth_client: &E,
+    beacon_api_client: &B,
+    layout: &EthProofLayout<'_>,
+    proof_slot: u64,
+    options: &EthProofOptions<'_>,
+) -> Result<ProofInjectionReport, ProofError> {
+    let EthProofOptions {
+        block_layout,
+        block_cache,
+        ..
+    } = *options;
+    // Only finalized slots are cached, so the finality of a cached slot is not checked again.
+    let cached_block = block_cache.and_then(|cache| cache.get(proof_slot));
+    let (proof_block_number, proof_block_hash) = if let Some(execution_block) = cached_block {
+        execution_block
+    } else {
+        let finalized_slot = beacon_api_client.finalized_slot().await?;
+        if proof_slot > finalized_slot {
+            return Err(ProofError::SlotNotFinalized {
+                slot: proof_slot,
+                finalized_slot,
+            });
+        }
+        let execution_block = execution_block_at_slot(beacon_api_client, proof_slot, block_layout)
+            .await
+            .map_err(|e| {
+                e.context(format!(
+                    "No beacon block at finalized slot {proof_slot}, the slot may have been skipped"
+                ))
+            })?;
+        if let Some(cache) = block_cache {
+            cache.insert(proof_slot, execution_block);
+        }
+        execution_block
+    };
+    tracing::debug!(
+        "Generating ethereum proofs at block {proof_block_number} ({proof_block_hash}) of slot {proof_slot}"
+    );
+
+    // The proofs are pinned to the block hash so that they cannot be generated against a
+    // reorged block with the same number.
+    let proof_block_id = ProofBlockId::Hash(proof_block_hash);
+
+    Ok(inject_ethereum_proofs_at_block(
+        recv_msgs,
+        ack_msgs,
+        timeout_msgs,
+        eth_client,
+        layout,
+        None,
+        proof_block_id,
+        proof_slot,
+        options,
+    )
+    .await?)
+}
+
+/// Generates and injects ethereum proofs like [`inject_ethereum_proofs`], but of the storage
+/// `keys` computed by the caller instead of the keys derived from the paths of the messages, for
+/// IBC contracts whose storage layout is not described by a [`SlotLayout`]. The keys are in the
+/// order of the messages: the recv messages, then the ack messages, then the timeout messages.
+///
+/// The proofs are generated at `proof_block_id`, and the proof height of the messages is
+/// `proof_height`.
+/// # Errors
+/// Returns an error if there is not one key per message, if distinct keys are given for the same
+/// path, or if a proof cannot be generated for any of the provided messages.
+#[allow(clippy::too_many_arguments)]
+pub async fn inject_ethereum_proofs_raw<E: EthProofClient>(
+    recv_msgs: &mut [MsgRecvPacket],
+    ack_msgs: &mut [MsgAcknowledgement],
+    timeout_msgs: &mut [MsgTimeout],
+    keys: &[B256],
+    eth_client: &E,
+    ibc_contract_address: &str,
+    proof_block_id: ProofBlockId,
+    proof_height: Height,
+    proof_codec: &dyn ProofCodec,
+    options: &EthProofOptions<'_>,
+) -> Result<ProofInjectionReport, ProofError> {
+    let storage_keys = raw_storage_keys(recv_msgs, ack_msgs, timeout_msgs, keys)?;
+
+    Ok(inject_ethereum_proofs_at_block(
+        recv_msgs,
+        ack_msgs,
+        timeout_msgs,
+        eth_client,
+        &EthProofLayout {
+            ibc_contract_address,
+            commitment_slots: CommitmentSlots::default(),
+            slot_layout: SlotLayout::Flat,
+            revision_number: proof_height.revision_number,
+            proof_codec,
+        },
+        Some(&storage_keys),
+        proof_block_id,
+        proof_height.revision_height,
+        options,
+    )
+    .await?)
+}
+
+/// Maps the path of each message to its storage key in `keys`, in the order of
+/// [`inject_ethereum_proofs_raw`].
+fn raw_storage_keys(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+    keys: &[B256],
+) -> Result<HashMap<Vec<u8>, U256>> {
+    let expected = expected_proof_count(recv_msgs, ack_msgs, timeout_msgs);
+    anyhow::ensure!(
+        keys.len() == expected,
+        "Expected {expected} storage keys, one per message, but {} were given",
+        keys.len()
+    );
+    let paths = recv_msgs
+        .iter()
+        .map(|msg| packet_paths(&Packet::from(msg.packet.clone().unwrap())).commitment)
+        .chain(
+            ack_msgs
+                .iter()
+                .map(|msg| packet_paths(&Packet::from(msg.packet.clone().unwrap())).ack),
+        )
+        .chain(
+            timeout_msgs
+                .iter()
+                .map(|msg| packet_paths(&Packet::from(msg.packet.clone().unwrap())).receipt),
+        );
+
+    let mut storage_keys = HashMap::with_capacity(keys.len());
+    for (path, key) in paths.zip(keys) {
+        let key = U256::from_be_bytes(key.0);
+        if let Some(previous) = storage_keys.insert(path.clone(), key) {
+            anyhow::ensure!(
+                previous == key,
+                "Distinct storage keys {previous:#x} and {key:#x} were given for path {}",
+                hex::encode(path)
+            );
+        }
+    }
+
+    Ok(storage_keys)
+}
+
+/// Generates and injects ethereum proofs like [`inject_ethereum_proofs`], but at the latest
+/// execution block verified by the light client `client_id` on the destination chain, as looked
+/// up with `dest_client`. The proofs are generated at exactly the block hash of the client head,
+/// so they are verifiable by the light client as is, without waiting for it to be updated.
+///
+/// The proof height of the messages is the slot of the client head in the revision number of
+/// `layout`.
+/// # Errors
+/// Returns an error if the client head cannot be looked up, or if a proof cannot be generated
+/// for any of the provided messages.
+#[allow(clippy::too_many_arguments)]
+pub async fn prove_at_client_head<E: EthProofClient, D: ClientHeadClient>(
+    recv_msgs: &mut [MsgRecvPacket],
+    ack_msgs: &mut [MsgAcknowledgement],
+    timeout_msgs: &mut [MsgTimeout],
+    eth_client: &E,
+    dest_client: &D,
+    client_id: &str,
+    layout: &EthProofLayout<'_>,
+    options: &EthProofOptions<'_>,
+) -> Result<ProofInjectionReport, ProofError> {
+    let head = dest_client.client_head(client_id).await?;
+    tracing::debug!(
+        "Generating ethereum proofs at block {} of slot {}, the head of client {client_id}",
+        head.execution_block_hash,
+        head.slot
+    );
+
+    Ok(inject_ethereum_proofs_at_block(
+        recv_msgs,
+        ack_msgs,
+        timeout_msgs,
+        eth_client,
+        layout,
+        None,
+        ProofBlockId::Hash(head.execution_block_hash),
+        head.slot,
+        options,
+    )
+    .await?)
+}
+
+/// The default number of slots kept by a [`BeaconBlockCache`].
+pub const DEFAULT_BEACON_BLOCK_CACHE_CAPACITY: usize = 64;
+
+/// A bounded cache of the number and hash of the execution block of the beacon block at a slot,
+/// so that the batches proved at the same slot, e.g. in a tight relay loop, fetch its beacon block
+/// once. Once `capacity` slots are cached, the least recently used slot is evicted.
+/// Only finalized slots are cached by [`inject_ethereum_proofs`], as their block cannot change.
+#[derive(Debug)]
+pub struct BeaconBlockCache {
+    capacity: usize,
+    /// The cached slots and their execution blocks, from the least to the most recently used.
+    entries: Mutex<VecDeque<(u64, (u64, B256))>>,
+}
+
+impl BeaconBlockCache {
+    /// Creates a new empty `BeaconBlockCache` that keeps up to `capacity` slots.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Returns the number of cached slots.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Returns whether no slot is cached.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the cached execution block of `slot`, if any, and marks it as the most recently
+    /// used.
+    fn get(&self, slot: u64) -> Option<(u64, B256)> {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        let position = entries
+            .iter()
+            .position(|(cached_slot, _)| *cached_slot == slot)?;
+        let entry = entries.remove(position)?;
+        entries.push_back(entry);
+        Some(entry.1)
+    }
+
+    /// Caches the execution block of `slot`, evicting the least recently used slot if the cache
+    /// is full.
+    fn insert(&self, slot: u64, execution_block: (u64, B256)) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|(cached_slot, _)| *cached_slot != slot);
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back((slot, execution_block));
+    }
+}
+
+impl Default for BeaconBlockCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_BEACON_BLOCK_CACHE_CAPACITY)
+    }
+}
+
+/// Returns the number and hash of the execution block of the beacon block at `slot`.
+/// The beacon block is fetched as JSON and read in `block_layout`, falling back to SSZ if JSON is
+/// not available.
+async fn execution_block_at_slot<B: BeaconBlockClient>(
+    beacon_api_client: &B,
+    slot: u64,
+    block_layout: BeaconBlockLayout,
+) -> Result<(u64, B256)> {
+    let block_id = slot.to_string();
+    let beacon_block = beacon_api_client.beacon_block_json(&block_id).await;
+    match beacon_block.and_then(|beacon_block| block_layout.execution_block(&beacon_block)) {
+        Ok(execution_block) => Ok(execution_block),
+        Err(json_err) => {
+            tracing::debug!("Fetching the SSZ encoded beacon block, as JSON failed: {json_err}");
+            let signed_beacon_block = beacon_api_client
+                .beacon_block_ssz(&block_id)
+                .await
+                .map_err(|e| e.context(json_err))?;
+            execution_block_from_ssz(&signed_beacon_block)
+        }
+    }
+}
+
+/// Returns the latest finalized slot, checking that its execution block is at least
+/// `min_execution_block`, e.g. the block of the latest packet of a batch, so that the proofs
+/// generated at this slot include all the packet commitments. The beacon blocks are read in
+/// `block_layout`.
+/// # Errors
+/// Returns an error if the block is not finalized yet, or if the beacon blocks cannot be fetched.
+pub async fn resolve_finalized_proof_slot<B: BeaconBlockClient>(
+    beacon_api_client: &B,
+    min_execution_block: u64,
+    block_layout: BeaconBlockLayout,
+) -> Result<u64> {
+    let finalized_slot = beacon_api_client.finalized_slot().await?;
+    let (finalized_block_number, _) =
+        execution_block_at_slot(beacon_api_client, finalized_slot, block_layout).await?;
+    anyhow::ensure!(
+        finalized_block_number >= min_execution_block,
+        "Block {min_execution_block} is not finalized yet, the latest finalized block is {finalized_block_number}"
+    );
+    Ok(finalized_slot)
+}
+
+/// Returns the number of blocks that must still be finalized before the commitment written at
+/// `write_block` is finalized, given the latest `finalized_block`, i.e. until a proof of the
+/// commitment is safe to submit, see [`resolve_finalized_proof_slot`]. This is zero once the
+/// block is finalized.
+#[must_use]
+pub const fn blocks_until_final(write_block: u64, finalized_block: u64) -> u64 {
+    write_block.saturating_sub(finalized_block)
+}
+
+/// Returns the earliest finalized slot whose execution payload includes the execution block
+/// `block_number`, so that the block is proved at a safe and minimal slot.
+///
+/// # Arguments
+/// - `beacon_api_client` - The beacon node to query.
+/// - `block_number` - The execution block to cover.
+/// - `search_from_slot` - A slot at or before the slot of the block, e.g. the latest slot of the
+///   light client, which bounds the search.
+/// - `block_layout` - The layout in which the beacon blocks are read.
+/// # Errors
+/// Returns an error if the block is not finalized yet, or if the beacon blocks cannot be fetched.
+pub async fn min_finalized_slot_for_block<B: BeaconBlockClient>(
+    beacon_api_client: &B,
+    block_number: u64,
+    search_from_slot: u64,
+    block_layout: BeaconBlockLayout,
+) -> Result<u64> {
+    let finalized_slot =
+        resolve_finalized_proof_slot(beacon_api_client, block_number, block_layout).await?;
+
+    // The execution block numbers increase with the slots, so the earliest slot covering the
+    // block is found by bisection. Missed slots have no payload, and are covered by the next
+    // slot with a block.
+    let (mut low, mut high) = (search_from_slot.min(finalized_slot), finalized_slot);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let (_, mid_block_number) =
+            first_execution_block_from_slot(beacon_api_client, mid, finalized_slot, block_layout)
+                .await?;
+        if mid_block_number >= block_number {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    let (slot, _) =
+        first_execution_block_from_slot(beacon_api_client, low, finalized_slot, block_layout)
+            .await?;
+    Ok(slot)
+}
+
+/// Returns the first slot from `slot` up to `max_slot` that has a block, along with the number of
+/// its execution block. Only the slots reported as not found are skipped, any other failure of
+/// the beacon node is returned.
+async fn first_execution_block_from_slot<B: BeaconBlockClient>(
+    beacon_api_client: &B,
+    slot: u64,
+    max_slot: u64,
+    block_layout: BeaconBlockLayout,
+) -> Result<(u64, u64)> {
+    for slot in slot..=max_slot {
+        match execution_block_at_slot(beacon_api_client, slot, block_layout).await {
+            Ok((block_number, _)) => return Ok((slot, block_number)),
+            Err(e) if is_missing_block(&e) => {
+                tracing::debug!("Skipping slot {slot} without a block: {e}");
+            }
+            Err(e) => return Err(e.context(format!("Failed to fetch the block of slot {slot}"))),
+        }
+    }
+    anyhow::bail!("No block found between slots {slot} and {max_slot}")
+}
+
+/// Returns whether `err` reports that the beacon node has no block at the requested slot, e.g.
+/// because the slot was missed, rather than a failure to fetch or decode the block.
+fn is_missing_block(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<BeaconApiClientError>(),
+        Some(BeaconApiClientError::NotFound(_))
+    )
+}
+
+/// Decodes the number and hash of the execution block from an SSZ encoded signed beacon block.
+/// Only the offsets leading to the execution payload are decoded, as their layout is shared by
+/// all the forks since Deneb.
+fn execution_block_from_ssz(signed_beacon_block: &[u8]) -> Result<(u64, B256)> {
+    /// The position of the body offset in a `BeaconBlock`, after the slot, the proposer index,
+    /// the parent root and the state root.
+    const BODY_OFFSET_POSITION: usize = 80;
+    /// The position of the execution payload offset in a `BeaconBlockBody`, after the randao
+    /// reveal, the eth1 data, the graffiti, the offsets of the five operation lists and the sync
+    /// aggregate.
+    const EXECUTION_PAYLOAD_OFFSET_POSITION: usize = 380;
+    const BLOCK_NUMBER_POSITION: usize = 404;
+    const BLOCK_HASH_POSITION: usize = 472;
+
+    let ssz_field = |container: &[u8], position: usize, len: usize| {
+        container
+            .get(position..position + len)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| anyhow::anyhow!("SSZ encoded beacon block is too short"))
+    };
+    let ssz_variable_field = |container: &[u8], offset_position: usize| {
+        let offset = ssz_field(container, offset_position, 4)?;
+        let offset = u32::from_le_bytes(offset.try_into().unwrap()) as usize;
+        container
+            .get(offset..)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| anyhow::anyhow!("Invalid offset in SSZ encoded beacon block"))
+    };
+
+    // The message is the first field of the `SignedBeaconBlock`.
+    let message = ssz_variable_field(signed_beacon_block, 0)?;
+    let body = ssz_variable_field(&message, BODY_OFFSET_POSITION)?;
+    let execution_payload = ssz_variable_field(&body, EXECUTION_PAYLOAD_OFFSET_POSITION)?;
+
+    let block_number = ssz_field(&execution_payload, BLOCK_NUMBER_POSITION, 8)?;
+    let block_hash = ssz_field(&execution_payload, BLOCK_HASH_POSITION, 32)?;
+    Ok((
+        u64::from_le_bytes(block_number.try_into().unwrap()),
+        B256::from_slice(&block_hash),
+    ))
+}
+
+/// Generates and injects ethereum storage proofs served by `state` into the messages, with
+/// `proof_slot_height` as their proof height. This allows testing the ethereum proofs
+/// deterministically, without an RPC or a beacon chain.
+#[cfg(test)]
+pub(crate) async fn inject_ethereum_proofs_with_state(
+    recv_msgs: &mut [MsgRecvPacket],
+    ack_msgs: &mut [MsgAcknowledgement],
+    timeout_msgs: &mut [MsgTimeout],
+    state: &StorageSnapshot,
+    ibc_contract_slot: U256,
+    proof_slot_height: Height,
+) -> Result<ProofInjectionReport> {
+    inject_ethereum_proofs_at_block(
+        recv_msgs,
+        ack_msgs,
+        timeout_msgs,
+        state,
+        &EthProofLayout {
+            ibc_contract_address: &state.address().to_string(),
+            commitment_slots: CommitmentSlots::uniform(ibc_contract_slot),
+            slot_layout: SlotLayout::Flat,
+            revision_number: proof_slot_height.revision_number,
+            proof_codec: &ProofEncoding::Json,
+        },
+        None,
+        ProofBlockId::Number(0),
+        proof_slot_height.revision_height,
+        &EthProofOptions::default(),
+    )
+    .await
+}
+
+/// Generates and injects ethereum proofs at `proof_block_id`, with `proof_slot` as the revision
+/// height of the proof height of the messages. The storage keys are looked up in `storage_keys` if
+/// set, and derived from the slots of `layout` otherwise.
+#[allow(clippy::too_many_arguments)]
+async fn inject_ethereum_proofs_at_block<E: EthProofClient>(
+    recv_msgs: &mut [MsgRecvPacket],
+    ack_msgs: &mut [MsgAcknowledgement],
+    timeout_msgs: &mut [MsgTimeout],
+    eth_client: &E,
+    layout: &EthProofLayout<'_>,
+    storage_keys: Option<&HashMap<Vec<u8>, U256>>,
+    proof_block_id: ProofBlockId,
+    proof_slot: u64,
+    options: &EthProofOptions<'_>,
+) -> Result<ProofInjectionReport> {
+    let EthProofLayout {
+        ibc_contract_address,
+        commitment_slots,
+        slot_layout,
+        revision_number,
+        proof_codec,
+    } = *layout;
+    let EthProofOptions {
+        verify,
+        trie_node_decoding,
+        max_proof_depth,
+        require_proof_block,
+        batch_deadline,
+        cancellation,
+        metrics,
+        request_id,
+        ..
+    } = *options;
+    let proof_slot_height = Height {
+        revision_number,
+        revision_height: proof_slot,
+    };
+    let commitment_slot = commitment_slots.slot(ProofKind::Commitment);
+    let ack_slot = commitment_slots.slot(ProofKind::Acknowledgement);
+    let receipt_slot = commitment_slots.slot(ProofKind::Receipt);
+    let paths = recv_msgs
+        .iter()
+        .map(|msg| {
+            let packet = Packet::from(msg.packet.clone().unwrap());
+            (packet_paths(&packet).commitment, commitment_slot)
+        })
+        .chain(ack_msgs.iter().map(|msg| {
+            let packet = Packet::from(msg.packet.clone().unwrap());
+            (packet_paths(&packet).ack, ack_slot)
+        }))
+        .chain(timeout_msgs.iter().map(|msg| {
+            let packet = Packet::from(msg.packet.clone().unwrap());
+            (packet_paths(&packet).receipt, receipt_slot)
+        }))
+        .collect::<Vec<_>>();
+
+    let fetch_proofs = get_commitment_proofs_batch(
+        eth_client,
+        ibc_contract_address,
+        proof_block_id,
+        &paths,
+        slot_layout,
+        &StorageProofOptions {
+            slot_cache: storage_keys,
+            trie_node_decoding,
+            verify,
+            max_proof_depth,
+            require_proof_block,
+            request_id,
+        },
+    );
+    let stop = pin!(batch_stop(batch_deadline, cancellation));
+    let storage_proofs = match future::select(Box::pin(fetch_proofs), stop).await {
+        Either::Left((storage_proofs, _)) => storage_proofs,
+        Either::Right((stop, _)) => Err(stop.into_error(vec![]).into()),
+    }
+    .inspect_err(|_| {
+        if let Some(metrics) = metrics {
+            metrics.add_failed(paths.len() as u64);
+        }
+    })?;
+    let mut storage_proofs = paths.iter().map(|(path, _)| path).zip(storage_proofs);
+
+    // recv messages
+    for (msg, (commitment_path, storage_proof)) in recv_msgs.iter_mut().zip(&mut storage_proofs) {
+        let packet = Packet::from(msg.packet.clone().unwrap());
+        let check = async {
+            ensure_storage_membership(&storage_proof, commitment_path)?;
+            if storage_proof.value != U256::from_be_slice(&packet.commitment()) {
+                anyhow::bail!("Membership value does not match the packet commitment")
+            }
+
+            msg.proof_commitment = proof_codec.encode(&storage_proof)?;
+            msg.proof_height = Some(proof_slot_height);
+            Ok(())
+        };
+        instrument_proof(check, proof_span("recv", &packet, commitment_path), metrics).await?;
+    }
+
+    // ack messages
+    for (msg, (ack_path, storage_proof)) in ack_msgs.iter_mut().zip(&mut storage_proofs) {
+        let packet = Packet::from(msg.packet.clone().unwrap());
+        let check = async {
+            ensure_storage_membership(&storage_proof, ack_path)?;
+
+            msg.proof_acked = proof_codec.encode(&storage_proof)?;
+            msg.proof_height = Some(proof_slot_height);
+            Ok(())
+        };
+        instrument_proof(check, proof_span("ack", &packet, ack_path), metrics).await?;
+    }
+
+    // timeout messages
+    for (msg, (receipt_path, storage_proof)) in timeout_msgs.iter_mut().zip(&mut storage_proofs) {
+        let packet = Packet::from(msg.packet.clone().unwrap());
+        let check = async {
+            assert_timeout_non_membership(
+                msg,
+                &storage_proof.value.to_be_bytes::<32>(),
+                ReceiptScheme::Ethereum,
+            )?;
+            // A receipt stored as the zero word proves a zero value too, but by inclusion.
+            if storage_proof_terminal(&storage_proof)? == StorageProofTerminal::Inclusion {
+                anyhow::bail!(
+                    "Receipt path {} is included in the storage trie with a zero value, the packet cannot be timed out",
+                    hex::encode(receipt_path)
+                )
+            }
+            msg.proof_unreceived = proof_codec.encode(&storage_proof)?;
+            msg.proof_height = Some(proof_slot_height);
+            Ok(())
+        };
+        instrument_proof(check, proof_span("timeout", &packet, receipt_path), metrics).await?;
+    }
+
+    ensure_proofs_injected(recv_msgs, ack_msgs, timeout_msgs)?;
+    ensure_proof_heights(recv_msgs, ack_msgs, timeout_msgs, &proof_slot_height)?;
+    Ok(ProofInjectionReport::new(
+        proof_slot_height,
+        recv_msgs,
+        ack_msgs,
+        timeout_msgs,
+    ))
+}
+
+/// Checks that the commitment at `path` proved by `storage_proof` is not empty.
+fn ensure_storage_membership(storage_proof: &StorageProof, path: &[u8]) -> Result<(), ProofError> {
+    if !storage_proof.value.is_zero() {
+        return Ok(());
+    }
+    let path = hex::encode(path);
+    Err(if storage_proof.proof.is_empty() {
+        ProofError::MembershipValueEmpty { path }
+    } else {
+        ProofError::UnexpectedNonMembership { path }
+    })
+}
+
+/// The encoding of the storage proofs set on the messages. The light client accepts all of them,
+/// see [`ethereum_light_client::membership::decode_storage_proof`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProofEncoding {
+    /// The JSON encoding of the proof.
+    #[default]
+    Json,
+    /// The zlib compressed JSON encoding of the proof, if this reduces its size, and the JSON
+    /// encoding otherwise.
+    CompressedJson,
+    /// The compact binary encoding of the proof, see [`encode_compact_storage_proof`].
+    Compact,
+}
+
+impl ProofEncoding {
+    /// Sniffs the encoding of a proof from its first byte: a JSON proof starts with `{`, a zlib
+    /// stream with the `0x78` header byte, and a compact proof with the
+    /// [`COMPACT_STORAGE_PROOF_TAG`]. Returns `None` if the proof has none of them.
+    #[must_use]
+    pub fn detect(proof: &[u8]) -> Option<Self> {
+        match *proof.first()? {
+            b'{' => Some(Self::Json),
+            0x78 => Some(Self::CompressedJson),
+            COMPACT_STORAGE_PROOF_TAG => Some(Self::Compact),
+            _ => None,
+        }
+    }
+}
+
+/// The `ProofCodec` trait defines the serialization of the storage proofs set on the messages,
+/// so that the relayer is not coupled to a single encoding decoded by the counterparty.
+pub trait ProofCodec: Send + Sync {
+    /// Serializes the storage proof to be set on a message.
+    /// # Errors
+    /// Returns an error if the proof cannot be serialized.
+    fn encode(&self, storage_proof: &StorageProof) -> Result<Vec<u8>>;
+
+    /// Deserializes a storage proof serialized by [`ProofCodec::encode`].
+    /// # Errors
+    /// Returns an error if the proof cannot be deserialized.
+    fn decode(&self, proof: &[u8]) -> Result<StorageProof>;
+}
+
+/// The default [`ProofCodec`], which serializes the proofs as JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonProofCodec;
+
+impl ProofCodec for JsonProofCodec {
+    fn encode(&self, storage_proof: &StorageProof) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(storage_proof)?)
+    }
+
+    fn decode(&self, proof: &[u8]) -> Result<StorageProof> {
+        Ok(serde_json::from_slice(proof)?)
+    }
+}
+
+/// A [`ProofCodec`] that serializes the proofs in the compact binary encoding, see
+/// [`encode_compact_storage_proof`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CompactProofCodec;
+
+impl ProofCodec for CompactProofCodec {
+    fn encode(&self, storage_proof: &StorageProof) -> Result<Vec<u8>> {
+        encode_compact_storage_proof(storage_proof)
+    }
+
+    fn decode(&self, proof: &[u8]) -> Result<StorageProof> {
+        decode_compact_storage_proof(proof).map_err(|e| anyhow::anyhow!("{e}"))
+    }
+}
+
+impl ProofCodec for ProofEncoding {
+    fn encode(&self, storage_proof: &StorageProof) -> Result<Vec<u8>> {
+        encode_storage_proof(storage_proof, *self)
+    }
+
+    fn decode(&self, proof: &[u8]) -> Result<StorageProof> {
+        decode_storage_proof(proof).map_err(|e| anyhow::anyhow!("{e}"))
+    }
+}
+
+/// Serializes the storage proof to be set on a message in `encoding`.
+/// # Errors
+/// Returns an error if the proof cannot be serialized or compressed.
+pub fn encode_storage_proof(
+    storage_proof: &StorageProof,
+    encoding: ProofEncoding,
+) -> Result<Vec<u8>> {
+    let proof = match encoding {
+        ProofEncoding::Json => return Ok(serde_json::to_vec(storage_proof)?),
+        ProofEncoding::CompressedJson => serde_json::to_vec(storage_proof)?,
+        ProofEncoding::Compact => return encode_compact_storage_proof(storage_proof),
+    };
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&proof)?;
+    let compressed = encoder.finish()?;
+    Ok(if compressed.len() < proof.len() {
+        compressed
+    } else {
+        proof
+    })
+}
+
+/// Serializes the storage proof in the compact binary encoding decoded by
+/// [`ethereum_light_client::membership::decode_compact_storage_proof`]: the
+/// [`COMPACT_STORAGE_PROOF_TAG`], the key, the big-endian value, and every trie node prefixed by
+/// its length as a big-endian `u16`. Unlike JSON, the words and the nodes are not hex encoded,
+/// which halves the calldata of the proof.
+/// # Errors
+/// Returns an error if a trie node is longer than `u16::MAX` bytes.
+pub fn encode_compact_storage_proof(storage_proof: &StorageProof) -> Result<Vec<u8>> {
+    let nodes_len = storage_proof
+        .proof
+        .iter()
+        .map(|node| node.len() + 2)
+        .sum::<usize>();
+    let mut proof = Vec::with_capacity(65 + nodes_len);
+    proof.push(COMPACT_STORAGE_PROOF_TAG);
+    proof.extend_from_slice(storage_proof.key.as_slice());
+    proof.extend_from_slice(&storage_proof.value.to_be_bytes::<32>());
+    for node in &storage_proof.proof {
+        let len = u16::try_from(node.len())
+            .map_err(|_| anyhow::anyhow!("Trie node of {} bytes is too long", node.len()))?;
+        proof.extend_from_slice(&len.to_be_bytes());
+        proof.extend_from_slice(node);
+    }
+    Ok(proof)
+}
+
+/// Checks that the proofs of all the messages are in the `expected` encoding, so that a batch
+/// mixing the proofs of differently configured provers is caught before it is submitted. As
+/// [`ProofEncoding::CompressedJson`] falls back to JSON when compression does not reduce the size
+/// of a proof, JSON proofs are also accepted in that case.
+/// # Errors
+/// Returns an error with the first message whose proof is in another encoding.
+pub fn assert_single_proof_encoding(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+    expected: ProofEncoding,
+) -> Result<()> {
+    let proofs = recv_msgs
+        .iter()
+        .map(|msg| ("recv", msg.packet.as_ref(), &msg.proof_commitment))
+        .chain(
+            ack_msgs
+                .iter()
+                .map(|msg| ("ack", msg.packet.as_ref(), &msg.proof_acked)),
+        )
+        .chain(
+            timeout_msgs
+                .iter()
+                .map(|msg| ("timeout", msg.packet.as_ref(), &msg.proof_unreceived)),
+        );
+
+    for (kind, packet, proof) in proofs {
+        let encoding = ProofEncoding::detect(proof);
+        let matches = encoding == Some(expected)
+            || (expected == ProofEncoding::CompressedJson && encoding == Some(ProofEncoding::Json));
+        anyhow::ensure!(
+            matches,
+            "The proof of the {kind} message of packet {} is encoded as {encoding:?}, expected {expected:?}",
+            packet.map_or(0, |packet| packet.sequence)
+        );
+    }
+    Ok(())
+}
+
+/// Verifies the injected ethereum storage proof of every message against the single expected
+/// `storage_root` of the IBC contract, as a final check before the batch is submitted. The proofs
+/// are decoded as the light client does, see
+/// [`ethereum_light_client::membership::decode_storage_proof`].
+/// # Errors
+/// Returns an error with the first message whose proof cannot be decoded or does not verify
+/// against `storage_root`.
+pub fn verify_batch_against_root(
+    recv_msgs: &[MsgRecvPacket],
+    ack_msgs: &[MsgAcknowledgement],
+    timeout_msgs: &[MsgTimeout],
+    storage_root: B256,
+) -> Result<()> {
+    let msg_proofs = recv_msgs
+        .iter()
+        .map(|msg| ("recv", &msg.packet, &msg.proof_commitment))
+        .chain(
+            ack_msgs
+                .iter()
+                .map(|msg| ("ack", &msg.packet, &msg.proof_acked)),
+        )
+        .chain(
+            timeout_msgs
+                .iter()
+                .map(|msg| ("timeout", &msg.packet, &msg.proof_unreceived)),
+        );
+
+    for (kind, packet, proof) in msg_proofs {
+        let sequence = packet.as_ref().map_or(0, |packet| packet.sequence);
+        let storage_proof = decode_storage_proof(proof).map_err(|e| {
+            anyhow::anyhow!("Failed to decode the proof of the {kind} of packet {sequence}: {e}")
+        })?;
+        verify_storage_proof(&storage_proof, storage_root).map_err(|e| {
+            anyhow::anyhow!("Invalid proof of the {kind} of packet {sequence}: {e}")
+        })?;
+    }
+    Ok(())
+}
+
+/// A transaction of the plan returned by [`plan_client_updates`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedTx {
+    /// The height the light client is updated to at the start of the transaction, if any.
+    pub update_height: Option<u64>,
+    /// The indices of the messages in the transaction, which are proved at the latest height of
+    /// the light client.
+    pub msgs: Vec<usize>,
+}
+
+/// Plans the transactions relaying messages that must be proved at or above their
+/// `min_proof_heights`, using as few light client updates as possible.
+/// A commitment proved at a height stays provable at every later height until it is relayed, so
+/// a single update to the highest required height covers all the messages, and no update is
+/// needed if the light client is already at `trusted_height` above all of them.
+///
+/// # Arguments
+/// - `min_proof_heights` - The minimum proof height of each message.
+/// - `trusted_height` - The latest height of the light client.
+/// - `max_msgs_per_tx` - The maximum number of messages in a transaction, including the update.
+///
+/// # Returns
+/// The planned transactions, in the order they must be submitted.
+/// # Errors
+/// Returns an error if `max_msgs_per_tx` leaves no room for a message next to the update.
+pub fn plan_client_updates(
+    min_proof_heights: &[u64],
+    trusted_height: u64,
+    max_msgs_per_tx: usize,
+) -> Result<Vec<PlannedTx>> {
+    let mut update_height = min_proof_heights
+        .iter()
+        .copied()
+        .max()
+        .filter(|height| *height > trusted_height);
+    let mut capacity = max_msgs_per_tx.saturating_sub(usize::from(update_height.is_some()));
+    anyhow::ensure!(
+        capacity > 0 || min_proof_heights.is_empty(),
+        "A transaction of {max_msgs_per_tx} messages cannot relay a message"
+    );
+
+    let mut msgs = 0..min_proof_heights.len();
+    let mut plan = vec![];
+    while !msgs.is_empty() {
+        plan.push(PlannedTx {
+            update_height: update_height.take(),
+            msgs: msgs.by_ref().take(capacity).collect(),
+        });
+        capacity = max_msgs_per_tx;
+    }
+    Ok(plan)
+}
+
+/// The block at which an ethereum storage proof is generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofBlockId {
+    /// The block with the given number.
+    Number(u64),
+    /// The block with the given hash. Unlike a number, the hash stays tied to the same block even
+    /// if the chain is reorged.
+    Hash(B256),
+}
+
+impl ProofBlockId {
+    /// Returns the block identifier as accepted by the `eth_getProof` RPC method.
+    #[must_use]
+    pub fn to_rpc_param(&self) -> String {
+        match self {
+            Self::Number(block_number) => format!("0x{block_number:x}"),
+            Self::Hash(block_hash) => hex::encode_prefixed(block_hash),
+        }
+    }
+}
+
+/// The storage slots of the mappings in which the IBC contract stores each kind of path, so that
+/// the packet commitments, the acknowledgements and the receipts can be stored in different
+/// mappings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CommitmentSlots {
+    /// The slot of the packet commitments.
+    pub commitment: U256,
+    /// The slot of the acknowledgement commitments.
+    pub ack: U256,
+    /// The slot of the packet receipts.
+    pub receipt: U256,
+}
+
+impl CommitmentSlots {
+    /// Creates the slots of an IBC contract that stores all the paths in the mapping at `slot`.
+    #[must_use]
+    pub const fn uniform(slot: U256) -> Self {
+        Self {
+            commitment: slot,
+            ack: slot,
+            receipt: slot,
+        }
+    }
+
+    /// Returns the slot of the mapping in which the paths proved by `kind` are stored.
+    #[must_use]
+    pub const fn slot(&self, kind: ProofKind) -> U256 {
+        match kind {
+            ProofKind::Commitment => self.commitment,
+            ProofKind::Acknowledgement => self.ack,
+            ProofKind::Receipt => self.receipt,
+        }
+    }
+}
+
+impl From<U256> for CommitmentSlots {
+    fn from(slot: U256) -> Self {
+        Self::uniform(slot)
+    }
+}
+
+/// The layout of the mapping in which the IBC contract stores the commitments, which determines
+/// the storage key of a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlotLayout {
+    /// A flat `mapping(bytes32 => bytes32)` from the hash of the path to the commitment, see
+    /// [`evm_ics26_commitment_path`].
+    #[default]
+    Flat,
+    /// A nested `mapping(bytes32 => mapping(uint64 => bytes32))` from the hash of the channel of
+    /// the path to the sequence to the commitment. The channel is the path without its trailing
+    /// sequence, i.e. the client id followed by the kind of the path.
+    Nested,
+    /// A mapping whose storage key is `keccak256(abi.encode(path, slot))`, i.e. the hash of the
+    /// ABI encoding of the path as `bytes` and of the slot.
+    AbiEncodedPath,
+}
+
+impl SlotLayout {
+    /// Returns the storage key of `path` in the mapping at `slot`.
+    /// # Errors
+    /// Returns an error if the path of the nested layout does not end with a sequence.
+    pub fn storage_key(self, path: &[u8], slot: U256) -> Result<U256> {
+        match self {
+            Self::Flat => Ok(evm_ics26_commitment_path(path, slot)),
+            Self::Nested => {
+                let sequence_position = path.len().checked_sub(8).ok_or_else(|| {
+                    anyhow::anyhow!("Path {} does not end with a sequence", hex::encode(path))
+                })?;
+                let (channel, sequence) = path.split_at(sequence_position);
+                let channel_slot = evm_ics26_commitment_path(channel, slot);
+
+                let mut hasher = Keccak256::new();
+                hasher.update(B256::left_padding_from(sequence));
+                hasher.update(channel_slot.to_be_bytes::<32>());
+                Ok(hasher.finalize().into())
+            }
+            Self::AbiEncodedPath => {
+                let encoded = (Bytes::copy_from_slice(path), slot).abi_encode_params();
+                Ok(keccak256(encoded).into())
+            }
+        }
+    }
+
+    /// Returns the storage key of `path` for the channel `version`, when the IBC contract keeps
+    /// the commitments of each version in a `mapping(string => ...)` at `slot`, whose values are
+    /// mappings of this layout. The mapping of a version is at the slot
+    /// `keccak256(version . slot)`, as for any mapping with a string key.
+    /// # Errors
+    /// Returns an error if the path of the nested layout does not end with a sequence.
+    pub fn versioned_storage_key(self, path: &[u8], slot: U256, version: &str) -> Result<U256> {
+        let version_slot = keccak256([version.as_bytes(), &slot.to_be_bytes::<32>()].concat());
+        self.storage_key(path, version_slot.into())
+    }
+}
+
+/// The layout of the JSON encoded beacon blocks, which determines where the execution block of a
+/// beacon block is read from. The layout depends on the version of the beacon API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BeaconBlockLayout {
+    /// The execution block is read from `message.body.execution_payload`.
+    #[default]
+    ExecutionPayload,
+    /// The execution block is read from `message.body.execution_payload_header`, as served for
+    /// blinded beacon blocks.
+    ExecutionPayloadHeader,
+}
+
+impl BeaconBlockLayout {
+    /// Returns the JSON pointer to the execution payload of the beacon block.
+    #[must_use]
+    pub const fn execution_payload_pointer(self) -> &'static str {
+        match self {
+            Self::ExecutionPayload => "/message/body/execution_payload",
+            Self::ExecutionPayloadHeader => "/message/body/execution_payload_header",
+        }
+    }
+
+    /// Returns the number and hash of the execution block of the JSON encoded `beacon_block`.
+    /// # Errors
+    /// Returns an error if the beacon block has no execution payload in this layout, or if the
+    /// block number or hash are invalid.
+    pub fn execution_block(self, beacon_block: &serde_json::Value) -> Result<(u64, B256)> {
+        let execution_payload = self.execution_payload(beacon_block)?;
+        let block_number = match &execution_payload["block_number"] {
+            serde_json::Value::String(block_number) => block_number.parse()?,
+            block_number => block_number
+                .as_u64()
+                .ok_or_else(|| anyhow::anyhow!("Invalid execution block number: {block_number}"))?,
+        };
+        let block_hash = serde_json::from_value(execution_payload["block_hash"].clone())?;
+        Ok((block_number, block_hash))
+    }
+
+    /// Returns the unix timestamp, in seconds, of the execution payload of the JSON encoded
+    /// `beacon_block`.
+    /// # Errors
+    /// Returns an error if the beacon block has no execution payload in this layout, or if its
+    /// timestamp is invalid.
+    pub fn execution_payload_timestamp(self, beacon_block: &serde_json::Value) -> Result<u64> {
+        let execution_payload = self.execution_payload(beacon_block)?;
+        Ok(match &execution_payload["timestamp"] {
+            serde_json::Value::String(timestamp) => timestamp.parse()?,
+            timestamp => timestamp.as_u64().ok_or_else(|| {
+                anyhow::anyhow!("Invalid execution payload timestamp: {timestamp}")
+            })?,
+        })
+    }
+
+    fn execution_payload(self, beacon_block: &serde_json::Value) -> Result<&serde_json::Value> {
+        let pointer = self.execution_payload_pointer();
+        beacon_block.pointer(pointer).ok_or_else(|| {
+            anyhow::anyhow!("The beacon block has no execution payload at {pointer}")
+        })
+    }
+}
+
+/// Checks that the execution payload of the JSON encoded `beacon_block`, read in `block_layout`,
+/// is at most `max_payload_age` old at the current time of `clock`, so that the proofs are not
+/// generated against a very old execution block, e.g. of a stalled beacon node.
+/// # Errors
+/// Returns an error if the execution payload has no valid timestamp, or if it is older than
+/// `max_payload_age`.
+pub fn ensure_recent_execution_payload(
+    beacon_block: &serde_json::Value,
+    block_layout: BeaconBlockLayout,
+    clock: &dyn Clock,
+    max_payload_age: Duration,
+) -> Result<()> {
+    let timestamp = block_layout.execution_payload_timestamp(beacon_block)?;
+    let age = clock.now_unix_secs().saturating_sub(timestamp);
+    anyhow::ensure!(
+        age <= max_payload_age.as_secs(),
+        "The execution payload at {timestamp} is {age}s old, more than the maximum age of {}s",
+        max_payload_age.as_secs()
+    );
+    Ok(())
+}
+
+/// The options of [`get_commitment_proof`] and [`get_commitment_proofs_batch`]. The default
+/// options decode the proofs strictly, and neither verify nor bound them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StorageProofOptions<'a> {
+    /// The pre-computed storage keys of the paths, if any, used as is instead of being derived.
+    pub slot_cache: Option<&'a HashMap<Vec<u8>, U256>>,
+    /// How the trie nodes of the proofs are decoded.
+    pub trie_node_decoding: TrieNodeDecoding,
+    /// Whether the proofs are verified against the storage root of the contract returned with
+    /// them, so that a malformed or stale proof is caught before it is submitted.
+    pub verify: bool,
+    /// The maximum number of trie nodes of a proof, if any, as a pathologically deep proof is a
+    /// sign of a misconfigured slot.
+    pub max_proof_depth: Option<usize>,
+    /// Whether the proofs must be checked against the header of the block they are served at, so
+    /// that a client that does not serve block headers is an error instead of skipping the check.
+    pub require_proof_block: bool,
+    /// The request id attached to the proof request, if any.
+    pub request_id: Option<&'a str>,
+}
+
+/// Fetches the storage proof of the commitment at `path` in the IBC contract.
+///
+/// The commitments are stored in a mapping at the storage slot `slot` of the contract, so the
+/// storage key of `path` is derived from `slot` with `slot_layout`. With [`SlotLayout::Flat`],
+/// this is [`evm_ics26_commitment_path`], i.e. `keccak256(keccak256(path) ++ slot)`, as the
+/// Solidity compiler lays out `mapping(bytes32 => bytes32)`.
+/// The proof is decoded, checked and verified as set in `options`, see [`StorageProofOptions`].
+/// If the client serves block headers, see [`EthProofClient::block_header`], the block served
+/// with the proof is read back and checked to be `block_id`, and the account proof is verified
+/// against its state root, as a node may serve the proof at another block, e.g. if it pruned the
+/// requested block. The check is skipped if the client does not serve block headers, unless it is
+/// required in `options`.
+/// # Errors
+/// Returns an error if the storage key cannot be derived, if the proof cannot be fetched or
+/// decoded, if the verification of the proof fails, if the proof is deeper than
+/// `max_proof_depth`, if the proof is of another account than
+/// `ibc_contrct_address`, [`ProofError::MissingStorageProof`] if the RPC returns no storage proof,
+/// [`ProofError::ProofBlockMismatch`] if the proof is served at another block, or if the block
+/// header is required but not served.
+pub async fn get_commitment_proof<C: EthProofClient>(
+    eth_client: &C,
+    ibc_contrct_address: &str,
+    block_id: ProofBlockId,
+    path: Vec<u8>,
+    slot: U256,
+    slot_layout: SlotLayout,
+    options: &StorageProofOptions<'_>,
+) -> Result<StorageProof> {
+    let mut storage_proofs = get_commitment_proofs_batch(
+        eth_client,
+        ibc_contrct_address,
+        block_id,
+        &[(path, slot)],
+        slot_layout,
+        options,
+    )
+    .await?;
+    Ok(storage_proofs.remove(0))
+}
+
+/// Discovers the slot of the mapping in which the IBC contract stores the packet commitments, when
+/// it is not known, from the known commitment of `packet`. Every layout of `layouts` is tried at
+/// every slot of `candidate_slots`, in order, by reading the storage key of the commitment path
+/// with `eth_getStorageAt`, until one stores the commitment of the packet.
+/// Returns the discovered slot along with its layout.
+/// # Errors
+/// Returns an error if a storage slot cannot be read, or if no candidate slot stores the
+/// commitment of the packet.
+pub async fn discover_commitment_slot<C: EthProofClient>(
+    eth_client: &C,
+    ibc_contract_address: &str,
+    block_id: ProofBlockId,
+    packet: &Packet,
+    candidate_slots: &[U256],
+    layouts: &[SlotLayout],
+) -> Result<(U256, SlotLayout)> {
+    let commitment_path = packet_paths(packet).commitment;
+    let commitment = U256::from_be_slice(&packet.commitment());
+    for slot in candidate_slots {
+        for layout in layouts {
+            let storage_key = layout.storage_key(&commitment_path, *slot)?;
+            let value = eth_client
+                .storage_at(ibc_contract_address, storage_key, block_id.to_rpc_param())
+                .await?;
+            if value == commitment {
+                tracing::debug!("Discovered the commitment slot {slot} with the {layout:?} layout");
+                return Ok((*slot, *layout));
+            }
+        }
+    }
+    anyhow::bail!(
+        "None of the {} candidate slots stores the commitment of packet {} from client {}",
+        candidate_slots.len(),
+        packet.sequence,
+        packet.sourceClient
+    )
+}
+
+/// Fetches the storage proofs of the commitments at `paths` in the IBC contract with a single
+/// `eth_getProof` request, and returns them in the order of `paths`. Each path is paired with the
+/// slot of the mapping it is stored in, see [`CommitmentSlots`]. The storage proofs returned
+/// by the RPC are matched to the paths by their storage key.
+///
+/// The storage keys are derived, and the proofs decoded and verified, as in
+/// [`get_commitment_proof`].
+/// # Errors
+/// Returns an error if a storage key cannot be derived, if the proofs cannot be fetched or
+/// decoded, if the verification of a proof fails, if a proof is deeper than `max_proof_depth`,
+/// if the proofs are of another account than
+/// `ibc_contrct_address`, [`ProofError::MissingStorageProof`] if the RPC returns no storage proof
+/// for a path, [`ProofError::ProofBlockMismatch`] if the proofs are served at another block, or
+/// if the block header is required but not served.
+pub async fn get_commitment_proofs_batch<C: EthProofClient>(
+    eth_client: &C,
+    ibc_contrct_address: &str,
+    block_id: ProofBlockId,
+    paths: &[(Vec<u8>, U256)],
+    slot_layout: SlotLayout,
+    options: &StorageProofOptions<'_>,
+) -> Result<Vec<StorageProof>> {
+    let StorageProofOptions {
+        slot_cache,
+        trie_node_decoding,
+        verify,
+        max_proof_depth,
+        require_proof_block,
+        request_id,
+    } = *options;
+    if paths.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let storage_keys = paths
+        .iter()
+        .map(|(path, slot)| {
+            slot_cache
+                .and_then(|slot_cache| slot_cache.get(path))
+                .map_or_else(|| slot_layout.storage_key(path, *slot), |key| Ok(*key))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let storage_keys_hex = storage_keys
+        .iter()
+        .map(|storage_key| hex::encode(storage_key.to_be_bytes_vec()))
+        .collect::<Vec<_>>();
+
+    let (proof, block_header) = eth_client
+        .get_proof_at_block(
+            ibc_contrct_address,
+            storage_keys_hex.clone(),
+            block_id,
+            request_id,
+        )
+        .await?;
+    ensure_proof_account(&proof, ibc_contrct_address)?;
+    match block_header {
+        Some(block_header) => ensure_proof_block(block_id, block_header, &proof)?,
+        None => anyhow::ensure!(
+            !require_proof_block,
+            "The client does not serve the header of block {}, the proofs cannot be checked to be served at it",
+            block_id.to_rpc_param()
+        ),
+    }
+    let rpc_storage_proofs = proof
+        .storage_proof
+        .iter()
+        .map(|storage_proof| (storage_proof.key.as_b256(), storage_proof))
+        .collect::<HashMap<_, _>>();
+
+    storage_keys
+        .into_iter()
+        .zip(storage_keys_hex)
+        .map(|(storage_key, storage_key_hex)| {
+            let storage_proof = rpc_storage_proofs
+                .get(&B256::from(storage_key.to_be_bytes::<32>()))
+                .ok_or(ProofError::MissingStorageProof {
+                    storage_key: storage_key_hex,
+                })?;
+
+            let storage_proof = StorageProof {
+                key: storage_proof.key.as_b256(),
+                value: storage_proof.value,
+                proof: trie_node_decoding.decode(&storage_proof.proof)?,
+            };
+            if let Some(max_proof_depth) = max_proof_depth {
+                anyhow::ensure!(
+                    storage_proof.proof.len() <= max_proof_depth,
+                    "Storage proof of key {storage_key_hex} has {} nodes, more than the maximum \
+                     depth {max_proof_depth}",
+                    storage_proof.proof.len()
+                );
+            }
+            if verify {
+                verify_storage_proof(&storage_proof, proof.storage_hash)?;
+            }
+            anyhow::Ok(storage_proof)
+        })
+        .collect()
+}
+
+/// Waits for the commitment at `path` in the IBC contract to be written, as streamed by the state
+/// diffs of `eth_ws`, and fetches its storage proof from `eth_ws` at the block that wrote it, so
+/// that the commitment is proved as soon as it is written instead of after polling for events.
+/// The storage key of `path` is derived from `slot` with `slot_layout`, as in
+/// [`get_commitment_proof`]. Writes of the zero word, i.e. deletions, are ignored.
+/// Returns the number of the block that wrote the commitment, along with its proof.
+/// # Errors
+/// Returns an error if the storage key cannot be derived, if the subscription fails or ends
+/// before the commitment is written, or if the proof cannot be fetched or does not prove the
+/// written value.
+pub async fn subscribe_commitment<W: StateDiffClient + EthProofClient>(
+    eth_ws: &W,
+    ibc_contract_address: &str,
+    path: Vec<u8>,
+    slot: U256,
+    slot_layout: SlotLayout,
+) -> Result<(u64, StorageProof)> {
+    let storage_key = B256::from(slot_layout.storage_key(&path, slot)?);
+    let mut storage_diffs = eth_ws.subscribe_storage_diffs(ibc_contract_address).await?;
+    let diff = loop {
+        let Some(diff) = storage_diffs.next().await else {
+            anyhow::bail!(
+                "State diff subscription ended before the commitment at {storage_key} was written"
+            )
+        };
+        let diff = diff?;
+        if diff.key == storage_key && !diff.value.is_zero() {
+            break diff;
+        }
+    };
+    tracing::debug!(
+        "Commitment at {storage_key} was written at block {}",
+        diff.block_number
+    );
+
+    let storage_proof = get_commitment_proof(
+        eth_ws,
+        ibc_contract_address,
+        ProofBlockId::Number(diff.block_number),
+        path,
+        slot,
+        slot_layout,
+        &StorageProofOptions {
+            verify: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+    anyhow::ensure!(
+        storage_proof.value == diff.value,
+        "Proved commitment {} does not match the written commitment {}",
+        storage_proof.value,
+        diff.value
+    );
+    Ok((diff.block_number, storage_proof))
+}
+
+/// Checks that the proofs returned by the node are of the account of the requested
+/// `ibc_contract_address`, so that the storage of another account is never proved as the IBC
+/// contract's, e.g. if the node misroutes the request.
+fn ensure_proof_account(
+    proof: &EIP1186AccountProofResponse,
+    ibc_contract_address: &str,
+) -> Result<()> {
+    let requested: Address = ibc_contract_address.parse()?;
+    anyhow::ensure!(
+        proof.address == requested,
+        "Proofs of the IBC contract {requested} were returned for account {}",
+        proof.address
+    );
+    Ok(())
+}
+
+/// Checks that the block served along with the proofs, as read back from the node, is the
+/// requested `block_id`, and that the account proof links the storage root of the proofs to the
+/// state root of the block, so that the proofs are not silently generated against another state.
+fn ensure_proof_block(
+    block_id: ProofBlockId,
+    block_header: ExecutionBlockHeader,
+    proof: &EIP1186AccountProofResponse,
+) -> Result<(), ProofError> {
+    let is_requested_block = match block_id {
+        ProofBlockId::Number(block_number) => block_header.number == block_number,
+        ProofBlockId::Hash(block_hash) => block_header.hash == block_hash,
+    };
+    let served = if !is_requested_block {
+        format!("{} ({})", block_header.number, block_header.hash)
+    } else if let Err(e) = verify_account_storage_root(
+        block_header.state_root,
+        proof.address,
+        &proof.account_proof,
+        proof.storage_hash,
+    ) {
+        format!(
+            "another state than {} ({}), as the account proof does not verify against its state root {}: {e}",
+            block_header.number, block_header.hash, block_header.state_root
+        )
+    } else {
+        return Ok(());
+    };
+    Err(ProofError::ProofBlockMismatch {
+        requested: block_id.to_rpc_param(),
+        served,
+    })
+}
+
+/// Verifies the storage proof against the `storage_root` of the contract. A zero value is
+/// verified as the exclusion of the key, as the storage trie does not store zero values.
+fn verify_storage_proof(storage_proof: &StorageProof, storage_root: B256) -> Result<()> {
+    if storage_proof.value.is_zero() {
+        verify_storage_exclusion_proof(&storage_root, &storage_proof.key, &storage_proof.proof)
+    } else {
+        verify_storage_inclusion_proof(
+            &storage_root,
+            &storage_proof.key,
+            &rlp_bytes(&storage_proof.value.to_be_bytes_trimmed_vec()),
+            &storage_proof.proof,
+        )
+    }
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "Storage proof of key {} does not verify against the storage root {storage_root}: {e}",
+            storage_proof.key
+        )
+    })
+}
+
+/// How the trie nodes of the storage proofs returned by the RPC are decoded. Some RPCs return
+/// proofs with extra trailing nodes, or nodes with trailing bytes after their encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrieNodeDecoding {
+    /// Every node must be exactly one RLP encoded list.
+    #[default]
+    Strict,
+    /// The bytes trailing the RLP encoded list of a node are dropped, and so are the nodes from
+    /// the first node that is malformed or not referenced by the node before it.
+    Lenient,
+}
+
+impl TrieNodeDecoding {
+    /// Decodes the trie `nodes` of a storage proof, from the root down.
+    /// # Errors
+    /// Returns an error if a node is malformed in the strict decoding.
+    pub fn decode(self, nodes: &[Bytes]) -> Result<Vec<Bytes>> {
+        let mut decoded: Vec<Bytes> = Vec::with_capacity(nodes.len());
+        for (i, node) in nodes.iter().enumerate() {
+            let node = match (self, rlp_list_len(node)) {
+                (_, Some(len)) if len == node.len() => node.clone(),
+                (Self::Lenient, Some(len)) if len < node.len() => {
+                    Bytes::copy_from_slice(&node[..len])
+                }
+                (Self::Strict, _) => {
+                    anyhow::bail!("Trie node {i} of the proof is not a single RLP encoded list")
+                }
+                (Self::Lenient, _) => {
+                    tracing::debug!("Dropping the trie nodes of the proof from malformed node {i}");
+                    break;
+                }
+            };
+            if self == Self::Lenient
+                && decoded
+                    .last()
+                    .is_some_and(|parent| !references_node(parent, &node))
+            {
+                tracing::debug!("Dropping the trie nodes of the proof from unreferenced node {i}");
+                break;
+            }
+            decoded.push(node);
+        }
+        Ok(decoded)
+    }
+}
+
+/// Returns the length of the RLP encoded list at the start of `node`, including its header.
+fn rlp_list_len(node: &[u8]) -> Option<usize> {
+    match rlp_header(node)? {
+        (true, offset, payload_len) => offset.checked_add(payload_len),
+        (false, ..) => None,
+    }
+}
+
+/// Decodes the header of the RLP item at the start of `bytes` into whether the item is a list,
+/// and the offset and the length of its payload.
+fn rlp_header(bytes: &[u8]) -> Option<(bool, usize, usize)> {
+    let (&prefix, rest) = bytes.split_first()?;
+    let (is_list, short_offset, long_offset) = match prefix {
+        0x00..=0x7f => return Some((false, 0, 1)),
+        0x80..=0xbf => (false, 0x80, 0xb7),
+        0xc0..=0xff => (true, 0xc0, 0xf7),
+    };
+    if prefix <= long_offset {
+        return Some((is_list, 1, usize::from(prefix - short_offset)));
+    }
+    let len_of_len = usize::from(prefix - long_offset);
+    let payload_len = rest
+        .get(..len_of_len)?
+        .iter()
+        .try_fold(0_usize, |len, byte| {
+            len.checked_mul(256)?.checked_add(usize::from(*byte))
+        })?;
+    Some((is_list, 1 + len_of_len, payload_len))
+}
+
+/// Splits the RLP encoded list `node` into the encodings of its items.
+fn rlp_list_items(node: &[u8]) -> Option<Vec<&[u8]>> {
+    let (true, offset, len) = rlp_header(node)? else {
+        return None;
+    };
+    let mut payload = node.get(offset..offset.checked_add(len)?)?;
+    let mut items = vec![];
+    while !payload.is_empty() {
+        let (_, offset, len) = rlp_header(payload)?;
+        let item_len = offset.checked_add(len)?;
+        items.push(payload.get(..item_len)?);
+        payload = &payload[item_len..];
+    }
+    Some(items)
+}
+
+/// Returns the payload of the RLP encoded string `item`.
+fn rlp_string(item: &[u8]) -> Option<&[u8]> {
+    let (false, offset, len) = rlp_header(item)? else {
+        return None;
+    };
+    item.get(offset..offset.checked_add(len)?)
+}
+
+/// Whether a storage proof proves the inclusion of its key in the storage trie, or its exclusion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageProofTerminal {
+    /// The proof ends at a value stored at the full path of the key.
+    Inclusion,
+    /// The path of the key leaves the trie, or the trie is empty.
+    Exclusion,
+}
+
+/// Returns whether `storage_proof` proves the inclusion or the exclusion of its key, by following
+/// the path of the hashed key down its trie nodes to the terminal node. Unlike the proved value,
+/// this tells a slot that is explicitly stored as zero apart from a missing slot.
+/// # Errors
+/// Returns an error if a node is malformed, or if the proof ends before a terminal node.
+pub fn storage_proof_terminal(storage_proof: &StorageProof) -> Result<StorageProofTerminal> {
+    if storage_proof.proof.is_empty() {
+        // Only the empty trie has no node on the path of a key.
+        return Ok(StorageProofTerminal::Exclusion);
+    }
+
+    let path = nibbles(keccak256(storage_proof.key).as_slice());
+    let mut depth = 0;
+    for (i, node) in storage_proof.proof.iter().enumerate() {
+        let malformed = || anyhow::anyhow!("Trie node {i} of the proof is malformed");
+        let items = rlp_list_items(node).ok_or_else(malformed)?;
+        let child = match items.as_slice() {
+            [children @ .., value] if children.len() == 16 => {
+                let Some(&nibble) = path.get(depth) else {
+                    let value = rlp_string(value).ok_or_else(malformed)?;
+                    return Ok(if value.is_empty() {
+                        StorageProofTerminal::Exclusion
+                    } else {
+                        StorageProofTerminal::Inclusion
+                    });
+                };
+                depth += 1;
+                children[usize::from(nibble)]
+            }
+            [encoded_path, child] => {
+                let encoded_path = rlp_string(encoded_path).ok_or_else(malformed)?;
+                let (&flag, rest) = encoded_path.split_first().ok_or_else(malformed)?;
+                let mut node_path = if flag & 0x10 == 0 {
+                    vec![]
+                } else {
+                    vec![flag & 0x0f]
+                };
+                node_path.extend(nibbles(rest));
+                if !path[depth..].starts_with(&node_path) {
+                    return Ok(StorageProofTerminal::Exclusion);
+                }
+                depth += node_path.len();
+                if flag & 0x20 != 0 {
+                    return Ok(if depth == path.len() {
+                        StorageProofTerminal::Inclusion
+                    } else {
+                        StorageProofTerminal::Exclusion
+                    });
+                }
+                *child
+            }
+            _ => return Err(malformed()),
+        };
+        if rlp_string(child).is_some_and(<[u8]>::is_empty) {
+            return Ok(StorageProofTerminal::Exclusion);
+        }
+    }
+    anyhow::bail!(
+        "Storage proof of key {} ends before a terminal node",
+        storage_proof.key
+    )
+}
+
+/// Returns whether the trie node `parent` references `child`, either by hash or by embedding it.
+fn references_node(parent: &[u8], child: &[u8]) -> bool {
+    let hash = keccak256(child);
+    parent.windows(32).any(|window| window == hash.as_slice())
+        || parent.windows(child.len()).any(|window| window == child)
+}
+
+/// A [`ProofInjector`] that generates ethereum storage proofs, see [`inject_ethereum_proofs`].
+pub struct EthereumProofInjector<E, B> {
+    /// The execution client of the source chain.
+    pub eth_client: E,
+    /// The beacon API client of the source chain.
+    pub beacon_api_client: B,
+    /// The layout of the beacon blocks served by the beacon API client.
+    pub block_layout: BeaconBlockLayout,
+    /// The cache of the execution blocks of the beacon blocks, if any, shared across the batches.
+    pub block_cache: Option<Arc<BeaconBlockCache>>,
+    /// The address of the IBC contract.
+    pub ibc_contract_address: String,
+    /// The storage slots of the commitments in the IBC contract.
+    pub commitment_slots: CommitmentSlots,
+    /// The layout of the commitments in the storage of the IBC contract.
+    pub slot_layout: SlotLayout,
+    /// The revision number of the proof height, as tracked by the counterparty client.
+    pub revision_number: u64,
+    /// The beacon slot at which the proofs are generated.
+    pub proof_slot: u64,
+    /// The deadline for generating the proofs of a batch, if any.
+    pub batch_deadline: Option<Instant>,
+    /// The token cancelling the proof generation of a batch, if any.
+    pub cancellation: Option<CancellationToken>,
+    /// The encoding of the proofs.
+    pub proof_encoding: ProofEncoding,
+    /// Whether the proofs are verified against the storage root of the contract before they are
+    /// injected.
+    pub verify: bool,
+    /// How the trie nodes of the proofs returned by the execution client are decoded.
+    pub trie_node_decoding: TrieNodeDecoding,
+    /// The maximum number of trie nodes of a proof, if any.
+    pub max_proof_depth: Option<usize>,
+    /// Whether the proofs must be checked against the header of the block they are served at.
+    pub require_proof_block: bool,
+    /// The metrics counting the generated and failed proofs, if any, shared across the batches.
+    pub metrics: Option<Arc<ProofMetrics>>,
+    /// The request id attached to the proof requests, if any.
+    pub request_id: Option<String>,
+}
+
+#[async_trait::async_trait]
+impl<E: EthProofClient, B: BeaconBlockClient> ProofInjector for EthereumProofInjector<E, B> {
+    async fn inject(
+        &self,
+        recv_msgs: &mut [MsgRecvPacket],
+        ack_msgs: &mut [MsgAcknowledgement],
+        timeout_msgs: &mut [MsgTimeout],
+    ) -> Result<()> {
+        inject_ethereum_proofs(
+            recv_msgs,
+            ack_msgs,
+            timeout_msgs,
+            &self.eth_client,
+            &self.beacon_api_client,
+            &EthProofLayout {
+                ibc_contract_address: &self.ibc_contract_address,
+                commitment_slots: self.commitment_slots,
+                slot_layout: self.slot_layout,
+                revision_number: self.revision_number,
+                proof_codec: &self.proof_encoding,
+            },
+            self.proof_slot,
+            &EthProofOptions {
+                block_layout: self.block_layout,
+                block_cache: self.block_cache.as_deref(),
+                verify: self.verify,
+                trie_node_decoding: self.trie_node_decoding,
+                max_proof_depth: self.max_proof_depth,
+                require_proof_block: self.require_proof_block,
+                batch_deadline: self.batch_deadline,
+                cancellation: self.cancellation.as_ref(),
+                metrics: self.metrics.as_deref(),
+                request_id: self.request_id.as_deref(),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        time::Duration,
+    };
+
+    use alloy::{
+        primitives::{b256, keccak256, Address, Bytes, FixedBytes, B256, U256},
+        rpc::types::EIP1186AccountProofResponse,
+    };
+    use ethereum_apis::beacon_api::error::{BeaconApiClientError, NotFoundError};
+    use ethereum_light_client::{
+        client_state::ClientState,
+        consensus_state::ConsensusState,
+        membership::{evm_ics26_commitment_path, verify_membership, verify_non_membership},
+    };
+    use ethereum_types::{
+        consensus::beacon_block::BeaconBlock, execution::storage_proof::StorageProof,
+    };
+    use futures::{
+        executor::block_on,
+        stream::{self, BoxStream},
+        StreamExt,
+    };
+    use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
+    use ibc_proto_eureka::ibc::core::{
+        channel::v2::{Acknowledgement, MsgAcknowledgement, MsgRecvPacket, MsgTimeout},
+        client::v1::Height,
+    };
+
+    use crate::utils::{
+        client::{
+            BeaconBlockClient, ClientHead, ClientHeadClient, EthProofClient, ExecutionBlockHeader,
+            StateDiffClient, StorageDiff,
+        },
+        clock::MockClock,
+        cosmos::{ProofInjectionReport, ProofKind},
+        error::ProofError,
+        snapshot::StorageSnapshot,
+    };
+
+    use super::{
+        assert_single_proof_encoding, blocks_until_final, decode_storage_proof,
+        discover_commitment_slot, encode_compact_storage_proof, encode_storage_proof,
+        ensure_recent_execution_payload, ethereum_receipt_exists, execution_block_at_slot,
+        first_execution_block_from_slot, get_commitment_proof, get_commitment_proofs_batch,
+        inject_ethereum_proofs, inject_ethereum_proofs_at_block, inject_ethereum_proofs_raw,
+        inject_ethereum_proofs_with_state, min_finalized_slot_for_block, plan_client_updates,
+        prove_at_client_head, resolve_finalized_proof_slot, storage_proof_terminal,
+        subscribe_commitment, verify_batch_against_root, BeaconBlockCache, BeaconBlockLayout,
+        CommitmentSlots, CompactProofCodec, EthProofLayout, EthProofOptions, JsonProofCodec,
+        PlannedTx, ProofBlockId, ProofCodec, ProofEncoding, SlotLayout, StorageProofOptions,
+        StorageProofTerminal, TrieNodeDecoding, DEFAULT_BEACON_BLOCK_CACHE_CAPACITY,
+    };
+
+    fn test_packet() -> Packet {
+        Packet {
+            sequence: 1,
+            sourceClient: "07-tendermint-0".to_string(),
+            destClient: "08-wasm-0".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![],
+        }
+    }
+
+    fn recv_msg(packet: Packet) -> MsgRecvPacket {
+        MsgRecvPacket {
+            packet: Some(packet.into()),
+            ..Default::default()
+        }
+    }
+
+    /// A mock ethereum client that records the blocks and the ids of the proof requests.
+    #[derive(Default)]
+    struct MockEthClient {
+        requested_blocks: Mutex<Vec<String>>,
+        request_ids: Mutex<Vec<Option<String>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockEthClient {
+        async fn get_proof(
+            &self,
+            _address: &str,
+            storage_keys: Vec<String>,
+            block_id: String,
+            request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            self.requested_blocks.lock().unwrap().push(block_id);
+            self.request_ids
+                .lock()
+                .unwrap()
+                .push(request_id.map(ToString::to_string));
+            Ok(serde_json::from_value(serde_json::json!({
+                "address": "0x0000000000000000000000000000000000000000",
+                "balance": "0x0",
+                "codeHash": B256::ZERO,
+                "nonce": "0x0",
+                "storageHash": B256::ZERO,
+                "accountProof": [],
+                "storageProof": [{
+                    "key": format!("0x{}", storage_keys[0]),
+                    "value": "0x1",
+                    "proof": [],
+                }],
+            }))?)
+        }
+    }
+
+    /// A mock ethereum client that replays a recorded `eth_getProof` response.
+    struct MockRecordedEthClient(&'static str);
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockRecordedEthClient {
+        async fn get_proof(
+            &self,
+            _address: &str,
+            _storage_keys: Vec<String>,
+            _block_id: String,
+            _request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            Ok(serde_json::from_str(self.0)?)
+        }
+    }
+
+    /// A mock ethereum client serving the proofs of a snapshot in the reverse order of the
+    /// requested storage keys, counting the requests.
+    struct MockReversedEthClient {
+        state: StorageSnapshot,
+        requests: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockReversedEthClient {
+        async fn get_proof(
+            &self,
+            address: &str,
+            storage_keys: Vec<String>,
+            block_id: String,
+            request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            let mut proof = self
+                .state
+                .get_proof(address, storage_keys, block_id, request_id)
+                .await?;
+            proof.storage_proof.reverse();
+            Ok(proof)
+        }
+    }
+
+    /// A mock ethereum client serving the proofs of a snapshot with a stale storage root.
+    struct MockStaleEthClient(StorageSnapshot);
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockStaleEthClient {
+        async fn get_proof(
+            &self,
+            address: &str,
+            storage_keys: Vec<String>,
+            block_id: String,
+            request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            let mut proof = self
+                .0
+                .get_proof(address, storage_keys, block_id, request_id)
+                .await?;
+            proof.storage_hash = B256::repeat_byte(0x33);
+            Ok(proof)
+        }
+    }
+
+    /// A mock ethereum client serving the proofs of a snapshot with a corrupted trie node.
+    struct MockCorruptedEthClient(StorageSnapshot);
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockCorruptedEthClient {
+        async fn get_proof(
+            &self,
+            address: &str,
+            storage_keys: Vec<String>,
+            block_id: String,
+            request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            let mut proof = self
+                .0
+                .get_proof(address, storage_keys, block_id, request_id)
+                .await?;
+            for storage_proof in &mut proof.storage_proof {
+                if let Some(node) = storage_proof.proof.last_mut() {
+                    let mut corrupted = node.to_vec();
+                    if let Some(byte) = corrupted.last_mut() {
+                        *byte ^= 0xff;
+                    }
+                    *node = corrupted.into();
+                }
+            }
+            Ok(proof)
+        }
+    }
+
+    /// A mock ethereum client serving the proofs of a snapshot along with the header of the block
+    /// it claims to serve them at.
+    struct MockBlockHeaderEthClient {
+        state: StorageSnapshot,
+        header: ExecutionBlockHeader,
+    }
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockBlockHeaderEthClient {
+        async fn get_proof(
+            &self,
+            address: &str,
+            storage_keys: Vec<String>,
+            block_id: String,
+            request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            self.state
+                .get_proof(address, storage_keys, block_id, request_id)
+                .await
+        }
+
+        async fn block_header(
+            &self,
+            _block_id: ProofBlockId,
+        ) -> anyhow::Result<Option<ExecutionBlockHeader>> {
+            Ok(Some(self.header))
+        }
+    }
+
+    /// A mock ethereum client serving the proofs of a snapshot with an extra trailing node, and
+    /// trailing bytes after the encoding of the root node.
+    struct MockMalformedEthClient(StorageSnapshot);
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockMalformedEthClient {
+        async fn get_proof(
+            &self,
+            address: &str,
+            storage_keys: Vec<String>,
+            block_id: String,
+            request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            let mut response = self
+                .0
+                .get_proof(address, storage_keys, block_id, request_id)
+                .await?;
+            let proof = &mut response.storage_proof[0].proof;
+            proof[0] = [proof[0].to_vec(), vec![0]].concat().into();
+            proof.push(Bytes::from_static(&[0xc2, 0x80, 0x80]));
+            Ok(response)
+        }
+    }
+
+    /// A recorded `eth_getProof` response of a node that returned no storage proof.
+    const EMPTY_STORAGE_PROOF_RESPONSE: &str = r#"{
+        "address": "0x5fbdb2315678afecb367f032d93f642f64180aa3",
+        "balance": "0x0",
+        "codeHash": "0x2a3f92b1e7c1596d6ba9c5049f0b1cd1d5f5c9b6bc8e0a7e2d5ee6a6c46e4bd3",
+        "nonce": "0x1",
+        "storageHash": "0x6a4cbb5c1d348a6ab8a3a9a64a9eed7d4d9e5f0b6c0f8a0bd1e2c1d0f6c0a4e1",
+        "accountProof": [],
+        "storageProof": []
+    }"#;
+
+    /// A mock beacon node that only serves SSZ encoded blocks.
+    struct MockSszBeaconClient {
+        signed_beacon_block: Vec<u8>,
+    }
+
+    #[async_trait::async_trait]
+    impl BeaconBlockClient for MockSszBeaconClient {
+        async fn beacon_block(&self, _block_id: &str) -> anyhow::Result<BeaconBlock> {
+            anyhow::bail!("406 Not Acceptable")
+        }
+
+        async fn beacon_block_ssz(&self, _block_id: &str) -> anyhow::Result<Vec<u8>> {
+            Ok(self.signed_beacon_block.clone())
+        }
+
+        async fn finalized_slot(&self) -> anyhow::Result<u64> {
+            Ok(0)
+        }
+    }
+
+    /// A mock beacon node that only serves JSON encoded blocks.
+    struct MockJsonBeaconClient {
+        beacon_block: serde_json::Value,
+    }
+
+    #[async_trait::async_trait]
+    impl BeaconBlockClient for MockJsonBeaconClient {
+        async fn beacon_block(&self, _block_id: &str) -> anyhow::Result<BeaconBlock> {
+            Ok(serde_json::from_value(self.beacon_block.clone())?)
+        }
+
+        async fn beacon_block_json(&self, _block_id: &str) -> anyhow::Result<serde_json::Value> {
+            Ok(self.beacon_block.clone())
+        }
+
+        async fn beacon_block_ssz(&self, _block_id: &str) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("404 Not Found")
+        }
+
+        async fn finalized_slot(&self) -> anyhow::Result<u64> {
+            // Every slot is finalized.
+            Ok(u64::MAX)
+        }
+    }
+
+    /// A mock beacon node serving the SSZ encoded blocks of `execution_blocks`, given as
+    /// `slot => execution block number`. The other slots are missed.
+    struct MockSlotsBeaconClient {
+        execution_blocks: BTreeMap<u64, u64>,
+        finalized_slot: u64,
+    }
+
+    #[async_trait::async_trait]
+    impl BeaconBlockClient for MockSlotsBeaconClient {
+        async fn beacon_block(&self, _block_id: &str) -> anyhow::Result<BeaconBlock> {
+            anyhow::bail!("406 Not Acceptable")
+        }
+
+        async fn beacon_block_ssz(&self, block_id: &str) -> anyhow::Result<Vec<u8>> {
+            let block_number = self
+                .execution_blocks
+                .get(&block_id.parse()?)
+                .ok_or_else(|| {
+                    BeaconApiClientError::NotFound(NotFoundError {
+                        status_code: 404,
+                        error: "Not Found".to_string(),
+                        message: format!("No block at slot {block_id}"),
+                    })
+                })?;
+            Ok(ssz_signed_beacon_block(*block_number, B256::ZERO))
+        }
+
+        async fn finalized_slot(&self) -> anyhow::Result<u64> {
+            Ok(self.finalized_slot)
+        }
+    }
+
+    /// A mock beacon client whose node keeps failing.
+    struct MockUnavailableBeaconClient;
+
+    #[async_trait::async_trait]
+    impl BeaconBlockClient for MockUnavailableBeaconClient {
+        async fn beacon_block(&self, _block_id: &str) -> anyhow::Result<BeaconBlock> {
+            anyhow::bail!("503 Service Unavailable")
+        }
+
+        async fn beacon_block_ssz(&self, _block_id: &str) -> anyhow::Result<Vec<u8>> {
+            anyhow::bail!("503 Service Unavailable")
+        }
+
+        async fn finalized_slot(&self) -> anyhow::Result<u64> {
+            Ok(108)
+        }
+    }
+
+    /// Encodes a signed beacon block with the execution payload up to the block hash, and the
+    /// offsets leading to it.
+    fn ssz_signed_beacon_block(block_number: u64, block_hash: B256) -> Vec<u8> {
+        let mut execution_payload = vec![0; 504];
+        execution_payload[404..412].copy_from_slice(&block_number.to_le_bytes());
+        execution_payload[472..504].copy_from_slice(block_hash.as_slice());
+        let mut body = vec![0; 400];
+        body[380..384].copy_from_slice(&400_u32.to_le_bytes());
+        body.extend(execution_payload);
+        let mut message = vec![0; 84];
+        message[80..84].copy_from_slice(&84_u32.to_le_bytes());
+        message.extend(body);
+        let mut signed_beacon_block = vec![0; 100];
+        signed_beacon_block[..4].copy_from_slice(&100_u32.to_le_bytes());
+        signed_beacon_block.extend(message);
+        signed_beacon_block
+    }
+
+    #[test]
+    fn test_ethereum_receipt_exists() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            evm_ics26_commitment_path(&packet.receipt_commitment_path(), slot).into(),
+            U256::from(1),
+        );
+        let receipt_exists = |packet: &Packet| {
+            block_on(ethereum_receipt_exists(
+                &state,
+                packet.receipt_commitment_path(),
+                &state.address().to_string(),
+                ProofBlockId::Number(0),
+                slot,
+                SlotLayout::Flat,
+            ))
+            .unwrap()
+        };
+        assert!(receipt_exists(&packet));
+        assert!(!receipt_exists(&Packet {
+            sequence: 2,
+            ..test_packet()
+        }));
+    }
+
+    #[test]
+    fn test_discover_commitment_slot() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            SlotLayout::Nested
+                .storage_key(&packet.commitment_path(), slot)
+                .unwrap()
+                .into(),
+            U256::from_be_slice(&packet.commitment()),
+        );
+        // Another packet is stored at the slot of the flat layout.
+        state.insert(
+            evm_ics26_commitment_path(&packet.commitment_path(), U256::from(2)).into(),
+            U256::from(1),
+        );
+        let candidate_slots = (0..10).map(U256::from).collect::<Vec<_>>();
+        let layouts = [
+            SlotLayout::Flat,
+            SlotLayout::AbiEncodedPath,
+            SlotLayout::Nested,
+        ];
+        let discover = |packet: &Packet, layouts: &[SlotLayout]| {
+            block_on(discover_commitment_slot(
+                &state,
+                &state.address().to_string(),
+                ProofBlockId::Number(0),
+                packet,
+                &candidate_slots,
+                layouts,
+            ))
+        };
+
+        assert_eq!(
+            discover(&packet, &layouts).unwrap(),
+            (slot, SlotLayout::Nested)
+        );
+        assert!(discover(&packet, &layouts[..2]).is_err());
+        let unknown_packet = Packet {
+            sequence: 2,
+            ..test_packet()
+        };
+        assert!(discover(&unknown_packet, &layouts).is_err());
+    }
+
+    #[test]
+    fn test_nested_slot_layout() {
+        let packet = test_packet();
+        let slot = U256::from(1);
+        // keccak256(uint256(1) . keccak256(keccak256("07-tendermint-0" . 0x01) . uint256(1)))
+        let nested_key: U256 =
+            b256!("45a57eab7e14a15c1675e831ffcc620152485fb39d8d3317bcb74ffe6752ca42").into();
+
+        assert_eq!(
+            SlotLayout::Nested
+                .storage_key(&packet.commitment_path(), slot)
+                .unwrap(),
+            nested_key
+        );
+        assert_eq!(
+            SlotLayout::Flat
+                .storage_key(&packet.commitment_path(), slot)
+                .unwrap(),
+            evm_ics26_commitment_path(&packet.commitment_path(), slot)
+        );
+        assert!(SlotLayout::Nested.storage_key(b"short", slot).is_err());
+
+        // The commitments of each channel version are stored in a mapping of their own.
+        let path = packet.commitment_path();
+        let versioned_key = |version: &str| {
+            SlotLayout::Flat
+                .versioned_storage_key(&path, slot, version)
+                .unwrap()
+        };
+        assert_ne!(versioned_key("ics20-1"), versioned_key("ics20-2"));
+        let version_slot = keccak256([b"ics20-1".as_slice(), &slot.to_be_bytes::<32>()].concat());
+        assert_eq!(
+            versioned_key("ics20-1"),
+            evm_ics26_commitment_path(&path, version_slot.into())
+        );
+
+        // keccak256(abi.encode("07-tendermint-0" . 0x01 . uint64(1), uint256(1)))
+        let abi_encoded_key: U256 =
+            b256!("58960c2e1cf524583ffb00b0dc8183ad4348e37ca4d25fc64fcfeaa555053f04").into();
+        assert_eq!(
+            SlotLayout::AbiEncodedPath
+                .storage_key(&packet.commitment_path(), slot)
+                .unwrap(),
+            abi_encoded_key
+        );
+
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(nested_key.into(), U256::from_be_slice(&packet.commitment()));
+        let storage_proof = block_on(get_commitment_proof(
+            &state,
+            &state.address().to_string(),
+            ProofBlockId::Number(0),
+            packet.commitment_path(),
+            slot,
+            SlotLayout::Nested,
+            &StorageProofOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(
+            storage_proof.value,
+            U256::from_be_slice(&packet.commitment())
+        );
+    }
+
+    #[test]
+    fn test_get_commitment_proof_slot_cache() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        // The pre-computed key is not the one derived from the slot.
+        let cached_key = B256::repeat_byte(0x33);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(cached_key, U256::from_be_slice(&packet.commitment()));
+        let slot_cache = HashMap::from([(packet.commitment_path(), cached_key.into())]);
+        let get_proof = |slot_cache| {
+            block_on(get_commitment_proof(
+                &state,
+                &state.address().to_string(),
+                ProofBlockId::Number(0),
+                packet.commitment_path(),
+                slot,
+                SlotLayout::Flat,
+                &StorageProofOptions {
+                    slot_cache,
+                    ..Default::default()
+                },
+            ))
+            .unwrap()
+        };
+
+        let storage_proof = get_proof(Some(&slot_cache));
+        assert_eq!(storage_proof.key, cached_key);
+        assert_eq!(
+            storage_proof.value,
+            U256::from_be_slice(&packet.commitment())
+        );
+
+        // Without the cache, the key is derived from the slot, where nothing is stored.
+        let storage_proof = get_proof(None);
+        assert_eq!(
+            storage_proof.key,
+            B256::from(evm_ics26_commitment_path(&packet.commitment_path(), slot))
+        );
+        assert!(storage_proof.value.is_zero());
+    }
+
+    #[test]
+    fn test_get_commitment_proof_lenient_decoding() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let key = evm_ics26_commitment_path(&packet.commitment_path(), slot);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(key.into(), U256::from_be_slice(&packet.commitment()));
+        state.insert(B256::repeat_byte(0x22), U256::from(1));
+        let (_, expected_proof) = state.prove(key.into());
+        let address = state.address().to_string();
+        let client = MockMalformedEthClient(state);
+
+        let get_proof = |trie_node_decoding| {
+            block_on(get_commitment_proof(
+                &client,
+                &address,
+                ProofBlockId::Number(0),
+                packet.commitment_path(),
+                slot,
+                SlotLayout::Flat,
+                &StorageProofOptions {
+                    trie_node_decoding,
+                    ..Default::default()
+                },
+            ))
+        };
+        assert!(get_proof(TrieNodeDecoding::Strict).is_err());
+        let storage_proof = get_proof(TrieNodeDecoding::Lenient).unwrap();
+        assert_eq!(storage_proof.proof, expected_proof);
+
+        // Well formed proofs decode the same in both decodings.
+        assert_eq!(
+            TrieNodeDecoding::Strict.decode(&expected_proof).unwrap(),
+            expected_proof
+        );
+        assert_eq!(
+            TrieNodeDecoding::Lenient.decode(&expected_proof).unwrap(),
+            expected_proof
+        );
+    }
+
+    #[test]
+    fn test_get_commitment_proof_verification() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            evm_ics26_commitment_path(&packet.commitment_path(), slot).into(),
+            U256::from_be_slice(&packet.commitment()),
+        );
+        state.insert(B256::repeat_byte(0x22), U256::from(1));
+        let address = state.address().to_string();
+
+        let get_proof_from = |path: Vec<u8>| {
+            block_on(get_commitment_proof(
+                &state,
+                &address,
+                ProofBlockId::Number(0),
+                path,
+                slot,
+                SlotLayout::Flat,
+                &StorageProofOptions {
+                    verify: true,
+                    ..Default::default()
+                },
+            ))
+        };
+        // Both a stored commitment and an absent one verify.
+        get_proof_from(packet.commitment_path()).unwrap();
+        get_proof_from(packet.receipt_commitment_path()).unwrap();
+
+        let stale_client = MockStaleEthClient(state);
+        let get_stale_proof = |verify| {
+            block_on(get_commitment_proof(
+                &stale_client,
+                &address,
+                ProofBlockId::Number(0),
+                packet.commitment_path(),
+                slot,
+                SlotLayout::Flat,
+                &StorageProofOptions {
+                    verify,
+                    ..Default::default()
+                },
+            ))
+        };
+        assert!(get_stale_proof(true).is_err());
+        // The verification is skipped if not requested.
+        get_stale_proof(false).unwrap();
+    }
+
+    #[test]
+    fn test_get_commitment_proofs_batch() {
+        let packets = [1, 2, 3].map(|sequence| Packet {
+            sequence,
+            ..test_packet()
+        });
+        let slot = U256::from(5);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        for packet in &packets[..2] {
+            state.insert(
+                evm_ics26_commitment_path(&packet.commitment_path(), slot).into(),
+                U256::from_be_slice(&packet.commitment()),
+            );
+        }
+        let client = MockReversedEthClient {
+            state,
+            requests: AtomicUsize::new(0),
+        };
+
+        let paths = packets
+            .iter()
+            .map(|packet| (packet.commitment_path(), slot))
+            .collect::<Vec<_>>();
+        let storage_proofs = block_on(get_commitment_proofs_batch(
+            &client,
+            &client.state.address().to_string(),
+            ProofBlockId::Number(0),
+            &paths,
+            SlotLayout::Flat,
+            &StorageProofOptions {
+                verify: true,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        // The proofs are fetched with a single request, and returned in the order of the paths.
+        assert_eq!(client.requests.load(Ordering::SeqCst), 1);
+        for (packet, storage_proof) in packets.iter().zip(&storage_proofs) {
+            assert_eq!(
+                storage_proof.key,
+                B256::from(evm_ics26_commitment_path(&packet.commitment_path(), slot))
+            );
+        }
+        assert_eq!(
+            storage_proofs[0].value,
+            U256::from_be_slice(&packets[0].commitment())
+        );
+        assert!(storage_proofs[2].value.is_zero());
+    }
+
+    #[test]
+    fn test_get_commitment_proof_max_depth() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        for sequence in 1..=64 {
+            let packet = Packet {
+                sequence,
+                ..test_packet()
+            };
+            state.insert(
+                evm_ics26_commitment_path(&packet.commitment_path(), slot).into(),
+                U256::from_be_slice(&packet.commitment()),
+            );
+        }
+        let get_proof = |max_proof_depth| {
+            block_on(get_commitment_proof(
+                &state,
+                &state.address().to_string(),
+                ProofBlockId::Number(0),
+                packet.commitment_path(),
+                slot,
+                SlotLayout::Flat,
+                &StorageProofOptions {
+                    max_proof_depth,
+                    ..Default::default()
+                },
+            ))
+        };
+
+        let depth = get_proof(None).unwrap().proof.len();
+        assert!(depth > 1);
+        get_proof(Some(depth)).unwrap();
+        let err = get_proof(Some(depth - 1)).unwrap_err().to_string();
+        assert!(err.contains("more than the maximum depth"), "{err}");
+    }
+
+    #[test]
+    fn test_get_commitment_proof_account_mismatch() {
+        let get_proof = |ibc_contract_address| {
+            block_on(get_commitment_proof(
+                &MockRecordedEthClient(EMPTY_STORAGE_PROOF_RESPONSE),
+                ibc_contract_address,
+                ProofBlockId::Number(255),
+                test_packet().commitment_path(),
+                U256::ZERO,
+                SlotLayout::Flat,
+                &StorageProofOptions::default(),
+            ))
+            .unwrap_err()
+            .to_string()
+        };
+
+        // The recorded response is of the account 0x5fbdb2315678afecb367f032d93f642f64180aa3.
+        let err = get_proof("0x0000000000000000000000000000000000000001");
+        assert!(
+            err.contains("returned for account 0x5FbDB2315678afecb367f032d93F642f64180aa3"),
+            "{err}"
+        );
+        // The address is compared regardless of its checksum casing.
+        let err = get_proof("0x5FBDB2315678AFECB367F032D93F642F64180AA3");
+        assert!(err.contains("no storage proof"), "{err}");
+    }
+
+    #[test]
+    fn test_get_commitment_proof_without_storage_proof() {
+        let err = block_on(get_commitment_proof(
+            &MockRecordedEthClient(EMPTY_STORAGE_PROOF_RESPONSE),
+            "0x5fbdb2315678afecb367f032d93f642f64180aa3",
+            ProofBlockId::Number(255),
+            test_packet().commitment_path(),
+            U256::ZERO,
+            SlotLayout::Flat,
+            &StorageProofOptions::default(),
+        ))
+        .unwrap_err();
+
+        let storage_key = evm_ics26_commitment_path(&test_packet().commitment_path(), U256::ZERO);
+        match err.downcast_ref::<ProofError>() {
+            Some(ProofError::MissingStorageProof { storage_key: key }) => {
+                assert_eq!(*key, alloy::hex::encode(storage_key.to_be_bytes_vec()));
+            }
+            _ => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_get_commitment_proof_at_block_hash() {
+        let client = MockEthClient::default();
+        let block_hash = B256::repeat_byte(0xab);
+
+        let storage_proof = block_on(get_commitment_proof(
+            &client,
+            "0x0000000000000000000000000000000000000000",
+            ProofBlockId::Hash(block_hash),
+            test_packet().commitment_path(),
+            U256::ZERO,
+            SlotLayout::Flat,
+            &StorageProofOptions::default(),
+        ))
+        .unwrap();
+        block_on(get_commitment_proof(
+            &client,
+            "0x0000000000000000000000000000000000000000",
+            ProofBlockId::Number(255),
+            test_packet().commitment_path(),
+            U256::ZERO,
+            SlotLayout::Flat,
+            &StorageProofOptions {
+                request_id: Some("req-1"),
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+
+        assert_eq!(storage_proof.value, U256::from(1));
+        assert_eq!(
+            *client.requested_blocks.lock().unwrap(),
+            vec![format!("0x{}", "ab".repeat(32)), "0xff".to_string()]
+        );
+        assert_eq!(
+            *client.request_ids.lock().unwrap(),
+            vec![None, Some("req-1".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_proof_codec_round_trip() {
+        let storage_proof = StorageProof {
+            key: B256::repeat_byte(0x01),
+            value: U256::from(42),
+            proof: vec![vec![0xab; 100].into(), vec![0xcd; 40].into()],
+        };
+        let codecs: [&dyn ProofCodec; 5] = [
+            &JsonProofCodec,
+            &CompactProofCodec,
+            &ProofEncoding::Json,
+            &ProofEncoding::CompressedJson,
+            &ProofEncoding::Compact,
+        ];
+        for codec in codecs {
+            let proof = codec.encode(&storage_proof).unwrap();
+            assert_eq!(codec.decode(&proof).unwrap(), storage_proof);
+            // The light client decodes the proofs of every codec.
+            assert_eq!(decode_storage_proof(&proof).unwrap(), storage_proof);
+        }
+
+        // The codecs reject the proofs of one another.
+        let compact = CompactProofCodec.encode(&storage_proof).unwrap();
+        assert!(JsonProofCodec.decode(&compact).is_err());
+        let json = JsonProofCodec.encode(&storage_proof).unwrap();
+        assert!(CompactProofCodec.decode(&json).is_err());
+    }
+
+    #[test]
+    fn test_encode_storage_proof_round_trip() {
+        let storage_proof = StorageProof {
+            key: B256::repeat_byte(1),
+            value: U256::from(42),
+            proof: vec![Bytes::from(vec![0xab; 532]); 8],
+        };
+
+        let plain = encode_storage_proof(&storage_proof, ProofEncoding::Json).unwrap();
+        let compressed =
+            encode_storage_proof(&storage_proof, ProofEncoding::CompressedJson).unwrap();
+        assert!(compressed.len() < plain.len());
+        assert_eq!(decode_storage_proof(&plain).unwrap(), storage_proof);
+        assert_eq!(decode_storage_proof(&compressed).unwrap(), storage_proof);
+
+        // A proof that does not shrink when compressed is kept as is.
+        let empty_proof = StorageProof {
+            key: B256::ZERO,
+            value: U256::ZERO,
+            proof: vec![],
+        };
+        assert_eq!(
+            encode_storage_proof(&empty_proof, ProofEncoding::CompressedJson).unwrap(),
+            serde_json::to_vec(&empty_proof).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encode_compact_storage_proof_round_trip() {
+        let storage_proof = StorageProof {
+            key: B256::repeat_byte(1),
+            value: U256::from(42),
+            proof: vec![Bytes::from(vec![0xab; 532]), Bytes::from(vec![0xcd; 83])],
+        };
+
+        let json = encode_storage_proof(&storage_proof, ProofEncoding::Json).unwrap();
+        let compact = encode_storage_proof(&storage_proof, ProofEncoding::Compact).unwrap();
+        assert_eq!(
+            compact,
+            encode_compact_storage_proof(&storage_proof).unwrap()
+        );
+        assert!(
+            compact.len() < json.len() / 2,
+            "{} >= {}",
+            compact.len(),
+            json.len()
+        );
+        assert_eq!(decode_storage_proof(&compact).unwrap(), storage_proof);
+
+        let empty_proof = StorageProof {
+            key: B256::ZERO,
+            value: U256::ZERO,
+            proof: vec![],
+        };
+        let compact = encode_compact_storage_proof(&empty_proof).unwrap();
+        assert_eq!(compact.len(), 65);
+        assert_eq!(decode_storage_proof(&compact).unwrap(), empty_proof);
+
+        let long_node_proof = StorageProof {
+            proof: vec![Bytes::from(vec![0; usize::from(u16::MAX) + 1])],
+            ..empty_proof
+        };
+        assert!(encode_compact_storage_proof(&long_node_proof).is_err());
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_with_state() {
+        let ibc_contract_slot = U256::from(7);
+        let packet = test_packet();
+        let unreceived_packet = Packet {
+            sequence: 2,
+            ..test_packet()
+        };
+
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            evm_ics26_commitment_path(&packet.commitment_path(), ibc_contract_slot).into(),
+            U256::from_be_slice(&packet.commitment()),
+        );
+        state.insert(B256::repeat_byte(0x22), U256::from(1));
+
+        let proof_height = Height {
+            revision_number: 0,
+            revision_height: 42,
+        };
+        let mut recv_msgs = vec![recv_msg(packet.clone())];
+        let mut timeout_msgs = vec![MsgTimeout {
+            packet: Some(unreceived_packet.clone().into()),
+            ..Default::default()
+        }];
+        let report = block_on(inject_ethereum_proofs_with_state(
+            &mut recv_msgs,
+            &mut [],
+            &mut timeout_msgs,
+            &state,
+            ibc_contract_slot,
+            proof_height,
+        ))
+        .unwrap();
+        assert_eq!(recv_msgs[0].proof_height, Some(proof_height));
+        assert_eq!(
+            report,
+            ProofInjectionReport {
+                used_height: proof_height,
+                recv_count: 1,
+                ack_count: 0,
+                timeout_count: 1,
+            }
+        );
+
+        let consensus_state = ConsensusState {
+            slot: 42,
+            state_root: B256::ZERO,
+            storage_root: state.storage_root(),
+            timestamp: 0,
+            current_sync_committee: FixedBytes::default(),
+            next_sync_committee: None,
+        };
+        let client_state = ClientState {
+            ibc_commitment_slot: ibc_contract_slot,
+            ..Default::default()
+        };
+        verify_membership(
+            consensus_state.clone(),
+            client_state.clone(),
+            recv_msgs[0].proof_commitment.clone(),
+            vec![packet.commitment_path()],
+            packet.commitment(),
+        )
+        .unwrap();
+        verify_non_membership(
+            consensus_state,
+            client_state,
+            timeout_msgs[0].proof_unreceived.clone(),
+            vec![unreceived_packet.receipt_commitment_path()],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_revision_number() {
+        let ibc_contract_slot = U256::from(7);
+        let packet = test_packet();
+
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            evm_ics26_commitment_path(&packet.commitment_path(), ibc_contract_slot).into(),
+            U256::from_be_slice(&packet.commitment()),
+        );
+        let beacon_client = MockJsonBeaconClient {
+            beacon_block: serde_json::json!({
+                "message": { "body": { "execution_payload": {
+                    "block_number": "123",
+                    "block_hash": B256::repeat_byte(0xcd),
+                } } }
+            }),
+        };
+
+        let mut recv_msgs = vec![recv_msg(packet)];
+        block_on(inject_ethereum_proofs(
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+            &state,
+            &beacon_client,
+            &EthProofLayout {
+                ibc_contract_address: &state.address().to_string(),
+                commitment_slots: ibc_contract_slot.into(),
+                slot_layout: SlotLayout::Flat,
+                revision_number: 3,
+                proof_codec: &ProofEncoding::Json,
+            },
+            42,
+            &EthProofOptions {
+                block_layout: BeaconBlockLayout::ExecutionPayload,
+                ..Default::default()
+            },
+        ))
+        .unwrap();
+        assert_eq!(
+            recv_msgs[0].proof_height,
+            Some(Height {
+                revision_number: 3,
+                revision_height: 42,
+            })
+        );
+    }
+
+    #[test]
+    fn test_resolve_finalized_proof_slot() {
+        let client = MockSlotsBeaconClient {
+            execution_blocks: BTreeMap::from([(100, 50), (101, 51), (102, 52)]),
+            finalized_slot: 101,
+        };
+
+        assert_eq!(
+            block_on(resolve_finalized_proof_slot(
+                &client,
+                50,
+                BeaconBlockLayout::default()
+            ))
+            .unwrap(),
+            101
+        );
+        assert_eq!(
+            block_on(resolve_finalized_proof_slot(
+                &client,
+                51,
+                BeaconBlockLayout::default()
+            ))
+            .unwrap(),
+            101
+        );
+        // The block of slot 102 is not finalized yet.
+        let err = block_on(resolve_finalized_proof_slot(
+            &client,
+            52,
+            BeaconBlockLayout::default(),
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("not finalized yet"));
+    }
+
+    #[test]
+    fn test_min_finalized_slot_for_block() {
+        // Slots 103, 104 and 107 are missed.
+        let client = MockSlotsBeaconClient {
+            execution_blocks: BTreeMap::from([
+                (100, 50),
+                (101, 51),
+                (102, 52),
+                (105, 53),
+                (106, 54),
+                (108, 55),
+                (109, 56),
+            ]),
+            finalized_slot: 108,
+        };
+
+        for (block_number, slot) in [(50, 100), (52, 102), (53, 105), (55, 108)] {
+            assert_eq!(
+                block_on(min_finalized_slot_for_block(
+                    &client,
+                    block_number,
+                    100,
+                    BeaconBlockLayout::default()
+                ))
+                .unwrap(),
+                slot
+            );
+        }
+        // The search starts at the given slot.
+        assert_eq!(
+            block_on(min_finalized_slot_for_block(
+                &client,
+                50,
+                103,
+                BeaconBlockLayout::default()
+            ))
+            .unwrap(),
+            105
+        );
+        // The block of slot 109 is not finalized yet.
+        assert!(block_on(min_finalized_slot_for_block(
+            &client,
+            56,
+            100,
+            BeaconBlockLayout::default()
+        ))
+        .is_err());
+
+        // Only the missed slots are skipped, the failures of the beacon node are returned.
+        assert_eq!(
+            block_on(first_execution_block_from_slot(
+                &client,
+                103,
+                108,
+                BeaconBlockLayout::default()
+            ))
+            .unwrap(),
+            (105, 53)
+        );
+        let err = block_on(first_execution_block_from_slot(
+            &MockUnavailableBeaconClient,
+            103,
+            108,
+            BeaconBlockLayout::default(),
+        ))
+        .unwrap_err();
+        assert!(format!("{err:#}").contains("503 Service Unavailable"));
+    }
+
+    #[test]
+    fn test_execution_block_at_slot_layouts() {
+        let block_hash = B256::repeat_byte(0xcd);
+        let execution_payload = serde_json::json!({
+            "block_number": "123",
+            "block_hash": block_hash,
+        });
+
+        let client = MockJsonBeaconClient {
+            beacon_block: serde_json::json!({
+                "message": { "body": { "execution_payload": execution_payload } }
+            }),
+        };
+        assert_eq!(
+            block_on(execution_block_at_slot(
+                &client,
+                10,
+                BeaconBlockLayout::ExecutionPayload
+            ))
+            .unwrap(),
+            (123, block_hash)
+        );
+        assert!(block_on(execution_block_at_slot(
+            &client,
+            10,
+            BeaconBlockLayout::ExecutionPayloadHeader
+        ))
+        .is_err());
+
+        let client = MockJsonBeaconClient {
+            beacon_block: serde_json::json!({
+                "message": { "body": { "execution_payload_header": execution_payload } }
+            }),
+        };
+        assert_eq!(
+            block_on(execution_block_at_slot(
+                &client,
+                10,
+                BeaconBlockLayout::ExecutionPayloadHeader
+            ))
+            .unwrap(),
+            (123, block_hash)
+        );
+    }
+
+    #[test]
+    fn test_ensure_recent_execution_payload() {
+        let beacon_block = |timestamp| {
+            serde_json::json!({
+                "message": { "body": { "execution_payload": { "timestamp": timestamp } } }
+            })
+        };
+        let clock = MockClock::new(1_700_000_000);
+        let max_payload_age = Duration::from_secs(60);
+        let ensure_recent = |beacon_block| {
+            ensure_recent_execution_payload(
+                &beacon_block,
+                BeaconBlockLayout::ExecutionPayload,
+                &clock,
+                max_payload_age,
+            )
+        };
+
+        assert!(ensure_recent(beacon_block(serde_json::json!("1699999988"))).is_ok());
+        assert!(ensure_recent(beacon_block(serde_json::json!(1_699_999_940))).is_ok());
+        let err = ensure_recent(beacon_block(serde_json::json!("1699999000")))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("is 1000s old"), "{err}");
+        // A beacon block without an execution payload timestamp is rejected.
+        assert!(ensure_recent(serde_json::json!({})).is_err());
+        assert!(ensure_recent(beacon_block(serde_json::json!(null))).is_err());
+    }
+
+    #[test]
+    fn test_execution_block_at_slot_from_ssz() {
+        let block_hash = B256::repeat_byte(0xcd);
+        let client = MockSszBeaconClient {
+            signed_beacon_block: ssz_signed_beacon_block(123, block_hash),
+        };
+        assert_eq!(
+            block_on(execution_block_at_slot(
+                &client,
+                10,
+                BeaconBlockLayout::default()
+            ))
+            .unwrap(),
+            (123, block_hash)
+        );
+
+        let client = MockSszBeaconClient {
+            signed_beacon_block: vec![4, 0, 0, 0],
+        };
+        assert!(block_on(execution_block_at_slot(
+            &client,
+            10,
+            BeaconBlockLayout::default()
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn test_plan_client_updates() {
+        let min_proof_heights = [12, 10, 15, 11, 15];
+
+        let plan = plan_client_updates(&min_proof_heights, 9, 3).unwrap();
+        assert_eq!(
+            plan,
+            vec![
+                PlannedTx {
+                    update_height: Some(15),
+                    msgs: vec![0, 1],
+                },
+                PlannedTx {
+                    update_height: None,
+                    msgs: vec![2, 3, 4],
+                },
+            ]
+        );
+        assert_eq!(
+            plan.iter().filter(|tx| tx.update_height.is_some()).count(),
+            1
+        );
+
+        // No update is needed if the light client is already past all the heights.
+        let plan = plan_client_updates(&min_proof_heights, 15, 3).unwrap();
+        assert!(plan.iter().all(|tx| tx.update_height.is_none()));
+        assert_eq!(plan.len(), 2);
+
+        assert!(plan_client_updates(&[], 9, 3).unwrap().is_empty());
+        assert!(plan_client_updates(&min_proof_heights, 9, 1).is_err());
+    }
+
+    #[test]
+    fn test_verify_batch_against_root() {
+        let ibc_contract_slot = U256::from(7);
+        let packets = [1, 2, 3].map(|sequence| Packet {
+            sequence,
+            ..test_packet()
+        });
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        for packet in &packets {
+            state.insert(
+                evm_ics26_commitment_path(&packet.commitment_path(), ibc_contract_slot).into(),
+                U256::from_be_slice(&packet.commitment()),
+            );
+        }
+        let mut recv_msgs = packets.clone().map(recv_msg).to_vec();
+        block_on(inject_ethereum_proofs_with_state(
+            &mut recv_msgs,
+            &mut [],
+            &mut [],
+            &state,
+            ibc_contract_slot,
+            Height::default(),
+        ))
+        .unwrap();
+        verify_batch_against_root(&recv_msgs, &[], &[], state.storage_root()).unwrap();
+
+        // The proof of the second packet is generated against another state.
+        let key: B256 =
+            evm_ics26_commitment_path(&packets[1].commitment_path(), ibc_contract_slot).into();
+        let mut other_state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        other_state.insert(key, U256::from(1));
+        let (value, proof) = other_state.prove(key);
+        recv_msgs[1].proof_commitment =
+            encode_storage_proof(&StorageProof { key, value, proof }, ProofEncoding::Json).unwrap();
+
+        let err = verify_batch_against_root(&recv_msgs, &[], &[], state.storage_root())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("recv of packet 2"), "{err}");
+        assert!(verify_batch_against_root(&recv_msgs[..1], &[], &[], state.storage_root()).is_ok());
+        assert!(
+            verify_batch_against_root(&recv_msgs[..1], &[], &[], other_state.storage_root())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_get_commitment_proof_block_mismatch() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            evm_ics26_commitment_path(&packet.commitment_path(), slot).into(),
+            U256::from_be_slice(&packet.commitment()),
+        );
+        let address = state.address().to_string();
+        let (state_root, _) = state.account_proof();
+        let mut client = MockBlockHeaderEthClient {
+            state,
+            header: ExecutionBlockHeader {
+                number: 10,
+                hash: B256::repeat_byte(0xaa),
+                state_root,
+            },
+        };
+
+        let get_proof_at = |client: &MockBlockHeaderEthClient, block_id| {
+            block_on(get_commitment_proof(
+                client,
+                &address,
+                block_id,
+                packet.commitment_path(),
+                slot,
+                SlotLayout::Flat,
+                &StorageProofOptions::default(),
+            ))
+        };
+        get_proof_at(&client, ProofBlockId::Number(10)).unwrap();
+        get_proof_at(&client, ProofBlockId::Hash(B256::repeat_byte(0xaa))).unwrap();
+
+        let err = get_proof_at(&client, ProofBlockId::Hash(B256::repeat_byte(0xbb))).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProofError>(),
+            Some(ProofError::ProofBlockMismatch { .. })
+        ));
+
+        // The node served the proof at an earlier block than requested.
+        client.header.number = 9;
+        let err = get_proof_at(&client, ProofBlockId::Number(10)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProofError>(),
+            Some(ProofError::ProofBlockMismatch { requested, .. }) if requested == "0xa"
+        ));
+
+        // The node served the proof of another state at the requested block.
+        client.header.number = 10;
+        client.header.state_root = B256::repeat_byte(0xcc);
+        let err = get_proof_at(&client, ProofBlockId::Number(10)).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ProofError>(),
+            Some(ProofError::ProofBlockMismatch { .. })
+        ));
+
+        // A client without block headers skips the check, unless it is required.
+        let get_unchecked_proof = |require_proof_block| {
+            block_on(get_commitment_proof(
+                &client.state,
+                &address,
+                ProofBlockId::Number(10),
+                packet.commitment_path(),
+                slot,
+                SlotLayout::Flat,
+                &StorageProofOptions {
+                    require_proof_block,
+                    ..Default::default()
+                },
+            ))
+        };
+        get_unchecked_proof(false).unwrap();
+        assert!(get_unchecked_proof(true).is_err());
+    }
+
+    #[test]
+    fn test_blocks_until_final() {
+        assert_eq!(blocks_until_final(120, 100), 20);
+        assert_eq!(blocks_until_final(101, 100), 1);
+        // The commitment is finalized once its block is.
+        assert_eq!(blocks_until_final(100, 100), 0);
+        assert_eq!(blocks_until_final(90, 100), 0);
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_commitment_slots() {
+        let slots = CommitmentSlots {
+            commitment: U256::from(1),
+            ack: U256::from(2),
+            receipt: U256::from(3),
+        };
+        assert_eq!(
+            CommitmentSlots::from(U256::from(4)),
+            CommitmentSlots::uniform(U256::from(4))
+        );
+        assert_eq!(slots.slot(ProofKind::Acknowledgement), U256::from(2));
+
+        let packet = test_packet();
+        let ack_packet = Packet {
+            sequence: 2,
+            ..test_packet()
+        };
+        let timeout_packet = Packet {
+            sequence: 3,
+            ..test_packet()
+        };
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            evm_ics26_commitment_path(&packet.commitment_path(), slots.commitment).into(),
+            U256::from_be_slice(&packet.commitment()),
+        );
+        state.insert(
+            evm_ics26_commitment_path(&ack_packet.ack_commitment_path(), slots.ack).into(),
+            U256::from(1),
+        );
+        // The receipt of the timed out packet exists only in the mapping of the commitments.
+        state.insert(
+            evm_ics26_commitment_path(&timeout_packet.receipt_commitment_path(), slots.commitment)
+                .into(),
+            U256::from(1),
+        );
+
+        let inject = |slots: CommitmentSlots| {
+            let mut recv_msgs = vec![recv_msg(packet.clone())];
+            let mut ack_msgs = vec![MsgAcknowledgement {
+                packet: Some(ack_packet.clone().into()),
+                ..Default::default()
+            }];
+            let mut timeout_msgs = vec![MsgTimeout {
+                packet: Some(timeout_packet.clone().into()),
+                ..Default::default()
+            }];
+            block_on(inject_ethereum_proofs_at_block(
+                &mut recv_msgs,
+                &mut ack_msgs,
+                &mut timeout_msgs,
+                &state,
+                &EthProofLayout {
+                    ibc_contract_address: &state.address().to_string(),
+                    commitment_slots: slots,
+                    slot_layout: SlotLayout::Flat,
+                    revision_number: 0,
+                    proof_codec: &ProofEncoding::Json,
+                },
+                None,
+                ProofBlockId::Number(0),
+                0,
+                &EthProofOptions::default(),
+            ))
+        };
+        inject(slots).unwrap();
+        // With a single slot, the ack is missing and the receipt exists.
+        assert!(inject(CommitmentSlots::uniform(slots.commitment)).is_err());
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_raw() {
+        let packet = test_packet();
+        let timeout_packet = Packet {
+            sequence: 2,
+            ..test_packet()
+        };
+        // The keys are derived externally, from a slot that no slot layout describes.
+        let slot = U256::from(42);
+        let commitment_key = evm_ics26_commitment_path(&packet.commitment_path(), slot);
+        let receipt_key =
+            evm_ics26_commitment_path(&timeout_packet.receipt_commitment_path(), slot);
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(
+            commitment_key.into(),
+            U256::from_be_slice(&packet.commitment()),
+        );
+
+        let inject = |keys: &[B256]| {
+            let mut recv_msgs = vec![recv_msg(packet.clone())];
+            let mut timeout_msgs = vec![MsgTimeout {
+                packet: Some(timeout_packet.clone().into()),
+                ..Default::default()
+            }];
+            block_on(inject_ethereum_proofs_raw(
+                &mut recv_msgs,
+                &mut [],
+                &mut timeout_msgs,
+                keys,
+                &state,
+                &state.address().to_string(),
+                ProofBlockId::Number(0),
+                Height::default(),
+                &ProofEncoding::Json,
+                &EthProofOptions::default(),
+            ))
+            .map(|_| (recv_msgs, timeout_msgs))
+        };
+
+        let keys: [B256; 2] = [commitment_key.into(), receipt_key.into()];
+        let (recv_msgs, timeout_msgs) = inject(&keys).unwrap();
+        let recv_proof = decode_storage_proof(&recv_msgs[0].proof_commitment).unwrap();
+        assert_eq!(recv_proof.key, keys[0]);
+        assert_eq!(recv_proof.value, U256::from_be_slice(&packet.commitment()));
+        let timeout_proof = decode_storage_proof(&timeout_msgs[0].proof_unreceived).unwrap();
+        assert_eq!(timeout_proof.key, keys[1]);
+
+        // The commitment is not at the keys of another slot.
+        let wrong_key = evm_ics26_commitment_path(&packet.commitment_path(), U256::from(7));
+        assert!(inject(&[wrong_key.into(), keys[1]]).is_err());
+        assert!(inject(&keys[..1]).is_err());
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_unfinalized_and_skipped_slots() {
+        // Slot 101 is skipped, and slot 103 is not finalized yet.
+        let beacon_client = MockSlotsBeaconClient {
+            execution_blocks: BTreeMap::from([(100, 50), (102, 52), (103, 53)]),
+            finalized_slot: 102,
+        };
+        let state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        let inject = |proof_slot| {
+            block_on(inject_ethereum_proofs(
+                &mut [],
+                &mut [],
+                &mut [],
+                &state,
+                &beacon_client,
+                &EthProofLayout {
+                    ibc_contract_address: &state.address().to_string(),
+                    commitment_slots: U256::from(7).into(),
+                    slot_layout: SlotLayout::Flat,
+                    revision_number: 0,
+                    proof_codec: &ProofEncoding::Json,
+                },
+                proof_slot,
+                &EthProofOptions::default(),
+            ))
+        };
+
+        inject(102).unwrap();
+        let err = inject(101).unwrap_err().to_string();
+        assert!(err.contains("slot 101"), "{err}");
+        assert!(err.contains("skipped"), "{err}");
+        assert!(matches!(
+            inject(103).unwrap_err(),
+            ProofError::SlotNotFinalized {
+                slot: 103,
+                finalized_slot: 102
+            }
+        ));
+    }
+
+    /// A mock beacon node that counts the fetched beacon blocks and finalized slots.
+    struct MockCountingBeaconClient {
+        client: MockSlotsBeaconClient,
+        fetched_blocks: AtomicUsize,
+        fetched_finalized_slots: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl BeaconBlockClient for MockCountingBeaconClient {
+        async fn beacon_block(&self, block_id: &str) -> anyhow::Result<BeaconBlock> {
+            self.client.beacon_block(block_id).await
+        }
+
+        async fn beacon_block_ssz(&self, block_id: &str) -> anyhow::Result<Vec<u8>> {
+            self.fetched_blocks.fetch_add(1, Ordering::SeqCst);
+            self.client.beacon_block_ssz(block_id).await
+        }
+
+        async fn finalized_slot(&self) -> anyhow::Result<u64> {
+            self.fetched_finalized_slots.fetch_add(1, Ordering::SeqCst);
+            self.client.finalized_slot().await
+        }
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_beacon_block_cache() {
+        let beacon_client = MockCountingBeaconClient {
+            client: MockSlotsBeaconClient {
+                execution_blocks: BTreeMap::from([(100, 50), (101, 51), (102, 52)]),
+                finalized_slot: 102,
+            },
+            fetched_blocks: AtomicUsize::new(0),
+            fetched_finalized_slots: AtomicUsize::new(0),
+        };
+        let state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        let cache = BeaconBlockCache::new(2);
+        let inject = |proof_slot| {
+            block_on(inject_ethereum_proofs(
+                &mut [],
+                &mut [],
+                &mut [],
+                &state,
+                &beacon_client,
+                &EthProofLayout {
+                    ibc_contract_address: &state.address().to_string(),
+                    commitment_slots: U256::from(7).into(),
+                    slot_layout: SlotLayout::Flat,
+                    revision_number: 0,
+                    proof_codec: &ProofEncoding::Json,
+                },
+                proof_slot,
+                &EthProofOptions {
+                    block_cache: Some(&cache),
+                    ..Default::default()
+                },
+            ))
+            .unwrap();
+        };
+        let fetched_blocks = || beacon_client.fetched_blocks.load(Ordering::SeqCst);
+
+        // The batches at the same slot fetch its beacon block and check its finality once.
+        inject(100);
+        inject(100);
+        assert_eq!(fetched_blocks(), 1);
+        assert_eq!(
+            beacon_client.fetched_finalized_slots.load(Ordering::SeqCst),
+            1
+        );
+
+        // Slot 100 is the most recently used when slot 102 evicts slot 101.
+        inject(101);
+        inject(100);
+        inject(102);
+        assert_eq!(fetched_blocks(), 3);
+        assert_eq!(cache.len(), 2);
+        inject(100);
+        assert_eq!(fetched_blocks(), 3);
+        inject(101);
+        assert_eq!(fetched_blocks(), 4);
+
+        // A cache without capacity caches nothing.
+        let cache = BeaconBlockCache::new(0);
+        cache.insert(100, (50, B256::ZERO));
+        assert!(cache.is_empty());
+        assert_eq!(
+            BeaconBlockCache::default().capacity,
+            DEFAULT_BEACON_BLOCK_CACHE_CAPACITY
+        );
+    }
+
+    /// A mock WebSocket client that streams `diffs` and serves the proofs from `state`.
+    struct MockStateDiffClient<'a> {
+        diffs: Vec<StorageDiff>,
+        state: &'a StorageSnapshot,
+    }
+
+    #[async_trait::async_trait]
+    impl StateDiffClient for MockStateDiffClient<'_> {
+        async fn subscribe_storage_diffs(
+            &self,
+            _address: &str,
+        ) -> anyhow::Result<BoxStream<'static, anyhow::Result<StorageDiff>>> {
+            Ok(stream::iter(self.diffs.clone().into_iter().map(Ok)).boxed())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl EthProofClient for MockStateDiffClient<'_> {
+        async fn get_proof(
+            &self,
+            address: &str,
+            storage_keys: Vec<String>,
+            block_id: String,
+            request_id: Option<&str>,
+        ) -> anyhow::Result<EIP1186AccountProofResponse> {
+            self.state
+                .get_proof(address, storage_keys, block_id, request_id)
+                .await
+        }
+    }
+
+    #[test]
+    fn test_subscribe_commitment() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let key: B256 = evm_ics26_commitment_path(&packet.commitment_path(), slot).into();
+        let commitment = U256::from_be_slice(&packet.commitment());
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(key, commitment);
+        let other_diff = StorageDiff {
+            block_number: 11,
+            key: B256::repeat_byte(0xaa),
+            value: U256::from(1),
+        };
+        let subscribe = |diffs: Vec<StorageDiff>| {
+            let client = MockStateDiffClient {
+                diffs,
+                state: &state,
+            };
+            block_on(subscribe_commitment(
+                &client,
+                &state.address().to_string(),
+                packet.commitment_path(),
+                slot,
+                SlotLayout::Flat,
+            ))
+        };
+
+        // The writes to other slots and the deletions are skipped.
+        let (block_number, storage_proof) = subscribe(vec![
+            other_diff,
+            StorageDiff {
+                block_number: 11,
+                key,
+                value: U256::ZERO,
+            },
+            StorageDiff {
+                block_number: 12,
+                key,
+                value: commitment,
+            },
+        ])
+        .unwrap();
+        assert_eq!(block_number, 12);
+        assert_eq!(storage_proof.key, key);
+        assert_eq!(storage_proof.value, commitment);
+
+        // The subscription ends before the commitment is written.
+        assert!(subscribe(vec![other_diff]).is_err());
+        // The proved commitment is not the written one.
+        assert!(subscribe(vec![StorageDiff {
+            block_number: 12,
+            key,
+            value: U256::from(1),
+        }])
+        .is_err());
+    }
+
+    /// Returns a storage proof of `key` whose single leaf stores the zero value at the full path
+    /// of the key, as served by a node for a slot explicitly set to zero.
+    fn zero_inclusion_proof(key: B256) -> StorageProof {
+        let mut leaf = vec![0xe4, 0xa1, 0x20];
+        leaf.extend_from_slice(keccak256(key).as_slice());
+        leaf.extend_from_slice(&[0x81, 0x80]);
+        StorageProof {
+            key,
+            value: U256::ZERO,
+            proof: vec![leaf.into()],
+        }
+    }
+
+    #[test]
+    fn test_storage_proof_terminal() {
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        for i in 1..=8_u8 {
+            state.insert(B256::repeat_byte(i), U256::from(i));
+        }
+        let prove = |key: B256| {
+            let (value, proof) = state.prove(key);
+            StorageProof { key, value, proof }
+        };
+
+        assert_eq!(
+            storage_proof_terminal(&prove(B256::repeat_byte(3))).unwrap(),
+            StorageProofTerminal::Inclusion
+        );
+        let exclusion = prove(B256::repeat_byte(9));
+        assert!(exclusion.value.is_zero());
+        assert_eq!(
+            storage_proof_terminal(&exclusion).unwrap(),
+            StorageProofTerminal::Exclusion
+        );
+        let empty = StorageSnapshot::new(Address::repeat_byte(0x11));
+        let (value, proof) = empty.prove(B256::repeat_byte(1));
+        let empty_trie = StorageProof {
+            key: B256::repeat_byte(1),
+            value,
+            proof,
+        };
+        assert_eq!(
+            storage_proof_terminal(&empty_trie).unwrap(),
+            StorageProofTerminal::Exclusion
+        );
+
+        // A zero value proved by inclusion is a stored slot, not a missing one.
+        let zero_inclusion = zero_inclusion_proof(B256::repeat_byte(1));
+        assert_eq!(
+            storage_proof_terminal(&zero_inclusion).unwrap(),
+            StorageProofTerminal::Inclusion
+        );
+        // The same leaf does not include another key.
+        let other_key = StorageProof {
+            key: B256::repeat_byte(2),
+            ..zero_inclusion.clone()
+        };
+        assert_eq!(
+            storage_proof_terminal(&other_key).unwrap(),
+            StorageProofTerminal::Exclusion
+        );
+
+        // A proof cut before its terminal node, or with a malformed node, is rejected.
+        let mut truncated = prove(B256::repeat_byte(3));
+        truncated.proof.pop();
+        assert!(storage_proof_terminal(&truncated).is_err());
+        let malformed = StorageProof {
+            proof: vec![vec![0xab; 40].into()],
+            ..zero_inclusion
+        };
+        assert!(storage_proof_terminal(&malformed).is_err());
+    }
+
+    /// A mock destination chain whose light clients are all at `head`.
+    struct MockClientHeadClient {
+        head: ClientHead,
+        queried_clients: Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ClientHeadClient for MockClientHeadClient {
+        async fn client_head(&self, client_id: &str) -> anyhow::Result<ClientHead> {
+            self.queried_clients
+                .lock()
+                .unwrap()
+                .push(client_id.to_string());
+            Ok(self.head)
+        }
+    }
+
+    #[test]
+    fn test_prove_at_client_head() {
+        let eth_client = MockEthClient::default();
+        let dest_client = MockClientHeadClient {
+            head: ClientHead {
+                slot: 96,
+                execution_block_hash: B256::repeat_byte(0xcd),
+            },
+            queried_clients: Mutex::new(vec![]),
+        };
+        let mut ack_msgs = vec![MsgAcknowledgement {
+            packet: Some(test_packet().into()),
+            ..Default::default()
+        }];
+
+        let report = block_on(prove_at_client_head(
+            &mut [],
+            &mut ack_msgs,
+            &mut [],
+            &eth_client,
+            &dest_client,
+            "08-wasm-0",
+            &EthProofLayout {
+                ibc_contract_address: "0x0000000000000000000000000000000000000000",
+                commitment_slots: CommitmentSlots::uniform(U256::ZERO),
+                slot_layout: SlotLayout::Flat,
+                revision_number: 1,
+                proof_codec: &ProofEncoding::Json,
+            },
+            &EthProofOptions::default(),
+        ))
+        .unwrap();
+
+        assert_eq!(
+            *dest_client.queried_clients.lock().unwrap(),
+            vec!["08-wasm-0"]
+        );
+        assert_eq!(
+            *eth_client.requested_blocks.lock().unwrap(),
+            vec![format!("0x{}", "cd".repeat(32))]
+        );
+        let expected_height = Height {
+            revision_number: 1,
+            revision_height: 96,
+        };
+        assert_eq!(report.used_height, expected_height);
+        assert_eq!(ack_msgs[0].proof_height, Some(expected_height));
+    }
+
+    #[test]
+    fn test_assert_single_proof_encoding() {
+        let storage_proof = StorageProof {
+            key: B256::repeat_byte(0x01),
+            value: U256::from(42),
+            proof: vec![vec![0xab; 100].into()],
+        };
+        let msg = |encoding| MsgRecvPacket {
+            proof_commitment: encode_storage_proof(&storage_proof, encoding).unwrap(),
+            ..recv_msg(test_packet())
+        };
+        let compressed =
+            encode_storage_proof(&storage_proof, ProofEncoding::CompressedJson).unwrap();
+        assert_eq!(
+            ProofEncoding::detect(&compressed),
+            Some(ProofEncoding::CompressedJson)
+        );
+
+        let uniform = [msg(ProofEncoding::Compact), msg(ProofEncoding::Compact)];
+        assert!(assert_single_proof_encoding(&uniform, &[], &[], ProofEncoding::Compact).is_ok());
+        // A JSON proof may be set when its compression does not reduce its size.
+        let json = [msg(ProofEncoding::Json), msg(ProofEncoding::CompressedJson)];
+        assert!(
+            assert_single_proof_encoding(&json, &[], &[], ProofEncoding::CompressedJson).is_ok()
+        );
+
+        let mixed = [msg(ProofEncoding::Json), msg(ProofEncoding::Compact)];
+        let err = assert_single_proof_encoding(&mixed, &[], &[], ProofEncoding::Json).unwrap_err();
+        assert!(err.to_string().contains("Compact"));
+        assert!(assert_single_proof_encoding(&mixed, &[], &[], ProofEncoding::Compact).is_err());
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_verification() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let storage_key: B256 = evm_ics26_commitment_path(&packet.commitment_path(), slot).into();
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(storage_key, U256::from_be_slice(&packet.commitment()));
+        state.insert(B256::repeat_byte(0x22), U256::from(1));
+        let address = state.address().to_string();
+
+        let client = MockCorruptedEthClient(state);
+        let inject = |verify| {
+            let mut recv_msgs = vec![recv_msg(packet.clone())];
+            block_on(inject_ethereum_proofs_raw(
+                &mut recv_msgs,
+                &mut [],
+                &mut [],
+                &[storage_key],
+                &client,
+                &address,
+                ProofBlockId::Number(0),
+                Height::default(),
+                &ProofEncoding::Json,
+                &EthProofOptions {
+                    verify,
+                    ..Default::default()
+                },
+            ))
+        };
+        let err = inject(true).unwrap_err();
+        assert!(format!("{err:#}").contains("does not verify"));
+        // The corrupted proof is injected if the verification is not requested.
+        inject(false).unwrap();
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_lenient_decoding() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let storage_key: B256 = evm_ics26_commitment_path(&packet.commitment_path(), slot).into();
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(storage_key, U256::from_be_slice(&packet.commitment()));
+        state.insert(B256::repeat_byte(0x22), U256::from(1));
+        let address = state.address().to_string();
+
+        let client = MockMalformedEthClient(state);
+        let inject = |trie_node_decoding| {
+            let mut recv_msgs = vec![recv_msg(packet.clone())];
+            block_on(inject_ethereum_proofs_raw(
+                &mut recv_msgs,
+                &mut [],
+                &mut [],
+                &[storage_key],
+                &client,
+                &address,
+                ProofBlockId::Number(0),
+                Height::default(),
+                &ProofEncoding::Json,
+                &EthProofOptions {
+                    trie_node_decoding,
+                    verify: true,
+                    ..Default::default()
+                },
+            ))
+        };
+        assert!(inject(TrieNodeDecoding::Strict).is_err());
+        inject(TrieNodeDecoding::Lenient).unwrap();
+    }
+
+    #[test]
+    fn test_inject_ethereum_proofs_max_proof_depth() {
+        let packet = test_packet();
+        let slot = U256::from(5);
+        let storage_key: B256 = evm_ics26_commitment_path(&packet.commitment_path(), slot).into();
+        let mut state = StorageSnapshot::new(Address::repeat_byte(0x11));
+        state.insert(storage_key, U256::from_be_slice(&packet.commitment()));
+        state.insert(B256::repeat_byte(0x22), U256::from(1));
+        let (_, proof) = state.prove(storage_key);
+        let address = state.address().to_string();
+
+        let inject = |max_proof_depth| {
+            let mut recv_msgs = vec![recv_msg(packet.clone())];
+            block_on(inject_ethereum_proofs_raw(
+                &mut recv_msgs,
+                &mut [],
+                &mut [],
+                &[storage_key],
+                &state,
+                &address,
+                ProofBlockId::Number(0),
+                Height::default(),
+                &ProofEncoding::Json,
+                &EthProofOptions {
+                    max_proof_depth,
+                    ..Default::default()
+                },
+            ))
+        };
+        inject(Some(proof.len())).unwrap();
+        let err = inject(Some(proof.len() - 1)).unwrap_err();
+        assert!(format!("{err:#}").contains("more than the maximum depth"));
+    }
+}
@@ -0,0 +1,127 @@
+//! Relayer utilities for selecting the heights at which proofs are generated.
+
+use std::{
+    ops::RangeInclusive,
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+
+use super::client::LatestHeightClient;
+
+/// Selects the highest proof height that both the light client on the target chain and the source
+/// node can serve.
+///
+/// # Arguments
+/// - `light_client_heights` - The range of heights the light client can verify proofs at.
+/// - `source_heights` - The range of heights the source node can generate proofs at.
+///
+/// # Errors
+/// Returns an error if no height is served by both.
+pub fn highest_common_height(
+    light_client_heights: &RangeInclusive<u64>,
+    source_heights: &RangeInclusive<u64>,
+) -> Result<u64> {
+    let lowest = *light_client_heights.start().max(source_heights.start());
+    let highest = *light_client_heights.end().min(source_heights.end());
+    if lowest > highest {
+        anyhow::bail!(
+            "No common proof height: light client can verify {:?}, source node can serve {:?}",
+            light_client_heights,
+            source_heights
+        );
+    }
+
+    Ok(highest)
+}
+
+/// The `CachedLatestHeight` caches the latest height looked up by the underlying client for
+/// `ttl`, so that successive proof injections do not each query the status of the node.
+pub struct CachedLatestHeight<C> {
+    client: C,
+    ttl: Duration,
+    cached: Mutex<Option<(Instant, u64)>>,
+}
+
+impl<C> CachedLatestHeight<C> {
+    /// Creates a new `CachedLatestHeight` that caches the heights looked up by `client` for `ttl`.
+    #[must_use]
+    pub const fn new(client: C, ttl: Duration) -> Self {
+        Self {
+            client,
+            ttl,
+            cached: Mutex::new(None),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<C: LatestHeightClient> LatestHeightClient for CachedLatestHeight<C> {
+    async fn latest_height(&self) -> Result<u64> {
+        let cached = *self.cached.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some((_, height)) =
+            cached.filter(|(looked_up_at, _)| looked_up_at.elapsed() < self.ttl)
+        {
+            return Ok(height);
+        }
+
+        let height = self.client.latest_height().await?;
+        *self.cached.lock().unwrap_or_else(PoisonError::into_inner) =
+            Some((Instant::now(), height));
+        Ok(height)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::atomic::{AtomicU64, Ordering},
+        time::Duration,
+    };
+
+    use futures::executor::block_on;
+
+    use crate::utils::client::LatestHeightClient;
+
+    use super::{highest_common_height, CachedLatestHeight};
+
+    /// A mock client whose latest height increases with every lookup.
+    #[derive(Default)]
+    struct MockHeightClient {
+        lookups: AtomicU64,
+    }
+
+    #[async_trait::async_trait]
+    impl LatestHeightClient for MockHeightClient {
+        async fn latest_height(&self) -> anyhow::Result<u64> {
+            Ok(100 + self.lookups.fetch_add(1, Ordering::Relaxed))
+        }
+    }
+
+    #[test]
+    fn test_highest_common_height_overlapping() {
+        assert_eq!(highest_common_height(&(10..=50), &(30..=100)).unwrap(), 50);
+        assert_eq!(highest_common_height(&(10..=50), &(20..=40)).unwrap(), 40);
+        assert_eq!(highest_common_height(&(10..=50), &(50..=60)).unwrap(), 50);
+    }
+
+    #[test]
+    fn test_highest_common_height_disjoint() {
+        assert!(highest_common_height(&(10..=50), &(51..=100)).is_err());
+        assert!(highest_common_height(&(60..=70), &(10..=50)).is_err());
+    }
+    #[test]
+    fn test_cached_latest_height() {
+        let cache = CachedLatestHeight::new(MockHeightClient::default(), Duration::from_secs(60));
+        assert_eq!(block_on(cache.latest_height()).unwrap(), 100);
+        assert_eq!(block_on(cache.latest_height()).unwrap(), 100);
+        assert_eq!(cache.client.lookups.load(Ordering::Relaxed), 1);
+
+        // An expired height is looked up again.
+        let cache = CachedLatestHeight::new(MockHeightClient::default(), Duration::ZERO);
+        assert_eq!(block_on(cache.latest_height()).unwrap(), 100);
+        assert_eq!(block_on(cache.latest_height()).unwrap(), 101);
+        assert_eq!(cache.client.lookups.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,171 @@
+//! Relayer utilities for monitoring relay performance.
+
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::events::EurekaEventWithHeight;
+
+/// Computes the time elapsed between the block at which the event was emitted and `now`.
+/// This is used to monitor the relaying SLA of packets.
+///
+/// # Arguments
+/// - `event` - The event emitted on the source chain.
+/// - `now` - The current unix timestamp in seconds.
+///
+/// # Returns
+/// The latency of the event, or `None` if the block timestamp of the event is unknown.
+#[must_use]
+pub fn packet_latency(event: &EurekaEventWithHeight, now: u64) -> Option<Duration> {
+    event
+        .block_timestamp
+        .map(|block_timestamp| Duration::from_secs(now.saturating_sub(block_timestamp)))
+}
+
+/// Tracks the number of messages relayed over a sliding window, so that the relay throughput can
+/// be used for autoscaling decisions.
+#[derive(Debug, Clone)]
+pub struct ThroughputTracker {
+    /// The length of the window over which the throughput is computed.
+    window: Duration,
+    /// The unix timestamps in seconds at which messages were relayed, with the number of messages
+    /// relayed at that time, in ascending order of time.
+    relays: VecDeque<(u64, u64)>,
+}
+
+impl ThroughputTracker {
+    /// Creates a tracker that computes the throughput over the last `window`.
+    #[must_use]
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            relays: VecDeque::new(),
+        }
+    }
+
+    /// Records that `count` messages were relayed at the unix timestamp `timestamp` in seconds.
+    /// Relays recorded out of order are counted at the time of the latest relay.
+    pub fn record(&mut self, timestamp: u64, count: u64) {
+        let timestamp = self
+            .relays
+            .back()
+            .map_or(timestamp, |(latest, _)| timestamp.max(*latest));
+        match self.relays.back_mut() {
+            Some((latest, relayed)) if *latest == timestamp => *relayed += count,
+            _ => self.relays.push_back((timestamp, count)),
+        }
+        self.prune(timestamp);
+    }
+
+    /// Returns the number of messages relayed per second over the window ending at the unix
+    /// timestamp `now` in seconds.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn packets_per_second(&mut self, now: u64) -> f64 {
+        self.prune(now);
+        if self.window.is_zero() {
+            return 0.0;
+        }
+        let relayed: u64 = self.relays.iter().map(|(_, count)| count).sum();
+        relayed as f64 / self.window.as_secs_f64()
+    }
+
+    /// Drops the relays that are outside of the window ending at `now`.
+    fn prune(&mut self, now: u64) {
+        let start = now.saturating_sub(self.window.as_secs());
+        while self
+            .relays
+            .front()
+            .is_some_and(|(timestamp, _)| *timestamp <= start)
+        {
+            self.relays.pop_front();
+        }
+    }
+}
+
+/// Counts the proofs generated and the proofs that failed during the proof injection, so that the
+/// caller can export them to its monitoring. The counters are shared by the concurrent proofs of
+/// a batch, and accumulate across the batches it is passed to.
+#[derive(Debug, Default)]
+pub struct ProofMetrics {
+    generated: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl ProofMetrics {
+    /// Returns the number of proofs generated.
+    #[must_use]
+    pub fn generated(&self) -> u64 {
+        self.generated.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of proofs that failed to be generated or checked.
+    #[must_use]
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+
+    /// Records that `count` proofs were generated.
+    pub fn add_generated(&self, count: u64) {
+        self.generated.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records that `count` proofs failed to be generated or checked.
+    pub fn add_failed(&self, count: u64) {
+        self.failed.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
+
+    use crate::events::{EurekaEvent, EurekaEventWithHeight};
+
+    use super::{packet_latency, ThroughputTracker};
+
+    fn send_event(block_timestamp: Option<u64>) -> EurekaEventWithHeight {
+        EurekaEventWithHeight {
+            event: EurekaEvent::SendPacket(Packet {
+                sequence: 1,
+                sourceClient: "client-0".to_string(),
+                destClient: "client-1".to_string(),
+                timeoutTimestamp: 1_700_001_000,
+                payloads: vec![],
+            }),
+            block_number: Some(100),
+            block_timestamp,
+        }
+    }
+
+    #[test]
+    fn test_packet_latency() {
+        let event = send_event(Some(1_700_000_000));
+        assert_eq!(
+            packet_latency(&event, 1_700_000_042),
+            Some(Duration::from_secs(42))
+        );
+        // A block time in the future saturates to zero.
+        assert_eq!(packet_latency(&event, 1_699_999_999), Some(Duration::ZERO));
+        assert_eq!(packet_latency(&send_event(None), 1_700_000_042), None);
+    }
+
+    #[test]
+    fn test_throughput_tracker() {
+        let mut tracker = ThroughputTracker::new(Duration::from_secs(10));
+        assert!(tracker.packets_per_second(1_700_000_000).abs() < f64::EPSILON);
+
+        tracker.record(1_700_000_000, 10);
+        tracker.record(1_700_000_005, 20);
+        tracker.record(1_700_000_005, 5);
+        assert!((tracker.packets_per_second(1_700_000_009) - 3.5).abs() < f64::EPSILON);
+
+        // The first relay falls out of the window.
+        assert!((tracker.packets_per_second(1_700_000_010) - 2.5).abs() < f64::EPSILON);
+        assert!(tracker.packets_per_second(1_700_000_015).abs() < f64::EPSILON);
+    }
+}
@@ -7,6 +7,8 @@ use std::time::{Duration, Instant};
 /// Retries an operation until the condition is met or a timeout occurs.
 ///
 /// The basic version just checks for a boolean condition.
+/// # Errors
+/// Returns an error if the condition returns an error or if the timeout is exceeded.
 pub async fn wait_for_condition<F, Fut>(
     timeout: Duration,
     interval: Duration,
@@ -31,5 +33,17 @@ where
     anyhow::bail!("Timeout exceeded")
 }
 
+pub mod client;
+pub mod clock;
+pub mod commitment;
 pub mod cosmos;
+pub mod error;
 pub mod eth_eureka;
+pub mod eth_proof;
+pub mod height;
+pub mod metrics;
+pub mod packet;
+pub mod proof_spec;
+pub mod relay;
+pub mod retry;
+pub mod snapshot;
@@ -0,0 +1,1038 @@
+//! Relayer utilities for validating packets and their routing before they are relayed.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeInclusive,
+    time::Duration,
+};
+
+use alloy::{primitives::U256, sol_types::SolValue};
+use anyhow::Result;
+use ibc_eureka_solidity_types::{
+    ics26::IICS26RouterMsgs::Packet, msgs::IICS20TransferMsgs::FungibleTokenPacketData,
+};
+use serde::Deserialize;
+
+use super::cosmos::ProofKind;
+use crate::events::{EurekaEvent, EurekaEventWithHeight};
+
+/// The minimum and maximum lengths of a client id, as accepted by `validateCustomIBCIdentifier`
+/// of the IBC contracts.
+const CLIENT_ID_LEN: RangeInclusive<usize> = 4..=128;
+
+/// The characters allowed in a client id besides the ASCII alphanumerics, as accepted by
+/// `validateCustomIBCIdentifier` of the IBC contracts.
+const CLIENT_ID_SPECIAL_CHARS: [char; 9] = ['.', '_', '+', '-', '#', '[', ']', '<', '>'];
+
+/// Validates the format of a client id against the identifier format of the IBC contracts, and
+/// returns it unchanged. Client ids are case sensitive, so a mis-cased id is a different client
+/// and is never rewritten, while an id that cannot be a client id, e.g. with surrounding
+/// whitespace, is rejected instead of silently matching no packet.
+/// # Errors
+/// Returns an error if the client id is malformed.
+pub fn normalize_client_id(client_id: &str) -> Result<String> {
+    anyhow::ensure!(
+        CLIENT_ID_LEN.contains(&client_id.len()),
+        "Client id {client_id:?} is not between {} and {} characters long",
+        CLIENT_ID_LEN.start(),
+        CLIENT_ID_LEN.end()
+    );
+    if let Some(c) = client_id
+        .chars()
+        .find(|c| !c.is_ascii_alphanumeric() && !CLIENT_ID_SPECIAL_CHARS.contains(c))
+    {
+        anyhow::bail!("Client id {client_id:?} has the invalid character {c:?}");
+    }
+    Ok(client_id.to_string())
+}
+
+/// The canonical payload encoding of each payload encoding version.
+const PAYLOAD_ENCODINGS: [(u8, &str); 2] =
+    [(1, "application/json"), (2, "application/x-solidity-abi")];
+
+/// The legacy short names of the payload encodings, along with their canonical names.
+const PAYLOAD_ENCODING_ALIASES: [(&str, &str); 3] = [
+    ("json", "application/json"),
+    ("abi", "application/x-solidity-abi"),
+    ("solidity-abi", "application/x-solidity-abi"),
+];
+
+/// Checks that every payload of the packet is encoded in the canonical encoding of the payload
+/// encoding `version`, i.e. `application/json` for version 1 and `application/x-solidity-abi` for
+/// version 2, and rewrites the encodings reported in a tolerant older form: with a different
+/// casing, surrounding whitespace, media type parameters or a legacy short name.
+/// The encoding is part of the packet commitment, so this only restores the encoding committed
+/// on chain when the event was decoded loosely, e.g. by an indexer.
+/// # Errors
+/// Returns an error if `version` is unknown, or if a payload is in another encoding.
+pub fn canonicalize_payload(packet: &mut Packet, version: u8) -> Result<()> {
+    let Some((_, expected)) = PAYLOAD_ENCODINGS.iter().find(|(v, _)| *v == version) else {
+        anyhow::bail!("Unknown payload encoding version {version}")
+    };
+
+    for payload in &mut packet.payloads {
+        if payload.encoding == *expected {
+            continue;
+        }
+        let media_type = payload
+            .encoding
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let canonical = PAYLOAD_ENCODING_ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == media_type)
+            .map_or(media_type.as_str(), |(_, canonical)| *canonical);
+        anyhow::ensure!(
+            canonical == *expected,
+            "Payload of packet {} to port {} is encoded in {}, expected {expected}",
+            packet.sequence,
+            payload.destPort,
+            payload.encoding
+        );
+
+        tracing::warn!(
+            "Payload encoding {} of packet {} was canonicalized to {expected}",
+            payload.encoding,
+            packet.sequence
+        );
+        payload.encoding = (*expected).to_string();
+    }
+    Ok(())
+}
+
+/// Checks that the packet sequence is not 0, which is invalid in IBC and can only come from a
+/// malformed event.
+/// # Errors
+/// Returns an error if the packet sequence is 0.
+pub fn ensure_nonzero_sequence(packet: &Packet) -> Result<()> {
+    anyhow::ensure!(
+        packet.sequence != 0,
+        "Packet from client {} to client {} has the invalid sequence 0",
+        packet.sourceClient,
+        packet.destClient
+    );
+    Ok(())
+}
+
+/// Validates the data of every payload in the packet with `validate_packet_data`.
+/// Packets that fail the validation are logged, so that malformed packets are flagged early.
+#[must_use]
+pub fn is_packet_data_valid(
+    packet: &Packet,
+    validate_packet_data: &dyn Fn(&[u8]) -> Result<()>,
+) -> bool {
+    packet
+        .payloads
+        .iter()
+        .all(|payload| match validate_packet_data(&payload.value) {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!(
+                    "Packet {} from client {} has invalid data for port {}: {}",
+                    packet.sequence,
+                    packet.sourceClient,
+                    payload.destPort,
+                    e
+                );
+                false
+            }
+        })
+}
+
+/// Checks that every payload of the packet is routed to an app in `known_apps`, identified by
+/// its destination port. Packets destined for unknown apps are logged, as they cannot be
+/// received on the destination chain.
+#[must_use]
+#[allow(clippy::implicit_hasher)]
+pub fn is_destined_to_known_apps(packet: &Packet, known_apps: &HashSet<String>) -> bool {
+    packet.payloads.iter().all(|payload| {
+        let is_known = known_apps.contains(&payload.destPort);
+        if !is_known {
+            tracing::warn!(
+                "Packet {} from client {} is destined for unknown app {}",
+                packet.sequence,
+                packet.sourceClient,
+                payload.destPort
+            );
+        }
+        is_known
+    })
+}
+
+/// The allow-lists of the packets served by a relayer, so that several relayers can run on the
+/// same chains without competing for the same packets. An empty allow-list allows everything, so
+/// the default filter relays every packet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacketFilter {
+    /// The allowed source clients of the packets.
+    pub source_clients: HashSet<String>,
+    /// The allowed destination clients of the packets.
+    pub dest_clients: HashSet<String>,
+    /// The allowed source ports of the payloads of the packets.
+    pub source_ports: HashSet<String>,
+}
+
+impl PacketFilter {
+    /// Returns whether the packet passes every allow-list of the filter. A packet is only
+    /// allowed by the source ports if all of its payloads are.
+    #[must_use]
+    pub fn allows(&self, packet: &Packet) -> bool {
+        let is_allowed = |allow_list: &HashSet<String>, value: &String| {
+            allow_list.is_empty() || allow_list.contains(value)
+        };
+
+        is_allowed(&self.source_clients, &packet.sourceClient)
+            && is_allowed(&self.dest_clients, &packet.destClient)
+            && packet
+                .payloads
+                .iter()
+                .all(|payload| is_allowed(&self.source_ports, &payload.sourcePort))
+    }
+}
+
+/// The client ids retired by a chain id migration, mapped to the ids of the clients that replaced
+/// them. The packets sent before the migration reference the retired clients, while their
+/// commitments must be proved in the store of the current clients.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientIdMigrations {
+    /// The ids of the current clients, which are resolved as is. If empty, every client id that is
+    /// not migrated is assumed to be current.
+    pub current_clients: HashSet<String>,
+    /// The retired client ids, mapped to the ids of the clients that replaced them.
+    pub migrated_clients: HashMap<String, String>,
+}
+
+impl ClientIdMigrations {
+    /// Returns the id of the current client of `client_id`, i.e. the client that replaced it if it
+    /// was retired, or the id itself if it is current.
+    /// # Errors
+    /// Returns an error if `client_id` is neither current nor migrated, i.e. an old id without a
+    /// mapping, whose commitments cannot be located.
+    pub fn resolve<'a>(&'a self, client_id: &'a str) -> Result<&'a str> {
+        if let Some(current_client_id) = self.migrated_clients.get(client_id) {
+            tracing::debug!("Resolved migrated client {client_id} to {current_client_id}");
+            return Ok(current_client_id);
+        }
+        anyhow::ensure!(
+            self.current_clients.is_empty() || self.current_clients.contains(client_id),
+            "Client {client_id} is not current and has no migration to a current client"
+        );
+        Ok(client_id)
+    }
+
+    /// Returns the path of `packet` proved by `kind`, with the client of the chain storing it
+    /// resolved to its current client, i.e. the source client for the packet commitment and the
+    /// destination client for the acknowledgement and the receipt.
+    /// # Errors
+    /// Returns an error if the client cannot be resolved, see [`Self::resolve`].
+    pub fn proof_path(&self, packet: &Packet, kind: ProofKind) -> Result<Vec<u8>> {
+        let mut packet = packet.clone();
+        Ok(match kind {
+            ProofKind::Commitment => {
+                packet.sourceClient = self.resolve(&packet.sourceClient)?.to_string();
+                packet.commitment_path()
+            }
+            ProofKind::Acknowledgement => {
+                packet.destClient = self.resolve(&packet.destClient)?.to_string();
+                packet.ack_commitment_path()
+            }
+            ProofKind::Receipt => {
+                packet.destClient = self.resolve(&packet.destClient)?.to_string();
+                packet.receipt_commitment_path()
+            }
+        })
+    }
+}
+
+/// Removes the send packet events whose packets have already timed out at `now`, the current unix
+/// timestamp in seconds. Receiving such a packet is doomed to fail, so it must be timed out
+/// instead. Acknowledgement events are kept.
+#[must_use]
+pub fn drop_expired_send_events(
+    events: Vec<EurekaEventWithHeight>,
+    now: u64,
+) -> Vec<EurekaEventWithHeight> {
+    events
+        .into_iter()
+        .filter(|e| match &e.event {
+            EurekaEvent::SendPacket(packet) if now >= packet.timeoutTimestamp => {
+                tracing::debug!(
+                    "Dropping the send event of packet {} from client {}, which timed out at {}",
+                    packet.sequence,
+                    packet.sourceClient,
+                    packet.timeoutTimestamp
+                );
+                false
+            }
+            EurekaEvent::SendPacket(_) | EurekaEvent::WriteAcknowledgement(..) => true,
+        })
+        .collect()
+}
+
+/// Checks that the source and destination clients of the packet form one of the
+/// `registered_connections`, given as `(source_client, destination_client)` pairs. Packets on
+/// unknown connections are logged, as relaying them would deliver them to the wrong chain.
+#[must_use]
+#[allow(clippy::implicit_hasher)]
+pub fn is_on_registered_connection(
+    packet: &Packet,
+    registered_connections: &HashSet<(String, String)>,
+) -> bool {
+    let is_registered =
+        registered_connections.contains(&(packet.sourceClient.clone(), packet.destClient.clone()));
+    if !is_registered {
+        tracing::warn!(
+            "Packet {} is sent on the unregistered connection from client {} to client {}",
+            packet.sequence,
+            packet.sourceClient,
+            packet.destClient
+        );
+    }
+    is_registered
+}
+
+/// Returns the packet forward middleware metadata of the packet, found under the `forward` key of
+/// the JSON memo of an ICS20 payload. A packet with forward metadata is forwarded to another
+/// chain once received, so the caller must continue relaying it on the next hop.
+///
+/// # Returns
+/// The forward metadata of the first payload that has any, or `None` if no payload is forwarded.
+#[must_use]
+pub fn forward_metadata(packet: &Packet) -> Option<serde_json::Value> {
+    packet.payloads.iter().find_map(|payload| {
+        let data = serde_json::from_slice::<serde_json::Value>(&payload.value).ok()?;
+        let memo = serde_json::from_str::<serde_json::Value>(data.get("memo")?.as_str()?).ok()?;
+        memo.get("forward").cloned()
+    })
+}
+
+/// The acknowledgement written by the ICS20 transfer app when a transfer succeeds.
+pub const ICS20_SUCCESSFUL_ACKNOWLEDGEMENT: &[u8] = br#"{"result":"AQ=="}"#;
+
+/// Predicts the acknowledgement that the destination app writes when `packet` is received, so
+/// that the ack message can be pre-built before the destination chain writes it.
+/// The prediction assumes that the packet is received successfully, and must be discarded if a
+/// different acknowledgement is written.
+///
+/// # Returns
+/// The predicted acknowledgement, or `None` if the packet does not have a single payload or the
+/// acknowledgement of its app is not deterministic.
+#[must_use]
+pub fn predicted_ack(packet: &Packet) -> Option<Vec<u8>> {
+    let [payload] = packet.payloads.as_slice() else {
+        return None;
+    };
+    match payload.destPort.as_str() {
+        "transfer" => Some(ICS20_SUCCESSFUL_ACKNOWLEDGEMENT.to_vec()),
+        _ => None,
+    }
+}
+
+/// The version of the ICS20 transfer app.
+const ICS20_VERSION: &str = "ics20-1";
+
+/// The port of the ICS20 transfer app.
+const ICS20_PORT_ID: &str = "transfer";
+
+/// The JSON encoding of the [`FungibleTokenPacketData`], with the amount as a decimal string.
+#[derive(Deserialize)]
+struct JsonFungibleTokenPacketData {
+    denom: String,
+    sender: String,
+    receiver: String,
+    amount: String,
+    #[serde(default)]
+    memo: String,
+}
+
+/// Decodes the data of an ICS20 transfer packet and validates its transfer fields, so that a
+/// malformed transfer is caught before it is relayed, as the transfer app would reject it.
+/// The data is decoded from the encoding of the payload, either `application/x-solidity-abi` or
+/// `application/json`, see [`canonicalize_payload`].
+/// # Errors
+/// Returns an error if the packet does not have a single ICS20 payload, if its data cannot be
+/// decoded, or if the denom, the sender or the receiver is empty or the amount is zero.
+pub fn validate_ics20_packet(packet: &Packet) -> Result<FungibleTokenPacketData> {
+    let [payload] = packet.payloads.as_slice() else {
+        anyhow::bail!(
+            "Packet {} has {} payloads, expected a single ICS20 payload",
+            packet.sequence,
+            packet.payloads.len()
+        )
+    };
+    anyhow::ensure!(
+        payload.version == ICS20_VERSION,
+        "Packet {} has version {}, expected {ICS20_VERSION}",
+        packet.sequence,
+        payload.version
+    );
+    anyhow::ensure!(
+        payload.sourcePort == ICS20_PORT_ID && payload.destPort == ICS20_PORT_ID,
+        "Packet {} is not between {ICS20_PORT_ID} ports",
+        packet.sequence
+    );
+
+    let data = match payload.encoding.as_str() {
+        "application/x-solidity-abi" => FungibleTokenPacketData::abi_decode(&payload.value, true)?,
+        "application/json" => {
+            let data: JsonFungibleTokenPacketData = serde_json::from_slice(&payload.value)?;
+            FungibleTokenPacketData {
+                denom: data.denom,
+                sender: data.sender,
+                receiver: data.receiver,
+                amount: U256::from_str_radix(&data.amount, 10)?,
+                memo: data.memo,
+            }
+        }
+        encoding => anyhow::bail!(
+            "Packet {} has the unknown encoding {encoding}",
+            packet.sequence
+        ),
+    };
+    anyhow::ensure!(
+        !data.denom.is_empty(),
+        "Packet {} has an empty denom",
+        packet.sequence
+    );
+    anyhow::ensure!(
+        !data.sender.is_empty(),
+        "Packet {} has an empty sender",
+        packet.sequence
+    );
+    anyhow::ensure!(
+        !data.receiver.is_empty(),
+        "Packet {} has an empty receiver",
+        packet.sequence
+    );
+    anyhow::ensure!(
+        !data.amount.is_zero(),
+        "Packet {} transfers a zero amount",
+        packet.sequence
+    );
+    Ok(data)
+}
+
+/// Selects the packets that time out within `window` of `now`, so that the relayer can relay
+/// them before the packets that have more time left. Packets that have already timed out are not
+/// selected, as they can only be timed out.
+///
+/// # Arguments
+/// - `packets` - The packets to select from.
+/// - `now` - The current unix timestamp in seconds.
+/// - `window` - The window in which the packets time out.
+///
+/// # Returns
+/// The selected packets, sorted by the time left until they time out.
+#[must_use]
+pub fn packets_expiring_within(packets: &[Packet], now: u64, window: Duration) -> Vec<&Packet> {
+    let deadline = now.saturating_add(window.as_secs());
+    let mut expiring = packets
+        .iter()
+        .filter(|packet| packet.timeoutTimestamp > now && packet.timeoutTimestamp <= deadline)
+        .collect::<Vec<_>>();
+    expiring.sort_by_key(|packet| packet.timeoutTimestamp);
+    expiring
+}
+
+/// Predicts whether `packet` can be relayed before it times out, given the `expected_latency` of
+/// relaying a packet. Packets that time out before they can be received are not worth proving.
+///
+/// # Arguments
+/// - `packet` - The packet to relay.
+/// - `now` - The current unix timestamp in seconds.
+/// - `expected_latency` - The expected time until the packet is received on the target chain.
+#[must_use]
+pub fn is_relayable_given_latency(packet: &Packet, now: u64, expected_latency: Duration) -> bool {
+    now.saturating_add(expected_latency.as_secs()) < packet.timeoutTimestamp
+}
+
+/// Returns the key ordering `packet` in a relay scheduler, so that the packets of several channels
+/// are interleaved fairly by relaying them by sequence across the channels.
+/// IBC Eureka has no channels, so the channel of a packet is identified by its source client.
+///
+/// # Returns
+/// The channel and the sequence of the packet.
+#[must_use]
+pub fn fairness_key(packet: &Packet) -> (String, u64) {
+    (packet.sourceClient.clone(), packet.sequence)
+}
+
+/// The paths of the commitments of a packet in the IBC stores, as defined by IBC v2.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketPaths {
+    /// The path of the packet commitment on the source chain.
+    pub commitment: Vec<u8>,
+    /// The path of the acknowledgement commitment on the destination chain.
+    pub ack: Vec<u8>,
+    /// The path of the packet receipt on the destination chain.
+    pub receipt: Vec<u8>,
+}
+
+impl PacketPaths {
+    /// Returns the path proved by a proof of `kind`.
+    #[must_use]
+    pub fn path(&self, kind: ProofKind) -> &[u8] {
+        match kind {
+            ProofKind::Commitment => &self.commitment,
+            ProofKind::Acknowledgement => &self.ack,
+            ProofKind::Receipt => &self.receipt,
+        }
+    }
+}
+
+/// Derives the commitment, acknowledgement and receipt paths of `packet`, so that every proof
+/// backend proves the same paths.
+#[must_use]
+pub fn packet_paths(packet: &Packet) -> PacketPaths {
+    PacketPaths {
+        commitment: packet.commitment_path(),
+        ack: packet.ack_commitment_path(),
+        receipt: packet.receipt_commitment_path(),
+    }
+}
+
+/// Finds the pairs of packets whose commitment paths are identical, e.g. packets sent twice with
+/// the same sequence but different contents, which is a sign of malformed packets that the relayer
+/// should alert on, as only one of them can be committed on chain.
+///
+/// # Returns
+/// The pairs of indices in `packets` of the colliding packets, sorted, with the lower index first.
+#[must_use]
+pub fn find_path_collisions(packets: &[Packet]) -> Vec<(usize, usize)> {
+    let mut packets_by_path: HashMap<Vec<u8>, Vec<usize>> = HashMap::new();
+    for (index, packet) in packets.iter().enumerate() {
+        packets_by_path
+            .entry(packet.commitment_path())
+            .or_default()
+            .push(index);
+    }
+
+    let mut collisions = packets_by_path
+        .into_values()
+        .flat_map(|indices| {
+            indices
+                .iter()
+                .enumerate()
+                .flat_map(|(i, &first)| indices[i + 1..].iter().map(move |&second| (first, second)))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    collisions.sort_unstable();
+    collisions
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        time::Duration,
+    };
+
+    use alloy::{primitives::U256, sol_types::SolValue};
+    use ibc_eureka_solidity_types::{
+        ics26::IICS26RouterMsgs::{Packet, Payload},
+        msgs::IICS20TransferMsgs::FungibleTokenPacketData,
+    };
+
+    use crate::{
+        events::{EurekaEvent, EurekaEventWithHeight},
+        utils::cosmos::ProofKind,
+    };
+
+    use super::{
+        canonicalize_payload, drop_expired_send_events, fairness_key, find_path_collisions,
+        forward_metadata, is_destined_to_known_apps, is_on_registered_connection,
+        is_relayable_given_latency, normalize_client_id, packet_paths, packets_expiring_within,
+        predicted_ack, validate_ics20_packet, ClientIdMigrations, PacketFilter,
+        ICS20_SUCCESSFUL_ACKNOWLEDGEMENT,
+    };
+
+    fn packet_to(dest_ports: &[&str]) -> Packet {
+        Packet {
+            sequence: 1,
+            sourceClient: "client-0".to_string(),
+            destClient: "client-1".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: dest_ports
+                .iter()
+                .map(|dest_port| Payload {
+                    sourcePort: "transfer".to_string(),
+                    destPort: (*dest_port).to_string(),
+                    version: "ics20-1".to_string(),
+                    encoding: "application/json".to_string(),
+                    value: vec![].into(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_payload() {
+        let mut packet = packet_to(&["transfer", "transfer"]);
+        packet.payloads[0].encoding = " Application/JSON; charset=utf-8".to_string();
+        packet.payloads[1].encoding = "json".to_string();
+        canonicalize_payload(&mut packet, 1).unwrap();
+        assert!(packet
+            .payloads
+            .iter()
+            .all(|payload| payload.encoding == "application/json"));
+        // A canonical packet is left as is.
+        let canonical = packet.clone();
+        canonicalize_payload(&mut packet, 1).unwrap();
+        assert_eq!(packet, canonical);
+
+        let mut legacy_abi = packet_to(&["transfer"]);
+        legacy_abi.payloads[0].encoding = "abi".to_string();
+        canonicalize_payload(&mut legacy_abi, 2).unwrap();
+        assert_eq!(
+            legacy_abi.payloads[0].encoding,
+            "application/x-solidity-abi"
+        );
+
+        // A payload in another encoding, or an unknown version, is rejected.
+        assert!(canonicalize_payload(&mut packet_to(&["transfer"]), 2).is_err());
+        assert!(canonicalize_payload(&mut packet_to(&["transfer"]), 3).is_err());
+    }
+
+    #[test]
+    fn test_is_destined_to_known_apps() {
+        let known_apps = HashSet::from(["transfer".to_string()]);
+
+        assert!(is_destined_to_known_apps(
+            &packet_to(&["transfer"]),
+            &known_apps
+        ));
+        assert!(!is_destined_to_known_apps(
+            &packet_to(&["unknown"]),
+            &known_apps
+        ));
+        assert!(!is_destined_to_known_apps(
+            &packet_to(&["transfer", "unknown"]),
+            &known_apps
+        ));
+    }
+
+    #[test]
+    fn test_packet_filter() {
+        let packet = packet_to(&["transfer"]);
+        assert!(PacketFilter::default().allows(&packet));
+
+        let filter = PacketFilter {
+            source_clients: HashSet::from(["client-0".to_string()]),
+            source_ports: HashSet::from(["transfer".to_string()]),
+            ..Default::default()
+        };
+        assert!(filter.allows(&packet));
+        assert!(!filter.allows(&Packet {
+            sourceClient: "client-2".to_string(),
+            ..packet.clone()
+        }));
+
+        let mut other_port = packet.clone();
+        other_port.payloads[0].sourcePort = "other".to_string();
+        assert!(!filter.allows(&other_port));
+
+        let filter = PacketFilter {
+            dest_clients: HashSet::from(["client-2".to_string()]),
+            ..Default::default()
+        };
+        assert!(!filter.allows(&packet));
+    }
+
+    #[test]
+    fn test_drop_expired_send_events() {
+        let now = 1_700_000_000;
+        let event = |event| EurekaEventWithHeight {
+            event,
+            block_number: None,
+            block_timestamp: None,
+        };
+        let pending = Packet {
+            timeoutTimestamp: now + 60,
+            ..packet_to(&["transfer"])
+        };
+        let expired = Packet {
+            sequence: 2,
+            timeoutTimestamp: now,
+            ..packet_to(&["transfer"])
+        };
+        let events = vec![
+            event(EurekaEvent::SendPacket(pending)),
+            event(EurekaEvent::SendPacket(expired.clone())),
+            event(EurekaEvent::WriteAcknowledgement(expired, vec![])),
+        ];
+
+        let kept = drop_expired_send_events(events.clone(), now - 1);
+        assert_eq!(kept, events);
+
+        let kept = drop_expired_send_events(events.clone(), now);
+        assert_eq!(kept, vec![events[0].clone(), events[2].clone()]);
+    }
+
+    #[test]
+    fn test_is_on_registered_connection() {
+        let registered_connections =
+            HashSet::from([("client-0".to_string(), "client-1".to_string())]);
+
+        assert!(is_on_registered_connection(
+            &packet_to(&["transfer"]),
+            &registered_connections
+        ));
+        let reversed = Packet {
+            sourceClient: "client-1".to_string(),
+            destClient: "client-0".to_string(),
+            ..packet_to(&["transfer"])
+        };
+        assert!(!is_on_registered_connection(
+            &reversed,
+            &registered_connections
+        ));
+        let unknown = Packet {
+            destClient: "client-2".to_string(),
+            ..packet_to(&["transfer"])
+        };
+        assert!(!is_on_registered_connection(
+            &unknown,
+            &registered_connections
+        ));
+    }
+
+    #[test]
+    fn test_forward_metadata() {
+        let packet_with_memo = |memo: &str| {
+            let mut packet = packet_to(&["transfer"]);
+            packet.payloads[0].value = serde_json::to_vec(&serde_json::json!({
+                "denom": "uatom",
+                "amount": "1",
+                "sender": "sender",
+                "receiver": "receiver",
+                "memo": memo,
+            }))
+            .unwrap()
+            .into();
+            packet
+        };
+        let forward = serde_json::json!({
+            "receiver": "next-receiver",
+            "port": "transfer",
+            "channel": "client-2",
+        });
+
+        assert_eq!(
+            forward_metadata(&packet_with_memo(
+                &serde_json::json!({ "forward": forward }).to_string()
+            )),
+            Some(forward)
+        );
+        assert_eq!(forward_metadata(&packet_with_memo("")), None);
+        assert_eq!(forward_metadata(&packet_with_memo(r#"{"wasm":{}}"#)), None);
+        assert_eq!(forward_metadata(&packet_to(&["transfer"])), None);
+    }
+
+    #[test]
+    fn test_predicted_ack() {
+        assert_eq!(
+            predicted_ack(&packet_to(&["transfer"])),
+            Some(ICS20_SUCCESSFUL_ACKNOWLEDGEMENT.to_vec())
+        );
+        assert_eq!(predicted_ack(&packet_to(&["unknown"])), None);
+        assert_eq!(predicted_ack(&packet_to(&["transfer", "transfer"])), None);
+        assert_eq!(predicted_ack(&packet_to(&[])), None);
+    }
+
+    #[test]
+    fn test_client_id_migrations() {
+        let migrations = ClientIdMigrations {
+            current_clients: HashSet::from(["client-2".to_string(), "client-1".to_string()]),
+            migrated_clients: HashMap::from([("client-0".to_string(), "client-2".to_string())]),
+        };
+        // The packet was sent from client-0, before its chain id migrated to client-2.
+        let old_packet = packet_to(&["transfer"]);
+        let current_packet = Packet {
+            sourceClient: "client-2".to_string(),
+            ..old_packet.clone()
+        };
+
+        assert_eq!(migrations.resolve("client-0").unwrap(), "client-2");
+        assert_eq!(migrations.resolve("client-1").unwrap(), "client-1");
+        assert_eq!(
+            migrations
+                .proof_path(&old_packet, ProofKind::Commitment)
+                .unwrap(),
+            current_packet.commitment_path()
+        );
+        assert_eq!(
+            migrations
+                .proof_path(&old_packet, ProofKind::Receipt)
+                .unwrap(),
+            old_packet.receipt_commitment_path()
+        );
+
+        // An old id without a mapping is flagged.
+        assert!(migrations.resolve("client-3").is_err());
+        let unmapped_packet = Packet {
+            destClient: "client-3".to_string(),
+            ..old_packet
+        };
+        assert!(migrations
+            .proof_path(&unmapped_packet, ProofKind::Acknowledgement)
+            .is_err());
+        assert!(migrations
+            .proof_path(&unmapped_packet, ProofKind::Commitment)
+            .is_ok());
+
+        // Without current clients, the ids that are not migrated are current.
+        assert_eq!(
+            ClientIdMigrations::default().resolve("client-3").unwrap(),
+            "client-3"
+        );
+    }
+
+    #[test]
+    fn test_normalize_client_id() {
+        assert_eq!(
+            normalize_client_id("07-tendermint-0").unwrap(),
+            "07-tendermint-0"
+        );
+        assert_eq!(normalize_client_id("client-12").unwrap(), "client-12");
+        assert_eq!(normalize_client_id("my.client").unwrap(), "my.client");
+        assert_eq!(
+            normalize_client_id("cosmos_hub+[1]<#>").unwrap(),
+            "cosmos_hub+[1]<#>"
+        );
+        let longest = "a".repeat(128);
+        assert_eq!(normalize_client_id(&longest).unwrap(), longest);
+
+        // Client ids are case sensitive, so a mis-cased id is kept as is.
+        assert_eq!(normalize_client_id("Hub-1").unwrap(), "Hub-1");
+        assert_eq!(normalize_client_id("08-Wasm-1").unwrap(), "08-Wasm-1");
+
+        for malformed in [
+            "",
+            "abc",
+            " 08-wasm-1 ",
+            "08-wasm-1\n",
+            "07 tendermint-0",
+            "07/tendermint-0",
+            "client-é",
+            &"a".repeat(129),
+        ] {
+            assert!(normalize_client_id(malformed).is_err(), "{malformed}");
+        }
+    }
+    #[test]
+    fn test_packets_expiring_within() {
+        let now = 1_700_000_000;
+        let packet_with_timeout = |sequence, timeout_timestamp| Packet {
+            sequence,
+            timeoutTimestamp: timeout_timestamp,
+            ..packet_to(&["transfer"])
+        };
+        let packets = vec![
+            // Already timed out.
+            packet_with_timeout(1, now),
+            packet_with_timeout(2, now + 60),
+            packet_with_timeout(3, now + 10),
+            // At the edge of the window.
+            packet_with_timeout(4, now + 300),
+            // Outside of the window.
+            packet_with_timeout(5, now + 301),
+        ];
+
+        let expiring = packets_expiring_within(&packets, now, Duration::from_secs(300))
+            .into_iter()
+            .map(|packet| packet.sequence)
+            .collect::<Vec<_>>();
+        assert_eq!(expiring, vec![3, 2, 4]);
+
+        assert!(packets_expiring_within(&packets, now, Duration::ZERO).is_empty());
+    }
+
+    #[test]
+    fn test_is_relayable_given_latency() {
+        let now = 1_700_000_000;
+        let packet_with_timeout = |timeout_timestamp| Packet {
+            timeoutTimestamp: timeout_timestamp,
+            ..packet_to(&["transfer"])
+        };
+        let latency = Duration::from_secs(30);
+
+        assert!(is_relayable_given_latency(
+            &packet_with_timeout(now + 600),
+            now,
+            latency
+        ));
+        assert!(!is_relayable_given_latency(
+            &packet_with_timeout(now + 5),
+            now,
+            latency
+        ));
+        // A packet timing out as it is received is not relayable.
+        assert!(!is_relayable_given_latency(
+            &packet_with_timeout(now + 30),
+            now,
+            latency
+        ));
+    }
+
+    #[test]
+    fn test_fairness_key() {
+        let packet = Packet {
+            sequence: 7,
+            ..packet_to(&["transfer"])
+        };
+        assert_eq!(fairness_key(&packet), ("client-0".to_string(), 7));
+
+        // Ordering by the sequence first interleaves the channels.
+        let mut packets = [
+            ("client-0", 1),
+            ("client-0", 2),
+            ("client-2", 1),
+            ("client-2", 2),
+        ]
+        .map(|(source_client, sequence)| Packet {
+            sequence,
+            sourceClient: source_client.to_string(),
+            ..packet_to(&["transfer"])
+        });
+        packets.sort_by_key(|packet| {
+            let (channel, sequence) = fairness_key(packet);
+            (sequence, channel)
+        });
+        assert_eq!(
+            packets.iter().map(fairness_key).collect::<Vec<_>>(),
+            vec![
+                ("client-0".to_string(), 1),
+                ("client-2".to_string(), 1),
+                ("client-0".to_string(), 2),
+                ("client-2".to_string(), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_path_collisions() {
+        let packet = |sequence, dest_client: &str| Packet {
+            sequence,
+            destClient: dest_client.to_string(),
+            ..packet_to(&["transfer"])
+        };
+        let packets = vec![
+            packet(1, "client-1"),
+            packet(2, "client-1"),
+            // The same sequence from the same source client, sent to another client.
+            packet(1, "client-2"),
+            Packet {
+                sourceClient: "client-3".to_string(),
+                ..packet(1, "client-1")
+            },
+            packet(1, "client-1"),
+        ];
+
+        assert_eq!(find_path_collisions(&packets), vec![(0, 2), (0, 4), (2, 4)]);
+        assert!(find_path_collisions(&packets[..2]).is_empty());
+    }
+
+    #[test]
+    fn test_packet_paths() {
+        let packet = Packet {
+            sequence: 0x0102,
+            ..packet_to(&["transfer"])
+        };
+        let paths = packet_paths(&packet);
+
+        assert_eq!(paths.commitment, b"client-0\x01\0\0\0\0\0\0\x01\x02");
+        assert_eq!(paths.ack, b"client-1\x03\0\0\0\0\0\0\x01\x02");
+        assert_eq!(paths.receipt, b"client-1\x02\0\0\0\0\0\0\x01\x02");
+        assert_eq!(paths.path(ProofKind::Commitment), packet.commitment_path());
+        assert_eq!(
+            paths.path(ProofKind::Acknowledgement),
+            packet.ack_commitment_path()
+        );
+        assert_eq!(
+            paths.path(ProofKind::Receipt),
+            packet.receipt_commitment_path()
+        );
+    }
+
+    #[test]
+    fn test_validate_ics20_packet() {
+        let data = FungibleTokenPacketData {
+            denom: "uatom".to_string(),
+            sender: "cosmos1sender".to_string(),
+            receiver: "0x0000000000000000000000000000000000000001".to_string(),
+            amount: U256::from(100),
+            memo: String::new(),
+        };
+        let transfer = |encoding: &str, value: Vec<u8>| {
+            let mut packet = packet_to(&["transfer"]);
+            packet.payloads[0].encoding = encoding.to_string();
+            packet.payloads[0].value = value.into();
+            packet
+        };
+        // The solidity types do not derive `PartialEq`.
+        let fields = |data: FungibleTokenPacketData| {
+            (
+                data.denom,
+                data.sender,
+                data.receiver,
+                data.amount,
+                data.memo,
+            )
+        };
+
+        let abi = transfer("application/x-solidity-abi", data.abi_encode());
+        assert_eq!(
+            fields(validate_ics20_packet(&abi).unwrap()),
+            fields(data.clone())
+        );
+        let json = transfer(
+            "application/json",
+            serde_json::to_vec(&serde_json::json!({
+                "denom": "uatom",
+                "sender": "cosmos1sender",
+                "receiver": "0x0000000000000000000000000000000000000001",
+                "amount": "100",
+            }))
+            .unwrap(),
+        );
+        assert_eq!(
+            fields(validate_ics20_packet(&json).unwrap()),
+            fields(data.clone())
+        );
+
+        // Undecodable data.
+        assert!(
+            validate_ics20_packet(&transfer("application/x-solidity-abi", vec![1, 2])).is_err()
+        );
+        assert!(validate_ics20_packet(&transfer("application/json", b"{}".to_vec())).is_err());
+        // Invalid transfer fields.
+        for data in [
+            FungibleTokenPacketData {
+                amount: U256::ZERO,
+                ..data.clone()
+            },
+            FungibleTokenPacketData {
+                receiver: String::new(),
+                ..data.clone()
+            },
+            FungibleTokenPacketData {
+                denom: String::new(),
+                ..data.clone()
+            },
+        ] {
+            let packet = transfer("application/x-solidity-abi", data.abi_encode());
+            assert!(validate_ics20_packet(&packet).is_err());
+        }
+        // Not a single transfer payload.
+        let mut packet = abi.clone();
+        packet.payloads[0].version = "ics27-1".to_string();
+        assert!(validate_ics20_packet(&packet).is_err());
+        let mut packet = abi.clone();
+        packet.payloads.push(packet.payloads[0].clone());
+        assert!(validate_ics20_packet(&packet).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! Relayer utilities for verifying the proofs of the IBC store of `CosmosSDK` chains locally.
+
+use anyhow::Result;
+use ibc_core_commitment_types::{
+    commitment::CommitmentRoot,
+    merkle::{MerklePath, MerkleProof},
+    proto::ics23::{self, HostFunctionsManager},
+    specs::ProofSpecs,
+};
+
+/// The proof spec of the IBC store of a `CosmosSDK` chain.
+/// The root multistore always uses the tendermint proof spec, but the IBC store can either be an
+/// iavl tree or a simple merkle tree, depending on the chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProofSpec {
+    /// The IBC store is an iavl tree, as used by the `CosmosSDK`.
+    #[default]
+    Iavl,
+    /// The IBC store is a simple merkle tree, as used by tendermint.
+    Tendermint,
+}
+
+impl ProofSpec {
+    /// Returns the proof specs of the IBC store followed by the root multistore.
+    #[must_use]
+    pub fn proof_specs(self) -> ProofSpecs {
+        let store_spec = match self {
+            Self::Iavl => ics23::iavl_spec(),
+            Self::Tendermint => ics23::tendermint_spec(),
+        };
+        vec![store_spec, ics23::tendermint_spec()].into()
+    }
+}
+
+/// The parameters to verify the tendermint proofs locally before they are injected, so that an
+/// invalid proof is caught by the relayer instead of failing on the target chain.
+#[derive(Debug, Clone, Copy)]
+pub struct LocalVerification<'a> {
+    /// The proof spec of the IBC store of the source chain.
+    pub proof_spec: ProofSpec,
+    /// The app hash committing to the state at the proof height.
+    pub app_hash: &'a [u8],
+}
+
+impl LocalVerification<'_> {
+    /// Verifies the proof of `value` at `path`. An empty value is verified as a non-membership
+    /// proof.
+    /// # Errors
+    /// Returns an error if the proof does not verify against the app hash.
+    pub fn verify(&self, proof: &MerkleProof, path: &[Vec<u8>], value: &[u8]) -> Result<()> {
+        let merkle_path = MerklePath {
+            key_path: path.iter().cloned().map(Into::into).collect(),
+        };
+        let root = CommitmentRoot::from_bytes(self.app_hash).into();
+        let specs = self.proof_spec.proof_specs();
+
+        if value.is_empty() {
+            proof.verify_non_membership::<HostFunctionsManager>(&specs, root, merkle_path)
+        } else {
+            proof.verify_membership::<HostFunctionsManager>(
+                &specs,
+                root,
+                merkle_path,
+                value.to_vec(),
+                0,
+            )
+        }
+        .map_err(|e| anyhow::anyhow!("Proof verification failed with {:?}: {e}", self.proof_spec))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ibc_core_commitment_types::{
+        merkle::MerkleProof,
+        proto::ics23::{
+            self, calculate_existence_root, commitment_proof, CommitmentProof, ExistenceProof,
+            HostFunctionsManager, LeafOp,
+        },
+    };
+
+    use super::{LocalVerification, ProofSpec};
+
+    /// The leaf prefix of an iavl leaf at height 0, with size 1 and version 1.
+    const IAVL_LEAF_PREFIX: &[u8] = &[0, 2, 2];
+
+    /// Builds a two layer proof of `value` at `[store_key, key]`, where the leaves of the store
+    /// have `leaf_prefix`, and returns it with the app hash it proves against.
+    fn store_proof(key: &[u8], value: &[u8], leaf_prefix: &[u8]) -> (MerkleProof, Vec<u8>) {
+        let leaf = ics23::tendermint_spec().leaf_spec.unwrap();
+        let store_proof = ExistenceProof {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            leaf: Some(LeafOp {
+                prefix: leaf_prefix.to_vec(),
+                ..leaf.clone()
+            }),
+            path: vec![],
+        };
+        let store_root = calculate_existence_root::<HostFunctionsManager>(&store_proof).unwrap();
+        let multistore_proof = ExistenceProof {
+            key: b"ibc".to_vec(),
+            value: store_root,
+            leaf: Some(leaf),
+            path: vec![],
+        };
+        let app_hash = calculate_existence_root::<HostFunctionsManager>(&multistore_proof).unwrap();
+
+        let proof = MerkleProof {
+            proofs: [store_proof, multistore_proof]
+                .into_iter()
+                .map(|proof| CommitmentProof {
+                    proof: Some(commitment_proof::Proof::Exist(proof)),
+                })
+                .collect(),
+        };
+        (proof, app_hash)
+    }
+
+    #[test]
+    fn test_verify_iavl_proof() {
+        let path = [b"ibc".to_vec(), b"commitment".to_vec()];
+        let (proof, app_hash) = store_proof(&path[1], b"value", IAVL_LEAF_PREFIX);
+        let verification = LocalVerification {
+            proof_spec: ProofSpec::Iavl,
+            app_hash: &app_hash,
+        };
+
+        verification.verify(&proof, &path, b"value").unwrap();
+        assert!(verification.verify(&proof, &path, b"other").is_err());
+    }
+
+    #[test]
+    fn test_verify_tendermint_proof() {
+        let path = [b"ibc".to_vec(), b"commitment".to_vec()];
+        let (proof, app_hash) = store_proof(&path[1], b"value", &[0]);
+        let verification = LocalVerification {
+            proof_spec: ProofSpec::Tendermint,
+            app_hash: &app_hash,
+        };
+
+        verification.verify(&proof, &path, b"value").unwrap();
+        assert!(verification.verify(&proof, &path, b"other").is_err());
+
+        // The leaf of a simple merkle tree is not a valid iavl leaf.
+        let iavl_verification = LocalVerification {
+            proof_spec: ProofSpec::Iavl,
+            ..verification
+        };
+        assert!(iavl_verification.verify(&proof, &path, b"value").is_err());
+    }
+}
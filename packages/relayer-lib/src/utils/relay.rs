@@ -0,0 +1,735 @@
+//! Relayer utilities for proving relay messages to `CosmosSDK` chains one at a time.
+
+use std::{future::Future, time::Instant};
+
+use alloy::{
+    hex,
+    primitives::{b256, B256},
+};
+use anyhow::Result;
+use futures::{stream, Stream, StreamExt};
+use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
+use ibc_proto_eureka::ibc::core::{
+    channel::v2::{MsgAcknowledgement, MsgRecvPacket, MsgTimeout},
+    client::v1::Height,
+};
+use serde::{Deserialize, Serialize};
+
+use super::packet::forward_metadata;
+
+/// A relay message that is submitted to a `CosmosSDK` chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum RelayMessage {
+    /// A receive packet message.
+    Recv(MsgRecvPacket),
+    /// An acknowledgement message.
+    Ack(MsgAcknowledgement),
+    /// A timeout message.
+    Timeout(MsgTimeout),
+}
+
+impl RelayMessage {
+    /// Sets the proof and the proof height of the message.
+    pub fn set_proof(&mut self, proof: Vec<u8>, proof_height: Height) {
+        match self {
+            Self::Recv(msg) => {
+                msg.proof_commitment = proof;
+                msg.proof_height = Some(proof_height);
+            }
+            Self::Ack(msg) => {
+                msg.proof_acked = proof;
+                msg.proof_height = Some(proof_height);
+            }
+            Self::Timeout(msg) => {
+                msg.proof_unreceived = proof;
+                msg.proof_height = Some(proof_height);
+            }
+        }
+    }
+
+    /// Returns the proof of the message.
+    #[must_use]
+    pub fn proof(&self) -> &[u8] {
+        match self {
+            Self::Recv(msg) => &msg.proof_commitment,
+            Self::Ack(msg) => &msg.proof_acked,
+            Self::Timeout(msg) => &msg.proof_unreceived,
+        }
+    }
+
+    /// Returns the path proved by the message: the packet commitment for a recv, the
+    /// acknowledgement commitment for an ack and the packet receipt for a timeout.
+    /// # Errors
+    /// Returns an error if the message has no packet.
+    pub fn proved_path(&self) -> Result<Vec<u8>> {
+        let packet = self.packet()?;
+        Ok(match self {
+            Self::Recv(_) => packet.commitment_path(),
+            Self::Ack(_) => packet.ack_commitment_path(),
+            Self::Timeout(_) => packet.receipt_commitment_path(),
+        })
+    }
+
+    /// Returns the name of the relay action of the message.
+    #[must_use]
+    pub const fn action(&self) -> &'static str {
+        match self {
+            Self::Recv(_) => "recv",
+            Self::Ack(_) => "ack",
+            Self::Timeout(_) => "timeout",
+        }
+    }
+
+    fn packet(&self) -> Result<Packet> {
+        match self {
+            Self::Recv(msg) => msg.packet.clone(),
+            Self::Ack(msg) => msg.packet.clone(),
+            Self::Timeout(msg) => msg.packet.clone(),
+        }
+        .map(Packet::from)
+        .ok_or_else(|| anyhow::anyhow!("The relay message has no packet"))
+    }
+
+    /// Returns the forward metadata of the packet if this message receives a packet that is
+    /// forwarded to another chain, see [`forward_metadata`].
+    #[must_use]
+    pub fn forward_metadata(&self) -> Option<serde_json::Value> {
+        match self {
+            Self::Recv(msg) => forward_metadata(&Packet::from(msg.packet.clone()?)),
+            Self::Ack(_) | Self::Timeout(_) => None,
+        }
+    }
+}
+
+/// The universal error acknowledgement written by the router when an application fails to
+/// receive a packet, i.e. `sha256("UNIVERSAL_ERROR_ACKNOWLEDGEMENT")`.
+pub const UNIVERSAL_ERROR_ACK: B256 =
+    b256!("4774d4a575993f963b1c06573736617a457abef8589178db8d10c94b4ab511ab");
+
+/// The slack of an error acknowledgement in [`priority_score`], i.e. it is scheduled as a packet
+/// that times out in 10 minutes, as the sender of the packet is waiting for its refund.
+pub const ERROR_ACK_SLACK_SECS: u64 = 600;
+
+/// The slack in [`priority_score`] of the messages without a deadline, and the maximum slack of
+/// the packets to receive.
+pub const MAX_SLACK_SECS: u64 = 1 << 32;
+
+/// Returns the priority of `msg` at the unix timestamp `now`, in seconds, for a scheduler that
+/// submits the messages of higher scores first. The score decreases with the slack of the
+/// message, i.e. the time left before it can no longer be relayed usefully:
+/// - A packet to receive has the time left before its `timeoutTimestamp`, so the packets closest
+///   to timing out are received first.
+/// - An acknowledgement that carries a [`UNIVERSAL_ERROR_ACK`] has [`ERROR_ACK_SLACK_SECS`].
+/// - Any other acknowledgement and a timeout have [`MAX_SLACK_SECS`].
+#[must_use]
+pub fn priority_score(msg: &RelayMessage, now: u64) -> u64 {
+    let slack = match msg {
+        RelayMessage::Recv(_) => msg.packet().map_or(MAX_SLACK_SECS, |packet| {
+            packet.timeoutTimestamp.saturating_sub(now)
+        }),
+        RelayMessage::Ack(ack_msg) => {
+            let is_error_ack = ack_msg.acknowledgement.as_ref().is_some_and(|ack| {
+                ack.app_acknowledgements
+                    .iter()
+                    .any(|ack| ack.as_slice() == UNIVERSAL_ERROR_ACK.as_slice())
+            });
+            if is_error_ack {
+                ERROR_ACK_SLACK_SECS
+            } else {
+                MAX_SLACK_SECS
+            }
+        }
+        RelayMessage::Timeout(_) => MAX_SLACK_SECS,
+    };
+    MAX_SLACK_SECS - slack.min(MAX_SLACK_SECS)
+}
+
+/// The `ProofBackend` trait defines the interface for a service that generates the proof for a
+/// single [`RelayMessage`].
+#[async_trait::async_trait]
+pub trait ProofBackend {
+    /// Generates and injects the proof into the message.
+    async fn prove(&self, msg: &mut RelayMessage) -> Result<()>;
+}
+
+/// The `MockProofBackend` injects mock proofs for testing purposes.
+pub struct MockProofBackend;
+
+#[async_trait::async_trait]
+impl ProofBackend for MockProofBackend {
+    async fn prove(&self, msg: &mut RelayMessage) -> Result<()> {
+        msg.set_proof(b"mock".to_vec(), Height::default());
+        Ok(())
+    }
+}
+
+/// The proving scheme of the succinct proofs, which must match the verifier of the counterparty
+/// light client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ZkScheme {
+    /// A compressed SP1 STARK proof.
+    #[default]
+    Sp1,
+    /// An SP1 proof wrapped in a Groth16 SNARK, for verifiers on the EVM.
+    Groth16,
+}
+
+/// The `ZkProverService` trait defines the interface for a prover service that generates
+/// succinct proofs of the paths of a chain, for zk light clients.
+#[async_trait::async_trait]
+pub trait ZkProverService: Sync {
+    /// Requests the succinct proof of `path` in `scheme`, and returns it with the height it
+    /// proves.
+    async fn prove_path(&self, path: &[u8], scheme: ZkScheme) -> Result<(Vec<u8>, Height)>;
+}
+
+/// The `ZkProofBackend` proves the messages with the succinct proofs of a zk prover service
+/// instead of merkle proofs.
+pub struct ZkProofBackend<S> {
+    prover: S,
+    scheme: ZkScheme,
+}
+
+impl<S: ZkProverService> ZkProofBackend<S> {
+    /// Creates a new `ZkProofBackend` requesting the proofs in `scheme` from `prover`.
+    #[must_use]
+    pub const fn new(prover: S, scheme: ZkScheme) -> Self {
+        Self { prover, scheme }
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: ZkProverService> ProofBackend for ZkProofBackend<S> {
+    async fn prove(&self, msg: &mut RelayMessage) -> Result<()> {
+        let path = msg.proved_path()?;
+        let (proof, proof_height) = self.prover.prove_path(&path, self.scheme).await?;
+        msg.set_proof(proof, proof_height);
+        Ok(())
+    }
+}
+
+/// The structured record of a proved relay message, emitted as JSON for log aggregation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayActionRecord {
+    /// The relay action, i.e. `recv`, `ack` or `timeout`.
+    pub action: String,
+    /// The sequence of the packet.
+    pub sequence: u64,
+    /// The source client of the packet.
+    pub source_client: String,
+    /// The destination client of the packet.
+    pub dest_client: String,
+    /// The hex encoded path proved by the message.
+    pub path: String,
+    /// The size of the proof in bytes.
+    pub proof_size: usize,
+    /// The time taken to generate the proof, in milliseconds.
+    pub proof_duration_ms: u64,
+}
+
+/// The `JsonEventsBackend` proves the messages with `backend` and, if `emit_json_events` is set,
+/// hands a JSON encoded [`RelayActionRecord`] of each proved message to `emit`, e.g. to log it.
+pub struct JsonEventsBackend<'a, B: ?Sized, E> {
+    backend: &'a B,
+    emit_json_events: bool,
+    emit: E,
+}
+
+impl<'a, B: ProofBackend + ?Sized, E: Fn(&str)> JsonEventsBackend<'a, B, E> {
+    /// Creates a new `JsonEventsBackend` wrapping `backend`.
+    #[must_use]
+    pub const fn new(backend: &'a B, emit_json_events: bool, emit: E) -> Self {
+        Self {
+            backend,
+            emit_json_events,
+            emit,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<B, E> ProofBackend for JsonEventsBackend<'_, B, E>
+where
+    B: ProofBackend + Sync + ?Sized,
+    E: Fn(&str) + Sync,
+{
+    async fn prove(&self, msg: &mut RelayMessage) -> Result<()> {
+        let start = Instant::now();
+        self.backend.prove(msg).await?;
+        if !self.emit_json_events {
+            return Ok(());
+        }
+
+        let packet = msg.packet()?;
+        let record = RelayActionRecord {
+            action: msg.action().to_string(),
+            sequence: packet.sequence,
+            source_client: packet.sourceClient,
+            dest_client: packet.destClient,
+            path: hex::encode(msg.proved_path()?),
+            proof_size: msg.proof().len(),
+            proof_duration_ms: u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX),
+        };
+        (self.emit)(&serde_json::to_string(&record)?);
+        Ok(())
+    }
+}
+
+/// Proves a copy of `msg` with each of the `backends` and reports the size of the proof each
+/// backend produces, so that operators can compare the cost of the routes of the same packet.
+///
+/// # Arguments
+/// - `msg` - The message to prove.
+/// - `backends` - The backends to compare, with the names they are reported under.
+///
+/// # Returns
+/// The proof size in bytes of each backend, in the order of `backends`.
+/// # Errors
+/// Returns an error if any of the backends cannot prove the message.
+pub async fn proof_sizes<'a>(
+    msg: &RelayMessage,
+    backends: &[(&'a str, &dyn ProofBackend)],
+) -> Result<Vec<(&'a str, usize)>> {
+    let mut sizes = Vec::with_capacity(backends.len());
+    for (name, backend) in backends {
+        let mut msg = msg.clone();
+        backend.prove(&mut msg).await?;
+        sizes.push((*name, msg.proof().len()));
+    }
+    Ok(sizes)
+}
+
+/// Proves each message of `stream` as it flows, yielding the proved messages in order.
+///
+/// # Arguments
+/// - `stream` - The stream of messages to prove.
+/// - `backend` - The backend used to generate the proofs.
+/// - `buffer_size` - The maximum number of messages being proved at the same time.
+/// # Errors
+/// Returns an error if `buffer_size` is zero, as the stream would never make progress.
+pub fn inject_proofs_stream<'a, S, B>(
+    stream: S,
+    backend: &'a B,
+    buffer_size: usize,
+) -> Result<impl Stream<Item = Result<RelayMessage>> + 'a>
+where
+    S: Stream<Item = RelayMessage> + 'a,
+    B: ProofBackend + ?Sized,
+{
+    anyhow::ensure!(buffer_size > 0, "The buffer size must be positive");
+
+    Ok(stream
+        .map(move |mut msg| async move {
+            backend.prove(&mut msg).await?;
+            anyhow::Ok(msg)
+        })
+        .buffered(buffer_size))
+}
+
+/// Proves the commitment of each of `packets`, yielding every packet with its proof as soon as
+/// the proof completes, so that the proved packets can be submitted without waiting for the
+/// whole batch. At most `max_concurrency` proofs are generated at the same time, and they are
+/// yielded in the order they complete rather than in the order of `packets`.
+///
+/// # Arguments
+/// - `packets` - The packets whose commitments are proved.
+/// - `backend` - The backend used to generate the proofs, as for a [`RelayMessage::Recv`].
+/// - `max_concurrency` - The maximum number of proofs being generated at the same time.
+/// # Errors
+/// Returns an error if `max_concurrency` is zero, as the stream would never make progress.
+pub fn stream_proofs<'a, B>(
+    packets: Vec<Packet>,
+    backend: &'a B,
+    max_concurrency: usize,
+) -> Result<impl Stream<Item = Result<(Packet, Vec<u8>)>> + 'a>
+where
+    B: ProofBackend + ?Sized,
+{
+    anyhow::ensure!(
+        max_concurrency > 0,
+        "The maximum proof concurrency must be positive"
+    );
+
+    Ok(stream::iter(packets)
+        .map(move |packet| async move {
+            let mut msg = RelayMessage::Recv(MsgRecvPacket {
+                packet: Some(packet.clone().into()),
+                ..Default::default()
+            });
+            backend.prove(&mut msg).await?;
+            anyhow::Ok((packet, msg.proof().to_vec()))
+        })
+        .buffer_unordered(max_concurrency))
+}
+
+/// Proves `msgs` and hands them to `submit` in batches of at most `batch_size` messages, in
+/// order. A batch is only submitted once all of its messages are proved.
+///
+/// # Arguments
+/// - `msgs` - The messages to prove and submit.
+/// - `backend` - The backend used to generate the proofs.
+/// - `batch_size` - The maximum number of messages in a submitted batch.
+/// - `submit` - The function that submits a batch of proved messages to the target chain.
+/// # Errors
+/// Returns an error if `batch_size` is zero, or if a proof cannot be generated or a batch cannot
+/// be submitted. The batches submitted before the error are not rolled back.
+pub async fn inject_and_submit<B, F, Fut>(
+    msgs: Vec<RelayMessage>,
+    backend: &B,
+    batch_size: usize,
+    submit: F,
+) -> Result<()>
+where
+    B: ProofBackend + ?Sized,
+    F: Fn(Vec<RelayMessage>) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    anyhow::ensure!(batch_size > 0, "The batch size must be positive");
+
+    let mut batches = inject_proofs_stream(stream::iter(msgs), backend, batch_size)?
+        .chunks(batch_size)
+        .boxed_local();
+    while let Some(batch) = batches.next().await {
+        submit(batch.into_iter().collect::<Result<Vec<_>>>()?).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, sync::Mutex, time::Duration};
+
+    use futures::{executor::block_on, stream, StreamExt};
+    use futures_timer::Delay;
+    use ibc_eureka_solidity_types::ics26::IICS26RouterMsgs::Packet;
+    use ibc_proto_eureka::ibc::core::{
+        channel::v2::{Acknowledgement, MsgAcknowledgement, MsgRecvPacket, MsgTimeout},
+        client::v1::Height,
+    };
+
+    use super::{
+        inject_and_submit, inject_proofs_stream, priority_score, proof_sizes, stream_proofs,
+        JsonEventsBackend, MockProofBackend, ProofBackend, RelayActionRecord, RelayMessage,
+        ZkProofBackend, ZkProverService, ZkScheme, UNIVERSAL_ERROR_ACK,
+    };
+
+    /// A mock prover service that returns a canned zk proof, tagged with the proved path, and
+    /// records the requested schemes.
+    #[derive(Default)]
+    struct MockZkProver {
+        schemes: Mutex<Vec<ZkScheme>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ZkProverService for MockZkProver {
+        async fn prove_path(
+            &self,
+            path: &[u8],
+            scheme: ZkScheme,
+        ) -> anyhow::Result<(Vec<u8>, Height)> {
+            self.schemes.lock().unwrap().push(scheme);
+            let proof = [b"zk-proof:".as_slice(), path].concat();
+            let height = Height {
+                revision_number: 0,
+                revision_height: 42,
+            };
+            Ok((proof, height))
+        }
+    }
+
+    /// A mock backend that injects proofs of a fixed size.
+    struct SizedProofBackend(usize);
+
+    #[async_trait::async_trait]
+    impl ProofBackend for SizedProofBackend {
+        async fn prove(&self, msg: &mut RelayMessage) -> anyhow::Result<()> {
+            msg.set_proof(vec![0; self.0], Height::default());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_priority_score() {
+        let now = 1_700_000_000;
+        let recv = |timeout_timestamp| {
+            RelayMessage::Recv(MsgRecvPacket {
+                packet: Some(
+                    Packet {
+                        sequence: 1,
+                        sourceClient: "07-tendermint-0".to_string(),
+                        destClient: "08-wasm-0".to_string(),
+                        timeoutTimestamp: timeout_timestamp,
+                        payloads: vec![],
+                    }
+                    .into(),
+                ),
+                ..Default::default()
+            })
+        };
+        let ack = |app_ack: &[u8]| {
+            RelayMessage::Ack(MsgAcknowledgement {
+                acknowledgement: Some(Acknowledgement {
+                    app_acknowledgements: vec![app_ack.to_vec()],
+                }),
+                ..Default::default()
+            })
+        };
+
+        // The packets closer to timing out score higher.
+        let near_timeout = priority_score(&recv(now + 60), now);
+        let far_timeout = priority_score(&recv(now + 86_400), now);
+        assert!(near_timeout > far_timeout);
+        assert!(priority_score(&recv(now), now) > near_timeout);
+
+        // An error ack scores above an ack and a timeout, but below a packet about to time out.
+        let error_ack = priority_score(&ack(UNIVERSAL_ERROR_ACK.as_slice()), now);
+        let success_ack = priority_score(&ack(b"ok"), now);
+        assert!(error_ack > success_ack);
+        assert!(error_ack > far_timeout);
+        assert!(near_timeout > error_ack);
+        assert_eq!(
+            priority_score(&RelayMessage::Timeout(MsgTimeout::default()), now),
+            success_ack
+        );
+    }
+
+    #[test]
+    fn test_inject_proofs_stream() {
+        let msgs = vec![
+            RelayMessage::Recv(MsgRecvPacket::default()),
+            RelayMessage::Ack(MsgAcknowledgement::default()),
+            RelayMessage::Timeout(MsgTimeout::default()),
+        ];
+
+        let proved = block_on(
+            inject_proofs_stream(stream::iter(msgs.clone()), &MockProofBackend, 2)
+                .unwrap()
+                .collect::<Vec<_>>(),
+        )
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+
+        assert_eq!(proved.len(), 3);
+        assert!(matches!(proved[0], RelayMessage::Recv(_)));
+        assert!(matches!(proved[1], RelayMessage::Ack(_)));
+        assert!(matches!(proved[2], RelayMessage::Timeout(_)));
+        assert!(proved.iter().all(|msg| msg.proof() == b"mock"));
+
+        assert!(inject_proofs_stream(stream::iter(msgs), &MockProofBackend, 0).is_err());
+    }
+
+    /// A mock backend that proves the commitment of the packet with sequence 1 last, and tags the
+    /// proofs with the sequence of their packet.
+    struct SlowFirstProofBackend;
+
+    #[async_trait::async_trait]
+    impl ProofBackend for SlowFirstProofBackend {
+        async fn prove(&self, msg: &mut RelayMessage) -> anyhow::Result<()> {
+            let RelayMessage::Recv(recv_msg) = msg else {
+                anyhow::bail!("Only recv messages are proved");
+            };
+            let sequence = recv_msg.packet.as_ref().unwrap().sequence;
+            if sequence == 1 {
+                Delay::new(Duration::from_millis(50)).await;
+            }
+            msg.set_proof(sequence.to_be_bytes().to_vec(), Height::default());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_stream_proofs() {
+        let packets = (1..=3)
+            .map(|sequence| Packet {
+                sequence,
+                sourceClient: "07-tendermint-0".to_string(),
+                destClient: "08-wasm-0".to_string(),
+                timeoutTimestamp: 1_700_000_000,
+                payloads: vec![],
+            })
+            .collect::<Vec<_>>();
+
+        let proved = block_on(
+            stream_proofs(packets.clone(), &SlowFirstProofBackend, 3)
+                .unwrap()
+                .collect::<Vec<_>>(),
+        )
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+
+        assert_eq!(proved.len(), 3);
+        for (packet, proof) in &proved {
+            assert_eq!(proof, &packet.sequence.to_be_bytes());
+        }
+        // The slow proof does not hold back the others.
+        assert_eq!(proved[2].0, packets[0]);
+
+        // With a single proof at a time, the proofs are generated in the order of the packets.
+        let proved = block_on(
+            stream_proofs(packets.clone(), &SlowFirstProofBackend, 1)
+                .unwrap()
+                .collect::<Vec<_>>(),
+        )
+        .into_iter()
+        .collect::<anyhow::Result<Vec<_>>>()
+        .unwrap();
+        assert_eq!(proved[0].0, packets[0]);
+
+        assert!(stream_proofs(packets, &SlowFirstProofBackend, 0).is_err());
+    }
+
+    #[test]
+    fn test_zk_proof_backend() {
+        let packet = Packet {
+            sequence: 1,
+            sourceClient: "07-tendermint-0".to_string(),
+            destClient: "08-wasm-0".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![],
+        };
+        let backend = ZkProofBackend::new(MockZkProver::default(), ZkScheme::default());
+
+        let mut msg = RelayMessage::Timeout(MsgTimeout {
+            packet: Some(packet.clone().into()),
+            ..Default::default()
+        });
+        block_on(backend.prove(&mut msg)).unwrap();
+        assert_eq!(
+            msg.proof(),
+            [b"zk-proof:".as_slice(), &packet.receipt_commitment_path()].concat()
+        );
+        let RelayMessage::Timeout(timeout_msg) = msg else {
+            unreachable!()
+        };
+        assert_eq!(timeout_msg.proof_height.unwrap().revision_height, 42);
+
+        // A message without a packet has no path to prove.
+        let mut msg = RelayMessage::Recv(MsgRecvPacket::default());
+        assert!(block_on(backend.prove(&mut msg)).is_err());
+    }
+
+    #[test]
+    fn test_zk_proof_backend_scheme() {
+        let packet = Packet {
+            sequence: 1,
+            sourceClient: "07-tendermint-0".to_string(),
+            destClient: "08-wasm-0".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![],
+        };
+        for scheme in [ZkScheme::Sp1, ZkScheme::Groth16] {
+            let backend = ZkProofBackend::new(MockZkProver::default(), scheme);
+            let mut msg = RelayMessage::Recv(MsgRecvPacket {
+                packet: Some(packet.clone().into()),
+                ..Default::default()
+            });
+            block_on(backend.prove(&mut msg)).unwrap();
+            assert_eq!(*backend.prover.schemes.lock().unwrap(), vec![scheme]);
+        }
+    }
+
+    #[test]
+    fn test_json_events_backend() {
+        let packet = Packet {
+            sequence: 7,
+            sourceClient: "07-tendermint-0".to_string(),
+            destClient: "08-wasm-0".to_string(),
+            timeoutTimestamp: 1_700_000_000,
+            payloads: vec![],
+        };
+        let msg = RelayMessage::Ack(MsgAcknowledgement {
+            packet: Some(packet.clone().into()),
+            ..Default::default()
+        });
+        // The emitter is shared by the proof futures, so it must be `Sync`.
+        let records = Mutex::new(vec![]);
+        let emit = |record: &str| records.lock().unwrap().push(record.to_string());
+
+        block_on(JsonEventsBackend::new(&MockProofBackend, false, emit).prove(&mut msg.clone()))
+            .unwrap();
+        assert!(records.lock().unwrap().is_empty());
+
+        block_on(JsonEventsBackend::new(&MockProofBackend, true, emit).prove(&mut msg.clone()))
+            .unwrap();
+        let records = records.into_inner().unwrap();
+        assert_eq!(records.len(), 1);
+        let record: RelayActionRecord = serde_json::from_str(&records[0]).unwrap();
+        assert_eq!(record.action, "ack");
+        assert_eq!(record.sequence, 7);
+        assert_eq!(record.source_client, "07-tendermint-0");
+        assert_eq!(record.dest_client, "08-wasm-0");
+        assert_eq!(
+            record.path,
+            alloy::hex::encode(packet.ack_commitment_path())
+        );
+        assert_eq!(record.proof_size, b"mock".len());
+    }
+
+    #[test]
+    fn test_proof_sizes() {
+        let msg = RelayMessage::Recv(MsgRecvPacket::default());
+        let sizes = block_on(proof_sizes(
+            &msg,
+            &[
+                ("tendermint", &MockProofBackend),
+                ("ethereum", &SizedProofBackend(532)),
+            ],
+        ))
+        .unwrap();
+
+        assert_eq!(sizes, vec![("tendermint", 4), ("ethereum", 532)]);
+        // The message itself is not proved.
+        assert!(msg.proof().is_empty());
+    }
+
+    #[test]
+    fn test_inject_and_submit() {
+        let msgs = (0..5)
+            .map(|sequence| {
+                RelayMessage::Recv(MsgRecvPacket {
+                    signer: sequence.to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+        let submitted = RefCell::new(vec![]);
+
+        block_on(inject_and_submit(
+            msgs.clone(),
+            &MockProofBackend,
+            2,
+            |batch| {
+                submitted.borrow_mut().push(batch);
+                async { Ok(()) }
+            },
+        ))
+        .unwrap();
+
+        let submitted = submitted.into_inner();
+        assert_eq!(
+            submitted.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![2, 2, 1]
+        );
+        assert!(submitted.iter().flatten().all(|msg| msg.proof() == b"mock"));
+        let submitted_signers = submitted
+            .into_iter()
+            .flatten()
+            .map(|msg| match msg {
+                RelayMessage::Recv(msg) => msg.signer,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(submitted_signers, vec!["0", "1", "2", "3", "4"]);
+
+        assert!(
+            block_on(inject_and_submit(msgs, &MockProofBackend, 0, |_| async {
+                Ok(())
+            }))
+            .is_err()
+        );
+    }
+}
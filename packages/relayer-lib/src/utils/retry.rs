@@ -0,0 +1,264 @@
+//! Relayer utilities for scheduling the retries of failed relay messages.
+
+use std::{
+    collections::hash_map::{DefaultHasher, RandomState},
+    future::Future,
+    hash::{BuildHasher, Hash, Hasher},
+    time::{Duration, Instant},
+};
+
+use futures_timer::Delay;
+
+use super::error::{categorize_failure, FailureCategory, ProofError};
+
+/// The policy used to space out the retries of a failed message.
+/// The delay doubles with every attempt, up to `max_delay`, and is extended by a random fraction
+/// of up to `jitter` of itself, so that messages failing together are not all retried at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// The delay before the first retry.
+    pub base_delay: Duration,
+    /// The maximum delay before a retry, before the jitter is added.
+    pub max_delay: Duration,
+    /// The maximum fraction of the delay added as jitter, between 0 and 1.
+    pub jitter: f64,
+}
+
+/// A message that failed to be relayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageFailure {
+    /// The index of the message in its batch.
+    pub index: usize,
+    /// The number of times the message has failed.
+    pub attempts: u32,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the retry of a message that failed `attempts` times, before the
+    /// jitter is added.
+    #[must_use]
+    pub fn delay(&self, attempts: u32) -> Duration {
+        self.base_delay
+            .saturating_mul(2_u32.saturating_pow(attempts.saturating_sub(1)))
+            .min(self.max_delay)
+    }
+}
+
+/// Computes when each of the failed messages should be retried, for a scheduler to consume.
+///
+/// # Arguments
+/// - `failures` - The failed messages.
+/// - `policy` - The policy used to space out the retries.
+/// - `now` - The time at which the messages failed.
+///
+/// # Returns
+/// The index of each failed message along with the time at which it should be retried.
+#[must_use]
+pub fn next_retry_schedule(
+    failures: &[MessageFailure],
+    policy: &RetryPolicy,
+    now: Instant,
+) -> Vec<(usize, Instant)> {
+    failures
+        .iter()
+        .map(|failure| {
+            let delay = retry_delay(policy, failure.attempts, jitter_fraction(failure));
+            (failure.index, now + delay)
+        })
+        .collect()
+}
+
+/// Returns whether `err` is worth retrying. The failures of the RPC, e.g. transport errors and
+/// timeouts, and the [`ProofError`]s categorized as [`FailureCategory::Transient`], such as an
+/// empty membership value, are retried, but not the permanent ones, such as an unexpected
+/// non-membership, nor a cancellation, which must stop the retries.
+#[must_use]
+pub fn is_retryable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<ProofError>().is_none_or(|err| {
+        !matches!(err, ProofError::Cancelled { .. })
+            && categorize_failure(err) == FailureCategory::Transient
+    })
+}
+
+/// Runs `operation` until it succeeds, fails with an error that is not [`is_retryable`], or has
+/// been attempted `max_attempts` times, waiting between attempts as scheduled by `policy`.
+/// The jitter is drawn at random on every attempt, so that concurrent callers failing together
+/// do not all retry at once.
+/// # Errors
+/// Returns the error of the last attempt.
+pub async fn retry_with_backoff<T, F, Fut>(
+    policy: &RetryPolicy,
+    max_attempts: u32,
+    mut operation: F,
+) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match operation().await {
+            Err(err) if attempts < max_attempts && is_retryable(&err) => {
+                let delay = retry_delay(policy, attempts, random_fraction());
+                tracing::debug!(
+                    "Retrying in {delay:?} after attempt {attempts}/{max_attempts} failed: {err}"
+                );
+                Delay::new(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns the delay before the retry of a message that failed `attempts` times, extended by
+/// `fraction` of the policy's jitter.
+fn retry_delay(policy: &RetryPolicy, attempts: u32, fraction: f64) -> Duration {
+    let delay = policy.delay(attempts);
+    delay + delay.mul_f64(policy.jitter.clamp(0.0, 1.0) * fraction)
+}
+
+/// Derives a pseudo random fraction between 0 and 1 from the failure, so that the jitter spreads
+/// the retries of different messages while staying reproducible.
+#[allow(clippy::cast_precision_loss)]
+fn jitter_fraction(failure: &MessageFailure) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    failure.hash(&mut hasher);
+    hasher.finish() as f64 / u64::MAX as f64
+}
+
+/// Draws a random fraction between 0 and 1 from the randomly keyed hasher of the standard library,
+/// which is seeded differently for every call.
+#[allow(clippy::cast_precision_loss)]
+fn random_fraction() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::Cell,
+        time::{Duration, Instant},
+    };
+
+    use futures::executor::block_on;
+
+    use super::{
+        next_retry_schedule, random_fraction, retry_with_backoff, MessageFailure, RetryPolicy,
+    };
+    use crate::utils::error::ProofError;
+
+    #[test]
+    fn test_next_retry_schedule() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.5,
+        };
+        let now = Instant::now();
+        let failures = (1..=5)
+            .map(|attempts| MessageFailure { index: 0, attempts })
+            .collect::<Vec<_>>();
+
+        let delays = next_retry_schedule(&failures, &policy, now)
+            .into_iter()
+            .map(|(_, retry_at)| retry_at - now)
+            .collect::<Vec<_>>();
+
+        for (failure, delay) in failures.iter().zip(&delays) {
+            let exponential = policy.delay(failure.attempts);
+            assert!(*delay >= exponential && *delay <= exponential.mul_f64(1.5));
+        }
+        assert!(delays.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(failures
+            .iter()
+            .zip(&delays)
+            .any(|(failure, delay)| *delay != policy.delay(failure.attempts)));
+
+        // The delay is capped by the maximum delay.
+        assert_eq!(policy.delay(10), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_next_retry_schedule_spreads_messages() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_secs(10),
+            max_delay: Duration::from_secs(60),
+            jitter: 1.0,
+        };
+        let now = Instant::now();
+        let failures = (0..4)
+            .map(|index| MessageFailure { index, attempts: 1 })
+            .collect::<Vec<_>>();
+
+        let schedule = next_retry_schedule(&failures, &policy, now);
+        assert_eq!(
+            schedule.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert!(schedule.windows(2).any(|pair| pair[0].1 != pair[1].1));
+    }
+
+    #[test]
+    fn test_retry_with_backoff() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(10),
+            jitter: 0.5,
+        };
+        let attempts = Cell::new(0);
+        let failing_twice = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                anyhow::ensure!(attempt > 2, "502 Bad Gateway");
+                Ok(attempt)
+            }
+        };
+
+        assert_eq!(
+            block_on(retry_with_backoff(&policy, 3, failing_twice)).unwrap(),
+            3
+        );
+        attempts.set(0);
+        assert!(block_on(retry_with_backoff(&policy, 1, failing_twice)).is_err());
+        assert_eq!(attempts.get(), 1);
+
+        // Transient proof errors are retried, but not the permanent ones nor a cancellation.
+        let failing_with = |err: fn() -> ProofError| {
+            attempts.set(0);
+            let result = block_on(retry_with_backoff(&policy, 3, || {
+                attempts.set(attempts.get() + 1);
+                async move { anyhow::Result::<()>::Err(err().into()) }
+            }));
+            assert!(result.unwrap_err().downcast_ref::<ProofError>().is_some());
+            attempts.get()
+        };
+        assert_eq!(
+            failing_with(|| ProofError::MembershipValueEmpty {
+                path: "00".to_string(),
+            }),
+            3
+        );
+        assert_eq!(
+            failing_with(|| ProofError::UnexpectedNonMembership {
+                path: "00".to_string(),
+            }),
+            1
+        );
+        assert_eq!(
+            failing_with(|| ProofError::Cancelled { completed: vec![] }),
+            1
+        );
+    }
+
+    #[test]
+    fn test_random_fraction() {
+        let fractions = (0..8).map(|_| random_fraction()).collect::<Vec<_>>();
+        assert!(fractions
+            .iter()
+            .all(|fraction| (0.0..=1.0).contains(fraction)));
+        // Concurrent callers do not draw the same jitter.
+        assert!(fractions.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+}
@@ -0,0 +1,608 @@
+//! Relayer utilities for proving the storage of the IBC contract from a snapshot of its storage,
+//! rather than with a proof request per key.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use alloy::{
+    primitives::{keccak256, Address, Bytes, B256, U256},
+    rpc::types::{EIP1186AccountProofResponse, EIP1186StorageProof},
+};
+use anyhow::Result;
+use futures::{future, lock::Mutex};
+
+use super::{
+    client::{EthProofClient, ExecutionBlockHeader},
+    eth_proof::ProofBlockId,
+};
+
+/// An in-memory snapshot of the storage of an ethereum contract, which serves storage proofs from
+/// its Merkle Patricia trie.
+#[derive(Debug, Default)]
+pub struct StorageSnapshot {
+    address: Address,
+    storage: BTreeMap<B256, U256>,
+}
+
+impl StorageSnapshot {
+    /// Creates an empty storage for the contract at `address`.
+    #[must_use]
+    pub const fn new(address: Address) -> Self {
+        Self {
+            address,
+            storage: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the address of the contract.
+    #[must_use]
+    pub const fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Creates the snapshot of the storage of the contract at `address`.
+    #[must_use]
+    pub const fn from_storage(address: Address, storage: BTreeMap<B256, U256>) -> Self {
+        Self { address, storage }
+    }
+
+    /// Stores `value` at the storage `key`.
+    pub fn insert(&mut self, key: B256, value: U256) {
+        self.storage.insert(key, value);
+    }
+
+    /// Returns the storage root of the contract, which must match the storage root of the account
+    /// at the block of the snapshot for the proofs to verify.
+    #[must_use]
+    pub fn storage_root(&self) -> B256 {
+        self.trie().root()
+    }
+
+    /// Returns the state root of a state holding only the account of the contract, along with the
+    /// trie nodes that prove the account from this root. The snapshot has no other state, so the
+    /// account has a zero nonce, balance and code hash, and the storage root of the snapshot.
+    #[must_use]
+    pub fn account_proof(&self) -> (B256, Vec<Bytes>) {
+        self.account_proof_with_storage_root(self.storage_root())
+    }
+
+    /// Returns the value stored at `key`, and the trie nodes that prove it from the root.
+    /// If no value is stored at `key`, the nodes prove its absence.
+    #[must_use]
+    pub fn prove(&self, key: B256) -> (U256, Vec<Bytes>) {
+        self.prove_with_trie(&self.trie(), key)
+    }
+
+    /// Builds the storage trie of the contract, to serve the proofs of any number of keys.
+    fn trie(&self) -> Trie {
+        Trie::from_leaves(
+            self.storage
+                .iter()
+                .filter(|(_, value)| !value.is_zero())
+                .map(|(key, value)| {
+                    let value = value.to_be_bytes_trimmed_vec();
+                    (
+                        nibbles(keccak256(key).as_slice()),
+                        rlp_bytes(&rlp_bytes(&value)),
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the value stored at `key`, and the nodes of the storage `trie` that prove it.
+    fn prove_with_trie(&self, trie: &Trie, key: B256) -> (U256, Vec<Bytes>) {
+        let proof = trie.prove(&nibbles(keccak256(key).as_slice()));
+        let value = self.storage.get(&key).copied().unwrap_or_default();
+        (value, proof)
+    }
+
+    /// Returns the account proof of [`Self::account_proof`], for an already computed storage root.
+    fn account_proof_with_storage_root(&self, storage_root: B256) -> (B256, Vec<Bytes>) {
+        let account = rlp_list(&[
+            rlp_bytes(&[]),
+            rlp_bytes(&[]),
+            rlp_bytes(storage_root.as_slice()),
+            rlp_bytes(B256::ZERO.as_slice()),
+        ]);
+        let path = nibbles(keccak256(self.address).as_slice());
+        let state = Trie::from_leaves(vec![(path.clone(), rlp_bytes(&account))]);
+        (state.root(), state.prove(&path))
+    }
+
+    /// Proves the hex encoded `storage_keys` with the nodes of the storage `trie`.
+    fn storage_proofs(
+        &self,
+        trie: &Trie,
+        storage_keys: Vec<String>,
+    ) -> Result<Vec<EIP1186StorageProof>> {
+        let storage_proof = storage_keys
+            .into_iter()
+            .map(|key| {
+                let key: B256 = key.parse()?;
+                let (value, proof) = self.prove_with_trie(trie, key);
+                anyhow::Ok(serde_json::json!({
+                    "key": key,
+                    "value": value,
+                    "proof": proof,
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(serde_json::from_value(storage_proof.into())?)
+    }
+}
+
+#[async_trait::async_trait]
+impl EthProofClient for StorageSnapshot {
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        _block_id: String,
+        _request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        let address: Address = address.parse()?;
+        anyhow::ensure!(
+            address == self.address,
+            "The snapshot of the storage of {} cannot prove the storage of {address}",
+            self.address
+        );
+        let trie = self.trie();
+        let storage_proof = self.storage_proofs(&trie, storage_keys)?;
+        let storage_root = trie.root();
+        let (_, account_proof) = self.account_proof_with_storage_root(storage_root);
+
+        Ok(serde_json::from_value(serde_json::json!({
+            "address": self.address,
+            "balance": "0x0",
+            "codeHash": B256::ZERO,
+            "nonce": "0x0",
+            "storageHash": storage_root,
+            "accountProof": account_proof,
+            "storageProof": storage_proof,
+        }))?)
+    }
+}
+
+/// A Merkle Patricia trie, built once to serve the proofs of any number of paths from its nodes.
+struct Trie {
+    /// The root node, if the trie is not empty.
+    root: Option<TrieNode>,
+}
+
+/// A node of a [`Trie`], along with its encoding.
+struct TrieNode {
+    encoding: Vec<u8>,
+    children: TrieChildren,
+}
+
+/// The children of a [`TrieNode`].
+enum TrieChildren {
+    /// A leaf node has no children.
+    Leaf,
+    /// An extension node has a single child, under the nibbles of its prefix.
+    Extension(Vec<u8>, Box<TrieNode>),
+    /// A branch node has up to one child per nibble.
+    Branch(Vec<Option<TrieNode>>),
+}
+
+impl Trie {
+    /// Builds the trie of `leaves`, given as their paths in nibbles and their encoded values.
+    fn from_leaves(mut leaves: Vec<(Vec<u8>, Vec<u8>)>) -> Self {
+        leaves.sort();
+        Self {
+            root: (!leaves.is_empty()).then(|| build_node(&leaves, 0)),
+        }
+    }
+
+    /// Returns the root hash of the trie.
+    fn root(&self) -> B256 {
+        self.root.as_ref().map_or_else(
+            || keccak256(rlp_bytes(&[])),
+            |root| keccak256(&root.encoding),
+        )
+    }
+
+    /// Returns the nodes on the path to `path`, from the root down, which prove the value at
+    /// `path`, or its absence.
+    fn prove(&self, path: &[u8]) -> Vec<Bytes> {
+        let mut proof = vec![];
+        let mut node = self.root.as_ref();
+        let mut depth = 0;
+        while let Some(current) = node {
+            proof.push(current.encoding.clone().into());
+            node = match &current.children {
+                TrieChildren::Leaf => None,
+                TrieChildren::Extension(prefix, child) => {
+                    path[depth..].starts_with(prefix).then(|| {
+                        depth += prefix.len();
+                        child.as_ref()
+                    })
+                }
+                TrieChildren::Branch(children) => {
+                    let child = children[usize::from(path[depth])].as_ref();
+                    depth += 1;
+                    child
+                }
+            };
+        }
+        proof
+    }
+}
+
+/// The `StorageSnapshotClient` trait defines the interface for a client that fetches the whole
+/// storage of a contract at a block, e.g. through a state dump endpoint.
+#[async_trait::async_trait]
+pub trait StorageSnapshotClient: Sync {
+    /// Fetches the non-zero storage slots of the contract at `address` at `block_id`.
+    async fn storage_snapshot(&self, address: &str, block_id: &str)
+        -> Result<BTreeMap<B256, U256>>;
+}
+
+/// The `SnapshotProofClient` serves the proof requests of a block from a single snapshot of the
+/// contract storage, so that a large batch costs one snapshot request instead of one proof
+/// request per key. The account proof is fetched once per block from an execution client, and
+/// the snapshot is rejected if its storage root is not the one of the account at the block.
+pub struct SnapshotProofClient<S: StorageSnapshotClient, E: EthProofClient> {
+    client: S,
+    proof_client: E,
+    snapshot: Mutex<Option<Arc<BlockSnapshot>>>,
+}
+
+/// The snapshot of the contract storage at a block, along with its trie and the account proof
+/// served by the execution client at the block.
+struct BlockSnapshot {
+    address: String,
+    block_id: String,
+    storage: StorageSnapshot,
+    /// The storage trie of the snapshot, built once for all the proofs at the block.
+    trie: Trie,
+    account: EIP1186AccountProofResponse,
+    /// Whether the header of the block was requested along with the account proof.
+    header_requested: bool,
+    /// The header of the block served along with the account proof, if any.
+    header: Option<ExecutionBlockHeader>,
+}
+
+impl<S: StorageSnapshotClient, E: EthProofClient> SnapshotProofClient<S, E> {
+    /// Creates a new `SnapshotProofClient` that fetches the snapshots from `client`, and the
+    /// account proofs and block headers from `proof_client`.
+    #[must_use]
+    pub fn new(client: S, proof_client: E) -> Self {
+        Self {
+            client,
+            proof_client,
+            snapshot: Mutex::new(None),
+        }
+    }
+
+    /// Returns the snapshot of the contract at `address` at `block_id`, which is only fetched
+    /// if it differs from the snapshot of the previous request. If `header_block_id` is set, the
+    /// header of the block is fetched along with the account proof, from the same endpoint.
+    async fn snapshot(
+        &self,
+        address: &str,
+        block_id: String,
+        header_block_id: Option<ProofBlockId>,
+        request_id: Option<&str>,
+    ) -> Result<Arc<BlockSnapshot>> {
+        // The lock is held while fetching, so that concurrent requests share the same snapshot.
+        let mut cached = self.snapshot.lock().await;
+        if let Some(snapshot) = cached.as_ref().filter(|snapshot| {
+            snapshot.address == address
+                && snapshot.block_id == block_id
+                && (header_block_id.is_none() || snapshot.header_requested)
+        }) {
+            return Ok(snapshot.clone());
+        }
+
+        let fetch_account = async {
+            match header_block_id {
+                Some(header_block_id) => {
+                    self.proof_client
+                        .get_proof_at_block(address, vec![], header_block_id, request_id)
+                        .await
+                }
+                None => {
+                    let account = self
+                        .proof_client
+                        .get_proof(address, vec![], block_id.clone(), request_id)
+                        .await?;
+                    Ok((account, None))
+                }
+            }
+        };
+        let (storage, (account, header)) = future::try_join(
+            self.client.storage_snapshot(address, &block_id),
+            fetch_account,
+        )
+        .await?;
+        let storage = StorageSnapshot::from_storage(address.parse()?, storage);
+        let trie = storage.trie();
+        let storage_root = trie.root();
+        anyhow::ensure!(
+            storage_root == account.storage_hash,
+            "The snapshot of the storage of {address} at block {block_id} has the storage root {storage_root}, but the account is proven with the storage root {}",
+            account.storage_hash
+        );
+
+        let snapshot = Arc::new(BlockSnapshot {
+            address: address.to_string(),
+            block_id,
+            storage,
+            trie,
+            account,
+            header_requested: header_block_id.is_some(),
+            header,
+        });
+        *cached = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+}
+
+impl BlockSnapshot {
+    /// Proves `storage_keys` from the trie of the snapshot, along with the account proof of the
+    /// block.
+    fn get_proof(&self, storage_keys: Vec<String>) -> Result<EIP1186AccountProofResponse> {
+        Ok(EIP1186AccountProofResponse {
+            storage_proof: self.storage.storage_proofs(&self.trie, storage_keys)?,
+            ..self.account.clone()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl<S: StorageSnapshotClient, E: EthProofClient> EthProofClient for SnapshotProofClient<S, E> {
+    async fn get_proof(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: String,
+        request_id: Option<&str>,
+    ) -> Result<EIP1186AccountProofResponse> {
+        self.snapshot(address, block_id, None, request_id)
+            .await?
+            .get_proof(storage_keys)
+    }
+
+    async fn block_header(&self, block_id: ProofBlockId) -> Result<Option<ExecutionBlockHeader>> {
+        self.proof_client.block_header(block_id).await
+    }
+
+    async fn get_proof_at_block(
+        &self,
+        address: &str,
+        storage_keys: Vec<String>,
+        block_id: ProofBlockId,
+        request_id: Option<&str>,
+    ) -> Result<(EIP1186AccountProofResponse, Option<ExecutionBlockHeader>)> {
+        let snapshot = self
+            .snapshot(address, block_id.to_rpc_param(), Some(block_id), request_id)
+            .await?;
+        let proof = snapshot.get_proof(storage_keys)?;
+        Ok((proof, snapshot.header))
+    }
+}
+
+/// Builds the trie node of `leaves`, whose paths share their first `depth` nibbles.
+fn build_node(leaves: &[(Vec<u8>, Vec<u8>)], depth: usize) -> TrieNode {
+    if let [(path, value)] = leaves {
+        return TrieNode {
+            encoding: rlp_list(&[
+                rlp_bytes(&compact_path(&path[depth..], true)),
+                value.clone(),
+            ]),
+            children: TrieChildren::Leaf,
+        };
+    }
+
+    let first = &leaves[0].0;
+    let common = (depth..first.len())
+        .take_while(|i| leaves.iter().all(|(path, _)| path[*i] == first[*i]))
+        .count();
+    if common > 0 {
+        let prefix = first[depth..depth + common].to_vec();
+        let child = build_node(leaves, depth + common);
+        TrieNode {
+            encoding: rlp_list(&[
+                rlp_bytes(&compact_path(&prefix, false)),
+                child_ref(&child.encoding),
+            ]),
+            children: TrieChildren::Extension(prefix, Box::new(child)),
+        }
+    } else {
+        let children = (0..16_u8)
+            .map(|nibble| {
+                let start = leaves.partition_point(|(path, _)| path[depth] < nibble);
+                let end = leaves.partition_point(|(path, _)| path[depth] <= nibble);
+                (start != end).then(|| build_node(&leaves[start..end], depth + 1))
+            })
+            .collect::<Vec<_>>();
+        let mut refs = children
+            .iter()
+            .map(|child| {
+                child
+                    .as_ref()
+                    .map_or_else(|| rlp_bytes(&[]), |child| child_ref(&child.encoding))
+            })
+            .collect::<Vec<_>>();
+        // The keys have a fixed length, so no value is stored in a branch node.
+        refs.push(rlp_bytes(&[]));
+        TrieNode {
+            encoding: rlp_list(&refs),
+            children: TrieChildren::Branch(children),
+        }
+    }
+}
+
+/// Returns the reference to a child node, which is embedded if its encoding is short.
+fn child_ref(node: &[u8]) -> Vec<u8> {
+    if node.len() < 32 {
+        node.to_vec()
+    } else {
+        rlp_bytes(keccak256(node).as_slice())
+    }
+}
+
+/// Encodes a path with the hex prefix encoding of the Merkle Patricia trie.
+fn compact_path(nibbles: &[u8], is_leaf: bool) -> Vec<u8> {
+    let flag = if is_leaf { 2 } else { 0 };
+    let mut path = if nibbles.len() % 2 == 1 {
+        vec![((flag + 1) << 4) | nibbles[0]]
+    } else {
+        vec![flag << 4]
+    };
+    let rest = &nibbles[nibbles.len() % 2..];
+    path.extend(rest.chunks(2).map(|pair| (pair[0] << 4) | pair[1]));
+    path
+}
+
+/// Splits `bytes` into their nibbles, high nibble first.
+pub(crate) fn nibbles(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .flat_map(|byte| [byte >> 4, byte & 0x0f])
+        .collect()
+}
+
+/// Encodes `bytes` as an RLP string.
+pub(crate) fn rlp_bytes(bytes: &[u8]) -> Vec<u8> {
+    if let [byte] = bytes {
+        if *byte < 0x80 {
+            return vec![*byte];
+        }
+    }
+    [rlp_length_prefix(bytes.len(), 0x80), bytes.to_vec()].concat()
+}
+
+fn rlp_list(items: &[Vec<u8>]) -> Vec<u8> {
+    let payload = items.concat();
+    [rlp_length_prefix(payload.len(), 0xc0), payload].concat()
+}
+
+fn rlp_length_prefix(len: usize, offset: u8) -> Vec<u8> {
+    if len < 56 {
+        vec![offset + u8::try_from(len).unwrap()]
+    } else {
+        let len_bytes = len
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect::<Vec<_>>();
+        let len_of_len = u8::try_from(len_bytes.len()).unwrap();
+        [vec![offset + 55 + len_of_len], len_bytes].concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use alloy::primitives::{Address, B256, U256};
+    use futures::{executor::block_on, future};
+
+    use crate::utils::client::EthProofClient;
+
+    use super::{SnapshotProofClient, StorageSnapshot, StorageSnapshotClient};
+
+    /// A mock state dump endpoint that counts the snapshots it serves.
+    struct MockSnapshotClient {
+        storage: BTreeMap<B256, U256>,
+        snapshots: AtomicUsize,
+    }
+
+    #[async_trait::async_trait]
+    impl StorageSnapshotClient for MockSnapshotClient {
+        async fn storage_snapshot(
+            &self,
+            _address: &str,
+            _block_id: &str,
+        ) -> anyhow::Result<BTreeMap<B256, U256>> {
+            self.snapshots.fetch_add(1, Ordering::Relaxed);
+            Ok(self.storage.clone())
+        }
+    }
+
+    #[test]
+    fn test_snapshot_proof_client() {
+        let address = Address::repeat_byte(0x11);
+        let storage = (1..=4_u8)
+            .map(|i| (B256::repeat_byte(i), U256::from(i)))
+            .collect::<BTreeMap<_, _>>();
+        let expected = StorageSnapshot::from_storage(address, storage.clone());
+        let client = SnapshotProofClient::new(
+            MockSnapshotClient {
+                storage: storage.clone(),
+                snapshots: AtomicUsize::new(0),
+            },
+            StorageSnapshot::from_storage(address, storage),
+        );
+
+        let keys = (1..=5_u8).map(B256::repeat_byte).collect::<Vec<_>>();
+        let responses = block_on(future::try_join_all(keys.iter().map(|key| {
+            client.get_proof(
+                &address.to_string(),
+                vec![key.to_string()],
+                "0x10".to_string(),
+                None,
+            )
+        })))
+        .unwrap();
+
+        for (key, response) in keys.iter().zip(responses) {
+            let (value, proof) = expected.prove(*key);
+            assert_eq!(response.storage_hash, expected.storage_root());
+            assert_eq!(response.account_proof, expected.account_proof().1);
+            assert_eq!(response.storage_proof[0].value, value);
+            assert_eq!(response.storage_proof[0].proof, proof);
+        }
+        assert_eq!(client.client.snapshots.load(Ordering::Relaxed), 1);
+
+        // A request at another block fetches a new snapshot.
+        block_on(client.get_proof(
+            &address.to_string(),
+            vec![keys[0].to_string()],
+            "0x11".to_string(),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(client.client.snapshots.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn test_snapshot_proof_client_storage_root_mismatch() {
+        let address = Address::repeat_byte(0x11);
+        let storage = BTreeMap::from([(B256::repeat_byte(1), U256::from(1))]);
+        // The node proves the account with another storage than the one of the snapshot.
+        let mut state = StorageSnapshot::from_storage(address, storage.clone());
+        state.insert(B256::repeat_byte(2), U256::from(2));
+        let client = SnapshotProofClient::new(
+            MockSnapshotClient {
+                storage,
+                snapshots: AtomicUsize::new(0),
+            },
+            state,
+        );
+
+        let err = block_on(client.get_proof(
+            &address.to_string(),
+            vec![B256::repeat_byte(1).to_string()],
+            "0x10".to_string(),
+            None,
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("storage root"));
+
+        // The snapshot only proves the storage of its own contract.
+        let snapshot = StorageSnapshot::new(address);
+        block_on(snapshot.get_proof(
+            &Address::repeat_byte(0x22).to_string(),
+            vec![],
+            "0x10".to_string(),
+            None,
+        ))
+        .unwrap_err();
+    }
+}